@@ -78,6 +78,17 @@ fn benchmark_resolve(c: &mut Criterion) {
     c.bench_function("resolve plain query", |b| {
         b.iter(|| resolve(&config, "just a regular search query"));
     });
+    c.bench_function("resolve long plain query", |b| {
+        b.iter(|| {
+            resolve(
+                &config,
+                "how to write a criterion benchmark for an async rust web server behind a proxy",
+            )
+        });
+    });
+    c.bench_function("resolve query with unknown bang", |b| {
+        b.iter(|| resolve(&config, "!nosuchbang just a regular search query"));
+    });
     c.bench_function("resolve query with bang", |b| {
         b.iter(|| resolve(&config, "!gh just a regular search query"));
     });
@@ -0,0 +1,39 @@
+//! Compresses the embedded static files with gzip and brotli, so the binary
+//! carries the variants `/static/` serves and nothing is compressed at
+//! runtime.
+
+#[cfg(feature = "server")]
+fn main() {
+    use std::io::Write as _;
+    use std::path::PathBuf;
+
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").expect("Cargo sets OUT_DIR"));
+    // PNGs are compressed already
+    for name in ["style.css", "icon.svg"] {
+        let path = format!("res/{name}");
+        println!("cargo::rerun-if-changed={path}");
+        let data = std::fs::read(&path).unwrap_or_else(|e| panic!("Failed to read {path}: {e}"));
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        let gzip = encoder
+            .write_all(&data)
+            .and_then(|()| encoder.finish())
+            .expect("Compressing into memory can't fail");
+        let mut brotli = Vec::new();
+        brotli::BrotliCompress(
+            &mut &*data,
+            &mut brotli,
+            &brotli::enc::BrotliEncoderParams::default(),
+        )
+        .expect("Compressing into memory can't fail");
+
+        for (extension, compressed) in [("gz", gzip), ("br", brotli)] {
+            let target = out_dir.join(format!("{name}.{extension}"));
+            std::fs::write(&target, compressed)
+                .unwrap_or_else(|e| panic!("Failed to write {}: {e}", target.display()));
+        }
+    }
+}
+
+#[cfg(not(feature = "server"))]
+fn main() {}
@@ -0,0 +1,131 @@
+//! Aliases for triggers of the downloaded bang list.
+//!
+//! The default bang list only knows one trigger for most sites, while people
+//! often expect the spelled out name to work as well, like `!wiki` for `!w`.
+//! An alias resolves to the bang it points to when no bang has its trigger,
+//! and the resolution names the trigger of that bang. Aliases aren't bangs
+//! of their own, so they are left out of the listings, exports and counts.
+
+use std::collections::BTreeMap;
+
+/// The curated aliases and the triggers they stand for, used with
+/// `builtin_aliases`.
+pub const BUILTIN_ALIASES: [(&str, &str); 9] = [
+    ("wiki", "w"),
+    ("wikipedia", "w"),
+    ("google", "g"),
+    ("youtube", "yt"),
+    ("github", "gh"),
+    ("amazon", "a"),
+    ("reddit", "r"),
+    ("stackoverflow", "so"),
+    ("translate", "gt"),
+];
+
+/// The trigger `alias` stands for, with the built-in aliases if `builtin`
+/// and `overrides` on top of them. An override to an empty trigger removes
+/// the alias.
+#[must_use]
+pub fn alias_target(
+    alias: &str,
+    builtin: bool,
+    overrides: &BTreeMap<String, String>,
+) -> Option<String> {
+    let alias = alias.trim_start_matches('!').to_lowercase();
+    let overridden = overrides
+        .iter()
+        .rfind(|(name, _)| name.trim_start_matches('!').to_lowercase() == alias);
+    match overridden {
+        Some((_, trigger)) if trigger.is_empty() => None,
+        Some((_, trigger)) => Some(trigger.trim_start_matches('!').to_lowercase()),
+        None if builtin => {
+            BUILTIN_ALIASES
+                .iter()
+                .find(|(name, _)| *name == alias)
+                .map(|(_, trigger)| (*trigger).to_string())
+        },
+        None => None,
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+    use crate::bang::test_bang;
+    use crate::config::AppConfig;
+    use crate::resolver::{build_cache, resolve, resolve_trigger};
+
+    #[test]
+    fn test_aliases() {
+        let bangs = vec![
+            test_bang("w", "https://en.wikipedia.org/w/index.php?search={{{s}}}"),
+            test_bang("g", "https://www.google.com/search?q={{{s}}}"),
+            test_bang("google", "https://google.example.com/?q={{{s}}}"),
+        ];
+        let cache = build_cache(bangs, &[]);
+        let config = AppConfig::default();
+        let options = config.resolve_options();
+
+        let aliased = resolve(&cache, &options, "!wiki rust");
+        assert_eq!(aliased, resolve(&cache, &options, "!w rust"));
+        assert_eq!(
+            aliased.url,
+            "https://en.wikipedia.org/w/index.php?search=rust"
+        );
+        assert_eq!(
+            aliased.trigger.as_deref(),
+            Some("w"),
+            "the trigger of the bang"
+        );
+        assert_eq!(
+            resolve_trigger(&cache, &options, "!Wikipedia", "rust"),
+            Some(aliased)
+        );
+        // aliases aren't bangs of their own
+        assert!(!cache.contains_key("wiki"));
+        // bangs keep their trigger
+        assert_eq!(
+            resolve(&cache, &options, "!google rust").url,
+            "https://google.example.com/?q=rust"
+        );
+        // aliases of missing bangs are unknown bangs
+        assert!(resolve(&cache, &options, "!youtube rust").fallback);
+
+        let config = AppConfig {
+            builtin_aliases: false,
+            ..AppConfig::default()
+        };
+        assert!(resolve(&cache, &config.resolve_options(), "!wiki rust").fallback);
+    }
+
+    #[test]
+    fn test_alias_target() {
+        let none = BTreeMap::new();
+        assert_eq!(alias_target("wiki", false, &none), None);
+        assert_eq!(alias_target("wiki", true, &none).as_deref(), Some("w"));
+        let overrides = BTreeMap::from([
+            ("!Wiki".to_string(), "!wde".to_string()),
+            ("google".to_string(), String::new()),
+            ("docs".to_string(), "rs".to_string()),
+        ]);
+        assert_eq!(
+            alias_target("wiki", true, &overrides).as_deref(),
+            Some("wde")
+        );
+        assert_eq!(
+            alias_target("docs", true, &overrides).as_deref(),
+            Some("rs")
+        );
+        assert_eq!(
+            alias_target("wikipedia", true, &overrides).as_deref(),
+            Some("w")
+        );
+        assert_eq!(alias_target("google", true, &overrides), None);
+        assert_eq!(
+            alias_target("docs", false, &overrides).as_deref(),
+            Some("rs")
+        );
+        assert_eq!(alias_target("wikipedia", false, &overrides), None);
+    }
+}
@@ -0,0 +1,396 @@
+//! Static files served under `/static/`.
+//!
+//! The stylesheet and icons are embedded in the binary along with their gzip
+//! and brotli variants, compressed by the build script. Files in
+//! `ui.static_dir` replace the embedded ones with the same path, so they can
+//! be customized without rebuilding.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
+
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse as _, Response};
+use flate2::Crc;
+use tracing::error;
+
+/// The gzip and brotli variants of `res/{name}`, written by the build script.
+macro_rules! compressed {
+    ($name:literal) => {
+        [
+            Some(include_bytes!(concat!(env!("OUT_DIR"), "/", $name, ".gz")).as_slice()),
+            Some(include_bytes!(concat!(env!("OUT_DIR"), "/", $name, ".br")).as_slice()),
+        ]
+    };
+}
+
+/// Path, content type, contents, and gzip and brotli variants of the
+/// embedded files.
+type Embedded = (
+    &'static str,
+    &'static str,
+    &'static [u8],
+    [Option<&'static [u8]>; 2],
+);
+
+const EMBEDDED: [Embedded; 3] = [
+    (
+        "style.css",
+        "text/css; charset=utf-8",
+        include_bytes!("../res/style.css"),
+        compressed!("style.css"),
+    ),
+    (
+        "icon.svg",
+        "image/svg+xml",
+        include_bytes!("../res/icon.svg"),
+        compressed!("icon.svg"),
+    ),
+    // PNGs are compressed already
+    (
+        "icon64.png",
+        "image/png",
+        include_bytes!("../res/icon64.png"),
+        [None, None],
+    ),
+];
+
+/// Embedded files never change for a build, so browsers may keep them.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Files of `ui.static_dir` may change any time, browsers have to check.
+const REVALIDATE: &str = "no-cache";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    const fn name(self) -> Option<&'static str> {
+        match self {
+            Self::Identity => None,
+            Self::Gzip => Some("gzip"),
+            Self::Brotli => Some("br"),
+        }
+    }
+}
+
+/// An embedded file with its precompressed variants.
+#[derive(Debug)]
+pub struct Asset {
+    pub content_type: &'static str,
+    identity: &'static [u8],
+    gzip: Option<&'static [u8]>,
+    brotli: Option<&'static [u8]>,
+    /// Hash of the uncompressed contents, the base of the `ETag`s.
+    hash: String,
+}
+
+impl Asset {
+    fn new(
+        content_type: &'static str,
+        identity: &'static [u8],
+        [gzip, brotli]: [Option<&'static [u8]>; 2],
+    ) -> Self {
+        let smaller = |compressed: &&[u8]| compressed.len() < identity.len();
+        Self {
+            content_type,
+            identity,
+            gzip: gzip.filter(smaller),
+            brotli: brotli.filter(smaller),
+            hash: content_hash(identity),
+        }
+    }
+
+    /// The contents in `encoding`, uncompressed if there is no such variant.
+    fn body(&'static self, encoding: Encoding) -> &'static [u8] {
+        match encoding {
+            Encoding::Identity => None,
+            Encoding::Gzip => self.gzip,
+            Encoding::Brotli => self.brotli,
+        }
+        .unwrap_or(self.identity)
+    }
+}
+
+static ASSETS: LazyLock<HashMap<&'static str, Asset>> = LazyLock::new(|| {
+    EMBEDDED
+        .into_iter()
+        .map(|(path, content_type, contents, variants)| {
+            (path, Asset::new(content_type, contents, variants))
+        })
+        .collect()
+});
+
+/// The embedded file at `path`, relative to `/static/`.
+pub fn asset(path: &str) -> Option<&'static Asset> {
+    ASSETS.get(path)
+}
+
+fn content_hash(data: &[u8]) -> String {
+    let mut crc = Crc::new();
+    crc.update(data);
+    format!("{:08x}{:x}", crc.sum(), data.len())
+}
+
+/// The `ETag` of a variant. Variants need their own tag, as a cache may
+/// only answer with the variant it has if the tags are the same.
+fn etag(hash: &str, encoding: Encoding) -> String {
+    encoding.name().map_or_else(
+        || format!("\"{hash}\""),
+        |name| format!("\"{hash}-{name}\""),
+    )
+}
+
+/// Whether `If-None-Match` of `headers` lists a variant of the file with
+/// `hash`, so the copy of the client is up to date.
+fn not_modified(headers: &HeaderMap, hash: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| {
+            tag == "*"
+                || tag
+                    .strip_prefix('"')
+                    .and_then(|tag| tag.strip_suffix('"'))
+                    .and_then(|tag| tag.strip_prefix(hash))
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+        })
+}
+
+/// Whether `Accept-Encoding` of `headers` allows brotli and gzip.
+fn accepted_encodings(headers: &HeaderMap) -> (bool, bool) {
+    let mut brotli = false;
+    let mut gzip = false;
+    let codings = headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+    for coding in codings {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let accepted = !params.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+        match name.to_ascii_lowercase().as_str() {
+            "br" => brotli = accepted,
+            "gzip" | "x-gzip" => gzip = accepted,
+            "*" => {
+                brotli |= accepted;
+                gzip |= accepted;
+            },
+            _ => {},
+        }
+    }
+    (brotli, gzip)
+}
+
+/// The variant of `asset` to send for `headers`, brotli before gzip.
+pub fn negotiate(headers: &HeaderMap, asset: &Asset) -> Encoding {
+    let (brotli, gzip) = accepted_encodings(headers);
+    if brotli && asset.brotli.is_some() {
+        Encoding::Brotli
+    } else if gzip && asset.gzip.is_some() {
+        Encoding::Gzip
+    } else {
+        Encoding::Identity
+    }
+}
+
+/// Content type of files of `ui.static_dir` by their extension.
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("html") => "text/html; charset=utf-8",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Whether `path` is a valid path below `/static/`. Anything that could
+/// point outside of `ui.static_dir`, like `..` or an absolute path, isn't.
+pub fn is_valid_path(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// The existing file at the valid `path` below `dir`, unless a symlink leads
+/// out of it.
+async fn contained(dir: &Path, path: &str) -> Option<PathBuf> {
+    let dir = tokio::fs::canonicalize(dir).await.ok()?;
+    let file = tokio::fs::canonicalize(dir.join(path)).await.ok()?;
+    file.starts_with(&dir).then_some(file)
+}
+
+fn response(
+    status: StatusCode,
+    content_type: &'static str,
+    etag: &str,
+    cache_control: &'static str,
+    encoding: Option<&'static str>,
+    body: impl Into<Body>,
+) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(cache_control),
+    );
+    headers.insert(
+        header::ETAG,
+        HeaderValue::from_str(etag).expect("ETags are hex digits and quotes"),
+    );
+    headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if let Some(encoding) = encoding {
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+    }
+    (status, headers, body.into()).into_response()
+}
+
+/// Answer a request for `/static/{path}` with `headers`, with the file of
+/// `static_dir` if there is one, and the embedded file otherwise.
+pub async fn serve(static_dir: Option<&Path>, path: &str, headers: &HeaderMap) -> Response {
+    if !is_valid_path(path) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Some(dir) = static_dir
+        && let Some(file) = contained(dir, path).await
+    {
+        match tokio::fs::read(&file).await {
+            Ok(contents) => {
+                let hash = content_hash(&contents);
+                let etag = etag(&hash, Encoding::Identity);
+                let content_type = content_type(&file);
+                // compressed by the compression layer like dynamic responses
+                return if not_modified(headers, &hash) {
+                    response(
+                        StatusCode::NOT_MODIFIED,
+                        content_type,
+                        &etag,
+                        REVALIDATE,
+                        None,
+                        Vec::new(),
+                    )
+                } else {
+                    response(
+                        StatusCode::OK,
+                        content_type,
+                        &etag,
+                        REVALIDATE,
+                        None,
+                        contents,
+                    )
+                };
+            },
+            Err(e) => error!("Failed to read {}: {e}", file.display()),
+        }
+    }
+    let Some(asset) = asset(path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let encoding = negotiate(headers, asset);
+    let etag = etag(&asset.hash, encoding);
+    if not_modified(headers, &asset.hash) {
+        return response(
+            StatusCode::NOT_MODIFIED,
+            asset.content_type,
+            &etag,
+            IMMUTABLE,
+            None,
+            Vec::new(),
+        );
+    }
+    response(
+        StatusCode::OK,
+        asset.content_type,
+        &etag,
+        IMMUTABLE,
+        encoding.name(),
+        asset.body(encoding),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: header::HeaderName, value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(name, HeaderValue::from_static(value))])
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let css = asset("style.css").unwrap();
+        let png = asset("icon64.png").unwrap();
+        let negotiate_css = |value| negotiate(&headers(header::ACCEPT_ENCODING, value), css);
+        assert_eq!(negotiate_css("gzip, deflate, br, zstd"), Encoding::Brotli);
+        assert_eq!(negotiate_css("gzip, br;q=0"), Encoding::Gzip);
+        assert_eq!(negotiate_css("*"), Encoding::Brotli);
+        assert_eq!(negotiate_css("*;q=0"), Encoding::Identity);
+        assert_eq!(negotiate_css("deflate"), Encoding::Identity);
+        assert_eq!(negotiate(&HeaderMap::new(), css), Encoding::Identity);
+        assert_eq!(
+            negotiate(&headers(header::ACCEPT_ENCODING, "br"), png),
+            Encoding::Identity
+        );
+        assert_eq!(
+            css.body(Encoding::Identity),
+            include_bytes!("../res/style.css")
+        );
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let hash = &asset("style.css").unwrap().hash;
+        let matches = |value: String| {
+            not_modified(
+                &HeaderMap::from_iter([(
+                    header::IF_NONE_MATCH,
+                    HeaderValue::from_str(&value).unwrap(),
+                )]),
+                hash,
+            )
+        };
+        assert!(matches(etag(hash, Encoding::Identity)));
+        assert!(matches(format!("\"old\", {}", etag(hash, Encoding::Gzip))));
+        assert!(matches(format!("W/{}", etag(hash, Encoding::Brotli))));
+        assert!(matches("*".to_string()));
+        assert!(!matches(format!("\"{hash}0\"")));
+        assert!(!matches("\"old\"".to_string()));
+        assert!(!not_modified(&HeaderMap::new(), hash));
+    }
+
+    #[test]
+    fn test_is_valid_path() {
+        assert!(is_valid_path("style.css"));
+        assert!(is_valid_path("fonts/mono.woff2"));
+        for path in [
+            "",
+            "../secret.txt",
+            "fonts/../../secret.txt",
+            "/etc/passwd",
+            "./style.css",
+        ] {
+            assert!(!is_valid_path(path), "{path}");
+        }
+    }
+}
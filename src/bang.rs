@@ -1,8 +1,10 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "server", derive(bincode::Encode, bincode::Decode))]
 pub struct Bang {
     /// The category of the bang command (e.g., "Tech", "Entertainment").
     #[serde(alias = "category", rename = "c")]
@@ -25,9 +27,112 @@ pub struct Bang {
     /// The URL template where the search term is inserted.
     #[serde(alias = "url_template", rename = "u")]
     pub url_template: String,
+    /// Free text included in the redirect log whenever the bang is used, e.g.
+    /// to tell apart traffic from specific integrations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// How the search term is sent to the target, see [`BangMethod`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<BangMethod>,
+    /// Form field the search term is posted in, `q` if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_field: Option<String>,
+    /// Additional hidden form fields posted along with the search term.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_fields: Option<HashMap<String, String>>,
+    /// How the search term is encoded before it is substituted, see
+    /// [`TermEncoding`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub term_encoding: Option<TermEncoding>,
+    /// Template used instead of `url_template` for requests from a phone, e.g.
+    /// a link opening the app of the site.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mobile_url_template: Option<String>,
+    /// Templates used instead of `url_template` for browsers preferring one
+    /// of these languages, by language tag like `de` or `pt-BR`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub localized: Option<BTreeMap<String, String>>,
+    /// Whether the bang is used, `false` turns it off but keeps it in the file,
+    /// unless `prune_bangs` removes it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Unix timestamp from which on the bang isn't used anymore, e.g. for a
+    /// shortcut only needed during a project.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl Bang {
+    /// Whether the bang is used at `now`, in seconds since the Unix epoch: it
+    /// isn't disabled and hasn't expired.
+    pub fn is_active(&self, now: u64) -> bool {
+        self.enabled != Some(false) && self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    /// Bring the bang into its canonical form.
+    ///
+    /// Triggers are stored without the leading '!', but since that is how they
+    /// are typed, `trigger = "!w"` is accepted as well and means the same as
+    /// `trigger = "w"`. Only one '!' is stripped, `!!w` stays `!w`.
+    pub fn normalize(&mut self) {
+        let trigger = self.trigger.trim();
+        let trigger = trigger.strip_prefix('!').unwrap_or(trigger);
+        if trigger.len() != self.trigger.len() {
+            self.trigger = trigger.to_string();
+        }
+    }
+
+    /// Whether the term is inserted unencoded after the `?` of the template,
+    /// where a `&` or `#` in it cuts the parameter short.
+    #[must_use]
+    pub fn raw_term_in_query(&self) -> bool {
+        let term_at = self
+            .url_template
+            .find("{{{s}}}")
+            .unwrap_or(self.url_template.len());
+        self.term_encoding == Some(TermEncoding::None) && self.url_template[..term_at].contains('?')
+    }
+}
+
+/// HTTP method used to hand the search term to the target of a bang.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "server", derive(bincode::Encode, bincode::Decode))]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum BangMethod {
+    /// Redirect to the URL template with the term substituted.
+    #[default]
+    #[serde(alias = "GET")]
+    Get,
+    /// Submit the term as a form field to the URL template, for targets that
+    /// only accept POST requests.
+    #[serde(alias = "POST")]
+    Post,
+}
+
+/// How the search term is encoded before it is substituted into the URL
+/// template.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "server", derive(bincode::Encode, bincode::Decode))]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum TermEncoding {
+    /// Percent-encode everything but unreserved characters and slashes, for
+    /// terms in query parameters.
+    #[default]
+    Component,
+    /// Insert the term as typed, for templates expecting a whole URL like
+    /// `https://web.archive.org/web/{{{s}}}`. Only spaces, controls and
+    /// non-ASCII characters are encoded, since they can't be in a URL at all.
+    None,
+    /// Percent-encode what can't be in a URL path, like `?`, `#` and `%`,
+    /// keeping `/`, `:`, `&`, `=` and the like, for terms making up path
+    /// segments.
+    Path,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(bincode::Encode, bincode::Decode))]
 #[serde(rename_all = "PascalCase")]
 pub enum Category {
     Entertainment,
@@ -55,3 +160,55 @@ impl Display for Category {
         }
     }
 }
+
+/// A bang with `trigger` and `url_template` and the defaults otherwise, for
+/// tests to override the fields they need.
+#[cfg(test)]
+pub(crate) fn test_bang(trigger: &str, url_template: &str) -> Bang {
+    Bang {
+        trigger: trigger.to_string(),
+        url_template: url_template.to_string(),
+        ..Bang::default()
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use crate::config::FileConfig;
+
+    #[test]
+    fn test_normalize_trigger() {
+        let config: FileConfig = toml::from_str(
+            r#"
+            [[bangs]]
+            trigger = "!w"
+            url_template = "https://en.wikipedia.org/?search={{{s}}}"
+
+            [[bangs]]
+            trigger = "w"
+            url_template = "https://en.wikipedia.org/?search={{{s}}}"
+
+            [[bangs]]
+            trigger = " !gh"
+            url_template = "https://github.com/search?q={{{s}}}"
+
+            [[bangs]]
+            trigger = "!!g"
+            url_template = "https://www.google.com/search?q={{{s}}}"
+            "#,
+        )
+        .unwrap();
+
+        let triggers: Vec<String> = config
+            .bangs
+            .unwrap()
+            .into_iter()
+            .map(|mut bang| {
+                bang.normalize();
+                bang.trigger
+            })
+            .collect();
+        assert_eq!(triggers, ["w", "w", "gh", "!g"]);
+    }
+}
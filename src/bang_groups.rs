@@ -0,0 +1,370 @@
+//! The active bangs grouped by category and subcategory for `/bangs`.
+//!
+//! The downloaded list has thousands of bangs, too many for one table to be
+//! browsed. They are shown in collapsible `<details>` sections instead, which
+//! need no JavaScript, and groups larger than a page (`ui.page_size`, by
+//! default [`GROUP_PAGE_SIZE`]) link to further pages. Grouping and rendering
+//! the whole list takes a while, so both are kept until the cache changes.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::bang::Bang;
+use crate::resolver::{BangCache, BangOrigin, sorted_bangs, unicode_host};
+use crate::server::escape_html;
+
+/// Bangs shown per page of a group by default.
+pub const GROUP_PAGE_SIZE: usize = 200;
+/// Group of bangs without a category or subcategory.
+const UNCATEGORIZED: &str = "Other";
+
+/// The category and subcategory `bang` is grouped under, missing ones are
+/// "Other".
+#[must_use]
+pub fn group_names(bang: &Bang) -> (String, &str) {
+    let category = bang
+        .category
+        .as_ref()
+        .map_or_else(|| UNCATEGORIZED.to_string(), ToString::to_string);
+    let subcategory = bang
+        .subcategory
+        .as_deref()
+        .filter(|subcategory| !subcategory.trim().is_empty())
+        .unwrap_or(UNCATEGORIZED);
+    (category, subcategory)
+}
+
+/// A bang as shown on the page, with a Unicode host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub trigger: String,
+    pub url_template: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubcategoryGroup {
+    pub name: String,
+    pub rows: Vec<Row>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CategoryGroup {
+    pub name: String,
+    /// Ordered by name.
+    pub subcategories: Vec<SubcategoryGroup>,
+}
+
+impl CategoryGroup {
+    /// Number of bangs in all subcategories.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.subcategories.iter().map(|sub| sub.rows.len()).sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The bangs of one generation of the cache, grouped and rendered.
+#[derive(Debug)]
+pub struct GroupedBangs {
+    pub generation: u64,
+    /// Bangs shown per page of a group.
+    pub page_size: usize,
+    /// The configured bangs, which aren't grouped.
+    pub configured: Vec<Row>,
+    /// The downloaded bangs, ordered by category name.
+    pub categories: Vec<CategoryGroup>,
+    /// The rendered sections with the first page of every group.
+    pub html: String,
+}
+
+impl GroupedBangs {
+    /// Group the bangs of `cache`, alphabetically by trigger within groups,
+    /// into pages of `page_size`.
+    #[must_use]
+    pub fn new(cache: &BangCache, generation: u64, page_size: usize) -> Self {
+        let page_size = page_size.max(1);
+        let mut configured = Vec::new();
+        let mut groups: BTreeMap<String, BTreeMap<String, Vec<Row>>> = BTreeMap::new();
+        for (trigger, cached) in sorted_bangs(cache) {
+            let row = Row {
+                trigger: trigger.to_string(),
+                url_template: unicode_host(&cached.bang.url_template).into_owned(),
+            };
+            if cached.origin == BangOrigin::Config {
+                configured.push(row);
+                continue;
+            }
+            let (category, subcategory) = group_names(&cached.bang);
+            groups
+                .entry(category)
+                .or_default()
+                .entry(subcategory.to_string())
+                .or_default()
+                .push(row);
+        }
+        let categories = groups
+            .into_iter()
+            .map(|(name, subcategories)| {
+                CategoryGroup {
+                    name,
+                    subcategories: subcategories
+                        .into_iter()
+                        .map(|(name, rows)| SubcategoryGroup { name, rows })
+                        .collect(),
+                }
+            })
+            .collect();
+        let mut grouped = Self {
+            generation,
+            page_size,
+            configured,
+            categories,
+            html: String::new(),
+        };
+        grouped.html = grouped.render();
+        grouped
+    }
+
+    fn render(&self) -> String {
+        let mut html = String::new();
+        if !self.configured.is_empty() {
+            write!(
+                html,
+                "<details open><summary>Configured ({})</summary>",
+                self.configured.len()
+            )
+            .expect("Failed to write to HTML string");
+            render_rows(&mut html, &self.configured);
+            html.push_str("</details>");
+        }
+        for category in &self.categories {
+            write!(
+                html,
+                "<details><summary>{} ({})</summary>",
+                escape_html(&category.name),
+                category.len()
+            )
+            .expect("Failed to write to HTML string");
+            for subcategory in &category.subcategories {
+                write!(
+                    html,
+                    "<details><summary>{} ({})</summary>",
+                    escape_html(&subcategory.name),
+                    subcategory.rows.len()
+                )
+                .expect("Failed to write to HTML string");
+                render_page(&mut html, category, subcategory, 1, self.page_size);
+                html.push_str("</details>");
+            }
+            html.push_str("</details>");
+        }
+        html
+    }
+
+    /// Render page `page`, starting at 1, of the subcategory `subcategory` of
+    /// `category`, if it exists.
+    #[must_use]
+    pub fn render_group_page(
+        &self,
+        category: &str,
+        subcategory: &str,
+        page: usize,
+    ) -> Option<String> {
+        let category = self
+            .categories
+            .iter()
+            .find(|group| group.name == category)?;
+        let subcategory = category
+            .subcategories
+            .iter()
+            .find(|group| group.name == subcategory)?;
+        let pages = subcategory.rows.len().max(1).div_ceil(self.page_size);
+        if page == 0 || page > pages {
+            return None;
+        }
+        let mut html = format!(
+            "<h3>{} / {}</h3>",
+            escape_html(&category.name),
+            escape_html(&subcategory.name)
+        );
+        render_page(&mut html, category, subcategory, page, self.page_size);
+        Some(html)
+    }
+}
+
+/// Render the rows of page `page` of a group with links to its neighbors.
+fn render_page(
+    html: &mut String,
+    category: &CategoryGroup,
+    subcategory: &SubcategoryGroup,
+    page: usize,
+    page_size: usize,
+) {
+    let start = (page - 1) * page_size;
+    let end = (start + page_size).min(subcategory.rows.len());
+    render_rows(html, &subcategory.rows[start..end]);
+    let link = |page: usize, text: &str| {
+        format!(
+            r#"<a href="/bangs?category={}&amp;subcategory={}&amp;page={page}">{text}</a> "#,
+            urlencoding::encode(&category.name),
+            urlencoding::encode(&subcategory.name)
+        )
+    };
+    let pages = subcategory.rows.len().div_ceil(page_size);
+    if pages > 1 {
+        html.push_str("<p>");
+        if page > 1 {
+            html.push_str(&link(page - 1, "previous"));
+        }
+        write!(html, "{}-{end} of {} ", start + 1, subcategory.rows.len())
+            .expect("Failed to write to HTML string");
+        if page < pages {
+            html.push_str(&link(page + 1, "next"));
+        }
+        html.push_str("</p>");
+    }
+}
+
+fn render_rows(html: &mut String, rows: &[Row]) {
+    html.push_str("<table><th>Trigger</th><th>URL</th>");
+    for row in rows {
+        write!(
+            html,
+            "<tr><td><strong>{}</strong></td><td>{}</td></tr>",
+            escape_html(&row.trigger),
+            escape_html(&row.url_template)
+        )
+        .expect("Failed to write to HTML string");
+    }
+    html.push_str("</table>");
+}
+
+/// The grouping of a [`BangCache`], rebuilt when its generation changes.
+#[derive(Debug, Default)]
+pub struct GroupCache {
+    grouped: Mutex<Option<Arc<GroupedBangs>>>,
+}
+
+impl GroupCache {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            grouped: Mutex::new(None),
+        }
+    }
+
+    /// The grouped bangs of `cache` at `generation`, grouping them only if the
+    /// generation or `page_size` changed since the last call.
+    pub fn get(&self, cache: &BangCache, generation: u64, page_size: usize) -> Arc<GroupedBangs> {
+        let mut grouped = self.grouped.lock();
+        if let Some(current) = grouped.as_ref()
+            && current.generation == generation
+            && current.page_size == page_size
+        {
+            return Arc::clone(current);
+        }
+        let current = Arc::new(GroupedBangs::new(cache, generation, page_size));
+        *grouped = Some(Arc::clone(&current));
+        drop(grouped);
+        current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bang::{self, test_bang};
+    use crate::resolver::build_cache;
+
+    #[test]
+    fn test_group_bangs() {
+        let bang = |trigger: String, category, subcategory: Option<&str>| {
+            Bang {
+                category,
+                subcategory: subcategory.map(str::to_string),
+                ..test_bang(&trigger, "https://example.com/?q=<{{{s}}}>")
+            }
+        };
+        let mut remote: Vec<Bang> = (0..GROUP_PAGE_SIZE + 5)
+            .map(|i| {
+                bang(
+                    format!("gh{i:03}"),
+                    Some(bang::Category::Tech),
+                    Some("Programming"),
+                )
+            })
+            .collect();
+        remote.push(bang(
+            "w".to_string(),
+            Some(bang::Category::Research),
+            Some("Reference"),
+        ));
+        remote.push(bang("x".to_string(), None, None));
+        let configured = [bang("mine".to_string(), Some(bang::Category::Tech), None)];
+        let cache = build_cache(remote, &configured);
+
+        let groups = GroupCache::default();
+        let grouped = groups.get(&cache, 1, GROUP_PAGE_SIZE);
+        assert_eq!(grouped.configured.len(), 1);
+        let names: Vec<&str> = grouped
+            .categories
+            .iter()
+            .map(|category| category.name.as_str())
+            .collect();
+        assert_eq!(names, ["Other", "Research", "Tech"]);
+        assert_eq!(grouped.categories[2].len(), GROUP_PAGE_SIZE + 5);
+        assert!(
+            grouped
+                .html
+                .starts_with("<details open><summary>Configured (1)</summary>")
+        );
+        assert!(grouped.html.contains("<summary>Tech (205)</summary>"));
+        assert!(grouped.html.contains("&lt;{{{s}}}&gt;"));
+        assert!(grouped.html.contains("1-200 of 205"));
+        assert!(
+            grouped
+                .html
+                .contains("category=Tech&amp;subcategory=Programming&amp;page=2")
+        );
+        assert!(
+            !grouped.html.contains("gh204"),
+            "only the first page is rendered"
+        );
+
+        // the grouping is kept for the same generation
+        assert!(Arc::ptr_eq(
+            &grouped,
+            &groups.get(&BangCache::new(), 1, GROUP_PAGE_SIZE)
+        ));
+
+        let page = grouped.render_group_page("Tech", "Programming", 2).unwrap();
+        assert!(page.contains("gh204") && !page.contains("gh199"));
+        assert!(page.contains("201-205 of 205") && page.contains("page=1\">previous"));
+        assert!(
+            grouped
+                .render_group_page("Research", "Reference", 1)
+                .is_some()
+        );
+        assert_eq!(grouped.render_group_page("Tech", "Programming", 3), None);
+        assert_eq!(grouped.render_group_page("Tech", "Programming", 0), None);
+        assert_eq!(
+            grouped.render_group_page("Tech", "Programming", usize::MAX),
+            None
+        );
+        assert_eq!(grouped.render_group_page("Tech", "Nope", 1), None);
+
+        // and regrouped for another page size
+        let smaller = groups.get(&cache, 1, 100);
+        assert!(smaller.html.contains("1-100 of 205"));
+        let page = smaller.render_group_page("Tech", "Programming", 3).unwrap();
+        assert!(page.contains("201-205 of 205"));
+        assert_eq!(groups.get(&BangCache::new(), 2, 100).categories, []);
+    }
+}
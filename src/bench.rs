@@ -0,0 +1,147 @@
+//! Measuring how fast queries are resolved, for `redirector bench`.
+//!
+//! Only the pure resolution is timed, no I/O is involved, so the numbers show
+//! the cost of the resolver itself for the loaded cache.
+
+use std::fmt::Write as _;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::resolver::{self, BangCache, ResolveOptions};
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BenchReport {
+    pub queries: usize,
+    pub iterations: u32,
+    /// Number of bangs in the cache the queries were resolved with.
+    pub cache_entries: usize,
+    /// Time spent resolving, without the bookkeeping between resolutions.
+    pub total: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl BenchReport {
+    #[must_use]
+    pub fn resolutions(&self) -> usize {
+        self.queries * self.iterations as usize
+    }
+
+    /// Resolutions per second.
+    #[must_use]
+    pub fn throughput(&self) -> f64 {
+        #[expect(
+            clippy::cast_precision_loss,
+            reason = "an estimate, precise up to 2^52 resolutions"
+        )]
+        let resolutions = self.resolutions() as f64;
+        resolutions / self.total.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+/// Resolve every query `iterations` times, timing each resolution.
+///
+/// Returns nothing if there are no queries or iterations.
+#[must_use]
+pub fn run(
+    cache: &BangCache,
+    options: &ResolveOptions<'_>,
+    queries: &[String],
+    iterations: u32,
+) -> Option<BenchReport> {
+    let mut timings = Vec::with_capacity(queries.len() * iterations as usize);
+    for _ in 0..iterations {
+        for query in queries {
+            let start = Instant::now();
+            black_box(resolver::resolve(cache, options, black_box(query)));
+            timings.push(start.elapsed());
+        }
+    }
+    timings.sort_unstable();
+    Some(BenchReport {
+        queries: queries.len(),
+        iterations,
+        cache_entries: cache.len(),
+        total: timings.iter().sum(),
+        p50: percentile(&timings, 50)?,
+        p90: percentile(&timings, 90)?,
+        p99: percentile(&timings, 99)?,
+        max: *timings.last()?,
+    })
+}
+
+/// The nearest-rank `percent` percentile of the `sorted` timings.
+fn percentile(sorted: &[Duration], percent: usize) -> Option<Duration> {
+    let rank = (sorted.len() * percent).div_ceil(100);
+    sorted.get(rank.checked_sub(1)?).copied()
+}
+
+/// Read the queries of a benchmark file, one per line, skipping empty lines.
+#[must_use]
+pub fn parse_queries(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Render the report as a small table for the terminal.
+#[must_use]
+pub fn render_table(report: &BenchReport) -> String {
+    let mut table = String::new();
+    let mut row = |name: &str, value: String| {
+        writeln!(table, "{name:<12} {value}").expect("Failed to write to table string");
+    };
+    row("queries", report.queries.to_string());
+    row("iterations", report.iterations.to_string());
+    row("resolutions", report.resolutions().to_string());
+    row("cache size", format!("{} bangs", report.cache_entries));
+    row("total", format!("{:?}", report.total));
+    row(
+        "throughput",
+        format!("{:.0} resolutions/s", report.throughput()),
+    );
+    row("p50", format!("{:?}", report.p50));
+    row("p90", format!("{:?}", report.p90));
+    row("p99", format!("{:?}", report.p99));
+    row("max", format!("{:?}", report.max));
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    #[test]
+    fn test_percentile() {
+        let timings: Vec<Duration> = (1..=200).map(Duration::from_micros).collect();
+        assert_eq!(percentile(&timings, 50), Some(Duration::from_micros(100)));
+        assert_eq!(percentile(&timings, 99), Some(Duration::from_micros(198)));
+        assert_eq!(percentile(&timings, 100), Some(Duration::from_micros(200)));
+        assert_eq!(percentile(&timings[..1], 1), Some(Duration::from_micros(1)));
+        assert_eq!(percentile(&[], 50), None);
+    }
+
+    #[test]
+    fn test_run() {
+        let app_config = AppConfig::default();
+        let options = app_config.resolve_options();
+        let queries = parse_queries("!w rust\n\nrust lang\n");
+        assert_eq!(queries, ["!w rust", "rust lang"]);
+
+        let report = run(&BangCache::new(), &options, &queries, 3).unwrap();
+        assert_eq!(report.resolutions(), 6);
+        assert!(report.p50 <= report.p90 && report.p90 <= report.p99 && report.p99 <= report.max);
+        assert!(report.max <= report.total);
+        assert!(render_table(&report).contains("resolutions  6\n"));
+
+        assert_eq!(run(&BangCache::new(), &options, &[], 3), None);
+        assert_eq!(run(&BangCache::new(), &options, &queries, 0), None);
+    }
+}
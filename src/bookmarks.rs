@@ -0,0 +1,157 @@
+//! Importing keyword bookmarks from a bookmarks HTML export as bangs.
+//!
+//! Browsers export bookmarks in the Netscape bookmark file format, nested
+//! `<DL>` lists of folders, separators and `<A>` links. Firefox keeps the
+//! keyword of a bookmark in the `SHORTCUTURL` attribute and replaces `%s` in
+//! its URL with the search term, which is exactly what a bang does.
+
+use clap::ValueEnum;
+use scraper::{ElementRef, Html};
+use serde::Deserialize;
+
+use crate::bang::Bang;
+
+/// Formats bangs can be imported from.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum ImportFormat {
+    /// A bang list in the format of `bangs_url`
+    #[default]
+    Json,
+    /// The bookmarks HTML export of a browser, keyword bookmarks searching
+    /// with `%s` become bangs
+    BookmarksHtml,
+}
+
+impl ImportFormat {
+    /// Guess the format of `contents` from its first character, a JSON list
+    /// or HTML.
+    #[must_use]
+    pub fn detect(contents: &[u8]) -> Option<Self> {
+        let start = contents
+            .strip_prefix(b"\xEF\xBB\xBF")
+            .unwrap_or(contents)
+            .trim_ascii_start();
+        match start.first() {
+            Some(b'[') => Some(Self::Json),
+            Some(b'<') => Some(Self::BookmarksHtml),
+            _ => None,
+        }
+    }
+
+    /// Parse `contents` in this format, into the bangs and the number of
+    /// entries that were skipped because they aren't bangs.
+    pub fn parse(self, contents: &[u8]) -> serde_json::Result<(Vec<Bang>, usize)> {
+        match self {
+            Self::Json => serde_json::from_slice(contents).map(|bangs| (bangs, 0)),
+            Self::BookmarksHtml => {
+                let import = parse_bookmarks(&String::from_utf8_lossy(contents));
+                Ok((import.bangs, import.skipped))
+            },
+        }
+    }
+}
+
+/// Placeholder for the search term in bookmark URLs.
+const BOOKMARK_PLACEHOLDER: &str = "%s";
+
+/// The bangs found in a bookmarks export.
+#[derive(Debug, Default)]
+pub struct BookmarkImport {
+    /// One bang per keyword bookmark, in the order of the export.
+    pub bangs: Vec<Bang>,
+    /// Bookmarks left out because they have no keyword or no `%s`.
+    pub skipped: usize,
+}
+
+/// Turn the keyword bookmarks of a bookmarks HTML export into bangs, with the
+/// title of the bookmark as their short name.
+#[must_use]
+pub fn parse_bookmarks(html: &str) -> BookmarkImport {
+    let document = Html::parse_document(html);
+    let mut import = BookmarkImport::default();
+    let links = document
+        .root_element()
+        .descendants()
+        .filter_map(ElementRef::wrap)
+        .filter(|element| element.value().name() == "a");
+    for link in links {
+        let Some(href) = link.value().attr("href") else {
+            continue;
+        };
+        let keyword = link
+            .value()
+            .attr("shortcuturl")
+            .map(str::trim)
+            .filter(|keyword| !keyword.is_empty());
+        let Some(keyword) = keyword.filter(|_| href.contains(BOOKMARK_PLACEHOLDER)) else {
+            import.skipped += 1;
+            continue;
+        };
+        let title = link.text().collect::<String>();
+        let title = title.trim();
+        let mut bang = Bang {
+            trigger: keyword.to_string(),
+            url_template: href.replace(BOOKMARK_PLACEHOLDER, "{{{s}}}"),
+            short_name: (!title.is_empty()).then(|| title.to_string()),
+            ..Bang::default()
+        };
+        bang.normalize();
+        import.bangs.push(bang);
+    }
+    import
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bookmarks() {
+        let import = parse_bookmarks(include_str!("../res/bookmarks.html"));
+        let bangs: Vec<(&str, &str, Option<&str>)> = import
+            .bangs
+            .iter()
+            .map(|bang| {
+                (
+                    bang.trigger.as_str(),
+                    bang.url_template.as_str(),
+                    bang.short_name.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            bangs,
+            [
+                (
+                    "crates",
+                    "https://crates.io/search?q={{{s}}}",
+                    Some("Search crates.io")
+                ),
+                (
+                    "docs",
+                    "https://docs.rs/releases/search?query={{{s}}}&i-want=results",
+                    Some("docs.rs & friends")
+                ),
+                (
+                    "wt",
+                    "https://en.wiktionary.org/wiki/Special:Search?search={{{s}}}",
+                    Some("Wiktionary")
+                ),
+                ("man", "https://man.archlinux.org/search?q={{{s}}}", None),
+                (
+                    "osm",
+                    "https://www.openstreetmap.org/search?query={{{s}}}",
+                    Some("OpenStreetMap")
+                ),
+            ]
+        );
+        // a keyword without %s, a %s without keyword and two plain bookmarks
+        assert_eq!(import.skipped, 4);
+
+        let import = parse_bookmarks("<DL><p><DT><H3>Empty folder</H3><DL><p></DL><p><HR></DL>");
+        assert!(import.bangs.is_empty());
+        assert_eq!(import.skipped, 0);
+    }
+}
@@ -0,0 +1,151 @@
+//! A circuit breaker around the suggestion upstream.
+//!
+//! After [`BreakerConfig::failures`] failed requests in a row the upstream
+//! isn't asked for a cooldown, so `/suggest` answers locally right away
+//! instead of waiting for it to time out. Once the cooldown is over a single
+//! request probes it, a success closes the breaker again.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use tracing::{info, warn};
+
+use crate::config::BreakerConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakerState {
+    /// The upstream is asked, it failed this many times in a row.
+    Closed { failures: u32 },
+    /// The upstream isn't asked until the cooldown ends, then one request
+    /// probes it and the next one may only probe after another cooldown.
+    Open { until: Instant },
+}
+
+/// Whether the upstream may be asked, shared by its clones.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+impl CircuitBreaker {
+    #[must_use]
+    pub fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(BreakerState::Closed { failures: 0 })),
+        }
+    }
+
+    fn cooldown(&self) -> Duration {
+        Duration::from_millis(self.config.cooldown_ms)
+    }
+
+    /// Whether the upstream may be asked at `now`. While the breaker is open
+    /// this lets a single probe through per cooldown.
+    pub fn allow(&self, now: Instant) -> bool {
+        let mut state = self.state.lock();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if now >= until => {
+                // a probe that never reports back, e.g. because the request
+                // timed out, is retried after the next cooldown
+                *state = BreakerState::Open {
+                    until: now + self.cooldown(),
+                };
+                true
+            },
+            BreakerState::Open { .. } => false,
+        }
+    }
+
+    /// The upstream answered, close the breaker.
+    pub fn record_success(&self) {
+        let mut state = self.state.lock();
+        if matches!(*state, BreakerState::Open { .. }) {
+            info!("The suggestion upstream answers again, asking it from now on");
+        }
+        *state = BreakerState::Closed { failures: 0 };
+    }
+
+    /// The upstream failed at `now`, open the breaker once it failed
+    /// [`BreakerConfig::failures`] times in a row or a probe failed.
+    pub fn record_failure(&self, now: Instant) {
+        let mut state = self.state.lock();
+        let failures = match *state {
+            BreakerState::Closed { failures } => failures + 1,
+            BreakerState::Open { .. } => self.config.failures,
+        };
+        *state = if self.config.failures != 0 && failures >= self.config.failures {
+            if matches!(*state, BreakerState::Closed { .. }) {
+                warn!(
+                    "The suggestion upstream failed {failures} times in a row, answering locally \
+                     for {} ms",
+                    self.config.cooldown_ms
+                );
+            }
+            BreakerState::Open {
+                until: now + self.cooldown(),
+            }
+        } else {
+            BreakerState::Closed { failures }
+        };
+    }
+
+    /// Whether the upstream is currently not asked.
+    #[must_use]
+    pub fn is_open(&self) -> bool {
+        matches!(*self.state.lock(), BreakerState::Open { .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker() {
+        let breaker = CircuitBreaker::new(BreakerConfig {
+            failures: 3,
+            cooldown_ms: 1000,
+        });
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        breaker.record_failure(at(0));
+        breaker.record_failure(at(1));
+        breaker.record_success();
+        breaker.record_failure(at(2));
+        breaker.record_failure(at(3));
+        assert!(breaker.allow(at(4)), "successes reset the failures");
+        breaker.record_failure(at(4));
+        assert!(breaker.is_open(), "3 failures in a row open the breaker");
+        assert!(!breaker.allow(at(5)));
+        assert!(!breaker.allow(at(1003)), "the cooldown isn't over yet");
+
+        assert!(
+            breaker.allow(at(1004)),
+            "after the cooldown one probe is let through"
+        );
+        assert!(!breaker.allow(at(1005)), "only one");
+        breaker.record_failure(at(1010));
+        assert!(
+            !breaker.allow(at(2009)),
+            "a failed probe starts another cooldown"
+        );
+        assert!(breaker.allow(at(2010)));
+        breaker.record_success();
+        assert!(!breaker.is_open(), "a successful probe closes the breaker");
+        assert!(breaker.allow(at(2011)));
+
+        let never = CircuitBreaker::new(BreakerConfig {
+            failures: 0,
+            cooldown_ms: 1000,
+        });
+        for ms in 0..10 {
+            never.record_failure(at(ms));
+        }
+        assert!(never.allow(at(10)), "failures = 0 never opens the breaker");
+    }
+}
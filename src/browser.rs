@@ -0,0 +1,157 @@
+//! Opening resolved queries in the browser, for `redirector open`.
+//!
+//! What opens the URL is behind [`Opener`], so everything but launching the
+//! browser itself can be tested.
+
+use std::io::{self, Write};
+
+use anyhow::{Context as _, bail};
+
+use crate::Resolution;
+
+/// Something that shows URLs to the user.
+pub trait Opener {
+    /// Open `url`, without waiting for it to be closed.
+    ///
+    /// # Errors
+    /// If `url` couldn't be opened.
+    fn open(&self, url: &str) -> io::Result<()>;
+}
+
+/// The default browser of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemBrowser;
+
+impl Opener for SystemBrowser {
+    fn open(&self, url: &str) -> io::Result<()> {
+        open::that_detached(url)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpenOptions {
+    /// Also write the URL to the output.
+    pub print: bool,
+    /// Only write the URL to the output, without opening it.
+    pub dry_run: bool,
+}
+
+/// Open the URL `resolution` redirects to with `opener`, writing it to `out`
+/// if `options` say so.
+///
+/// # Errors
+/// If the resolution posts its term, which needs a form submitted by the
+/// browser, or if opening or writing the URL fails.
+pub fn open_resolution(
+    resolution: &Resolution,
+    options: OpenOptions,
+    opener: &dyn Opener,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    if resolution.post.is_some() {
+        bail!(
+            "!{} posts the search term to {}, which can't be opened directly",
+            resolution.trigger.as_deref().unwrap_or_default(),
+            resolution.url
+        );
+    }
+    if options.print || options.dry_run {
+        writeln!(out, "{}", resolution.url).context("Failed to write the URL")?;
+    }
+    if !options.dry_run {
+        opener
+            .open(&resolution.url)
+            .with_context(|| format!("Failed to open '{}' in the browser", resolution.url))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::PostForm;
+
+    #[derive(Default)]
+    struct RecordingOpener {
+        opened: RefCell<Vec<String>>,
+        fail: bool,
+    }
+
+    impl Opener for RecordingOpener {
+        fn open(&self, url: &str) -> io::Result<()> {
+            if self.fail {
+                return Err(io::Error::other("no browser"));
+            }
+            self.opened.borrow_mut().push(url.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_open_resolution() {
+        let resolution = Resolution {
+            url: "https://www.youtube.com/results?search_query=rust%20talks".to_string(),
+            trigger: Some("yt".to_string()),
+            origin: None,
+            note: None,
+            post: None,
+            fallback: false,
+        };
+        let open = |options, opener: &RecordingOpener| {
+            let mut out = Vec::new();
+            open_resolution(&resolution, options, opener, &mut out)
+                .map(|()| String::from_utf8(out).unwrap())
+        };
+
+        let opener = RecordingOpener::default();
+        assert_eq!(open(OpenOptions::default(), &opener).unwrap(), "");
+        let print = OpenOptions {
+            print: true,
+            dry_run: false,
+        };
+        assert_eq!(
+            open(print, &opener).unwrap(),
+            format!("{}\n", resolution.url)
+        );
+        assert_eq!(*opener.opened.borrow(), [resolution.url.as_str(); 2]);
+
+        let opener = RecordingOpener::default();
+        let dry_run = OpenOptions {
+            print: false,
+            dry_run: true,
+        };
+        assert_eq!(
+            open(dry_run, &opener).unwrap(),
+            format!("{}\n", resolution.url)
+        );
+        assert!(opener.opened.borrow().is_empty());
+
+        let failing = RecordingOpener {
+            fail: true,
+            ..RecordingOpener::default()
+        };
+        let e = open(OpenOptions::default(), &failing).unwrap_err();
+        assert!(format!("{e:#}").contains("no browser"), "{e:#}");
+        // nothing is launched for a dry run
+        open(dry_run, &failing).unwrap();
+
+        let posted = Resolution {
+            post: Some(PostForm {
+                field: "q".to_string(),
+                term: "rust".to_string(),
+                hidden: BTreeMap::new(),
+            }),
+            ..resolution.clone()
+        };
+        let e =
+            open_resolution(&posted, OpenOptions::default(), &opener, &mut Vec::new()).unwrap_err();
+        assert!(
+            e.to_string().starts_with("!yt posts the search term"),
+            "{e}"
+        );
+        assert!(opener.opened.borrow().is_empty());
+    }
+}
@@ -0,0 +1,1186 @@
+//! The bang cache shared by all requests and keeping it up to date.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read as _, Write as _};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context as _, anyhow, bail};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+use tracing::{debug, error, info, warn};
+
+use crate::bang::Bang;
+use crate::compact::{RemovalReason, prune_file};
+use crate::config::{AppConfig, AppState, reload_bangs_now};
+use crate::firehose::FirehoseEvent;
+use crate::gzip::{MAX_DECOMPRESSED_SIZE, gunzip, is_gzip};
+use crate::resolver::{
+    self, BangCache, BangOrigin, Collision, CollisionPolicy, RelevanceOffsets, Resolution,
+};
+use crate::source::{self, disk_cache_path, load_bangs, load_disk_cache, load_pinned_bangs};
+
+/// Moved to [`crate::source::SourceMetadata`].
+#[deprecated(note = "moved to `redirector::source::SourceMetadata`")]
+pub type SourceMetadata = source::SourceMetadata;
+
+/// Moved to [`crate::source::pin_bangs`].
+///
+/// # Errors
+/// Like [`crate::source::pin_bangs`].
+#[deprecated(note = "moved to `redirector::source::pin_bangs`")]
+pub async fn pin_bangs(app_config: &AppConfig) -> anyhow::Result<usize> {
+    source::pin_bangs(app_config).await
+}
+
+/// Moved to [`crate::source::fetch_bang_file`].
+///
+/// # Errors
+/// Like [`crate::source::fetch_bang_file`].
+#[deprecated(note = "moved to `redirector::source::fetch_bang_file`")]
+pub async fn fetch_bang_file(app_config: &AppConfig, url: &str) -> anyhow::Result<Vec<u8>> {
+    source::fetch_bang_file(app_config, url).await
+}
+
+/// The active bangs by lowercase trigger.
+///
+/// Never lock [`AppState::config`](crate::config::AppState::config) while
+/// holding a lock of the cache, lock the configuration first.
+pub static BANG_CACHE: LazyLock<RwLock<BangCache>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+/// Incremented with every change of [`BANG_CACHE`], while it is locked for
+/// writing, so data derived from the cache can be kept until it changes.
+static BANG_CACHE_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// The generation of [`BANG_CACHE`], read it while holding a lock of the cache
+/// for it to match the content.
+#[must_use]
+pub fn cache_generation() -> u64 {
+    BANG_CACHE_GENERATION.load(Ordering::Acquire)
+}
+
+/// Mark [`BANG_CACHE`] as changed, call while holding its write lock.
+pub(crate) fn cache_changed() {
+    BANG_CACHE_GENERATION.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Complete the bang typed at the end of `query` to the known triggers
+/// starting with it, see [`resolver::complete`].
+#[must_use]
+pub fn complete_bangs(app_config: &AppConfig, query: &str, limit: usize) -> Vec<String> {
+    resolver::complete(
+        &BANG_CACHE.read(),
+        &app_config.resolve_options(),
+        query,
+        limit,
+    )
+}
+
+/// Resolve the query to the URL it redirects to.
+#[must_use]
+pub fn resolve(app_config: &AppConfig, query: &str) -> String {
+    resolve_with_info(app_config, query).url
+}
+
+/// Resolve the query against the global bang cache, see [`resolve_in`].
+#[must_use]
+pub fn resolve_with_info(app_config: &AppConfig, query: &str) -> Resolution {
+    resolve_in(&BANG_CACHE.read(), app_config, query)
+}
+
+/// Resolve the query against `cache`.
+#[must_use]
+pub fn resolve_in(cache: &BangCache, app_config: &AppConfig, query: &str) -> Resolution {
+    resolver::resolve(cache, &app_config.resolve_options(), query)
+}
+
+/// How often the bang list is updated.
+const UPDATE_INTERVAL: Duration = Duration::from_hours(12);
+/// How often [`periodic_update`] compares the wall-clock time against the last
+/// update. The timers of tokio stand still while the system sleeps, so this
+/// notices within a few minutes after a resume that an update is overdue.
+const UPDATE_CHECK_INTERVAL: Duration = Duration::from_mins(5);
+
+/// When [`periodic_update`] updates the bang list, by the wall-clock time of
+/// the last successful update and the failures since.
+#[derive(Debug)]
+struct UpdateSchedule {
+    interval: Duration,
+    last_update: Option<SystemTime>,
+    /// Failed updates in a row, the retries back off a minute per failure.
+    failures: u8,
+    /// When the current wait was expected to end.
+    wake_at: Option<SystemTime>,
+}
+
+impl UpdateSchedule {
+    const fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_update: None,
+            failures: 0,
+            wake_at: None,
+        }
+    }
+
+    /// How long to wait from `now` before checking again.
+    fn wait(&mut self, now: SystemTime) -> Duration {
+        let wait = if self.failures == 0 {
+            UPDATE_CHECK_INTERVAL.min(self.interval)
+        } else {
+            Duration::from_mins(u64::from(self.failures))
+        };
+        self.wake_at = Some(now + wait);
+        wait
+    }
+
+    /// How much later than scheduled the wait ended at `now`, if more than the
+    /// wait took. That happens when the system slept during it.
+    fn late_by(&self, now: SystemTime, waited: Duration) -> Option<Duration> {
+        let late = now.duration_since(self.wake_at?).ok()?;
+        (late > waited).then_some(late)
+    }
+
+    /// Whether an update is due at `now`: there was none yet, the last one
+    /// failed or it is older than the interval. A clock set back makes it due
+    /// as well, instead of waiting for the clock to catch up.
+    fn is_due(&self, now: SystemTime) -> bool {
+        self.failures != 0
+            || self.last_update.is_none_or(|last_update| {
+                now.duration_since(last_update)
+                    .map_or(true, |age| age >= self.interval)
+            })
+    }
+
+    /// An update succeeded at `now`, returns the failures before it.
+    fn record_success(&mut self, now: SystemTime) -> u8 {
+        self.last_update = Some(now);
+        std::mem::take(&mut self.failures)
+    }
+
+    fn record_failure(&mut self) {
+        self.failures = (self.failures + 1).min(30);
+    }
+}
+
+/// Keep the bang cache up to date, every 12 hours by the wall clock, also
+/// right after the system woke up from sleep.
+pub async fn periodic_update(app_state: AppState) {
+    let update_state = app_state.clone();
+    run_updates(&app_state, UPDATE_INTERVAL, SystemTime::now, move || {
+        let update_state = update_state.clone();
+        async move {
+            let prune_state = update_state.clone();
+            if let Err(e) =
+                tokio::task::spawn_blocking(move || prune_config_bangs(&prune_state)).await
+            {
+                error!("Failed to prune the configured bangs: {e}");
+            }
+            update_bangs(&update_state.get_config()).await
+        }
+    })
+    .await;
+}
+
+/// Take the configured bangs out of the cache as soon as their `expires_at`
+/// passes, not only with the next reload of the configured bangs.
+///
+/// Waits at most five minutes at a time, so a reloaded configuration and a
+/// system that slept are noticed.
+pub async fn periodic_expiry(app_state: AppState) {
+    #[expect(
+        clippy::infinite_loop,
+        reason = "This is intended to run indefinitely in the background"
+    )]
+    loop {
+        let now = source::unix_seconds(SystemTime::now());
+        let next = next_expiry(
+            app_state.config.read().bangs.as_deref().unwrap_or_default(),
+            now,
+        );
+        let wait = next.map_or(UPDATE_CHECK_INTERVAL, |expires_at| {
+            Duration::from_secs(expires_at - now).min(UPDATE_CHECK_INTERVAL)
+        });
+        sleep(wait).await;
+        if next.is_some_and(|expires_at| expires_at <= source::unix_seconds(SystemTime::now())) {
+            expire_config_bangs(&app_state, &BANG_CACHE);
+        }
+    }
+}
+
+/// The earliest `expires_at` of `bangs` after `now`.
+fn next_expiry(bangs: &[Bang], now: u64) -> Option<u64> {
+    bangs
+        .iter()
+        .filter_map(|bang| bang.expires_at)
+        .filter(|&expires_at| expires_at > now)
+        .min()
+}
+
+/// Rebuild the configured bangs of `cache`, leaving out the expired ones.
+fn expire_config_bangs(app_state: &AppState, cache: &RwLock<BangCache>) {
+    let config = app_state.config.read();
+    let expired = replace_config_bangs(
+        &mut cache.write(),
+        config.bangs.as_deref().unwrap_or_default(),
+        config.bang_collisions,
+        config.relevance_offset,
+    );
+    drop(config);
+    match expired {
+        Ok(()) => info!("Removed the expired configured bangs from the cache"),
+        Err(e) => error!("Failed to remove the expired configured bangs: {e:#}"),
+    }
+}
+
+/// Remove the disabled and expired bangs from the configuration file with
+/// `prune_bangs`, and reload the configured bangs if there were any.
+fn prune_config_bangs(app_state: &AppState) {
+    let config_path = {
+        let config = app_state.config.read();
+        if !config.prune_bangs || !config.persist || !config.config_path.exists() {
+            return;
+        }
+        config.config_path.clone()
+    };
+    let removed = match prune_file(&config_path, source::unix_seconds(SystemTime::now())) {
+        Ok(removed) => removed,
+        Err(e) => {
+            error!("Failed to prune the configured bangs: {e:#}");
+            return;
+        },
+    };
+    if removed.is_empty() {
+        return;
+    }
+    for removal in &removed {
+        let reason = match removal.reason {
+            RemovalReason::Disabled => "disabled",
+            RemovalReason::Expired => "expired",
+            RemovalReason::Duplicate => "duplicated",
+            RemovalReason::Overridden => "overridden",
+        };
+        info!(
+            "Removed the {reason} bang !{} from {}",
+            removal.trigger,
+            config_path.display()
+        );
+    }
+    if let Err(e) = reload_bangs_now(app_state) {
+        error!("Failed to reload the configured bangs after pruning them: {e:#}");
+    }
+}
+
+/// The loop of [`periodic_update`], with the wall clock and the update
+/// passed in.
+async fn run_updates<C, U, F>(
+    app_state: &AppState,
+    interval: Duration,
+    wall_clock: C,
+    mut update: U,
+) where
+    C: Fn() -> SystemTime,
+    U: FnMut() -> F,
+    F: Future<Output = anyhow::Result<source::SourceMetadata>>,
+{
+    let mut schedule = UpdateSchedule::new(interval);
+
+    #[expect(
+        clippy::infinite_loop,
+        reason = "This is intended to run indefinitely in the background"
+    )]
+    loop {
+        if schedule.is_due(wall_clock()) {
+            match update().await {
+                Ok(metadata) => {
+                    app_state.record_update();
+                    let failures = schedule.record_success(wall_clock());
+                    if failures != 0 {
+                        info!("The bang list is reachable again after {failures} failed updates");
+                    }
+                    let entries = metadata.entries;
+                    let source = metadata.source.clone();
+                    let changed = app_state.record_source(metadata);
+                    if changed {
+                        info!("Updated bang commands successfully, {entries} bangs");
+                    } else {
+                        debug!("Bang commands unchanged");
+                    }
+                    app_state
+                        .firehose
+                        .publish(FirehoseEvent::update_completed(&source, entries, changed));
+                },
+                Err(e) => {
+                    error!("Failed to update bang commands: {e:#}");
+                    app_state.record_update_failure(&e);
+                    schedule.record_failure();
+                },
+            }
+        }
+
+        let wait = schedule.wait(wall_clock());
+        sleep(wait).await;
+        if let Some(late) = schedule.late_by(wall_clock(), wait) {
+            info!(
+                "Woke up {} s later than scheduled, the system probably slept",
+                late.as_secs()
+            );
+        }
+    }
+}
+
+/// Update the bang cache with the latest bang commands.
+///
+/// In `offline` mode this is [`update_bangs_offline`].
+///
+/// # Errors
+/// If it fails to update the bang cache.
+pub async fn update_bangs(app_config: &AppConfig) -> anyhow::Result<source::SourceMetadata> {
+    if app_config.offline {
+        return update_bangs_offline(app_config);
+    }
+    let (bang_entries, metadata) = load_bangs(app_config, true).await?;
+    save_binary_cache(app_config, &bang_entries, &metadata);
+    update_cache(bang_entries, app_config)?;
+    Ok(metadata)
+}
+
+/// Like [`update_bangs`], but always downloads the bang list instead of
+/// using the one cached on disk. Pinned bangs are still used as is.
+///
+/// # Errors
+/// If it fails to update the bang cache or is called in `offline` mode.
+pub async fn refresh_bangs(app_config: &AppConfig) -> anyhow::Result<source::SourceMetadata> {
+    if app_config.offline {
+        bail!("Refusing to download the bang list in offline mode");
+    }
+    let (bang_entries, metadata) = load_bangs(app_config, false).await?;
+    save_binary_cache(app_config, &bang_entries, &metadata);
+    update_cache(bang_entries, app_config)?;
+    Ok(metadata)
+}
+
+/// Update the bang cache without network access, from the pinned snapshot
+/// or the bang list cached on disk, regardless of its age.
+///
+/// # Errors
+/// If neither is available.
+pub fn update_bangs_offline(app_config: &AppConfig) -> anyhow::Result<source::SourceMetadata> {
+    let (bang_entries, metadata) = if let Some(pinned_path) = &app_config.pinned_bangs_path {
+        load_pinned_bangs(pinned_path)?
+    } else {
+        let cache_path = disk_cache_path(app_config);
+        let contents = fs::read_to_string(&cache_path).with_context(|| {
+            format!(
+                "No bangs cached at {}, run once with network access first",
+                cache_path.display()
+            )
+        })?;
+        load_disk_cache(&cache_path, &contents)?
+    };
+    save_binary_cache(app_config, &bang_entries, &metadata);
+    update_cache(bang_entries, app_config)?;
+    Ok(metadata)
+}
+
+/// Where the bang list is kept with `binary_cache`.
+fn binary_cache_path(app_config: &AppConfig) -> PathBuf {
+    source::cache_file_path(app_config, "bin")
+}
+
+/// Start of every binary cache file.
+const BINARY_CACHE_MAGIC: &[u8; 4] = b"RDBC";
+/// Version of the binary cache format, bump it when the layout changes.
+const BINARY_CACHE_VERSION: u32 = 5;
+
+/// How the binary cache is compressed on disk. Compressed caches are detected
+/// by their contents on load, whatever the setting is.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum CacheCompression {
+    /// Fastest to load.
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+impl CacheCompression {
+    /// The levels `binary_cache_compression_level` may have.
+    #[must_use]
+    pub const fn levels(self) -> RangeInclusive<i32> {
+        match self {
+            Self::None => 0..=0,
+            Self::Gzip => 0..=9,
+            Self::Zstd => 1..=22,
+        }
+    }
+
+    /// The level used if `binary_cache_compression_level` isn't set.
+    #[must_use]
+    pub const fn default_level(self) -> i32 {
+        match self {
+            Self::None => 0,
+            Self::Gzip => 6,
+            Self::Zstd => 3,
+        }
+    }
+
+    /// Compress `data` at `level`, or the default level.
+    fn compress(self, data: Vec<u8>, level: Option<i32>) -> anyhow::Result<Vec<u8>> {
+        let level = level.unwrap_or_else(|| self.default_level());
+        Ok(match self {
+            Self::None => data,
+            Self::Gzip => {
+                let level = u32::try_from(level).context("Invalid gzip level")?;
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+                encoder.write_all(&data)?;
+                encoder.finish()?
+            },
+            Self::Zstd => zstd::encode_all(data.as_slice(), level)?,
+        })
+    }
+
+    /// The compression `data` starts like.
+    fn detect(data: &[u8]) -> Self {
+        if is_gzip(data) {
+            Self::Gzip
+        } else if data.starts_with(&ZSTD_MAGIC) {
+            Self::Zstd
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Decompress `data` to at most [`MAX_DECOMPRESSED_SIZE`] bytes if it starts
+/// like gzip or zstd, and return it as is otherwise.
+fn decompress_binary_cache(data: &[u8]) -> anyhow::Result<Cow<'_, [u8]>> {
+    match CacheCompression::detect(data) {
+        CacheCompression::None => Ok(Cow::Borrowed(data)),
+        CacheCompression::Gzip => Ok(Cow::Owned(gunzip(data, MAX_DECOMPRESSED_SIZE)?)),
+        CacheCompression::Zstd => {
+            let mut decompressed = Vec::new();
+            zstd::stream::read::Decoder::new(data)?
+                .take(MAX_DECOMPRESSED_SIZE as u64 + 1)
+                .read_to_end(&mut decompressed)
+                .context("Corrupt zstd data")?;
+            if decompressed.len() > MAX_DECOMPRESSED_SIZE {
+                bail!("Too large after decompression, the limit is {MAX_DECOMPRESSED_SIZE} bytes");
+            }
+            Ok(Cow::Owned(decompressed))
+        },
+    }
+}
+
+fn binary_cache_config() -> impl bincode::config::Config {
+    // a corrupt length prefix must not allocate gigabytes
+    bincode::config::standard().with_limit::<MAX_DECOMPRESSED_SIZE>()
+}
+
+/// Encode the bang list with its metadata in the binary cache format: the
+/// magic, the format version and then the release that wrote it, the metadata
+/// and the bangs, encoded with bincode.
+fn encode_binary_cache(
+    bangs: &[Bang],
+    metadata: &source::SourceMetadata,
+) -> anyhow::Result<Vec<u8>> {
+    let mut data = BINARY_CACHE_MAGIC.to_vec();
+    data.extend_from_slice(&BINARY_CACHE_VERSION.to_le_bytes());
+    bincode::encode_into_std_write(
+        (env!("CARGO_PKG_VERSION"), metadata, bangs),
+        &mut data,
+        binary_cache_config(),
+    )?;
+    Ok(data)
+}
+
+/// Decode a bang list encoded by [`encode_binary_cache`].
+///
+/// The layout of [`Bang`] changes between releases without a new format
+/// version, so files written by another release are refused as well.
+fn decode_binary_cache(data: &[u8]) -> anyhow::Result<(Vec<Bang>, source::SourceMetadata)> {
+    let data = data
+        .strip_prefix(BINARY_CACHE_MAGIC)
+        .context("Not a binary bang cache")?;
+    let (version, payload) = data
+        .split_first_chunk()
+        .context("Truncated binary bang cache")?;
+    let version = u32::from_le_bytes(*version);
+    if version != BINARY_CACHE_VERSION {
+        bail!("Binary bang cache has format version {version}, expected {BINARY_CACHE_VERSION}");
+    }
+    let ((release, metadata, bangs), read): ((String, source::SourceMetadata, Vec<Bang>), usize) =
+        bincode::decode_from_slice(payload, binary_cache_config())
+            .context("Corrupt binary bang cache")?;
+    if release != env!("CARGO_PKG_VERSION") {
+        bail!("Binary bang cache was written by release {release}");
+    }
+    if read != payload.len() {
+        bail!("Corrupt binary bang cache, trailing data");
+    }
+    Ok((bangs, metadata))
+}
+
+/// Read the binary cache at `path`, nothing if there is none.
+fn read_binary_cache(path: &Path) -> anyhow::Result<Option<(Vec<Bang>, source::SourceMetadata)>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    decompress_binary_cache(&data)
+        .and_then(|data| decode_binary_cache(&data))
+        .with_context(|| path.display().to_string())
+        .map(Some)
+}
+
+/// Write the bang list to the binary cache at `path` with `compression` at
+/// `level`, through a temporary file so a reader never sees half of it.
+fn write_binary_cache(
+    path: &Path,
+    bangs: &[Bang],
+    metadata: &source::SourceMetadata,
+    compression: CacheCompression,
+    level: Option<i32>,
+) -> anyhow::Result<()> {
+    let tmp_path = path.with_extension("bin.tmp");
+    let data = compression.compress(encode_binary_cache(bangs, metadata)?, level)?;
+    fs::write(&tmp_path, data)
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Keep the bang list in the binary cache, with `binary_cache` and `persist`.
+fn save_binary_cache(app_config: &AppConfig, bangs: &[Bang], metadata: &source::SourceMetadata) {
+    if !(app_config.binary_cache && app_config.persist) {
+        return;
+    }
+    if let Err(e) = write_binary_cache(
+        &binary_cache_path(app_config),
+        bangs,
+        metadata,
+        app_config.binary_cache_compression,
+        app_config.binary_cache_compression_level,
+    ) {
+        warn!("Failed to write the binary bang cache: {e:#}");
+    }
+}
+
+/// Fill the bang cache from the binary cache written by the last update.
+///
+/// Requests are answered right away then, while the list is refreshed in the
+/// background. Returns the metadata of the list, nothing without
+/// `binary_cache` or if there is no binary cache yet.
+///
+/// # Errors
+/// If the binary cache is unreadable, corrupt or from another release, the
+/// next update replaces it then.
+pub fn load_binary_cache(app_config: &AppConfig) -> anyhow::Result<Option<source::SourceMetadata>> {
+    if !app_config.binary_cache {
+        return Ok(None);
+    }
+    let Some((bang_entries, metadata)) = read_binary_cache(&binary_cache_path(app_config))? else {
+        return Ok(None);
+    };
+    update_cache(bang_entries, app_config)?;
+    Ok(Some(metadata))
+}
+
+/// The cache `bang_entries` would make up as the bang list, along with the
+/// configured bangs, and the colliding triggers.
+///
+/// # Errors
+/// If triggers collide and `bang_collisions` is `error`.
+pub fn effective_cache(
+    bang_entries: Vec<Bang>,
+    app_config: &AppConfig,
+) -> anyhow::Result<(BangCache, Vec<Collision>)> {
+    resolver::build_cache_with(
+        bang_entries,
+        &active_bangs(app_config.bangs.as_deref().unwrap_or_default()),
+        app_config.bang_collisions,
+        app_config.relevance_offset,
+    )
+    .map_err(|collision| anyhow!("Refusing to load the bangs, {collision}"))
+}
+
+/// Update the bang cache with the provided bang commands, along with the
+/// configured bangs.
+fn update_cache(bang_entries: Vec<Bang>, app_config: &AppConfig) -> anyhow::Result<()> {
+    let (cache, collisions) = effective_cache(bang_entries, app_config)?;
+    log_collisions(&collisions);
+    let mut guard = BANG_CACHE.write();
+    *guard = cache;
+    cache_changed();
+    drop(guard);
+    debug!("Bang commands updated successfully.");
+    Ok(())
+}
+
+/// The configured bangs in use now, without the disabled and expired ones.
+fn active_bangs(bangs: &[Bang]) -> Vec<Bang> {
+    let now = source::unix_seconds(SystemTime::now());
+    bangs
+        .iter()
+        .filter(|bang| bang.is_active(now))
+        .cloned()
+        .collect()
+}
+
+fn log_collisions(collisions: &[Collision]) {
+    for collision in collisions {
+        warn!(
+            "{collision}, keeping !{}, set bang_collisions to choose another one",
+            collision.kept.trigger
+        );
+    }
+}
+
+/// Replace the configured bangs in `cache` with the active ones of `bangs`,
+/// leaving the downloaded ones alone.
+///
+/// A downloaded bang shadowed by a configured bang that was removed comes back
+/// with the next full update. Downloaded bangs that are kept over configured
+/// ones because of the relevance `offsets` stay.
+///
+/// # Errors
+/// If triggers of `bangs` collide and `policy` is [`CollisionPolicy::Error`].
+/// The cache is left unchanged then.
+pub fn replace_config_bangs(
+    cache: &mut BangCache,
+    bangs: &[Bang],
+    policy: CollisionPolicy,
+    offsets: Option<RelevanceOffsets>,
+) -> anyhow::Result<()> {
+    let (configured, collisions) =
+        resolver::build_cache_with(Vec::new(), &active_bangs(bangs), policy, offsets)
+            .map_err(|collision| anyhow!("Refusing to load the configured bangs, {collision}"))?;
+    log_collisions(&collisions);
+    cache.retain(|_, cached| cached.origin != BangOrigin::Config);
+    for (trigger, cached) in configured {
+        let kept = cache
+            .get(&trigger)
+            .is_some_and(|remote| policy.keeps_remote(offsets, &remote.bang, &cached.bang));
+        if !kept {
+            cache.insert(trigger, cached);
+        }
+    }
+    cache_changed();
+    Ok(())
+}
+
+/// The bangs of `res/test-bangs.json`, an excerpt of the downloaded list for
+/// tests that can't depend on the network.
+#[cfg(test)]
+pub(crate) fn fixture_cache() -> BangCache {
+    let bangs = serde_json::from_str(include_str!("../res/test-bangs.json"))
+        .expect("Failed to parse the test bangs");
+    resolver::build_cache(bangs, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bang::{Category, TermEncoding, test_bang};
+    use crate::resolver::CachedBang;
+    use crate::source::content_hash;
+
+    const BANG_LIST: &str = r#"[{"t":"g","u":"https://www.google.com/search?q={{{s}}}"}]"#;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_update_after_sleep() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+        use parking_lot::Mutex;
+        use tokio::time::Instant;
+
+        // the wall clock runs with tokio's paused time, plus the time the
+        // system slept, during which the timers stand still
+        let started = (SystemTime::now(), Instant::now());
+        let slept = Arc::new(Mutex::new(Duration::ZERO));
+        let wall_clock = {
+            let slept = Arc::clone(&slept);
+            move || started.0 + started.1.elapsed() + *slept.lock()
+        };
+        let updates = Arc::new(AtomicUsize::new(0));
+        let failing = Arc::new(AtomicBool::new(false));
+        let update = {
+            let updates = Arc::clone(&updates);
+            let failing = Arc::clone(&failing);
+            move || {
+                updates.fetch_add(1, Ordering::SeqCst);
+                let failing = failing.load(Ordering::SeqCst);
+                async move {
+                    if failing {
+                        bail!("offline");
+                    }
+                    Ok(source::SourceMetadata {
+                        source: "test".to_string(),
+                        final_url: None,
+                        fetched_at: 0,
+                        last_modified: None,
+                        etag: None,
+                        content_length: None,
+                        entries: 1,
+                        content_hash: String::new(),
+                    })
+                }
+            }
+        };
+        let app_state = AppState::new(AppConfig::default());
+        let updater_state = app_state.clone();
+        tokio::spawn(async move {
+            run_updates(&updater_state, UPDATE_INTERVAL, wall_clock, update).await;
+        });
+        let updates = || updates.load(Ordering::SeqCst);
+        let second = Duration::from_secs(1);
+
+        sleep(second).await;
+        assert_eq!(updates(), 1, "the first update runs right away");
+        sleep(Duration::from_hours(11)).await;
+        assert_eq!(updates(), 1);
+
+        // waking up 13 hours later, 11 of them asleep, the update is overdue
+        *slept.lock() += Duration::from_hours(11);
+        sleep(UPDATE_CHECK_INTERVAL).await;
+        assert_eq!(updates(), 2, "overdue updates run after the next check");
+        sleep(Duration::from_hours(11)).await;
+        assert_eq!(updates(), 2, "the interval starts over from the update");
+
+        // failures are retried with a growing backoff
+        failing.store(true, Ordering::SeqCst);
+        *slept.lock() += Duration::from_hours(2);
+        sleep(UPDATE_CHECK_INTERVAL).await;
+        assert_eq!(updates(), 3);
+        assert!(app_state.last_update_error.read().is_some());
+        sleep(Duration::from_mins(1)).await;
+        assert_eq!(updates(), 4, "retried after a minute");
+        sleep(Duration::from_mins(1)).await;
+        assert_eq!(updates(), 4, "then after two");
+        sleep(Duration::from_mins(1)).await;
+        assert_eq!(updates(), 5);
+
+        // once the network is back the next retry refreshes the bangs
+        failing.store(false, Ordering::SeqCst);
+        sleep(Duration::from_mins(3)).await;
+        assert_eq!(updates(), 6);
+        assert_eq!(*app_state.last_update_error.read(), None);
+        sleep(Duration::from_hours(11)).await;
+        assert_eq!(updates(), 6, "back to the normal interval");
+    }
+
+    #[test]
+    fn test_update_schedule() {
+        let now = SystemTime::now();
+        let mut schedule = UpdateSchedule::new(UPDATE_INTERVAL);
+        assert!(schedule.is_due(now), "there was no update yet");
+        assert_eq!(schedule.record_success(now), 0);
+        assert!(!schedule.is_due(now + Duration::from_hours(11)));
+        assert!(schedule.is_due(now + UPDATE_INTERVAL));
+        assert!(
+            schedule.is_due(now - Duration::from_hours(1)),
+            "a clock set back doesn't delay the updates"
+        );
+
+        let wait = schedule.wait(now);
+        assert_eq!(wait, UPDATE_CHECK_INTERVAL);
+        assert_eq!(schedule.late_by(now + wait + wait, wait), None);
+        assert_eq!(
+            schedule.late_by(now + wait + Duration::from_hours(8), wait),
+            Some(Duration::from_hours(8)),
+            "a wait ending much later than scheduled means the system slept"
+        );
+
+        schedule.record_failure();
+        schedule.record_failure();
+        assert!(schedule.is_due(now), "failed updates are retried");
+        assert_eq!(schedule.wait(now), Duration::from_mins(2));
+        assert_eq!(schedule.record_success(now), 2);
+        assert_eq!(schedule.wait(now), UPDATE_CHECK_INTERVAL);
+    }
+
+    #[test]
+    fn test_resolve_with_bang() {
+        let config = AppConfig::default();
+        let cache = fixture_cache();
+
+        // Test with template that has {{{s}}}
+        let result = resolve_in(&cache, &config, "!g rust programming").url;
+        assert_eq!(result, "https://www.google.com/search?q=rust%20programming");
+
+        // Test with template that doesn't have {{{s}}}
+        let result = resolve_in(&cache, &config, "!gh rust programming").url;
+        assert_eq!(
+            result,
+            "https://github.com/search?utf8=%E2%9C%93&q=rust%20programming"
+        );
+
+        // Test with bang at different position
+        let result = resolve_in(&cache, &config, "rust !yt programming").url;
+        assert_eq!(
+            result,
+            "https://www.youtube.com/results?search_query=rust%20%20programming"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_without_bang() {
+        let config = AppConfig::default();
+
+        // Test with no bang
+        let result = resolve(&config, "rust programming");
+        assert_eq!(
+            result,
+            config.default_search.replace("{}", "rust%20programming")
+        );
+
+        // Test with non-matching bang
+        let result = resolve(&config, "!nonexistent rust programming");
+        assert_eq!(
+            result,
+            config
+                .default_search
+                .replace("{}", "%21nonexistent%20rust%20programming")
+        );
+    }
+
+    #[test]
+    fn test_resolve_edge_cases() {
+        let config = AppConfig::default();
+
+        let cache = fixture_cache();
+
+        // Empty query
+        let result = resolve_in(&cache, &config, "").url;
+        assert_eq!(result, config.default_search.replace("{}", ""));
+
+        // URL encoding special chars
+        let result = resolve_in(&cache, &config, "!g c++ & rust/wasm").url;
+        assert_eq!(
+            result,
+            "https://www.google.com/search?q=c%2B%2B%20%26%20rust/wasm"
+        );
+
+        // Only a bang with no search term
+        let result = resolve_in(&cache, &config, "!g").url;
+        assert_eq!(result, "https://www.google.com/search?q=");
+    }
+
+    #[test]
+    fn test_sanitize_query() {
+        let cache = BangCache::from([(
+            "g".to_string(),
+            CachedBang {
+                bang: test_bang("g", "https://www.google.com/search?q={{{s}}}"),
+                origin: BangOrigin::Remote,
+            },
+        )]);
+        let config = AppConfig::default();
+
+        for query in [
+            "\u{FEFF}!g foo",
+            "\u{200B}!g fo\u{200C}o\u{200D}",
+            "!g\u{200E} foo\u{200F}",
+            "\u{202A}!g \u{202B}foo\u{202C}\u{202D}\u{202E}",
+            "\u{2066}!g\u{2067} \u{2068}foo\u{2069}\u{061C}",
+        ] {
+            let resolution = resolve_in(&cache, &config, query);
+            assert_eq!(resolution.trigger.as_deref(), Some("g"), "{query:?}");
+            assert_eq!(
+                resolution.url, "https://www.google.com/search?q=foo",
+                "{query:?}"
+            );
+        }
+
+        let plain = resolve_in(&cache, &config, "rust\u{200B}lang");
+        assert_eq!(plain.url, config.default_search.replace("{}", "rustlang"));
+
+        let config = AppConfig {
+            sanitize_query: false,
+            ..AppConfig::default()
+        };
+        let resolution = resolve_in(&cache, &config, "\u{FEFF}!g foo");
+        assert!(resolution.fallback, "unsanitized BOM hides the bang");
+        assert_eq!(
+            resolution.url,
+            config.default_search.replace("{}", "%EF%BB%BF%21g%20foo")
+        );
+    }
+
+    #[test]
+    fn test_replace_config_bangs_offsets() {
+        let bang = |url_template: &str, relevance| {
+            Bang {
+                relevance,
+                ..test_bang("ddg", url_template)
+            }
+        };
+        let remote = bang("https://duckduckgo.example.com/?q=", Some(10));
+        let configured = [bang("https://mine.example.com/?q=", None)];
+        let offsets = Some(RelevanceOffsets {
+            remote: 0,
+            config: 0,
+        });
+        for (offsets, origin) in [(None, BangOrigin::Config), (offsets, BangOrigin::Remote)] {
+            let (mut cache, _) = resolver::build_cache_with(
+                vec![remote.clone()],
+                &configured,
+                CollisionPolicy::HighestRelevance,
+                offsets,
+            )
+            .unwrap();
+            assert_eq!(cache["ddg"].origin, origin);
+            // reloading the configured bangs comes to the same result
+            replace_config_bangs(
+                &mut cache,
+                &configured,
+                CollisionPolicy::HighestRelevance,
+                offsets,
+            )
+            .unwrap();
+            assert_eq!(cache["ddg"].origin, origin);
+        }
+    }
+
+    #[test]
+    fn test_inactive_config_bangs() {
+        let remote = test_bang("w", "https://en.wikipedia.org/?search={{{s}}}");
+        let configured = [
+            Bang {
+                enabled: Some(false),
+                url_template: "https://wiki.internal/?q={{{s}}}".to_string(),
+                ..remote.clone()
+            },
+            Bang {
+                expires_at: Some(1_700_000_000),
+                ..test_bang("conf", "https://conference.example.com/?q={{{s}}}")
+            },
+            Bang {
+                expires_at: Some(u64::MAX),
+                ..test_bang("gh", "https://github.com/search?q={{{s}}}")
+            },
+        ];
+        let app_config = AppConfig {
+            bangs: Some(configured.to_vec()),
+            ..AppConfig::default()
+        };
+        let (mut cache, _) = effective_cache(vec![remote], &app_config).unwrap();
+        replace_config_bangs(&mut cache, &configured, CollisionPolicy::KeepFirst, None).unwrap();
+        assert_eq!(
+            cache["w"].origin,
+            BangOrigin::Remote,
+            "a disabled bang doesn't shadow the downloaded one"
+        );
+        assert!(!cache.contains_key("conf"), "expired");
+        assert_eq!(cache["gh"].origin, BangOrigin::Config);
+    }
+
+    #[test]
+    fn test_expire_config_bangs() {
+        let now = source::unix_seconds(SystemTime::now());
+        let configured = vec![
+            Bang {
+                expires_at: Some(now),
+                ..test_bang("conf", "https://conference.example.com/?q={{{s}}}")
+            },
+            Bang {
+                expires_at: Some(now + 3600),
+                ..test_bang("gh", "https://github.com/search?q={{{s}}}")
+            },
+            test_bang("w", "https://en.wikipedia.org/?search={{{s}}}"),
+        ];
+        assert_eq!(next_expiry(&configured, now - 1), Some(now));
+        assert_eq!(next_expiry(&configured, now), Some(now + 3600));
+        assert_eq!(next_expiry(&configured, now + 3600), None);
+
+        // Built while !conf was still active.
+        let (cache, _) =
+            resolver::build_cache_with(Vec::new(), &configured, CollisionPolicy::KeepFirst, None)
+                .unwrap();
+        assert!(cache.contains_key("conf"));
+        let cache = RwLock::new(cache);
+        let app_state = AppState::new(AppConfig {
+            bangs: Some(configured),
+            ..AppConfig::default()
+        });
+        expire_config_bangs(&app_state, &cache);
+        let cache = cache.into_inner();
+        assert!(!cache.contains_key("conf"));
+        assert!(cache.contains_key("gh"));
+        assert!(cache.contains_key("w"));
+    }
+
+    #[test]
+    fn test_binary_cache_round_trip() {
+        let bangs: Vec<Bang> = (0..1000)
+            .map(|i| {
+                Bang {
+                    domain: Some(format!("b{i}.example")),
+                    relevance: Some(i),
+                    category: Some(Category::Tech),
+                    post_fields: (i % 2 == 0)
+                        .then(|| HashMap::from([("lang".to_string(), "en".to_string())])),
+                    term_encoding: Some(TermEncoding::Path),
+                    ..test_bang(
+                        &format!("b{i}"),
+                        &format!("https://b{i}.example/search?q={{{{{{s}}}}}}"),
+                    )
+                }
+            })
+            .collect();
+        let contents = serde_json::to_string(&bangs).unwrap();
+        let metadata = source::SourceMetadata {
+            source: "https://example.com/bang.js".to_string(),
+            final_url: Some("https://cdn.example.com/bang.js".to_string()),
+            fetched_at: 1_739_000_000,
+            last_modified: Some("Sat, 08 Feb 2025 07:33:20 GMT".to_string()),
+            etag: Some("\"abc\"".to_string()),
+            content_length: Some(contents.len() as u64),
+            entries: bangs.len(),
+            content_hash: content_hash(contents.as_bytes()),
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bang-cache.bin");
+        assert!(read_binary_cache(&path).unwrap().is_none());
+        write_binary_cache(&path, &bangs, &metadata, CacheCompression::None, None).unwrap();
+        let (read, read_metadata) = read_binary_cache(&path).unwrap().unwrap();
+        assert_eq!(read_metadata, metadata);
+        assert_eq!(
+            serde_json::to_value(&read).unwrap(),
+            serde_json::to_value(&bangs).unwrap()
+        );
+        assert!(
+            fs::metadata(&path).unwrap().len() < contents.len() as u64,
+            "the binary cache must be smaller than the JSON list"
+        );
+    }
+
+    #[test]
+    fn test_binary_cache_corrupt() {
+        let bangs: Vec<Bang> = serde_json::from_str(BANG_LIST).unwrap();
+        let metadata =
+            source::SourceMetadata::from_file(Path::new("bang.js"), BANG_LIST, bangs.len());
+        let data = encode_binary_cache(&bangs, &metadata).unwrap();
+        decode_binary_cache(&data).unwrap();
+
+        let mut other_version = data.clone();
+        other_version[BINARY_CACHE_MAGIC.len()] += 1;
+        let mut trailing = data.clone();
+        trailing.push(0);
+        for (corrupt, error) in [
+            (&b"[{\"t\":\"g\"}]"[..], "Not a binary bang cache"),
+            (&data[..6], "Truncated"),
+            (&data[..data.len() - 3], "Corrupt"),
+            (&other_version, "format version 6, expected 5"),
+            (&trailing, "trailing data"),
+        ] {
+            let e = decode_binary_cache(corrupt).unwrap_err();
+            assert!(format!("{e:#}").contains(error), "{error}: {e:#}");
+        }
+
+        // an unreadable cache is an error for the caller to fall back on
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bang-cache.bin");
+        fs::write(&path, &data[..data.len() / 2]).unwrap();
+        let e = read_binary_cache(&path).unwrap_err();
+        assert!(format!("{e:#}").contains("Corrupt"), "{e:#}");
+    }
+
+    #[test]
+    fn test_binary_cache_compression() {
+        let bangs: Vec<Bang> = (0..1000)
+            .map(|i| {
+                test_bang(
+                    &format!("b{i}"),
+                    &format!("https://b{i}.example/search?q={{{{{{s}}}}}}"),
+                )
+            })
+            .collect();
+        let metadata = source::SourceMetadata::from_file(Path::new("bang.js"), "", bangs.len());
+        let dir = tempfile::tempdir().unwrap();
+        let plain_path = dir.path().join("plain.bin");
+        write_binary_cache(&plain_path, &bangs, &metadata, CacheCompression::None, None).unwrap();
+        let plain_size = fs::metadata(&plain_path).unwrap().len();
+
+        for (compression, level) in [
+            (CacheCompression::Gzip, None),
+            (CacheCompression::Gzip, Some(9)),
+            (CacheCompression::Zstd, None),
+            (CacheCompression::Zstd, Some(19)),
+        ] {
+            let path = dir.path().join("bang-cache.bin");
+            write_binary_cache(&path, &bangs, &metadata, compression, level).unwrap();
+            let data = fs::read(&path).unwrap();
+            assert_eq!(CacheCompression::detect(&data), compression);
+            assert!(
+                (data.len() as u64) < plain_size / 2,
+                "{compression:?} {level:?} must compress"
+            );
+            let (read, read_metadata) = read_binary_cache(&path).unwrap().unwrap();
+            assert_eq!(read_metadata, metadata);
+            assert_eq!(read.len(), bangs.len());
+            assert_eq!(read[999].url_template, bangs[999].url_template);
+
+            // a cache written with other settings still loads
+            let (plain, _) = read_binary_cache(&plain_path).unwrap().unwrap();
+            assert_eq!(plain.len(), bangs.len());
+
+            fs::write(&path, &data[..data.len() / 2]).unwrap();
+            let e = read_binary_cache(&path).unwrap_err();
+            assert!(
+                format!("{e:#}").contains("Corrupt"),
+                "{compression:?}: {e:#}"
+            );
+        }
+
+        let config = AppConfig {
+            binary_cache_compression: CacheCompression::Gzip,
+            binary_cache_compression_level: Some(12),
+            ..AppConfig::default()
+        };
+        let problems = config.problems();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].key, "binary_cache_compression_level");
+        assert_eq!(
+            problems[0].problem,
+            "must be between 0 and 9 for this binary_cache_compression"
+        );
+    }
+
+    #[test]
+    fn test_complete_bangs() {
+        let config = AppConfig::default();
+        let cache = fixture_cache();
+
+        let completions = resolver::complete(&cache, &config.resolve_options(), "!g", 5);
+        assert_eq!(completions.first().map(String::as_str), Some("!g"));
+        assert!(completions.len() <= 5, "limit must be respected");
+        assert!(
+            completions.iter().all(|c| c.starts_with("!g")),
+            "all completions must start with the prefix: {completions:?}"
+        );
+
+        assert!(
+            resolver::complete(&cache, &config.resolve_options(), "g", 5).is_empty(),
+            "no '!' means no bang"
+        );
+        let completions = resolver::complete(&cache, &config.resolve_options(), "rust !g", 5);
+        assert_eq!(completions.first().map(String::as_str), Some("rust !g"));
+        assert!(
+            resolver::complete(&cache, &config.resolve_options(), "rust!g", 5).is_empty(),
+            "a '!' inside a word is no bang"
+        );
+    }
+}
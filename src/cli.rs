@@ -1,8 +1,12 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 
 use clap::{ArgAction, Parser, Subcommand};
 use clap_complete::Shell;
 
+use crate::bookmarks::ImportFormat;
+use crate::export::ExportFormat;
+
 /// Main CLI configuration.
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
@@ -23,6 +27,26 @@ pub struct Cli {
     #[arg(short, long)]
     pub search_suggestions: Option<String>,
 
+    /// Fetch the bang list once and save it to `pinned_bangs_path`, which is
+    /// then used instead of the remote list until pinned again
+    #[arg(long, global = true)]
+    pub pin_bangs: bool,
+
+    /// Never write to disk, bangs added at runtime and the downloaded bang
+    /// list only live in memory and are lost on restart
+    #[arg(long, global = true)]
+    pub no_persist: bool,
+
+    /// Start even if values of the configuration can't work, like a
+    /// `default_search` without a placeholder, warning about them instead
+    #[arg(long, global = true)]
+    pub ignore_bad_config: bool,
+
+    /// Configuration file to use instead of
+    /// `~/.config/redirector/config.toml`, e.g. to run several instances
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
     /// Increase logging verbosity
     #[clap(short, long, action = ArgAction::Count, global = true)]
     pub verbose: u8,
@@ -39,16 +63,240 @@ pub enum SubCommand {
         /// IP to serve the application on
         #[arg(short, long)]
         ip: Option<IpAddr>,
+
+        /// Address to listen on, like `127.0.0.1:8080` or `[::1]:8080`, instead
+        /// of --ip and --port
+        #[arg(short, long, value_parser = parse_listen)]
+        listen: Option<SocketAddr>,
+
+        /// Answer line-delimited JSON requests on stdin instead of serving
+        /// HTTP
+        #[arg(long)]
+        stdio: bool,
     },
-    #[command(about = "Resolve a search query", display_order = 2)]
+    #[command(about = "Resolve search queries", display_order = 2)]
     Resolve {
+        /// The search queries to resolve, only the search terms with
+        /// --trigger. Every one is resolved on its own and printed on its own
+        /// line, in order
+        #[arg(required = true, value_name = "QUERY")]
+        queries: Vec<String>,
+
+        /// Resolve the queries with this bang instead of looking for one in
+        /// them, offline from the cached bangs. Exits with 2 if the bang is
+        /// unknown
+        #[arg(short, long)]
+        trigger: Option<String>,
+
+        /// Print a JSON array of `{query, target, matched_trigger}` instead
+        #[arg(long)]
+        json: bool,
+    },
+    #[command(
+        about = "Resolve a search query and open it in the default browser, offline from the \
+                 cached bangs if possible",
+        display_order = 10
+    )]
+    Open {
         /// The search query to resolve
         #[arg(required = true)]
         query: String,
+
+        /// Also print the URL
+        #[arg(long)]
+        print: bool,
+
+        /// Only print the URL, without opening it
+        #[arg(long)]
+        dry_run: bool,
     },
     #[command(about = "Generate shell completions", display_order = 3)]
     Completions {
         #[clap(value_enum)]
         shell: Shell,
+
+        /// Directory to write the completion script to, named the way the
+        /// shell expects, like `_redirector` for zsh (defaults to stdout)
+        #[arg(short, long, value_name = "DIR")]
+        output: Option<PathBuf>,
+    },
+    #[command(about = "Export bangs as browser search engines", display_order = 4)]
+    Export {
+        /// Format to export the bangs in
+        #[arg(short, long, value_enum)]
+        format: ExportFormat,
+
+        /// File to write the export to (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    #[command(
+        about = "Ask a few questions and write a first configuration file",
+        display_order = 5
+    )]
+    Init {
+        /// Write the defaults without asking, for scripts
+        #[arg(long)]
+        defaults: bool,
+
+        /// Replace an existing configuration file
+        #[arg(long)]
+        force: bool,
+    },
+    #[command(about = "Manage the configuration file", display_order = 5)]
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    #[command(about = "Inspect the loaded bangs", display_order = 6)]
+    Bangs {
+        #[clap(subcommand)]
+        command: BangsCommand,
     },
+    #[command(
+        about = "Download the bang list and print where it came from",
+        display_order = 7
+    )]
+    Update,
+    #[command(
+        about = "Check that bangs resolve and the default search works",
+        display_order = 8
+    )]
+    Selftest,
+    #[command(
+        about = "Time resolving queries, offline from the cached bangs",
+        display_order = 9
+    )]
+    Bench {
+        /// File with the queries to resolve, one per line
+        #[arg(short, long)]
+        queries: PathBuf,
+
+        /// How often every query is resolved
+        #[arg(short = 'n', long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+        iterations: u32,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum BangsCommand {
+    #[command(about = "List the bangs, offline from the cached bangs")]
+    List {
+        /// Print tab-separated trigger, name and URL template lines for fuzzy
+        /// pickers like fzf
+        #[arg(long)]
+        pick_format: bool,
+    },
+    #[command(
+        about = "Request every configured bang with a probe term and report the ones that no \
+                 longer work, exits with 1 if any failed"
+    )]
+    Check {
+        /// Print the results as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    #[command(about = "Add the bangs of a file to the [[bangs]] of the configuration file")]
+    Import {
+        /// Format of the file
+        #[arg(short, long, value_enum, default_value_t)]
+        format: ImportFormat,
+        /// File to import the bangs from
+        file: PathBuf,
+    },
+    #[command(
+        about = "Compare the bangs in effect with another bang list, offline from the cached bangs"
+    )]
+    Diff {
+        /// File or URL of the bang list to compare with
+        source: String,
+        /// Format of the list, detected from its contents if not given
+        #[arg(short, long, value_enum)]
+        format: Option<ImportFormat>,
+        /// Print the differences as JSON instead of text
+        #[arg(long)]
+        json: bool,
+        /// Only compare the bangs of this category
+        #[arg(long, value_name = "CATEGORY")]
+        filter: Option<String>,
+        /// List every difference instead of the first few of each kind
+        #[arg(long)]
+        full: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    #[command(
+        about = "Print the effective configuration of the file and the command line as TOML, \
+                 without the admin token and the bangs"
+    )]
+    Show,
+    #[command(
+        about = "Check that the configuration file can be loaded, exits with 1 and prints where \
+                 the mistake is if it can't"
+    )]
+    Validate,
+    #[command(about = "Remove duplicated [[bangs]] entries, keeping the last definition")]
+    Compact {
+        /// Only print what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+impl Cli {
+    /// Split `serve --listen` into the IP and port.
+    ///
+    /// # Errors
+    /// If `--ip` or `--port` are given as well with another value.
+    pub fn apply_listen(&mut self) -> anyhow::Result<()> {
+        if let Some(SubCommand::Serve {
+            port, ip, listen, ..
+        }) = &mut self.command
+        {
+            (*ip, *port) = listen_address(listen.take(), *ip, *port)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a listen address like `127.0.0.1:8080` or `[::1]:8080`.
+///
+/// # Errors
+/// If `listen` is no such address, including `unix:` socket paths, which
+/// can't be listened on yet.
+pub fn parse_listen(listen: &str) -> Result<SocketAddr, String> {
+    if listen.starts_with("unix:") {
+        return Err(format!(
+            "Can't listen on '{listen}', Unix sockets aren't supported yet"
+        ));
+    }
+    listen.parse().map_err(|e| {
+        format!(
+            "Invalid listen address '{listen}', expected an IP and port like 127.0.0.1:3000: {e}"
+        )
+    })
+}
+
+/// Combine a listen address with a separately given IP and port, which may
+/// only repeat its values.
+///
+/// # Errors
+/// If `ip` or `port` differ from the ones of `listen`.
+pub fn listen_address(
+    listen: Option<SocketAddr>,
+    ip: Option<IpAddr>,
+    port: Option<u16>,
+) -> anyhow::Result<(Option<IpAddr>, Option<u16>)> {
+    let Some(listen) = listen else {
+        return Ok((ip, port));
+    };
+    if let Some(ip) = ip.filter(|&ip| ip != listen.ip()) {
+        anyhow::bail!("The listen address {listen} conflicts with the IP {ip}");
+    }
+    if let Some(port) = port.filter(|&port| port != listen.port()) {
+        anyhow::bail!("The listen address {listen} conflicts with the port {port}");
+    }
+    Ok((Some(listen.ip()), Some(listen.port())))
 }
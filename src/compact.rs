@@ -0,0 +1,404 @@
+//! Removing duplicated, disabled and expired `[[bangs]]` entries from the
+//! configuration file.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write as _;
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use serde::Serialize;
+use toml_edit::{DocumentMut, Item};
+
+use crate::config::CONFIG_FILE_WRITE;
+
+/// Why a `[[bangs]]` entry was removed.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemovalReason {
+    /// The entry is identical to the one that was kept.
+    Duplicate,
+    /// A later entry with the same trigger but different values takes
+    /// precedence.
+    Overridden,
+    /// The entry has `enabled = false`.
+    Disabled,
+    /// The `expires_at` of the entry has passed.
+    Expired,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Removal {
+    pub trigger: String,
+    pub reason: RemovalReason,
+}
+
+#[derive(Debug)]
+pub struct Compaction {
+    /// The compacted configuration file.
+    pub contents: String,
+    pub removed: Vec<Removal>,
+}
+
+/// Remove duplicated `[[bangs]]` entries, keeping the last definition of each
+/// trigger. Comments and formatting of everything else are left untouched.
+pub fn compact(contents: &str) -> Result<Compaction> {
+    let mut document: DocumentMut = contents.parse()?;
+    let mut removed = Vec::new();
+
+    if let Some(bangs) = document
+        .get_mut("bangs")
+        .and_then(Item::as_array_of_tables_mut)
+    {
+        let entries: Vec<(Option<String>, toml::Table)> = bangs
+            .iter()
+            .map(|table| {
+                let trigger = table
+                    .get("trigger")
+                    .and_then(Item::as_str)
+                    .map(str::to_string);
+                // compare the values rather than their formatting
+                let values = toml::from_str(&table.to_string()).unwrap_or_default();
+                (trigger, values)
+            })
+            .collect();
+
+        let mut last_index = HashMap::new();
+        for (index, (trigger, _)) in entries.iter().enumerate() {
+            if let Some(trigger) = trigger {
+                last_index.insert(trigger_key(trigger), index);
+            }
+        }
+
+        let mut to_remove = Vec::new();
+        for (index, (trigger, values)) in entries.iter().enumerate() {
+            let Some(trigger) = trigger else {
+                continue;
+            };
+            let kept = last_index[&trigger_key(trigger)];
+            if kept != index {
+                let reason = if entries[kept].1 == *values {
+                    RemovalReason::Duplicate
+                } else {
+                    RemovalReason::Overridden
+                };
+                to_remove.push(index);
+                removed.push(Removal {
+                    trigger: trigger.clone(),
+                    reason,
+                });
+            }
+        }
+
+        for index in to_remove.into_iter().rev() {
+            bangs.remove(index);
+        }
+    }
+
+    Ok(Compaction {
+        contents: document.to_string(),
+        removed,
+    })
+}
+
+/// The trigger as the bang cache keys it, normalized like
+/// [`Bang::normalize`](crate::bang::Bang::normalize) and lowercased, so `!GH`
+/// and `gh` are the same trigger.
+fn trigger_key(trigger: &str) -> String {
+    let trigger = trigger.trim();
+    trigger
+        .strip_prefix('!')
+        .unwrap_or(trigger)
+        .to_ascii_lowercase()
+}
+
+/// Remove the `[[bangs]]` entries that are disabled or expired at `now`, in
+/// seconds since the Unix epoch. Comments and formatting of everything else
+/// are left untouched.
+pub fn prune(contents: &str, now: u64) -> Result<Compaction> {
+    let mut document: DocumentMut = contents.parse()?;
+    let mut removed = Vec::new();
+
+    if let Some(bangs) = document
+        .get_mut("bangs")
+        .and_then(Item::as_array_of_tables_mut)
+    {
+        let mut to_remove = Vec::new();
+        for (index, table) in bangs.iter().enumerate() {
+            let expired = table
+                .get("expires_at")
+                .and_then(Item::as_integer)
+                .is_some_and(|expires_at| u64::try_from(expires_at).unwrap_or(0) <= now);
+            let reason = if table.get("enabled").and_then(Item::as_bool) == Some(false) {
+                RemovalReason::Disabled
+            } else if expired {
+                RemovalReason::Expired
+            } else {
+                continue;
+            };
+            to_remove.push(index);
+            removed.push(Removal {
+                trigger: table
+                    .get("trigger")
+                    .and_then(Item::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+                reason,
+            });
+        }
+
+        for index in to_remove.into_iter().rev() {
+            bangs.remove(index);
+        }
+    }
+
+    Ok(Compaction {
+        contents: document.to_string(),
+        removed,
+    })
+}
+
+/// Compact the configuration file at `path` in place.
+///
+/// The original file is kept next to it with a `.bak` extension and the new
+/// contents are written atomically. With `dry_run` nothing is written.
+pub fn compact_file(path: &Path, dry_run: bool) -> Result<Vec<Removal>> {
+    rewrite_file(path, dry_run, compact)
+}
+
+/// Remove the disabled and expired bangs from the configuration file at
+/// `path` in place, like [`compact_file`].
+pub fn prune_file(path: &Path, now: u64) -> Result<Vec<Removal>> {
+    rewrite_file(path, false, |contents| prune(contents, now))
+}
+
+/// Replace the configuration file at `path` by what `edit` makes of it,
+/// unless it removed nothing.
+fn rewrite_file(
+    path: &Path,
+    dry_run: bool,
+    edit: impl FnOnce(&str) -> Result<Compaction>,
+) -> Result<Vec<Removal>> {
+    let _writing = CONFIG_FILE_WRITE.lock();
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read configuration file at {}", path.display()))?;
+    let compaction = edit(&contents)
+        .with_context(|| format!("Failed to parse configuration file at {}", path.display()))?;
+
+    if dry_run || compaction.removed.is_empty() {
+        return Ok(compaction.removed);
+    }
+
+    let backup_path = path.with_extension("toml.bak");
+    fs::copy(path, &backup_path)
+        .with_context(|| format!("Failed to write backup to {}", backup_path.display()))?;
+    let tmp_path = path.with_extension("toml.tmp");
+    // the file may hold the admin_token, keep it as private as it was
+    let permissions = fs::metadata(path)
+        .with_context(|| format!("Failed to read configuration file at {}", path.display()))?
+        .permissions();
+    let mut tmp = fs::File::create(&tmp_path)
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    tmp.set_permissions(permissions)
+        .and_then(|()| tmp.write_all(compaction.contents.as_bytes()))
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    drop(tmp);
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to replace configuration file at {}", path.display()))?;
+
+    Ok(compaction.removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSY_CONFIG: &str = r#"# my redirector config
+port = 3000 # keep this port
+
+# search github
+[[bangs]]
+trigger = "gh"
+url_template = "https://github.com/search?q={{{s}}}"
+
+[[bangs]]
+trigger = "w"
+url_template = "https://old.wikipedia.org/?q={{{s}}}"
+
+[[bangs]]
+trigger   = "gh"
+url_template = "https://github.com/search?q={{{s}}}"
+
+# wikipedia, the good one
+[[bangs]]
+trigger = "w"
+url_template = "https://en.wikipedia.org/?q={{{s}}}"
+short_name = "Wikipedia"
+"#;
+
+    #[test]
+    fn test_compact() {
+        let compaction = compact(MESSY_CONFIG).unwrap();
+        assert_eq!(
+            compaction.removed,
+            [
+                Removal {
+                    trigger: "gh".to_string(),
+                    reason: RemovalReason::Duplicate,
+                },
+                Removal {
+                    trigger: "w".to_string(),
+                    reason: RemovalReason::Overridden,
+                },
+            ]
+        );
+
+        let contents = &compaction.contents;
+        assert!(contents.starts_with("# my redirector config\nport = 3000 # keep this port\n"));
+        assert!(contents.contains("# wikipedia, the good one"));
+        assert!(!contents.contains("old.wikipedia.org"));
+        assert_eq!(contents.matches("[[bangs]]").count(), 2);
+
+        let file_config: crate::config::FileConfig = toml::from_str(contents).unwrap();
+        let triggers: Vec<String> = file_config
+            .bangs
+            .unwrap()
+            .into_iter()
+            .map(|bang| bang.trigger)
+            .collect();
+        assert_eq!(triggers, ["gh", "w"]);
+
+        // compacting again is a no-op
+        let again = compact(contents).unwrap();
+        assert!(again.removed.is_empty(), "nothing left to compact");
+        assert_eq!(&again.contents, contents);
+    }
+
+    #[test]
+    fn test_compact_normalized_triggers() {
+        let contents = r#"[[bangs]]
+trigger = "GH"
+url_template = "https://github.com/search?q={{{s}}}"
+
+[[bangs]]
+trigger = "!gh"
+url_template = "https://github.com/search?q={{{s}}}"
+
+[[bangs]]
+trigger = "gh"
+url_template = "https://github.com/search?type=code&q={{{s}}}"
+"#;
+        let compaction = compact(contents).unwrap();
+        let removed: Vec<&str> = compaction
+            .removed
+            .iter()
+            .map(|removal| removal.trigger.as_str())
+            .collect();
+        assert_eq!(removed, ["GH", "!gh"]);
+        assert_eq!(compaction.contents.matches("[[bangs]]").count(), 1);
+        assert!(compaction.contents.contains("type=code"));
+    }
+
+    #[test]
+    fn test_compact_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, MESSY_CONFIG).unwrap();
+
+        let removed = compact_file(&path, true).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), MESSY_CONFIG);
+
+        let removed = compact_file(&path, false).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(
+            fs::read_to_string(path.with_extension("toml.bak")).unwrap(),
+            MESSY_CONFIG
+        );
+        assert_eq!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .matches("[[bangs]]")
+                .count(),
+            2
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rewrite_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, MESSY_CONFIG).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(compact_file(&path, false).unwrap().len(), 2);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_prune_file() {
+        let config = r#"port = 3000 # keep this port
+
+[[bangs]]
+trigger = "conf"
+url_template = "https://conference.example.com/?q={{{s}}}"
+expires_at = 1700000000 # the conference is over
+
+[[bangs]]
+trigger = "old"
+url_template = "https://old.example.com/?q={{{s}}}"
+enabled = false
+
+# still needed
+[[bangs]]
+trigger = "w"
+url_template = "https://en.wikipedia.org/?q={{{s}}}"
+expires_at = 2000000000
+
+[[bangs]]
+trigger = "gh"
+url_template = "https://github.com/search?q={{{s}}}"
+enabled = true
+"#;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, config).unwrap();
+
+        let now = 1_800_000_000;
+        assert_eq!(
+            prune_file(&path, now).unwrap(),
+            [
+                Removal {
+                    trigger: "conf".to_string(),
+                    reason: RemovalReason::Expired,
+                },
+                Removal {
+                    trigger: "old".to_string(),
+                    reason: RemovalReason::Disabled,
+                },
+            ]
+        );
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("port = 3000 # keep this port\n"));
+        assert!(contents.contains("# still needed"));
+        assert!(!contents.contains("conference.example.com"), "{contents}");
+        assert!(!contents.contains("old.example.com"), "{contents}");
+        assert_eq!(
+            fs::read_to_string(path.with_extension("toml.bak")).unwrap(),
+            config
+        );
+
+        let file_config: crate::config::FileConfig = toml::from_str(&contents).unwrap();
+        let bangs = file_config.bangs.unwrap();
+        assert!(bangs.iter().all(|bang| bang.is_active(now)));
+        assert_eq!(bangs.len(), 2);
+
+        // nothing left to prune, the file isn't rewritten
+        fs::remove_file(path.with_extension("toml.bak")).unwrap();
+        assert_eq!(prune_file(&path, now).unwrap(), []);
+        assert!(!path.with_extension("toml.bak").exists());
+    }
+}
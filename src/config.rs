@@ -1,33 +1,312 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
 use std::fmt::Write as _;
 use std::fs::read_to_string;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use anyhow::{Result, bail};
-use parking_lot::RwLock;
-use serde::{Deserialize, Serialize};
-use tracing::{debug, error, info};
+use parking_lot::{Mutex, RwLock};
+use reqwest::Client;
+use serde::{Deserialize, Deserializer, Serialize, de};
+use tracing::{debug, error, info, warn};
 
-use crate::bang::Bang;
-use crate::cli::{Cli, SubCommand};
-use crate::update_bangs;
+use crate::bang::{Bang, BangMethod, TermEncoding};
+use crate::bang_groups::GROUP_PAGE_SIZE;
+use crate::breaker::CircuitBreaker;
+use crate::cache::CacheCompression;
+use crate::cli::{Cli, SubCommand, listen_address, parse_listen};
+use crate::events::RecentEvents;
+use crate::firehose::{Firehose, FirehoseEvent};
+use crate::health::{HealthReport, HealthStatus};
+// the settings' types living in modules hidden from the docs
+pub use crate::outbound::{IpFamily, TlsVersion};
+use crate::outbound::{OutboundPolicy, guarded_client};
+use crate::popular::PopularBang;
+use crate::recent::RecentResolutions;
+use crate::reload::ReloadLimiter;
+use crate::resolver::{
+    CollisionPolicy, DecodeIncoming, LITERAL_BRACES, RelevanceOffsets, ResolveOptions, build_cache,
+    build_cache_with, resolve_trigger,
+};
+use crate::stats::{Stats, default_stats_path};
+pub use crate::suggest::{QueryEncoding, SuggestionFormat, SuggestionProvider};
+use crate::supervisor::Supervisor;
+use crate::usage::UsageLog;
+use crate::{BANG_CACHE, BangCache, SourceMetadata, replace_config_bangs, update_bangs};
 
 const DEFAULT_SEARCH: &str = "https://www.qwant.com/?q={}";
-const DEFAULT_SEARCH_SUGGESTIONS: &str = "https://search.brave.com/api/suggest?q={}";
+const DEFAULT_QUERY_PARAM: &str = "q";
+const DEFAULT_BANGS_JSON_LIMIT: usize = 500;
+const DEFAULT_MAX_CACHE_AGE: u64 = 24 * 60 * 60;
+const DEFAULT_SELFTEST_TRIGGER: &str = "w";
+const DEFAULT_SUGGESTION_MAX_RESPONSE_BYTES: usize = 256 * 1024;
+const DEFAULT_STATS_RETENTION_MONTHS: u32 = 24;
+const DEFAULT_MAX_CUSTOM_BANGS: usize = 5000;
+const DEFAULT_MAX_CONFIG_FILE_SIZE: u64 = 1024 * 1024;
+/// User-Agent parts of Android and iOS devices, iPads included.
+const DEFAULT_MOBILE_USER_AGENTS: [&str; 4] = ["Android", "iPhone", "iPad", "iPod"];
+
+/// Held while the configuration file is read and written back, so that
+/// appending bangs, compacting and pruning don't undo each other's edits.
+/// Lock it after [`AppState::config`] when holding both.
+pub(crate) static CONFIG_FILE_WRITE: Mutex<()> = Mutex::new(());
+
+fn default_mobile_user_agents() -> Vec<String> {
+    DEFAULT_MOBILE_USER_AGENTS.map(str::to_string).to_vec()
+}
+
+/// Well-known bangs of the default bang list.
+const DEFAULT_SUGGEST_TOP_BANGS: [&str; 8] = ["g", "w", "yt", "a", "gh", "r", "maps", "imdb"];
+
+fn default_suggest_top_bangs() -> Vec<String> {
+    DEFAULT_SUGGEST_TOP_BANGS.map(str::to_string).to_vec()
+}
 
 /// Configuration read from the file.
 #[derive(Deserialize, Debug, Default)]
 pub struct FileConfig {
     pub port: Option<u16>,
     pub ip: Option<IpAddr>,
+    /// `ip` and `port` in one, like `127.0.0.1:3000` or `[::1]:3000`.
+    #[serde(default, deserialize_with = "deserialize_listen")]
+    pub listen: Option<SocketAddr>,
     pub bangs_url: Option<String>,
     pub default_search: Option<String>,
     pub search_suggestions: Option<String>,
+    pub base_url: Option<String>,
+    pub max_cache_age: Option<u64>,
+    pub suggestion_allow_hosts: Option<Vec<String>>,
+    pub http2: Option<bool>,
+    pub sanitize_query: Option<bool>,
+    pub post_bangs: Option<bool>,
+    pub suggestion_format: Option<SuggestionFormat>,
+    pub pinned_bangs_path: Option<PathBuf>,
+    /// Deprecated, moved to `[ui]`.
+    pub landing_html_path: Option<PathBuf>,
+    pub offline: Option<bool>,
+    pub admin_token: Option<String>,
+    pub selftest_trigger: Option<String>,
+    pub suggestion_query_encoding: Option<QueryEncoding>,
+    pub ignore_quoted_bangs: Option<bool>,
+    pub routes: Option<RoutesConfig>,
+    pub suggestion_forward_headers: Option<Vec<String>>,
+    pub outbound_ip_family: Option<IpFamily>,
+    pub min_tls_version: Option<TlsVersion>,
+    pub reload_interval_ms: Option<u64>,
+    pub timeouts: Option<TimeoutsConfig>,
+    pub suggestion_breaker: Option<BreakerConfig>,
+    pub bang_limits: Option<BangLimitsConfig>,
+    pub bang_collisions: Option<CollisionPolicy>,
+    pub robots_txt: Option<String>,
+    pub robots_txt_path: Option<PathBuf>,
+    pub suggestion_max_response_bytes: Option<usize>,
+    pub min_suggest_len: Option<usize>,
+    pub domain_bangs: Option<bool>,
+    pub stats_persist: Option<bool>,
+    pub stats_path: Option<PathBuf>,
+    pub stats_retention_months: Option<u32>,
+    pub debug_recent_size: Option<usize>,
+    pub redact_queries: Option<bool>,
+    pub plus_as_space: Option<bool>,
+    pub suggestion_provider: Option<SuggestionProvider>,
+    pub persist: Option<bool>,
+    pub prune_bangs: Option<bool>,
+    /// Deprecated, moved to `[ui]`.
+    pub popular_bangs: Option<bool>,
+    pub query_param: Option<String>,
+    pub bangs_json_limit: Option<usize>,
+    pub suggest_default_search: Option<bool>,
+    pub advertise_suggestions: Option<bool>,
+    pub direct_url_navigation: Option<bool>,
+    pub direct_url_hosts: Option<Vec<String>>,
+    pub relevance_offset: Option<RelevanceOffsets>,
+    pub decode_incoming: Option<DecodeIncoming>,
+    pub binary_cache: Option<bool>,
+    pub binary_cache_compression: Option<CacheCompression>,
+    pub binary_cache_compression_level: Option<i32>,
+    pub mobile_default_search: Option<String>,
+    pub localized_default_search: Option<BTreeMap<String, String>>,
+    pub mobile_user_agents: Option<Vec<String>>,
+    pub suggest_top_bangs: Option<Vec<String>>,
+    pub default_search_bookmark: Option<bool>,
+    pub allow_insecure_bang_fetch: Option<bool>,
+    pub instance_headers: Option<bool>,
+    pub instance_name: Option<String>,
+    pub max_custom_bangs: Option<usize>,
+    pub max_config_file_size: Option<u64>,
+    pub query_prefix: Option<String>,
+    pub query_suffix: Option<String>,
+    pub ui: Option<UiConfig>,
+    pub builtin_aliases: Option<bool>,
+    pub aliases: Option<BTreeMap<String, String>>,
     pub bangs: Option<Vec<Bang>>,
 }
 
+/// The `[routes]` table, every optional endpoint is served unless turned off.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "Every route is toggled independently"
+)]
+pub struct RoutesConfig {
+    /// `/api/resolve`, `/resolve`, `/api/bangs` and `/bangs.json`.
+    pub api: bool,
+    pub suggest: bool,
+    pub add_bang: bool,
+    /// `/api/config/compact`, with an `admin_token`.
+    pub compact: bool,
+    pub metrics: bool,
+    /// `/reload` and `/bangs/reload`.
+    pub reload: bool,
+    pub restart: bool,
+    /// The endpoints requiring the `admin_token`.
+    pub admin: bool,
+}
+
+impl Default for RoutesConfig {
+    fn default() -> Self {
+        Self {
+            api: true,
+            suggest: true,
+            add_bang: true,
+            compact: true,
+            metrics: true,
+            reload: true,
+            restart: true,
+            admin: true,
+        }
+    }
+}
+
+/// The `[timeouts]` table, how long requests may take in milliseconds before
+/// they are answered with an error.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct TimeoutsConfig {
+    /// Searches on `/`.
+    pub redirect_ms: u64,
+    /// `/suggest`, including the request to the suggestion upstream, which
+    /// gets [`Self::suggestion_upstream`] of it.
+    pub suggest_ms: u64,
+    /// The `/bangs` page.
+    pub bangs_page_ms: u64,
+    /// Imports and the endpoints requiring the `admin_token`.
+    pub admin_ms: u64,
+}
+
+impl TimeoutsConfig {
+    /// How long the suggestion upstream may take, four fifths of
+    /// `suggest_ms`, so it fails before `/suggest` runs out of time.
+    #[must_use]
+    pub const fn suggestion_upstream(self) -> Duration {
+        Duration::from_millis(self.suggest_ms - self.suggest_ms / 5)
+    }
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        Self {
+            redirect_ms: 1000,
+            suggest_ms: 3000,
+            bangs_page_ms: 10_000,
+            admin_ms: 60_000,
+        }
+    }
+}
+
+/// The `[bang_limits]` table, how large a downloaded bang list may be, so a
+/// hostile `bangs_url` can't exhaust the memory.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct BangLimitsConfig {
+    /// Bytes of the response, after decompression. Larger lists are refused.
+    pub max_response_bytes: usize,
+    /// Bangs of the list. Lists with more are refused.
+    pub max_entries: usize,
+    /// Bytes of a trigger, bangs with longer ones are skipped.
+    pub max_trigger_length: usize,
+    /// Bytes of a URL template, bangs with longer ones are skipped.
+    pub max_template_length: usize,
+}
+
+impl Default for BangLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_response_bytes: 20 * 1024 * 1024,
+            max_entries: 100_000,
+            max_trigger_length: 100,
+            max_template_length: 4096,
+        }
+    }
+}
+
+/// The `[suggestion_breaker]` table, when to stop asking a failing suggestion
+/// upstream, see [`CircuitBreaker`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct BreakerConfig {
+    /// Failed requests in a row after which the upstream isn't asked, it is
+    /// always asked with 0.
+    pub failures: u32,
+    /// Milliseconds until a single request probes the upstream again.
+    pub cooldown_ms: u64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failures: 5,
+            cooldown_ms: 30_000,
+        }
+    }
+}
+
+/// The `[ui]` table, how the HTML pages look and what they show.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct UiConfig {
+    /// HTML page served at `/` for requests without a query instead of
+    /// redirecting to `/bangs`.
+    pub landing_html_path: Option<PathBuf>,
+    /// Show the most used bangs on the landing page, counted with
+    /// `stats_persist`.
+    pub popular_bangs: bool,
+    /// Stylesheet added to `/bangs` and `/admin` after the built-in one.
+    pub custom_css_path: Option<PathBuf>,
+    /// Bangs shown per page of a group on `/bangs`, between
+    /// [`MIN_UI_PAGE_SIZE`] and [`MAX_UI_PAGE_SIZE`].
+    pub page_size: usize,
+    /// Directory with files served under `/static/` instead of the built-in
+    /// ones with the same path.
+    pub static_dir: Option<PathBuf>,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            landing_html_path: None,
+            popular_bangs: true,
+            custom_css_path: None,
+            page_size: GROUP_PAGE_SIZE,
+            static_dir: None,
+        }
+    }
+}
+
+/// Default of `debug_recent_size`.
+pub const DEFAULT_DEBUG_RECENT_SIZE: usize = 200;
+
+/// Default of `reload_interval_ms`.
+pub const DEFAULT_RELOAD_INTERVAL_MS: u64 = 2000;
+
+pub const MIN_UI_PAGE_SIZE: usize = 10;
+pub const MAX_UI_PAGE_SIZE: usize = 5000;
+
 /// Configuration read from the CLI.
 #[derive(Debug, Default)]
 pub struct Config {
@@ -36,36 +315,407 @@ pub struct Config {
     pub bangs_url: Option<String>,
     pub default_search: Option<String>,
     pub search_suggestions: Option<String>,
+    /// `--config`, read instead of the file at [`default_config_path`].
+    pub config_path: Option<PathBuf>,
 }
 
 /// Final application configuration.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "These are independent settings, not states"
+)]
 pub struct AppConfig {
     pub port: u16,
     pub ip: IpAddr,
     pub bangs_url: String,
     pub default_search: String,
     pub search_suggestions: String,
+    /// Public URL the instance is reachable at, used for links handed out to
+    /// browsers. Defaults to `http://{ip}:{port}`.
+    pub base_url: Option<String>,
+    /// Seconds after which a bang cache that failed to update is reported as
+    /// degraded.
+    pub max_cache_age: u64,
+    /// Hosts the suggestion proxy may connect to even though they resolve to
+    /// loopback, private or link-local addresses.
+    pub suggestion_allow_hosts: Vec<String>,
+    /// Also accept cleartext HTTP/2 (h2c) connections.
+    pub http2: bool,
+    /// Strip zero-width and bidi control characters from queries.
+    pub sanitize_query: bool,
+    /// Answer bangs with `method = "post"` with a form posting the term to the
+    /// target, otherwise they are redirected to like any other bang.
+    pub post_bangs: bool,
+    /// Format of the responses of `search_suggestions`.
+    pub suggestion_format: SuggestionFormat,
+    /// Load the bangs from this snapshot instead of `bangs_url`, written with
+    /// `--pin-bangs`.
+    pub pinned_bangs_path: Option<PathBuf>,
+    /// Never connect to the network: bangs are only loaded from the pinned
+    /// snapshot or the disk cache and suggestions are answered locally.
+    pub offline: bool,
+    /// Bearer token required by the admin endpoints, they aren't served at all
+    /// without one.
+    #[serde(skip_serializing)]
+    pub admin_token: Option<String>,
+    /// Bang `selftest` expects to be loaded.
+    pub selftest_trigger: String,
+    /// How the query is encoded in `search_suggestions`.
+    pub suggestion_query_encoding: QueryEncoding,
+    /// Never treat tokens inside a `"double quoted"` phrase as bangs.
+    pub ignore_quoted_bangs: bool,
+    /// Which optional endpoints are served.
+    pub routes: RoutesConfig,
+    /// Request headers copied from `/suggest` requests to the suggestion
+    /// upstream, none by default.
+    pub suggestion_forward_headers: Vec<String>,
+    /// IP family of outbound connections, to the bang list and the suggestion
+    /// upstream.
+    pub outbound_ip_family: IpFamily,
+    /// Lowest TLS version of outbound connections, the TLS library's default
+    /// if unset.
+    pub min_tls_version: Option<TlsVersion>,
+    /// Shortest time between two reloads of the configuration, requests in
+    /// between are coalesced into the next one.
+    pub reload_interval_ms: u64,
+    /// How long requests to the routes may take.
+    pub timeouts: TimeoutsConfig,
+    /// When to stop asking a failing suggestion upstream.
+    pub suggestion_breaker: BreakerConfig,
+    /// How large a downloaded bang list may be.
+    pub bang_limits: BangLimitsConfig,
+    /// What to do with triggers that differ only in case but lead to different
+    /// URLs, within the downloaded or the configured bangs.
+    pub bang_collisions: CollisionPolicy,
+    /// Served as `/robots.txt`, disallowing all crawling if unset.
+    pub robots_txt: Option<String>,
+    /// File served as `/robots.txt` instead of `robots_txt`, read on every
+    /// request.
+    pub robots_txt_path: Option<PathBuf>,
+    /// Largest response of the suggestion upstream that is read, larger ones
+    /// are answered with the local suggestions.
+    pub suggestion_max_response_bytes: usize,
+    /// Shortest query, in characters, the suggestion upstream is asked for,
+    /// shorter ones are answered with the local suggestions.
+    pub min_suggest_len: usize,
+    /// Treat unknown bangs that look like a domain, like `!github.com`, as a
+    /// search within that site, or a visit if there is no search term.
+    pub domain_bangs: bool,
+    /// Count redirects per month in a store on disk, kept across restarts.
+    pub stats_persist: bool,
+    /// Where the monthly counts are stored, see [`default_stats_path`].
+    pub stats_path: Option<PathBuf>,
+    /// Months of counts kept, older ones are dropped. The current month is
+    /// always kept.
+    pub stats_retention_months: u32,
+    /// Redirects kept in memory for `/debug/recent`, none if 0 or with
+    /// `redact_queries`.
+    pub debug_recent_size: usize,
+    /// Keep search queries out of the logs and `/debug/recent`.
+    pub redact_queries: bool,
+    /// Whether a `+` in the `q` parameter is a space, like browsers encode it.
+    /// Turned off for clients sending literal pluses, `%20` always is a space.
+    pub plus_as_space: bool,
+    /// Where suggestions are fetched from, `search_suggestions` is the template
+    /// of the provider.
+    pub suggestion_provider: SuggestionProvider,
+    /// Write bangs added at runtime to the configuration file, and the bang
+    /// list and stats to disk. Without it, changes are lost on restart.
+    pub persist: bool,
+    /// Remove disabled and expired `[[bangs]]` from the configuration file
+    /// with every update of the bang list. Needs `persist`.
+    pub prune_bangs: bool,
+    /// Name of the parameter the search term is sent in, `q` unless a client
+    /// uses another one.
+    pub query_param: String,
+    /// Most bangs `/bangs.json` returns at once, and how many it returns
+    /// without a `limit`.
+    pub bangs_json_limit: usize,
+    /// Suggest searching without a bang for queries ending in a lone `!`, so
+    /// it is clear those still go to `default_search`.
+    pub suggest_default_search: bool,
+    /// List `/suggest` in the `OpenSearch` description, so browsers ask it for
+    /// suggestions. The endpoint keeps working without.
+    pub advertise_suggestions: bool,
+    /// Redirect queries that are a bare URL like `example.com/foo` to it
+    /// instead of searching them, see [`crate::resolver::direct_url`].
+    pub direct_url_navigation: bool,
+    /// Hosts `direct_url_navigation` redirects to, with their subdomains, any
+    /// host if empty.
+    pub direct_url_hosts: Vec<String>,
+    /// Lets bangs of different sources compete by relevance with
+    /// `bang_collisions = "highest_relevance"`, see
+    /// [`CollisionPolicy::keeps_remote`].
+    pub relevance_offset: Option<RelevanceOffsets>,
+    /// Whether queries encoded by the client are decoded once, see
+    /// [`crate::resolver::decode_incoming`].
+    pub decode_incoming: DecodeIncoming,
+    /// Also keep the processed bang list in a compact binary file, loaded at
+    /// startup before the list is refreshed in the background. Needs `persist`.
+    pub binary_cache: bool,
+    /// How the binary cache is compressed, to save disk space at the cost of
+    /// slower loading.
+    pub binary_cache_compression: CacheCompression,
+    /// Compression level of the binary cache, the default of the format if
+    /// unset.
+    pub binary_cache_compression_level: Option<i32>,
+    /// URL for queries without a known bang from a phone, `default_search` if
+    /// unset.
+    pub mobile_default_search: Option<String>,
+    /// URLs for queries without a known bang by language tag, used instead
+    /// of `default_search` for browsers preferring that language, see
+    /// [`crate::language`].
+    pub localized_default_search: BTreeMap<String, String>,
+    /// Case-insensitive parts of the User-Agent of phones, overridden by a
+    /// `mobile=1` or `mobile=0` cookie.
+    pub mobile_user_agents: Vec<String>,
+    /// Bangs suggested in this order for a query that is only `!`, unknown
+    /// ones are left out. If empty all triggers are completed, shortest first.
+    pub suggest_top_bangs: Vec<String>,
+    /// `default_search` and `mobile_default_search` are deliberately fixed
+    /// pages without a `{}` for the query.
+    pub default_search_bookmark: bool,
+    /// Follow redirects of an https `bangs_url` to plain http, which is
+    /// refused by default.
+    pub allow_insecure_bang_fetch: bool,
+    /// Send a `Server` header with the crate version on every response, and
+    /// `X-Instance` with `instance_name` if one is set. Off by default, the
+    /// exact version tells attackers which fixes an instance lacks.
+    pub instance_headers: bool,
+    /// Name of this instance, to tell which backend behind a load balancer
+    /// answered a request.
+    pub instance_name: Option<String>,
+    /// Most configured bangs `/add_bang` and the import endpoint add up to.
+    pub max_custom_bangs: usize,
+    /// Size in bytes `/add_bang` and the import endpoint grow the
+    /// configuration file up to.
+    pub max_config_file_size: u64,
+    /// Put in front of every search term, of bangs and the default search,
+    /// separated by a space.
+    pub query_prefix: Option<String>,
+    /// Appended to every search term, like `lang:en`.
+    pub query_suffix: Option<String>,
+    /// How the HTML pages look and what they show.
+    pub ui: UiConfig,
+    /// Resolve the curated aliases of [`crate::aliases::BUILTIN_ALIASES`] to
+    /// their bangs.
+    pub builtin_aliases: bool,
+    /// Aliases for downloaded bangs, like `wiki = "w"`, added to or replacing
+    /// the built-in ones. An empty trigger removes a built-in alias.
+    pub aliases: BTreeMap<String, String>,
     pub bangs: Option<Vec<Bang>>,
+    /// The configuration file, written to when bangs are added at runtime.
+    #[serde(skip)]
+    pub config_path: PathBuf,
 }
 
 #[derive(Clone, Debug)]
 pub struct AppState {
     pub config: Arc<RwLock<AppConfig>>,
+    /// When the bang cache was last updated successfully.
+    pub last_update: Arc<RwLock<Option<Instant>>>,
+    /// Why the last update of the bang cache failed, cleared by the next
+    /// successful one.
+    pub last_update_error: Arc<RwLock<Option<String>>>,
+    /// Which upstreams may be requested, rebuilt when `suggestion_allow_hosts`
+    /// changes.
+    pub suggestion_policy: Arc<RwLock<OutboundPolicy>>,
+    /// Client for requests to the suggestion upstream, restricted by
+    /// `suggestion_policy`.
+    pub suggestion_client: Arc<RwLock<Client>>,
+    /// Whether the suggestion upstream is asked, it isn't while it fails.
+    pub suggestion_breaker: CircuitBreaker,
+    /// Contents of `ui.landing_html_path`, if it could be read.
+    pub landing_page: Arc<RwLock<Option<Arc<str>>>>,
+    /// Contents of `ui.custom_css_path`, if it could be read.
+    pub custom_css: Arc<RwLock<Option<Arc<str>>>>,
+    /// Metadata of the bang lists loaded so far, one entry per source.
+    pub sources: Arc<RwLock<Vec<SourceMetadata>>>,
+    /// Which bangs were redirected through recently.
+    pub usage: Arc<Mutex<UsageLog>>,
+    /// Redirects per month, with `stats_persist`.
+    pub stats: Arc<Mutex<Stats>>,
+    /// The most used bangs, recomputed from `stats` in the background.
+    pub popular_bangs: Arc<RwLock<Vec<PopularBang>>>,
+    /// Answer searches with a 503 page, set through `/maintenance`.
+    pub maintenance: Arc<AtomicBool>,
+    /// The background tasks, like the bang updater.
+    pub tasks: Supervisor,
+    /// Keeps reloads of the configuration `reload_interval_ms` apart.
+    pub reload_limiter: ReloadLimiter,
+    /// Keeps reloads of the configured bangs `reload_interval_ms` apart.
+    pub bangs_reload_limiter: ReloadLimiter<usize>,
+    /// The last redirects, for `/debug/recent`.
+    pub recent_resolutions: RecentResolutions,
+    /// The last warnings and errors, for `/admin`. Only filled once its
+    /// layer is added to the tracing subscriber.
+    pub recent_events: RecentEvents,
+    /// Live events for `/api/events`.
+    pub firehose: Firehose,
 }
 
 impl AppState {
     #[must_use]
     pub fn new(config: AppConfig) -> Self {
-        Self {
+        let suggestion_policy = OutboundPolicy::new(&config.suggestion_allow_hosts);
+        let stats = if config.stats_persist && config.persist {
+            Stats::open(config.stats_path.clone().unwrap_or_else(default_stats_path))
+        } else {
+            Stats::default()
+        };
+        let app_state = Self {
+            suggestion_client: Arc::new(RwLock::new(guarded_client(
+                suggestion_policy.clone(),
+                config.outbound_ip_family,
+                config.min_tls_version,
+            ))),
+            suggestion_breaker: CircuitBreaker::new(config.suggestion_breaker),
+            suggestion_policy: Arc::new(RwLock::new(suggestion_policy)),
             config: Arc::new(RwLock::new(config)),
-        }
+            last_update: Arc::new(RwLock::new(None)),
+            last_update_error: Arc::new(RwLock::new(None)),
+            landing_page: Arc::new(RwLock::new(None)),
+            custom_css: Arc::new(RwLock::new(None)),
+            sources: Arc::new(RwLock::new(Vec::new())),
+            usage: Arc::new(Mutex::new(UsageLog::default())),
+            stats: Arc::new(Mutex::new(stats)),
+            popular_bangs: Arc::new(RwLock::new(Vec::new())),
+            maintenance: Arc::new(AtomicBool::new(false)),
+            tasks: Supervisor::default(),
+            reload_limiter: ReloadLimiter::new(),
+            bangs_reload_limiter: ReloadLimiter::new(),
+            recent_resolutions: RecentResolutions::default(),
+            recent_events: RecentEvents::default(),
+            firehose: Firehose::default(),
+        };
+        app_state.load_ui_files();
+        app_state
+    }
+
+    /// (Re-)read the page at `ui.landing_html_path` and the stylesheet at
+    /// `ui.custom_css_path`.
+    pub fn load_ui_files(&self) {
+        let ui = self.config.read().ui.clone();
+        let read = |path: Option<PathBuf>, what: &str| {
+            path.and_then(|path| {
+                match read_to_string(&path) {
+                    Ok(contents) => Some(Arc::from(contents)),
+                    Err(e) => {
+                        error!("Failed to read {what} at {}: {e}", path.display());
+                        None
+                    },
+                }
+            })
+        };
+        *self.landing_page.write() = read(ui.landing_html_path, "landing page");
+        *self.custom_css.write() = read(ui.custom_css_path, "custom CSS");
+    }
+
+    /// Rebuild `suggestion_policy` and `suggestion_client` for the
+    /// `suggestion_allow_hosts` configured now.
+    pub fn rebuild_suggestion_client(&self) {
+        let config = self.config.read();
+        let policy = OutboundPolicy::new(&config.suggestion_allow_hosts);
+        let client = guarded_client(
+            policy.clone(),
+            config.outbound_ip_family,
+            config.min_tls_version,
+        );
+        drop(config);
+        *self.suggestion_policy.write() = policy;
+        *self.suggestion_client.write() = client;
     }
 
     #[must_use]
     pub fn get_config(&self) -> AppConfig {
         self.config.read().clone()
     }
+
+    /// Mark the bang cache as freshly updated.
+    pub fn record_update(&self) {
+        *self.last_update.write() = Some(Instant::now());
+        *self.last_update_error.write() = None;
+    }
+
+    /// Remember why updating the bang cache failed.
+    pub fn record_update_failure(&self, error: &anyhow::Error) {
+        *self.last_update_error.write() = Some(format!("{error:#}"));
+    }
+
+    /// Remember the metadata of a freshly loaded bang list, replacing the
+    /// previous one of the same source.
+    ///
+    /// Returns whether the list changed since it was last loaded.
+    pub fn record_source(&self, metadata: SourceMetadata) -> bool {
+        let content_hash = metadata.content_hash.clone();
+        let mut sources = self.sources.write();
+        let previous = if let Some(source) = sources
+            .iter_mut()
+            .find(|source| source.source == metadata.source)
+        {
+            Some(std::mem::replace(source, metadata))
+        } else {
+            sources.push(metadata);
+            None
+        };
+        drop(sources);
+        previous.is_none_or(|previous| previous.content_hash != content_hash)
+    }
+
+    /// Time since the bang cache was last updated successfully.
+    #[must_use]
+    pub fn cache_age(&self) -> Option<Duration> {
+        self.last_update.read().map(|instant| instant.elapsed())
+    }
+
+    #[must_use]
+    pub fn health_report(&self) -> HealthReport {
+        let cache_age = self.cache_age();
+        let max_cache_age = self.config.read().max_cache_age;
+        let last_update_error = self.last_update_error.read().clone();
+        let tasks = self.tasks.statuses();
+        let mut status = HealthStatus::from_cache(
+            cache_age,
+            Duration::from_secs(max_cache_age),
+            last_update_error.is_some(),
+        );
+        if status.is_healthy() && !tasks.iter().all(|task| task.running) {
+            status = HealthStatus::Degraded;
+        }
+        HealthReport {
+            status,
+            cache_age_seconds: cache_age.map(|age| age.as_secs()),
+            max_cache_age_seconds: max_cache_age,
+            last_update_error,
+            maintenance: self.maintenance.load(Ordering::Relaxed),
+            sources: self.sources.read().clone(),
+            tasks,
+        }
+    }
+}
+
+/// The suggestion provider and the template to fetch suggestions with.
+///
+/// Without a provider, a template from the file or the command line is a
+/// `custom` one, as it was before providers could be chosen; a template on the
+/// command line always is.
+fn suggestion_upstream(
+    provider: Option<SuggestionProvider>,
+    cli_template: Option<String>,
+    file_template: Option<String>,
+) -> (SuggestionProvider, String) {
+    let provider = match provider {
+        _ if cli_template.is_some() => SuggestionProvider::Custom,
+        Some(provider) => provider,
+        None if file_template.is_some() => SuggestionProvider::Custom,
+        None => SuggestionProvider::default(),
+    };
+    let template = provider.template().map_or_else(
+        || cli_template.or(file_template).unwrap_or_default(),
+        str::to_string,
+    );
+    (provider, template)
 }
 
 impl Config {
@@ -78,11 +728,81 @@ impl Config {
         let file = file.unwrap_or(FileConfig {
             port: None,
             ip: None,
+            listen: None,
             bangs_url: None,
             default_search: None,
             search_suggestions: None,
+            base_url: None,
+            max_cache_age: None,
+            suggestion_allow_hosts: None,
+            http2: None,
+            sanitize_query: None,
+            post_bangs: None,
+            suggestion_format: None,
+            pinned_bangs_path: None,
+            landing_html_path: None,
+            offline: None,
+            admin_token: None,
+            selftest_trigger: None,
+            suggestion_query_encoding: None,
+            ignore_quoted_bangs: None,
+            routes: None,
+            suggestion_forward_headers: None,
+            outbound_ip_family: None,
+            min_tls_version: None,
+            reload_interval_ms: None,
+            timeouts: None,
+            suggestion_breaker: None,
+            bang_limits: None,
+            bang_collisions: None,
+            robots_txt: None,
+            robots_txt_path: None,
+            suggestion_max_response_bytes: None,
+            min_suggest_len: None,
+            domain_bangs: None,
+            stats_persist: None,
+            stats_path: None,
+            stats_retention_months: None,
+            debug_recent_size: None,
+            redact_queries: None,
+            plus_as_space: None,
+            suggestion_provider: None,
+            persist: None,
+            prune_bangs: None,
+            popular_bangs: None,
+            query_param: None,
+            bangs_json_limit: None,
+            suggest_default_search: None,
+            advertise_suggestions: None,
+            direct_url_navigation: None,
+            direct_url_hosts: None,
+            relevance_offset: None,
+            decode_incoming: None,
+            binary_cache: None,
+            binary_cache_compression: None,
+            binary_cache_compression_level: None,
+            mobile_default_search: None,
+            localized_default_search: None,
+            mobile_user_agents: None,
+            suggest_top_bangs: None,
+            default_search_bookmark: None,
+            allow_insecure_bang_fetch: None,
+            instance_headers: None,
+            instance_name: None,
+            max_custom_bangs: None,
+            max_config_file_size: None,
+            query_prefix: None,
+            query_suffix: None,
+            ui: None,
+            builtin_aliases: None,
+            aliases: None,
             bangs: None,
         });
+        let (suggestion_provider, search_suggestions) = suggestion_upstream(
+            file.suggestion_provider,
+            self.search_suggestions,
+            file.search_suggestions,
+        );
         AppConfig {
             port: self.port.or(file.port).unwrap_or(default.port),
             ip: self.ip.or(file.ip).unwrap_or(default.ip),
@@ -94,20 +814,179 @@ impl Config {
                 .default_search
                 .or(file.default_search)
                 .unwrap_or(default.default_search),
-            search_suggestions: self
-                .search_suggestions
-                .or(file.search_suggestions)
-                .unwrap_or(default.search_suggestions),
+            search_suggestions,
+            base_url: file.base_url,
+            max_cache_age: file.max_cache_age.unwrap_or(default.max_cache_age),
+            suggestion_allow_hosts: file.suggestion_allow_hosts.unwrap_or_default(),
+            http2: file.http2.unwrap_or(default.http2),
+            sanitize_query: file.sanitize_query.unwrap_or(default.sanitize_query),
+            post_bangs: file.post_bangs.unwrap_or(default.post_bangs),
+            suggestion_format: file.suggestion_format.unwrap_or_default(),
+            pinned_bangs_path: file.pinned_bangs_path,
+            offline: file.offline.unwrap_or(default.offline),
+            admin_token: file.admin_token,
+            selftest_trigger: file.selftest_trigger.unwrap_or(default.selftest_trigger),
+            suggestion_query_encoding: file.suggestion_query_encoding.unwrap_or_default(),
+            ignore_quoted_bangs: file
+                .ignore_quoted_bangs
+                .unwrap_or(default.ignore_quoted_bangs),
+            routes: file.routes.unwrap_or_default(),
+            suggestion_forward_headers: file.suggestion_forward_headers.unwrap_or_default(),
+            outbound_ip_family: file.outbound_ip_family.unwrap_or_default(),
+            min_tls_version: file.min_tls_version,
+            reload_interval_ms: file
+                .reload_interval_ms
+                .unwrap_or(default.reload_interval_ms),
+            timeouts: file.timeouts.unwrap_or_default(),
+            suggestion_breaker: file.suggestion_breaker.unwrap_or_default(),
+            bang_limits: file.bang_limits.unwrap_or_default(),
+            bang_collisions: file.bang_collisions.unwrap_or_default(),
+            robots_txt: file.robots_txt,
+            robots_txt_path: file.robots_txt_path,
+            suggestion_max_response_bytes: file
+                .suggestion_max_response_bytes
+                .unwrap_or(default.suggestion_max_response_bytes),
+            min_suggest_len: file.min_suggest_len.unwrap_or(default.min_suggest_len),
+            domain_bangs: file.domain_bangs.unwrap_or(default.domain_bangs),
+            stats_persist: file.stats_persist.unwrap_or(default.stats_persist),
+            stats_path: file.stats_path,
+            stats_retention_months: file
+                .stats_retention_months
+                .unwrap_or(default.stats_retention_months),
+            debug_recent_size: file.debug_recent_size.unwrap_or(default.debug_recent_size),
+            redact_queries: file.redact_queries.unwrap_or(default.redact_queries),
+            plus_as_space: file.plus_as_space.unwrap_or(default.plus_as_space),
+            suggestion_provider,
+            persist: file.persist.unwrap_or(default.persist),
+            prune_bangs: file.prune_bangs.unwrap_or(default.prune_bangs),
+            query_param: file.query_param.unwrap_or(default.query_param),
+            bangs_json_limit: file.bangs_json_limit.unwrap_or(default.bangs_json_limit),
+            suggest_default_search: file
+                .suggest_default_search
+                .unwrap_or(default.suggest_default_search),
+            advertise_suggestions: file
+                .advertise_suggestions
+                .unwrap_or(default.advertise_suggestions),
+            direct_url_navigation: file
+                .direct_url_navigation
+                .unwrap_or(default.direct_url_navigation),
+            direct_url_hosts: file.direct_url_hosts.unwrap_or(default.direct_url_hosts),
+            relevance_offset: file.relevance_offset,
+            decode_incoming: file.decode_incoming.unwrap_or(default.decode_incoming),
+            binary_cache: file.binary_cache.unwrap_or(default.binary_cache),
+            binary_cache_compression: file
+                .binary_cache_compression
+                .unwrap_or(default.binary_cache_compression),
+            binary_cache_compression_level: file.binary_cache_compression_level,
+            mobile_default_search: file.mobile_default_search,
+            localized_default_search: file
+                .localized_default_search
+                .unwrap_or(default.localized_default_search),
+            mobile_user_agents: file
+                .mobile_user_agents
+                .unwrap_or(default.mobile_user_agents),
+            suggest_top_bangs: file.suggest_top_bangs.unwrap_or(default.suggest_top_bangs),
+            default_search_bookmark: file
+                .default_search_bookmark
+                .unwrap_or(default.default_search_bookmark),
+            allow_insecure_bang_fetch: file
+                .allow_insecure_bang_fetch
+                .unwrap_or(default.allow_insecure_bang_fetch),
+            instance_headers: file.instance_headers.unwrap_or(default.instance_headers),
+            instance_name: file.instance_name,
+            max_custom_bangs: file.max_custom_bangs.unwrap_or(default.max_custom_bangs),
+            max_config_file_size: file
+                .max_config_file_size
+                .unwrap_or(default.max_config_file_size),
+            query_prefix: file.query_prefix,
+            query_suffix: file.query_suffix,
+            ui: file.ui.unwrap_or(default.ui),
+            builtin_aliases: file.builtin_aliases.unwrap_or(default.builtin_aliases),
+            aliases: file.aliases.unwrap_or_default(),
             bangs: file.bangs,
+            config_path: self.config_path.unwrap_or(default.config_path),
         }
     }
 }
 
+fn deserialize_listen<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<SocketAddr>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|listen| parse_listen(&listen).map_err(de::Error::custom))
+        .transpose()
+}
+
 impl FileConfig {
+    /// Split `listen` into `ip` and `port`.
+    ///
+    /// # Errors
+    /// If `ip` or `port` are set as well with another value.
+    pub fn apply_listen(&mut self) -> Result<()> {
+        (self.ip, self.port) = listen_address(self.listen.take(), self.ip, self.port)?;
+        Ok(())
+    }
+
+    /// Check that a `custom` suggestion provider comes with a template.
+    ///
+    /// # Errors
+    /// If `suggestion_provider` is `custom` without `search_suggestions`.
+    pub fn check_suggestion_provider(&self) -> Result<()> {
+        if self.suggestion_provider == Some(SuggestionProvider::Custom)
+            && self.search_suggestions.is_none()
+        {
+            bail!("suggestion_provider = \"custom\" needs a search_suggestions template");
+        }
+        Ok(())
+    }
+
+    /// Move the deprecated top-level `landing_html_path` and `popular_bangs`
+    /// into the `[ui]` table. They are ignored if there already is one.
+    ///
+    /// Returns the deprecation warnings.
+    pub fn migrate_ui(&mut self) -> Vec<String> {
+        let landing_html_path = self.landing_html_path.take();
+        let popular_bangs = self.popular_bangs.take();
+        let mut deprecated = Vec::new();
+        if landing_html_path.is_some() {
+            deprecated.push("landing_html_path");
+        }
+        if popular_bangs.is_some() {
+            deprecated.push("popular_bangs");
+        }
+        if self.ui.is_some() {
+            return deprecated
+                .into_iter()
+                .map(|key| {
+                    format!(
+                        "{key} is deprecated and ignored as there is a [ui] table, move it there"
+                    )
+                })
+                .collect();
+        }
+        if !deprecated.is_empty() {
+            let default = UiConfig::default();
+            self.ui = Some(UiConfig {
+                landing_html_path,
+                popular_bangs: popular_bangs.unwrap_or(default.popular_bangs),
+                ..default
+            });
+        }
+        deprecated
+            .into_iter()
+            .map(|key| format!("{key} is deprecated, move it into the [ui] table"))
+            .collect()
+    }
+
     /// Merge CLI configuration with an optional file configuration.
     /// CLI options take precedence over file values.
     #[must_use]
     pub fn merge(self, config: Config) -> AppConfig {
+        let (suggestion_provider, search_suggestions) = suggestion_upstream(
+            self.suggestion_provider,
+            config.search_suggestions,
+            self.search_suggestions,
+        );
         AppConfig {
             port: config.port.or(self.port).unwrap_or(3000),
             ip: config
@@ -122,11 +1001,87 @@ impl FileConfig {
                 .default_search
                 .or(self.default_search)
                 .unwrap_or_else(|| DEFAULT_SEARCH.to_string()),
-            search_suggestions: config
-                .search_suggestions
-                .or(self.search_suggestions)
-                .unwrap_or_else(|| DEFAULT_SEARCH_SUGGESTIONS.to_string()),
+            search_suggestions,
+            base_url: self.base_url,
+            max_cache_age: self.max_cache_age.unwrap_or(DEFAULT_MAX_CACHE_AGE),
+            suggestion_allow_hosts: self.suggestion_allow_hosts.unwrap_or_default(),
+            http2: self.http2.unwrap_or(false),
+            sanitize_query: self.sanitize_query.unwrap_or(true),
+            post_bangs: self.post_bangs.unwrap_or(true),
+            suggestion_format: self.suggestion_format.unwrap_or_default(),
+            pinned_bangs_path: self.pinned_bangs_path,
+            offline: self.offline.unwrap_or(false),
+            admin_token: self.admin_token,
+            selftest_trigger: self
+                .selftest_trigger
+                .unwrap_or_else(|| DEFAULT_SELFTEST_TRIGGER.to_string()),
+            suggestion_query_encoding: self.suggestion_query_encoding.unwrap_or_default(),
+            ignore_quoted_bangs: self.ignore_quoted_bangs.unwrap_or(true),
+            routes: self.routes.unwrap_or_default(),
+            suggestion_forward_headers: self.suggestion_forward_headers.unwrap_or_default(),
+            outbound_ip_family: self.outbound_ip_family.unwrap_or_default(),
+            min_tls_version: self.min_tls_version,
+            reload_interval_ms: self
+                .reload_interval_ms
+                .unwrap_or(DEFAULT_RELOAD_INTERVAL_MS),
+            timeouts: self.timeouts.unwrap_or_default(),
+            suggestion_breaker: self.suggestion_breaker.unwrap_or_default(),
+            bang_limits: self.bang_limits.unwrap_or_default(),
+            bang_collisions: self.bang_collisions.unwrap_or_default(),
+            robots_txt: self.robots_txt,
+            robots_txt_path: self.robots_txt_path,
+            suggestion_max_response_bytes: self
+                .suggestion_max_response_bytes
+                .unwrap_or(DEFAULT_SUGGESTION_MAX_RESPONSE_BYTES),
+            min_suggest_len: self.min_suggest_len.unwrap_or(1),
+            domain_bangs: self.domain_bangs.unwrap_or(false),
+            stats_persist: self.stats_persist.unwrap_or(false),
+            stats_path: self.stats_path,
+            stats_retention_months: self
+                .stats_retention_months
+                .unwrap_or(DEFAULT_STATS_RETENTION_MONTHS),
+            debug_recent_size: self.debug_recent_size.unwrap_or(DEFAULT_DEBUG_RECENT_SIZE),
+            redact_queries: self.redact_queries.unwrap_or(false),
+            plus_as_space: self.plus_as_space.unwrap_or(true),
+            suggestion_provider,
+            persist: self.persist.unwrap_or(true),
+            prune_bangs: self.prune_bangs.unwrap_or(false),
+            query_param: self
+                .query_param
+                .unwrap_or_else(|| DEFAULT_QUERY_PARAM.to_string()),
+            bangs_json_limit: self.bangs_json_limit.unwrap_or(DEFAULT_BANGS_JSON_LIMIT),
+            suggest_default_search: self.suggest_default_search.unwrap_or(true),
+            advertise_suggestions: self.advertise_suggestions.unwrap_or(true),
+            direct_url_navigation: self.direct_url_navigation.unwrap_or(false),
+            direct_url_hosts: self.direct_url_hosts.unwrap_or_default(),
+            relevance_offset: self.relevance_offset,
+            decode_incoming: self.decode_incoming.unwrap_or_default(),
+            binary_cache: self.binary_cache.unwrap_or(false),
+            binary_cache_compression: self.binary_cache_compression.unwrap_or_default(),
+            binary_cache_compression_level: self.binary_cache_compression_level,
+            mobile_default_search: self.mobile_default_search,
+            localized_default_search: self.localized_default_search.unwrap_or_default(),
+            mobile_user_agents: self
+                .mobile_user_agents
+                .unwrap_or_else(default_mobile_user_agents),
+            suggest_top_bangs: self
+                .suggest_top_bangs
+                .unwrap_or_else(default_suggest_top_bangs),
+            default_search_bookmark: self.default_search_bookmark.unwrap_or(false),
+            allow_insecure_bang_fetch: self.allow_insecure_bang_fetch.unwrap_or(false),
+            instance_headers: self.instance_headers.unwrap_or(false),
+            instance_name: self.instance_name,
+            max_custom_bangs: self.max_custom_bangs.unwrap_or(DEFAULT_MAX_CUSTOM_BANGS),
+            max_config_file_size: self
+                .max_config_file_size
+                .unwrap_or(DEFAULT_MAX_CONFIG_FILE_SIZE),
+            query_prefix: self.query_prefix,
+            query_suffix: self.query_suffix,
+            ui: self.ui.unwrap_or_default(),
+            builtin_aliases: self.builtin_aliases.unwrap_or(true),
+            aliases: self.aliases.unwrap_or_default(),
             bangs: self.bangs,
+            config_path: config.config_path.unwrap_or_else(default_config_path),
         }
     }
 }
@@ -138,42 +1093,389 @@ impl Default for AppConfig {
             ip: IpAddr::from([127, 0, 0, 1]),
             bangs_url: "https://duckduckgo.com/bang.js".to_string(),
             default_search: DEFAULT_SEARCH.to_string(),
-            search_suggestions: DEFAULT_SEARCH_SUGGESTIONS.to_string(),
+            search_suggestions: SuggestionProvider::Brave
+                .template()
+                .unwrap_or_default()
+                .to_string(),
+            base_url: None,
+            max_cache_age: DEFAULT_MAX_CACHE_AGE,
+            suggestion_allow_hosts: Vec::new(),
+            http2: false,
+            sanitize_query: true,
+            post_bangs: true,
+            suggestion_format: SuggestionFormat::Auto,
+            pinned_bangs_path: None,
+            offline: false,
+            admin_token: None,
+            selftest_trigger: DEFAULT_SELFTEST_TRIGGER.to_string(),
+            suggestion_query_encoding: QueryEncoding::Percent,
+            ignore_quoted_bangs: true,
+            routes: RoutesConfig::default(),
+            suggestion_forward_headers: Vec::new(),
+            outbound_ip_family: IpFamily::Auto,
+            min_tls_version: None,
+            reload_interval_ms: DEFAULT_RELOAD_INTERVAL_MS,
+            timeouts: TimeoutsConfig::default(),
+            suggestion_breaker: BreakerConfig::default(),
+            bang_limits: BangLimitsConfig::default(),
+            bang_collisions: CollisionPolicy::KeepFirst,
+            robots_txt: None,
+            robots_txt_path: None,
+            suggestion_max_response_bytes: DEFAULT_SUGGESTION_MAX_RESPONSE_BYTES,
+            min_suggest_len: 1,
+            domain_bangs: false,
+            stats_persist: false,
+            stats_path: None,
+            stats_retention_months: DEFAULT_STATS_RETENTION_MONTHS,
+            debug_recent_size: DEFAULT_DEBUG_RECENT_SIZE,
+            redact_queries: false,
+            plus_as_space: true,
+            suggestion_provider: SuggestionProvider::Brave,
+            persist: true,
+            prune_bangs: false,
+            query_param: DEFAULT_QUERY_PARAM.to_string(),
+            bangs_json_limit: DEFAULT_BANGS_JSON_LIMIT,
+            suggest_default_search: true,
+            advertise_suggestions: true,
+            direct_url_navigation: false,
+            direct_url_hosts: Vec::new(),
+            relevance_offset: None,
+            decode_incoming: DecodeIncoming::Never,
+            binary_cache: false,
+            binary_cache_compression: CacheCompression::None,
+            binary_cache_compression_level: None,
+            mobile_default_search: None,
+            localized_default_search: BTreeMap::new(),
+            mobile_user_agents: default_mobile_user_agents(),
+            suggest_top_bangs: default_suggest_top_bangs(),
+            default_search_bookmark: false,
+            allow_insecure_bang_fetch: false,
+            instance_headers: false,
+            instance_name: None,
+            max_custom_bangs: DEFAULT_MAX_CUSTOM_BANGS,
+            max_config_file_size: DEFAULT_MAX_CONFIG_FILE_SIZE,
+            query_prefix: None,
+            query_suffix: None,
+            ui: UiConfig::default(),
+            builtin_aliases: true,
+            aliases: BTreeMap::new(),
             bangs: None,
+            config_path: default_config_path(),
+        }
+    }
+}
+
+impl AppConfig {
+    /// The configuration as TOML, for `redirector config show`, without the
+    /// `admin_token` and the configured bangs.
+    ///
+    /// # Errors
+    /// If it can't be represented as TOML.
+    pub fn to_toml(&self) -> Result<String> {
+        let shown = Self {
+            bangs: None,
+            ..self.clone()
+        };
+        Ok(toml::to_string_pretty(&shown)?)
+    }
+
+    /// How many redirects `/debug/recent` keeps, none with `redact_queries`.
+    #[must_use]
+    pub const fn debug_recent_capacity(&self) -> usize {
+        if self.redact_queries {
+            0
+        } else {
+            self.debug_recent_size
+        }
+    }
+
+    /// The settings relevant for resolving queries.
+    #[must_use]
+    pub fn resolve_options(&self) -> ResolveOptions<'_> {
+        ResolveOptions {
+            default_search: &self.default_search,
+            sanitize_query: self.sanitize_query,
+            post_bangs: self.post_bangs,
+            ignore_quoted_bangs: self.ignore_quoted_bangs,
+            domain_bangs: self.domain_bangs,
+            direct_urls: self.direct_url_navigation,
+            direct_url_hosts: &self.direct_url_hosts,
+            decode_incoming: self.decode_incoming,
+            mobile: false,
+            languages: &[],
+            query_prefix: self.query_prefix.as_deref().unwrap_or_default(),
+            query_suffix: self.query_suffix.as_deref().unwrap_or_default(),
+            builtin_aliases: self.builtin_aliases,
+            aliases: &self.aliases,
+        }
+    }
+
+    /// The values that parse but can't work, e.g. a `default_search` without
+    /// a placeholder for the query or a `bangs_url` without a scheme.
+    #[must_use]
+    pub fn problems(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        let mut check = |key, value: &str, problem: Option<String>| {
+            if let Some(problem) = problem {
+                problems.push(ConfigProblem {
+                    key,
+                    value: value.to_string(),
+                    problem,
+                });
+            }
+        };
+        check("bangs_url", &self.bangs_url, url_problem(&self.bangs_url));
+        check(
+            "default_search",
+            &self.default_search,
+            template_problem(&self.default_search, self.default_search_bookmark),
+        );
+        for localized in self.localized_default_search.values() {
+            check(
+                "localized_default_search",
+                localized,
+                template_problem(localized, self.default_search_bookmark),
+            );
+        }
+        if let Some(mobile_default_search) = &self.mobile_default_search {
+            check(
+                "mobile_default_search",
+                mobile_default_search,
+                template_problem(mobile_default_search, self.default_search_bookmark),
+            );
+        }
+        // built-in providers come with their template
+        if self.suggestion_provider == SuggestionProvider::Custom {
+            check(
+                "search_suggestions",
+                &self.search_suggestions,
+                template_problem(&self.search_suggestions, false),
+            );
         }
+        if let Some(instance_name) = &self.instance_name {
+            check(
+                "instance_name",
+                instance_name,
+                (!instance_name
+                    .bytes()
+                    .all(|byte| byte == b' ' || byte.is_ascii_graphic()))
+                .then(|| "can only contain printable ASCII characters".to_string()),
+            );
+        }
+        if let Some(level) = self.binary_cache_compression_level {
+            let levels = self.binary_cache_compression.levels();
+            check(
+                "binary_cache_compression_level",
+                &level.to_string(),
+                (!levels.contains(&level)).then(|| {
+                    format!(
+                        "must be between {} and {} for this binary_cache_compression",
+                        levels.start(),
+                        levels.end()
+                    )
+                }),
+            );
+        }
+        if !(MIN_UI_PAGE_SIZE..=MAX_UI_PAGE_SIZE).contains(&self.ui.page_size) {
+            check(
+                "ui.page_size",
+                &self.ui.page_size.to_string(),
+                Some(format!(
+                    "must be between {MIN_UI_PAGE_SIZE} and {MAX_UI_PAGE_SIZE}"
+                )),
+            );
+        }
+        if let Some(custom_css_path) = &self.ui.custom_css_path {
+            check(
+                "ui.custom_css_path",
+                &custom_css_path.display().to_string(),
+                (!custom_css_path.is_file()).then(|| "is no file".to_string()),
+            );
+        }
+        if let Some(static_dir) = &self.ui.static_dir {
+            check(
+                "ui.static_dir",
+                &static_dir.display().to_string(),
+                (!static_dir.is_dir()).then(|| "is no directory".to_string()),
+            );
+        }
+        problems
+    }
+
+    /// How many of `bangs` can be added at runtime without exceeding
+    /// `max_custom_bangs` or, if they are persisted, `max_config_file_size`,
+    /// and the quota the rest would exceed.
+    #[must_use]
+    pub fn fit_quota(&self, bangs: &[Bang]) -> (usize, Option<QuotaExceeded>) {
+        let configured = self.bangs.as_ref().map_or(0, Vec::len);
+        // nothing is written without a configuration file
+        let mut size = self
+            .persist
+            .then(|| std::fs::metadata(&self.config_path).ok())
+            .flatten()
+            .map(|metadata| metadata.len());
+        for (fitting, bang) in bangs.iter().enumerate() {
+            if configured + fitting >= self.max_custom_bangs {
+                return (
+                    fitting,
+                    Some(QuotaExceeded::CustomBangs {
+                        max: self.max_custom_bangs,
+                    }),
+                );
+            }
+            if let Some(size) = &mut size {
+                let mut toml = String::new();
+                write_bang(&mut toml, bang.clone());
+                *size += toml.len() as u64;
+                if *size > self.max_config_file_size {
+                    return (
+                        fitting,
+                        Some(QuotaExceeded::ConfigFileSize {
+                            max: self.max_config_file_size,
+                        }),
+                    );
+                }
+            }
+        }
+        (bangs.len(), None)
+    }
+
+    /// The quotas for adding bangs at runtime that are used up to 90% or
+    /// more, warned about on startup.
+    #[must_use]
+    pub fn quota_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let configured = self.bangs.as_ref().map_or(0, Vec::len);
+        if configured * 10 >= self.max_custom_bangs * 9 {
+            warnings.push(format!(
+                "{configured} bangs are configured, /add_bang and imports stop at \
+                 max_custom_bangs = {}",
+                self.max_custom_bangs
+            ));
+        }
+        if let Ok(metadata) = std::fs::metadata(&self.config_path)
+            && metadata.len() * 10 >= self.max_config_file_size * 9
+        {
+            warnings.push(format!(
+                "The configuration file at {} has {} bytes, /add_bang and imports stop at \
+                 max_config_file_size = {}",
+                self.config_path.display(),
+                metadata.len(),
+                self.max_config_file_size
+            ));
+        }
+        warnings
+    }
+
+    /// Like [`Self::resolve_options`], for requests from a phone.
+    #[must_use]
+    pub fn mobile_resolve_options(&self) -> ResolveOptions<'_> {
+        ResolveOptions {
+            default_search: self
+                .mobile_default_search
+                .as_deref()
+                .unwrap_or(&self.default_search),
+            mobile: true,
+            ..self.resolve_options()
+        }
+    }
+
+    /// Whether a request is from a phone, by the `mobile` cookie if it is
+    /// set to `1` or `0` and otherwise by the User-Agent matching one of the
+    /// `mobile_user_agents`.
+    #[must_use]
+    pub fn is_mobile(&self, cookie: Option<&str>, user_agent: Option<&str>) -> bool {
+        let cookie = cookie
+            .into_iter()
+            .flat_map(|cookie| cookie.split(';'))
+            .find_map(|pair| {
+                match pair.trim().split_once('=') {
+                    Some(("mobile", "1")) => Some(true),
+                    Some(("mobile", "0")) => Some(false),
+                    _ => None,
+                }
+            });
+        cookie.unwrap_or_else(|| {
+            let user_agent = user_agent.unwrap_or_default().to_ascii_lowercase();
+            self.mobile_user_agents
+                .iter()
+                .any(|pattern| user_agent.contains(&pattern.to_ascii_lowercase()))
+        })
+    }
+
+    /// The public base URL of this instance, without a trailing slash.
+    #[must_use]
+    pub fn base_url(&self) -> String {
+        self.base_url.as_ref().map_or_else(
+            || format!("http://{}", SocketAddr::new(self.ip, self.port)),
+            |base_url| base_url.trim_end_matches('/').to_string(),
+        )
     }
 }
 
 impl From<Cli> for Config {
     fn from(cli: Cli) -> Self {
+        let config_path = cli.config;
         match cli.command {
-            Some(SubCommand::Serve { port, ip }) => {
+            Some(SubCommand::Serve { port, ip, .. }) => {
                 Self {
                     port,
                     ip,
                     bangs_url: cli.bangs_url,
                     default_search: cli.default_search,
                     search_suggestions: cli.search_suggestions,
+                    config_path,
                 }
             },
-            Some(SubCommand::Resolve { .. }) => {
+            Some(
+                SubCommand::Resolve { .. }
+                | SubCommand::Export { .. }
+                | SubCommand::Bangs { .. }
+                | SubCommand::Update
+                | SubCommand::Selftest,
+            ) => {
                 Self {
                     port: None,
                     ip: None,
                     bangs_url: cli.bangs_url,
                     default_search: cli.default_search,
                     search_suggestions: cli.search_suggestions,
+                    config_path,
+                }
+            },
+            _ => {
+                Self {
+                    config_path,
+                    ..Self::default()
                 }
             },
-            _ => Self::default(),
         }
     }
 }
 
 /// Reloads configuration from disk while preserving CLI options.
+///
+/// Reloads are at least `reload_interval_ms` apart, calls in between wait for
+/// the next reload and share its result.
 pub async fn reload_config(app_state: &AppState) -> Result<()> {
+    let interval = Duration::from_millis(app_state.config.read().reload_interval_ms);
+    let result = app_state
+        .reload_limiter
+        .run(interval, || {
+            async {
+                reload_config_now(app_state)
+                    .await
+                    .map_err(|e| Arc::from(e.to_string()))
+            }
+        })
+        .await;
+    result.map_err(|e| anyhow::anyhow!("{e}"))
+}
+
+async fn reload_config_now(app_state: &AppState) -> Result<()> {
     // Get new file config
-    let file_config = get_file_config();
+    let config_path = app_state.config.read().config_path.clone();
+    let file_config = get_file_config(&config_path);
 
     match file_config {
         Ok(config) => {
@@ -182,31 +1484,142 @@ pub async fn reload_config(app_state: &AppState) -> Result<()> {
                 current_config.clone()
             };
 
-            config_clone.bangs = config.bangs;
+            let allow_hosts_changed = apply_reloadable(&mut config_clone, config);
 
             // Reload bang cache with the clone
-            if let Err(e) = update_bangs(&config_clone).await {
-                error!("Failed to update bang commands: {e}");
-                bail!("Failed to update bang commands: {e}");
-            }
+            let metadata = match update_bangs(&config_clone).await {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    error!("Failed to update bang commands: {e:#}");
+                    app_state.record_update_failure(&e);
+                    bail!("Failed to update bang commands: {e:#}");
+                },
+            };
 
             {
                 let mut current_config = app_state.config.write();
                 *current_config = config_clone;
             }
+            app_state.record_update();
+            app_state.record_source(metadata);
+            app_state.load_ui_files();
+            crate::stats::reopen(app_state);
+            if allow_hosts_changed {
+                app_state.rebuild_suggestion_client();
+            }
 
             info!("Configuration reloaded successfully");
             Ok(())
         },
         Err(e) => {
             debug!("No valid configuration file found, nothing was changed.");
-            bail!("No valid configuration file found: {e}")
+            bail!("No valid configuration file found: {e:#}")
         },
     }
 }
 
-pub fn get_file_config() -> Result<FileConfig> {
-    let config_path: PathBuf = if let Ok(config_dir) = env::var("XDG_CONFIG_HOME")
+/// Take the settings a reload changes from `file_config` into `app_config`,
+/// with the defaults of [`FileConfig::merge`] for the missing ones.
+///
+/// Returns whether `suggestion_allow_hosts` changed.
+fn apply_reloadable(app_config: &mut AppConfig, file_config: FileConfig) -> bool {
+    let reloaded = file_config.merge(Config::default());
+    let allow_hosts_changed = reloaded.suggestion_allow_hosts != app_config.suggestion_allow_hosts;
+    app_config.bangs = reloaded.bangs;
+    app_config.ui = reloaded.ui;
+    app_config.builtin_aliases = reloaded.builtin_aliases;
+    app_config.aliases = reloaded.aliases;
+    app_config.suggest_default_search = reloaded.suggest_default_search;
+    app_config.advertise_suggestions = reloaded.advertise_suggestions;
+    app_config.min_suggest_len = reloaded.min_suggest_len;
+    app_config.prune_bangs = reloaded.prune_bangs;
+    app_config.suggest_top_bangs = reloaded.suggest_top_bangs;
+    app_config.robots_txt = reloaded.robots_txt;
+    app_config.robots_txt_path = reloaded.robots_txt_path;
+    app_config.reload_interval_ms = reloaded.reload_interval_ms;
+    app_config.stats_persist = reloaded.stats_persist;
+    app_config.stats_path = reloaded.stats_path;
+    app_config.stats_retention_months = reloaded.stats_retention_months;
+    app_config.suggestion_allow_hosts = reloaded.suggestion_allow_hosts;
+    allow_hosts_changed
+}
+
+/// Re-read only the `[[bangs]]` of the configuration file.
+///
+/// Unlike [`reload_config`] this neither downloads the bang list nor touches
+/// any other setting. Returns the number of configured bangs. Like
+/// [`reload_config`], reloads are at least `reload_interval_ms` apart.
+///
+/// # Errors
+/// If the configuration file can't be read or parsed, or its triggers collide
+/// while `bang_collisions` is `error`.
+pub async fn reload_bangs(app_state: &AppState) -> Result<usize> {
+    let interval = Duration::from_millis(app_state.config.read().reload_interval_ms);
+    let result = app_state
+        .bangs_reload_limiter
+        .run(interval, || {
+            async { reload_bangs_now(app_state).map_err(|e| Arc::from(format!("{e:#}"))) }
+        })
+        .await;
+    result.map_err(|e| anyhow::anyhow!("{e}"))
+}
+
+/// [`reload_bangs`] right away.
+///
+/// # Errors
+/// Like [`reload_bangs`].
+pub fn reload_bangs_now(app_state: &AppState) -> Result<usize> {
+    let config_path = app_state.config.read().config_path.clone();
+    let file_config = get_file_config(&config_path)?;
+    apply_file_bangs(app_state, &BANG_CACHE, file_config)
+}
+
+fn apply_file_bangs(
+    app_state: &AppState,
+    cache: &RwLock<BangCache>,
+    file_config: FileConfig,
+) -> Result<usize> {
+    let bangs = file_config.bangs;
+    let mut config = app_state.config.write();
+    replace_config_bangs(
+        &mut cache.write(),
+        bangs.as_deref().unwrap_or_default(),
+        config.bang_collisions,
+        config.relevance_offset,
+    )?;
+    let count = bangs.as_ref().map_or(0, Vec::len);
+    let previous = std::mem::replace(&mut config.bangs, bangs.clone());
+    drop(config);
+    if app_state.firehose.is_watched() {
+        let triggers = |bangs: &Option<Vec<Bang>>| -> BTreeSet<String> {
+            bangs
+                .iter()
+                .flatten()
+                .map(|bang| bang.trigger.clone())
+                .collect()
+        };
+        let (previous, current) = (triggers(&previous), triggers(&bangs));
+        for trigger in current.difference(&previous) {
+            app_state
+                .firehose
+                .publish(FirehoseEvent::bang_added(trigger));
+        }
+        for trigger in previous.difference(&current) {
+            app_state
+                .firehose
+                .publish(FirehoseEvent::bang_removed(trigger));
+        }
+    }
+    info!("Reloaded {count} configured bangs");
+    Ok(count)
+}
+
+/// Path of the configuration file without `--config`,
+/// `$XDG_CONFIG_HOME/redirector/config.toml` or
+/// `~/.config/redirector/config.toml`.
+#[must_use]
+pub fn default_config_path() -> PathBuf {
+    if let Ok(config_dir) = env::var("XDG_CONFIG_HOME")
         && !config_dir.is_empty()
     {
         PathBuf::from(config_dir)
@@ -218,21 +1631,253 @@ pub fn get_file_config() -> Result<FileConfig> {
             .join(".config")
             .join("redirector")
             .join("config.toml")
+    }
+}
+
+/// A value of the configuration that parses but can't work, which would
+/// otherwise only show when a request or update fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    pub key: &'static str,
+    pub value: String,
+    pub problem: String,
+}
+
+/// A quota adding bangs at runtime would exceed, see
+/// [`AppConfig::fit_quota`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum QuotaExceeded {
+    CustomBangs { max: usize },
+    ConfigFileSize { max: u64 },
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CustomBangs { max } => {
+                write!(
+                    f,
+                    "There already are max_custom_bangs = {max} configured bangs"
+                )
+            },
+            Self::ConfigFileSize { max } => {
+                write!(
+                    f,
+                    "The configuration file would grow beyond max_config_file_size = {max} bytes"
+                )
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} = {} {}",
+            self.key,
+            toml::Value::from(self.value.as_str()),
+            self.problem
+        )
+    }
+}
+
+/// The `{}` placeholders of `template`, not counting literal braces.
+fn placeholders(template: &str) -> usize {
+    template
+        .split(LITERAL_BRACES)
+        .map(|part| part.matches("{}").count())
+        .sum()
+}
+
+/// Why `url` can't be requested, if it can't.
+pub(crate) fn url_problem(url: &str) -> Option<String> {
+    let scheme = url.split_once("://").map(|(scheme, _)| scheme);
+    if !scheme.is_some_and(|scheme| {
+        scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+    }) {
+        return Some("needs an http:// or https:// scheme".to_string());
+    }
+    reqwest::Url::parse(url)
+        .err()
+        .map(|e| format!("is not a valid URL: {e}"))
+}
+
+/// Why the search `template` with the placeholder for the query can't work,
+/// if it can't. With `bookmark` it may have no placeholder.
+pub(crate) fn template_problem(template: &str, bookmark: bool) -> Option<String> {
+    match placeholders(template) {
+        0 if bookmark => {},
+        0 => return Some("has no {} placeholder for the query".to_string()),
+        1 => {},
+        count => return Some(format!("has {count} {{}} placeholders, expected one")),
+    }
+    url_problem(&template.replace(LITERAL_BRACES, "").replace("{}", ""))
+}
+
+/// Term the URL of a template is checked with in [`check_url_template`].
+pub const SAMPLE_TERM: &str = "sample term";
+
+/// What [`check_url_template`] found out about the template of a bang.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TemplateCheck {
+    /// Whether there is a `{{{s}}}` placeholder, the term is appended to the
+    /// template otherwise.
+    pub placeholder: bool,
+    /// The URL a search for [`SAMPLE_TERM`] redirects to.
+    pub url: String,
+    /// Whether `url` is an http(s) URL.
+    pub valid_url: bool,
+    /// Why `url` isn't one.
+    pub error: Option<String>,
+    /// Things that work, but likely not as meant.
+    pub warnings: Vec<String>,
+}
+
+/// Check the `url_template` of a bang, by resolving [`SAMPLE_TERM`] with it
+/// like a search would with `options`.
+#[must_use]
+pub fn check_url_template(options: &ResolveOptions<'_>, url_template: &str) -> TemplateCheck {
+    let bang = Bang {
+        trigger: "template".to_string(),
+        url_template: url_template.to_string(),
+        ..Bang::default()
     };
+    let cache = build_cache(Vec::new(), &[bang]);
+    let url = resolve_trigger(&cache, options, "template", SAMPLE_TERM)
+        .map(|resolution| resolution.url)
+        .unwrap_or_default();
 
+    let mut warnings = Vec::new();
+    let term_placeholders = url_template.matches("{{{s}}}").count();
+    match term_placeholders {
+        0 => warnings.push("No {{{s}}} placeholder, the term is appended".to_string()),
+        1 => {},
+        count => {
+            warnings.push(format!(
+                "{count} {{{{{{s}}}}}} placeholders, the term is inserted at each"
+            ));
+        },
+    }
+    if url_template.contains("%s") {
+        warnings.push("%s is no placeholder, use {{{s}}}".to_string());
+    }
+    if placeholders(url_template) > 0 {
+        warnings.push("{} is kept as it is, use {{{s}}} for the term".to_string());
+    }
+    let error = url_problem(&url);
+    TemplateCheck {
+        placeholder: term_placeholders > 0,
+        valid_url: error.is_none(),
+        url,
+        error,
+        warnings,
+    }
+}
+
+/// A configuration file that isn't valid TOML or doesn't match
+/// [`FileConfig`], with where the mistake is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigParseError {
+    pub message: String,
+    pub location: Option<ErrorLocation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorLocation {
+    /// Starting at 1.
+    pub line: usize,
+    /// In characters, starting at 1.
+    pub column: usize,
+    /// The whole line the mistake starts in.
+    pub snippet: String,
+}
+
+impl ConfigParseError {
+    fn new(contents: &str, e: &toml::de::Error) -> Self {
+        let location = e.span().and_then(|span| {
+            let start = span.start.min(contents.len());
+            let line_start = contents[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = contents[start..]
+                .find('\n')
+                .map_or(contents.len(), |i| start + i);
+            Some(ErrorLocation {
+                line: contents[..start].matches('\n').count() + 1,
+                column: contents.get(line_start..start)?.chars().count() + 1,
+                snippet: contents[line_start..line_end].trim_end().to_string(),
+            })
+        });
+        Self {
+            message: e.message().trim_end().to_string(),
+            location,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(location) = &self.location else {
+            return write!(f, "{}", self.message);
+        };
+        let number = location.line.to_string();
+        write!(
+            f,
+            "line {}, column {}: {}\n{number} | {}\n{:width$} | {:>column$}",
+            location.line,
+            location.column,
+            self.message,
+            location.snippet,
+            "",
+            "^",
+            width = number.len(),
+            column = location.column
+        )
+    }
+}
+
+impl std::error::Error for ConfigParseError {}
+
+/// Parse the contents of a configuration file.
+///
+/// # Errors
+/// With a [`ConfigParseError`] if it isn't valid, or if `listen` conflicts
+/// with `ip` or `port`.
+pub fn parse_file_config(contents: &str) -> Result<FileConfig> {
+    let mut conf =
+        toml::from_str::<FileConfig>(contents).map_err(|e| ConfigParseError::new(contents, &e))?;
+    conf.bangs.iter_mut().flatten().for_each(Bang::normalize);
+    for bang in conf.bangs.iter().flatten() {
+        if bang.raw_term_in_query() {
+            warn!(
+                "!{} inserts the search term unencoded into a query parameter, a & or # in it \
+                 ends the parameter. Use term_encoding = \"component\" unless the target expects \
+                 that",
+                bang.trigger
+            );
+        }
+    }
+    for deprecation in conf.migrate_ui() {
+        warn!("{deprecation}");
+    }
+    conf.apply_listen()?;
+    conf.check_suggestion_provider()?;
+    Ok(conf)
+}
+
+pub fn get_file_config(config_path: &Path) -> Result<FileConfig> {
     // Attempt to load the file configuration if it exists.
     if config_path.exists() {
-        match read_to_string(&config_path) {
+        match read_to_string(config_path) {
             Ok(contents) => {
-                match toml::from_str::<FileConfig>(&contents) {
+                match parse_file_config(&contents) {
                     Ok(conf) => Ok(conf),
                     Err(e) => {
                         error!(
-                            "Failed to parse configuration file at {}: {}",
-                            config_path.display(),
-                            e
+                            "Failed to parse configuration file at {}: {e}",
+                            config_path.display()
                         );
-                        bail!("Failed to parse configuration file: {e}")
+                        Err(e.context("Failed to parse configuration file"))
                     },
                 }
             },
@@ -251,40 +1896,46 @@ pub fn get_file_config() -> Result<FileConfig> {
     }
 }
 
-pub fn append_file_config(bang: Bang) {
-    let home_dir = env::var("HOME").unwrap_or_else(|_| ".".to_string());
-    let config_path = Path::new(&home_dir)
-        .join(".config")
-        .join("redirector")
-        .join("config.toml");
+pub fn append_file_config(config_path: &Path, bang: Bang) {
+    append_file_config_bangs(config_path, vec![bang]);
+}
 
+/// Append imported `bangs` to the configuration file, unless their triggers
+/// collide with the configured ones and `policy` refuses that, like the
+/// import endpoint does.
+pub fn import_file_config_bangs(
+    config_path: &Path,
+    bangs: Vec<Bang>,
+    policy: CollisionPolicy,
+) -> Result<()> {
+    if !config_path.exists() {
+        bail!(
+            "There is no configuration file at {} to import into",
+            config_path.display()
+        );
+    }
+    let mut configured = get_file_config(config_path)?.bangs.unwrap_or_default();
+    configured.extend(bangs.iter().cloned());
+    if let Err(collision) = build_cache_with(Vec::new(), &configured, policy, None) {
+        bail!("Refusing to import the bangs, {collision}");
+    }
+    append_file_config_bangs(config_path, bangs);
+    Ok(())
+}
+
+/// Append `bangs` to the configuration file, if there is one, in a single
+/// write.
+pub fn append_file_config_bangs(config_path: &Path, bangs: Vec<Bang>) {
+    let _writing = CONFIG_FILE_WRITE.lock();
     // Attempt to load the file configuration if it exists.
     if config_path.exists() {
-        match read_to_string(&config_path) {
+        match read_to_string(config_path) {
             Ok(mut contents) => {
-                // append the new bang to the config file
-                // TODO: dont use unwrap
-                write!(contents, "\n[[bangs]]").unwrap();
-                write!(contents, "\ntrigger = \"{}\"", bang.trigger).unwrap();
-                write!(contents, "\nurl_template = \"{}\"", bang.url_template).unwrap();
-                if let Some(category) = bang.category {
-                    write!(contents, "\ncategory = \"{category}\"").unwrap();
-                }
-                if let Some(domain) = bang.domain {
-                    write!(contents, "\ndomain = \"{domain}\"").unwrap();
-                }
-                if let Some(relevance) = bang.relevance {
-                    write!(contents, "\nrelevance = {relevance}").unwrap();
+                for bang in bangs {
+                    write_bang(&mut contents, bang);
                 }
-                if let Some(short_name) = bang.short_name {
-                    write!(contents, "\nshort_name = \"{short_name}\"").unwrap();
-                }
-                if let Some(subcategory) = bang.subcategory {
-                    write!(contents, "\nsubcategory = \"{subcategory}\"").unwrap();
-                }
-                writeln!(contents).unwrap();
 
-                if let Err(e) = std::fs::write(&config_path, contents) {
+                if let Err(e) = std::fs::write(config_path, contents) {
                     error!(
                         "Failed to write to configuration file at {}: {}",
                         config_path.display(),
@@ -306,3 +1957,625 @@ pub fn append_file_config(bang: Bang) {
         debug!("Configuration file not found at {}.", config_path.display());
     }
 }
+
+/// Append `bang` as a `[[bangs]]` table to `contents`.
+fn write_bang(contents: &mut String, bang: Bang) {
+    // TODO: dont use unwrap
+    write!(contents, "\n[[bangs]]").unwrap();
+    write!(contents, "\ntrigger = {}", toml::Value::from(bang.trigger)).unwrap();
+    write!(
+        contents,
+        "\nurl_template = {}",
+        toml::Value::from(bang.url_template)
+    )
+    .unwrap();
+    if let Some(category) = bang.category {
+        write!(contents, "\ncategory = \"{category}\"").unwrap();
+    }
+    if let Some(domain) = bang.domain {
+        write!(contents, "\ndomain = {}", toml::Value::from(domain)).unwrap();
+    }
+    if let Some(relevance) = bang.relevance {
+        write!(contents, "\nrelevance = {relevance}").unwrap();
+    }
+    if let Some(short_name) = bang.short_name {
+        write!(contents, "\nshort_name = {}", toml::Value::from(short_name)).unwrap();
+    }
+    if let Some(subcategory) = bang.subcategory {
+        write!(
+            contents,
+            "\nsubcategory = {}",
+            toml::Value::from(subcategory)
+        )
+        .unwrap();
+    }
+    if let Some(note) = bang.note {
+        write!(contents, "\nnote = {}", toml::Value::from(note)).unwrap();
+    }
+    if let Some(method) = bang.method {
+        let method = match method {
+            BangMethod::Get => "get",
+            BangMethod::Post => "post",
+        };
+        write!(contents, "\nmethod = \"{method}\"").unwrap();
+    }
+    if let Some(post_field) = bang.post_field {
+        write!(contents, "\npost_field = {}", toml::Value::from(post_field)).unwrap();
+    }
+    if let Some(post_fields) = bang.post_fields {
+        let post_fields: toml::Table = post_fields
+            .into_iter()
+            .map(|(name, value)| (name, toml::Value::from(value)))
+            .collect();
+        write!(
+            contents,
+            "\npost_fields = {}",
+            toml::Value::from(post_fields)
+        )
+        .unwrap();
+    }
+    if let Some(term_encoding) = bang.term_encoding {
+        let term_encoding = match term_encoding {
+            TermEncoding::Component => "component",
+            TermEncoding::None => "none",
+            TermEncoding::Path => "path",
+        };
+        write!(contents, "\nterm_encoding = \"{term_encoding}\"").unwrap();
+    }
+    if let Some(mobile_url_template) = bang.mobile_url_template {
+        write!(
+            contents,
+            "\nmobile_url_template = {}",
+            toml::Value::from(mobile_url_template)
+        )
+        .unwrap();
+    }
+    if let Some(localized) = bang.localized {
+        write!(contents, "\nlocalized = {}", toml::Value::from(localized)).unwrap();
+    }
+    if let Some(enabled) = bang.enabled {
+        write!(contents, "\nenabled = {enabled}").unwrap();
+    }
+    if let Some(expires_at) = bang.expires_at {
+        write!(contents, "\nexpires_at = {expires_at}").unwrap();
+    }
+    writeln!(contents).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bang::test_bang;
+    use crate::{BangOrigin, CachedBang};
+
+    #[test]
+    fn test_config_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("instance-2.toml");
+        std::fs::write(&path, "port = 4321\n").unwrap();
+
+        let app_config = get_file_config(&path).unwrap().merge(Config {
+            config_path: Some(path.clone()),
+            ..Config::default()
+        });
+        assert_eq!(app_config.port, 4321);
+        assert_eq!(app_config.config_path, path);
+        get_file_config(&dir.path().join("missing.toml")).unwrap_err();
+
+        append_file_config(
+            &app_config.config_path,
+            test_bang("rs", "https://docs.rs/releases/search?query={{{s}}}"),
+        );
+        let bangs = get_file_config(&path).unwrap().bangs.unwrap();
+        assert_eq!(bangs[0].trigger, "rs", "bangs are added to that file");
+    }
+
+    #[test]
+    fn test_reload_bangs_only() {
+        let app_state = AppState::new(AppConfig {
+            port: 1234,
+            default_search: "https://search.example.com/?q={}".to_string(),
+            bangs: Some(vec![test_bang("old", "https://old.example.com/?q=")]),
+            ..AppConfig::default()
+        });
+        let cache = RwLock::new(BangCache::from([
+            (
+                "g".to_string(),
+                CachedBang {
+                    bang: test_bang("g", "https://www.google.com/search?q="),
+                    origin: BangOrigin::Remote,
+                },
+            ),
+            (
+                "old".to_string(),
+                CachedBang {
+                    bang: test_bang("old", "https://old.example.com/?q="),
+                    origin: BangOrigin::Config,
+                },
+            ),
+        ]));
+
+        let file_config: FileConfig = toml::from_str(
+            r#"
+            port = 9999
+            default_search = "https://elsewhere.example.com/?q={}"
+
+            [[bangs]]
+            trigger = "new"
+            url_template = "https://new.example.com/?q="
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            apply_file_bangs(&app_state, &cache, file_config).unwrap(),
+            1
+        );
+
+        let config = app_state.get_config();
+        assert_eq!(config.port, 1234, "server settings must be left alone");
+        assert_eq!(config.default_search, "https://search.example.com/?q={}");
+        let triggers: Vec<&str> = config
+            .bangs
+            .iter()
+            .flatten()
+            .map(|bang| bang.trigger.as_str())
+            .collect();
+        assert_eq!(triggers, ["new"]);
+
+        let cache = cache.into_inner();
+        assert!(!cache.contains_key("old"), "removed bangs must be dropped");
+        assert_eq!(cache["new"].origin, BangOrigin::Config);
+        assert_eq!(cache["g"].origin, BangOrigin::Remote);
+    }
+
+    #[test]
+    fn test_apply_reloadable() {
+        let mut app_config = AppConfig {
+            port: 8080,
+            builtin_aliases: false,
+            min_suggest_len: 3,
+            prune_bangs: true,
+            stats_persist: true,
+            stats_retention_months: 6,
+            suggestion_allow_hosts: vec!["suggest.example.com".to_string()],
+            ..AppConfig::default()
+        };
+        let file_config = parse_file_config("advertise_suggestions = false\n").unwrap();
+        assert!(apply_reloadable(&mut app_config, file_config));
+
+        let default = AppConfig::default();
+        assert_eq!(app_config.port, 8080, "not reloaded");
+        assert!(!app_config.advertise_suggestions);
+        assert_eq!(app_config.builtin_aliases, default.builtin_aliases);
+        assert_eq!(app_config.min_suggest_len, default.min_suggest_len);
+        assert_eq!(app_config.prune_bangs, default.prune_bangs);
+        assert_eq!(app_config.stats_persist, default.stats_persist);
+        assert_eq!(
+            app_config.stats_retention_months,
+            default.stats_retention_months
+        );
+        assert_eq!(
+            app_config.suggestion_allow_hosts,
+            default.suggestion_allow_hosts
+        );
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let contents = "offline = true\n\n[routes]\napi = \"yes\"\n";
+        let e = parse_file_config(contents).unwrap_err();
+        let e = e.downcast_ref::<ConfigParseError>().unwrap();
+        let location = e.location.as_ref().unwrap();
+        assert_eq!((location.line, location.column), (4, 7));
+        assert_eq!(location.snippet, "api = \"yes\"");
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "line 4, column 7: {}\n4 | api = \"yes\"\n  |       ^",
+                e.message
+            )
+        );
+
+        let e = parse_file_config("port = 80\nbangs_url = \"unterminated\n").unwrap_err();
+        assert!(e.to_string().starts_with("line 2, column 26: "), "{e}");
+        assert_eq!(parse_file_config("port = 3000").unwrap().port, Some(3000));
+    }
+
+    #[test]
+    fn test_to_toml() {
+        let app_config = AppConfig {
+            admin_token: Some("hunter2".to_string()),
+            suggestion_provider: SuggestionProvider::Google,
+            ..AppConfig::default()
+        };
+        let toml = app_config.to_toml().unwrap();
+        assert!(
+            toml.contains("suggestion_provider = \"google\"\n"),
+            "{toml}"
+        );
+        assert!(
+            !toml.contains("hunter2") && !toml.contains("[[bangs]]"),
+            "{toml}"
+        );
+        assert!(
+            toml.contains("\n[ui]\npopular_bangs = true\npage_size = 200\n"),
+            "{toml}"
+        );
+        let shown: AppConfig = toml::from_str(&toml).unwrap();
+        assert_eq!(shown.ui, app_config.ui);
+    }
+
+    #[test]
+    fn test_ui_config() {
+        let parse = |toml: &str| anyhow::Ok(parse_file_config(toml)?.merge(Config::default()));
+
+        assert_eq!(parse("").unwrap().ui, UiConfig::default());
+        // keys missing from the table keep their defaults
+        let ui = parse("[ui]\npage_size = 50").unwrap().ui;
+        assert_eq!(
+            ui,
+            UiConfig {
+                page_size: 50,
+                ..UiConfig::default()
+            }
+        );
+        let ui = parse("[ui]\npopular_bangs = false\nlanding_html_path = \"/srv/landing.html\"")
+            .unwrap()
+            .ui;
+        assert!(!ui.popular_bangs);
+        assert_eq!(
+            ui.landing_html_path,
+            Some(PathBuf::from("/srv/landing.html"))
+        );
+        assert_eq!(ui.page_size, GROUP_PAGE_SIZE);
+        parse("[ui]\npage_size = \"many\"").unwrap_err();
+    }
+
+    #[test]
+    fn test_deprecated_ui_keys() {
+        let mut file_config: FileConfig =
+            toml::from_str("popular_bangs = false\nlanding_html_path = \"/srv/landing.html\"")
+                .unwrap();
+        let warnings = file_config.migrate_ui();
+        assert_eq!(
+            warnings,
+            [
+                "landing_html_path is deprecated, move it into the [ui] table",
+                "popular_bangs is deprecated, move it into the [ui] table"
+            ]
+        );
+        let ui = file_config.merge(Config::default()).ui;
+        assert!(!ui.popular_bangs);
+        assert_eq!(
+            ui.landing_html_path,
+            Some(PathBuf::from("/srv/landing.html"))
+        );
+
+        // the table takes precedence over the old keys
+        let mut file_config: FileConfig =
+            toml::from_str("popular_bangs = false\n[ui]\npage_size = 50").unwrap();
+        let warnings = file_config.migrate_ui();
+        assert_eq!(
+            warnings,
+            ["popular_bangs is deprecated and ignored as there is a [ui] table, move it there"]
+        );
+        let ui = file_config.merge(Config::default()).ui;
+        assert!(ui.popular_bangs);
+        assert_eq!(ui.page_size, 50);
+
+        let mut file_config: FileConfig = toml::from_str("port = 3000").unwrap();
+        assert_eq!(file_config.migrate_ui(), Vec::<String>::new());
+        assert_eq!(file_config.ui, None);
+    }
+
+    #[test]
+    fn test_suggestion_provider() {
+        let parse = |toml: &str, cli_template: Option<&str>| {
+            let cli = Config {
+                port: None,
+                ip: None,
+                bangs_url: None,
+                default_search: None,
+                search_suggestions: cli_template.map(str::to_string),
+                config_path: None,
+            };
+            anyhow::Ok(parse_file_config(toml)?.merge(cli))
+        };
+        let custom = "https://suggest.example.com/?q={}";
+
+        let config = parse("", None).unwrap();
+        assert_eq!(config.suggestion_provider, SuggestionProvider::Brave);
+        assert_eq!(
+            config.search_suggestions,
+            AppConfig::default().search_suggestions
+        );
+        let config = parse(r#"suggestion_provider = "duckduckgo""#, None).unwrap();
+        assert_eq!(config.suggestion_provider, SuggestionProvider::DuckDuckGo);
+        assert_eq!(config.search_suggestions, "https://duckduckgo.com/ac/?q={}");
+        // a template alone keeps working as before
+        let config = parse(&format!("search_suggestions = \"{custom}\""), None).unwrap();
+        assert_eq!(
+            (
+                config.suggestion_provider,
+                config.search_suggestions.as_str()
+            ),
+            (SuggestionProvider::Custom, custom)
+        );
+        // a preset wins over the template of the file, not over the command line
+        let toml = format!("suggestion_provider = \"qwant\"\nsearch_suggestions = \"{custom}\"");
+        let config = parse(&toml, None).unwrap();
+        assert_eq!(config.suggestion_provider, SuggestionProvider::Qwant);
+        let config = parse(&toml, Some(custom)).unwrap();
+        assert_eq!(
+            (
+                config.suggestion_provider,
+                config.search_suggestions.as_str()
+            ),
+            (SuggestionProvider::Custom, custom)
+        );
+
+        let e = parse(r#"suggestion_provider = "custom""#, None).unwrap_err();
+        assert!(
+            e.to_string()
+                .contains("needs a search_suggestions template"),
+            "{e}"
+        );
+        parse(r#"suggestion_provider = "bing""#, None).unwrap_err();
+    }
+
+    #[test]
+    fn test_problems() {
+        assert_eq!(AppConfig::default().problems(), []);
+        let problems = |config: AppConfig| {
+            config
+                .problems()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        for (config, problem) in [
+            (
+                AppConfig {
+                    default_search: "https://example.com/?q=".to_string(),
+                    ..AppConfig::default()
+                },
+                r#"default_search = "https://example.com/?q=" has no {} placeholder for the query"#,
+            ),
+            (
+                AppConfig {
+                    default_search: "https://example.com/?q={}&alt={}".to_string(),
+                    ..AppConfig::default()
+                },
+                r#"default_search = "https://example.com/?q={}&alt={}" has 2 {} placeholders, expected one"#,
+            ),
+            (
+                AppConfig {
+                    default_search: "example.com/?q={}".to_string(),
+                    ..AppConfig::default()
+                },
+                r#"default_search = "example.com/?q={}" needs an http:// or https:// scheme"#,
+            ),
+            (
+                AppConfig {
+                    mobile_default_search: Some("https://m.example.com/".to_string()),
+                    ..AppConfig::default()
+                },
+                r#"mobile_default_search = "https://m.example.com/" has no {} placeholder for the query"#,
+            ),
+            (
+                AppConfig {
+                    bangs_url: "duckduckgo.com/bang.js".to_string(),
+                    ..AppConfig::default()
+                },
+                r#"bangs_url = "duckduckgo.com/bang.js" needs an http:// or https:// scheme"#,
+            ),
+            (
+                AppConfig {
+                    bangs_url: "https://exa mple.com/bang.js".to_string(),
+                    ..AppConfig::default()
+                },
+                r#"bangs_url = "https://exa mple.com/bang.js" is not a valid URL: invalid international domain name"#,
+            ),
+            (
+                AppConfig {
+                    suggestion_provider: SuggestionProvider::Custom,
+                    search_suggestions: "https://example.com/suggest?q=".to_string(),
+                    ..AppConfig::default()
+                },
+                r#"search_suggestions = "https://example.com/suggest?q=" has no {} placeholder for the query"#,
+            ),
+            (
+                AppConfig {
+                    instance_name: Some("zürich-1".to_string()),
+                    ..AppConfig::default()
+                },
+                r#"instance_name = "zürich-1" can only contain printable ASCII characters"#,
+            ),
+            (
+                AppConfig {
+                    ui: UiConfig {
+                        page_size: 5,
+                        ..UiConfig::default()
+                    },
+                    ..AppConfig::default()
+                },
+                r#"ui.page_size = "5" must be between 10 and 5000"#,
+            ),
+            (
+                AppConfig {
+                    ui: UiConfig {
+                        custom_css_path: Some(PathBuf::from("/nonexistent/style.css")),
+                        ..UiConfig::default()
+                    },
+                    ..AppConfig::default()
+                },
+                r#"ui.custom_css_path = "/nonexistent/style.css" is no file"#,
+            ),
+        ] {
+            assert_eq!(problems(config), [problem]);
+        }
+
+        // literal braces are no placeholders
+        let config = AppConfig {
+            default_search: "https://example.com/?filter={{}}&q=".to_string(),
+            ..AppConfig::default()
+        };
+        assert_eq!(
+            config.problems()[0].problem,
+            "has no {} placeholder for the query"
+        );
+        // fixed pages are fine if that is deliberate
+        let bookmark = AppConfig {
+            default_search_bookmark: true,
+            mobile_default_search: Some("https://m.example.com/".to_string()),
+            ..config
+        };
+        assert_eq!(bookmark.problems(), []);
+        // the template of built-in providers isn't checked
+        let config = AppConfig {
+            search_suggestions: String::new(),
+            ..AppConfig::default()
+        };
+        assert_eq!(config.problems(), []);
+    }
+
+    #[test]
+    fn test_quota_warnings() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(&config_path, "x".repeat(899)).unwrap();
+        let bang = test_bang("w", "https://en.wikipedia.org/w/index.php?search={{{s}}}");
+        let config = AppConfig {
+            bangs: Some(vec![bang; 8]),
+            max_custom_bangs: 10,
+            max_config_file_size: 1000,
+            config_path: config_path.clone(),
+            ..AppConfig::default()
+        };
+        assert_eq!(config.quota_warnings(), Vec::<String>::new());
+
+        std::fs::write(&config_path, "x".repeat(900)).unwrap();
+        let mut bangs = config.bangs.clone().unwrap();
+        bangs.push(bangs[0].clone());
+        let config = AppConfig {
+            bangs: Some(bangs),
+            ..config
+        };
+        let warnings = config.quota_warnings();
+        assert_eq!(warnings.len(), 2, "{warnings:?}");
+        assert!(
+            warnings[0].starts_with("9 bangs are configured"),
+            "{warnings:?}"
+        );
+        assert!(
+            warnings[0].ends_with("max_custom_bangs = 10"),
+            "{warnings:?}"
+        );
+        assert!(warnings[1].contains("has 900 bytes"), "{warnings:?}");
+        assert!(
+            warnings[1].ends_with("max_config_file_size = 1000"),
+            "{warnings:?}"
+        );
+    }
+
+    #[test]
+    fn test_is_mobile() {
+        let config = AppConfig::default();
+        for (user_agent, mobile) in [
+            (
+                "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) \
+                 Chrome/126.0.0.0 Mobile Safari/537.36",
+                true,
+            ),
+            (
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 \
+                 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1",
+                true,
+            ),
+            (
+                "Mozilla/5.0 (iPad; CPU OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like \
+                 Gecko) Version/17.5 Mobile/15E148 Safari/604.1",
+                true,
+            ),
+            (
+                "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0",
+                false,
+            ),
+            (
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, \
+                 like Gecko) Version/17.5 Safari/605.1.15",
+                false,
+            ),
+            ("curl/8.8.0", false),
+        ] {
+            assert_eq!(
+                config.is_mobile(None, Some(user_agent)),
+                mobile,
+                "{user_agent}"
+            );
+        }
+        assert!(!config.is_mobile(None, None));
+
+        // the cookie takes precedence
+        let android = Some("Mozilla/5.0 (Linux; Android 14; Pixel 8)");
+        let firefox = Some("Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Firefox/128.0");
+        assert!(config.is_mobile(Some("theme=dark; mobile=1"), firefox));
+        assert!(config.is_mobile(Some("mobile=1"), None));
+        assert!(!config.is_mobile(Some("mobile=0; theme=dark"), android));
+        // other values and similar names are ignored
+        assert!(config.is_mobile(Some("mobile=yes; not_mobile=0"), android));
+        assert!(!config.is_mobile(Some("mobile=yes"), firefox));
+
+        let config = AppConfig {
+            mobile_user_agents: vec!["kindle".to_string()],
+            ..AppConfig::default()
+        };
+        assert!(config.is_mobile(None, Some("Mozilla/5.0 (Linux; U; en-US) Kindle/3.0")));
+        assert!(!config.is_mobile(None, android));
+    }
+
+    #[test]
+    fn test_listen() {
+        let parse = |toml: &str| {
+            let mut file_config: FileConfig = toml::from_str(toml)?;
+            file_config.apply_listen()?;
+            anyhow::Ok(file_config.merge(Config::default()))
+        };
+        let config = parse(r#"listen = "0.0.0.0:3001""#).unwrap();
+        assert_eq!((config.ip, config.port), (IpAddr::from([0, 0, 0, 0]), 3001));
+        let config = parse(r#"listen = "[::1]:8080""#).unwrap();
+        assert_eq!(
+            (config.ip.to_string(), config.port),
+            ("::1".to_string(), 8080)
+        );
+        // repeating the same values is fine
+        let config = parse("listen = \"127.0.0.1:8080\"\nport = 8080").unwrap();
+        assert_eq!(config.port, 8080);
+
+        let e = parse("listen = \"127.0.0.1:8080\"\nport = 9090").unwrap_err();
+        assert!(
+            e.to_string().contains("conflicts with the port 9090"),
+            "{e}"
+        );
+        let e = parse("listen = \"127.0.0.1:8080\"\nip = \"::1\"").unwrap_err();
+        assert!(e.to_string().contains("conflicts with the IP ::1"), "{e}");
+        for listen in [
+            "localhost:3000",
+            "127.0.0.1",
+            "::1:8080",
+            "unix:/run/redirector.sock",
+        ] {
+            let e = parse(&format!("listen = \"{listen}\"")).unwrap_err();
+            assert!(e.to_string().contains(&format!("'{listen}'")), "{e}");
+        }
+
+        // CLI options still take precedence over the file
+        let mut file_config: FileConfig = toml::from_str(r#"listen = "0.0.0.0:3001""#).unwrap();
+        file_config.apply_listen().unwrap();
+        let config = file_config.merge(Config {
+            port: Some(4000),
+            ..Config::default()
+        });
+        assert_eq!((config.ip, config.port), (IpAddr::from([0, 0, 0, 0]), 4000));
+    }
+}
@@ -0,0 +1,285 @@
+//! Comparing the bangs in effect with another bang list.
+//!
+//! Before pointing `bangs_url` somewhere else, or after the upstream list
+//! changed, it helps to see which triggers would appear, disappear or lead
+//! somewhere else. The other list is loaded like the configured one would be,
+//! with the configured bangs applied, so only differences of the lists
+//! themselves show up.
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+
+use anyhow::{Context as _, anyhow};
+use serde::Serialize;
+
+use crate::bang::Bang;
+use crate::bang_groups::group_names;
+use crate::bookmarks::ImportFormat;
+use crate::cache::effective_cache;
+use crate::config::AppConfig;
+use crate::gzip::{MAX_DECOMPRESSED_SIZE, maybe_gunzip};
+use crate::resolver::BangCache;
+use crate::source::fetch_bang_file;
+
+/// Number of bangs listed per kind of difference unless all are asked for.
+pub const SUMMARY_EXAMPLES: usize = 10;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DiffBang {
+    pub trigger: String,
+    pub url_template: String,
+}
+
+/// A trigger both sides have, with different templates.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChangedBang {
+    pub trigger: String,
+    pub current: String,
+    pub other: String,
+}
+
+/// The differences between two sets of bangs, each sorted by trigger.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BangDiff {
+    pub only_current: Vec<DiffBang>,
+    pub only_other: Vec<DiffBang>,
+    pub changed: Vec<ChangedBang>,
+}
+
+impl BangDiff {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.only_current.is_empty() && self.only_other.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Whether `bang` is in the category named `category`, ignoring case.
+fn in_category(bang: &Bang, category: &str) -> bool {
+    group_names(bang).0.eq_ignore_ascii_case(category)
+}
+
+/// The differences from `current` to `other`, only of bangs in `category` on
+/// either side if given.
+#[must_use]
+pub fn diff(current: &BangCache, other: &BangCache, category: Option<&str>) -> BangDiff {
+    let included = |bang: &Bang| category.is_none_or(|category| in_category(bang, category));
+    let triggers: BTreeSet<&String> = current.keys().chain(other.keys()).collect();
+    let mut diff = BangDiff::default();
+    for trigger in triggers {
+        let entry = |bang: &Bang| {
+            DiffBang {
+                trigger: trigger.clone(),
+                url_template: bang.url_template.clone(),
+            }
+        };
+        match (current.get(trigger), other.get(trigger)) {
+            (Some(current), None) if included(&current.bang) => {
+                diff.only_current.push(entry(&current.bang));
+            },
+            (None, Some(other)) if included(&other.bang) => {
+                diff.only_other.push(entry(&other.bang));
+            },
+            (Some(current), Some(other))
+                if current.bang.url_template != other.bang.url_template
+                    && (included(&current.bang) || included(&other.bang)) =>
+            {
+                diff.changed.push(ChangedBang {
+                    trigger: trigger.clone(),
+                    current: current.bang.url_template.clone(),
+                    other: other.bang.url_template.clone(),
+                });
+            },
+            _ => {},
+        }
+    }
+    diff
+}
+
+/// `diff` as text, comparing with `other_name`. Without `full` only the first
+/// [`SUMMARY_EXAMPLES`] bangs of each kind are listed.
+#[must_use]
+pub fn render_diff(diff: &BangDiff, other_name: &str, full: bool) -> String {
+    fn section<T>(
+        text: &mut String,
+        title: &str,
+        items: &[T],
+        full: bool,
+        line: impl Fn(&mut String, &T),
+    ) {
+        writeln!(text, "{title} ({}):", items.len()).expect("Failed to write to diff string");
+        let shown = if full {
+            items.len()
+        } else {
+            items.len().min(SUMMARY_EXAMPLES)
+        };
+        for item in &items[..shown] {
+            line(text, item);
+        }
+        if shown < items.len() {
+            writeln!(
+                text,
+                "  ... and {} more, --full lists all",
+                items.len() - shown
+            )
+            .expect("Failed to write to diff string");
+        }
+    }
+    let bang_line = |text: &mut String, bang: &DiffBang| {
+        writeln!(text, "  !{:<12} {}", bang.trigger, bang.url_template)
+            .expect("Failed to write to diff string");
+    };
+
+    let mut text = String::new();
+    section(
+        &mut text,
+        "Only in the current bangs",
+        &diff.only_current,
+        full,
+        bang_line,
+    );
+    section(
+        &mut text,
+        &format!("Only in {other_name}"),
+        &diff.only_other,
+        full,
+        bang_line,
+    );
+    section(
+        &mut text,
+        "Different templates",
+        &diff.changed,
+        full,
+        |text, changed| {
+            writeln!(
+                text,
+                "  !{:<12} {}\n   {:<12} {}",
+                changed.trigger, changed.current, "->", changed.other
+            )
+            .expect("Failed to write to diff string");
+        },
+    );
+    text
+}
+
+/// Whether `source` is a URL rather than a file path.
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// The bangs `source` would put in effect as the bang list, downloaded if
+/// it's a URL and read from disk otherwise, in `format` or the detected one.
+///
+/// # Errors
+/// If the source can't be read or parsed, or its triggers collide with
+/// `bang_collisions` set to `error`.
+pub async fn load_other(
+    app_config: &AppConfig,
+    source: &str,
+    format: Option<ImportFormat>,
+) -> anyhow::Result<BangCache> {
+    let contents = if is_url(source) {
+        fetch_bang_file(app_config, source).await?
+    } else {
+        std::fs::read(source).with_context(|| format!("Failed to read '{source}'"))?
+    };
+    let contents = maybe_gunzip(&contents, MAX_DECOMPRESSED_SIZE)
+        .with_context(|| format!("Failed to decompress '{source}'"))?;
+    let format = format
+        .or_else(|| ImportFormat::detect(&contents))
+        .ok_or_else(|| {
+            anyhow!("Unknown format of '{source}', neither a JSON list nor HTML, try --format")
+        })?;
+    let (mut bangs, _) = format
+        .parse(&contents)
+        .with_context(|| format!("Invalid bang list in '{source}'"))?;
+    for bang in &mut bangs {
+        bang.normalize();
+    }
+    Ok(effective_cache(bangs, app_config)?.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn fixture(app_config: &AppConfig, name: &str) -> BangCache {
+        load_other(
+            app_config,
+            &format!("{}/res/{name}", env!("CARGO_MANIFEST_DIR")),
+            None,
+        )
+        .await
+        .unwrap()
+    }
+
+    fn bang(trigger: &str, url_template: &str) -> DiffBang {
+        DiffBang {
+            trigger: trigger.to_string(),
+            url_template: url_template.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_diff() {
+        let app_config = AppConfig::default();
+        let current = fixture(&app_config, "diff-current.json").await;
+        let other = fixture(&app_config, "diff-other.json").await;
+
+        let all = diff(&current, &other, None);
+        assert_eq!(
+            all.only_current,
+            [
+                bang("rs", "https://docs.rs/releases/search?query={{{s}}}"),
+                bang("yt", "https://www.youtube.com/results?search_query={{{s}}}"),
+            ]
+        );
+        assert_eq!(
+            all.only_other,
+            [
+                bang("crates", "https://crates.io/search?q={{{s}}}"),
+                bang("hn", "https://hn.algolia.com/?q={{{s}}}"),
+            ]
+        );
+        let changed: Vec<&str> = all.changed.iter().map(|c| c.trigger.as_str()).collect();
+        assert_eq!(changed, ["a", "w"]);
+        assert_eq!(all.changed[0].other, "https://www.amazon.de/s?k={{{s}}}");
+        assert!(diff(&current, &current, None).is_empty());
+
+        let tech = diff(&current, &other, Some("tech"));
+        assert_eq!(tech.only_current.len(), 1);
+        assert_eq!(tech.only_other.len(), 1);
+        assert!(tech.changed.is_empty(), "gh is the same");
+        let news = diff(&current, &other, Some("News"));
+        assert_eq!(
+            news.only_other,
+            [bang("hn", "https://hn.algolia.com/?q={{{s}}}")]
+        );
+        assert!(news.only_current.is_empty() && news.changed.is_empty());
+
+        let text = render_diff(&all, "other.json", false);
+        assert!(text.starts_with("Only in the current bangs (2):\n  !rs "));
+        assert!(text.contains("Only in other.json (2):\n  !crates "));
+        assert!(text.contains(
+            "Different templates (2):\n  !a            https://www.amazon.com/s?k={{{s}}}\n   \
+             ->           https://www.amazon.de/s?k={{{s}}}\n"
+        ));
+    }
+
+    #[test]
+    fn test_summary() {
+        let diff = BangDiff {
+            only_other: (0..25)
+                .map(|i| bang(&format!("b{i:02}"), "https://example.com/?q={{{s}}}"))
+                .collect(),
+            ..BangDiff::default()
+        };
+        let summary = render_diff(&diff, "other", false);
+        assert!(summary.contains("Only in other (25):"));
+        assert!(summary.contains("!b09 "));
+        assert!(!summary.contains("!b10 "));
+        assert!(summary.contains("  ... and 15 more, --full lists all\n"));
+        let full = render_diff(&diff, "other", true);
+        assert!(full.contains("!b24 "));
+        assert!(!full.contains("more"));
+    }
+}
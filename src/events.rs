@@ -0,0 +1,132 @@
+//! The most recent warnings and errors, shown on the `/admin` page.
+//!
+//! The [`RecentEventsLayer`] of [`RecentEvents`] is added to the tracing
+//! subscriber and keeps the last [`CAPACITY`] `WARN` and `ERROR` events in
+//! memory, so problems can be spotted without access to the logs.
+
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use parking_lot::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+
+/// Number of events kept, older ones are dropped.
+pub const CAPACITY: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentEvent {
+    pub at: SystemTime,
+    pub level: Level,
+    /// Module the event was logged in.
+    pub target: String,
+    /// The message followed by the other fields of the event.
+    pub message: String,
+}
+
+/// Ring of the last [`CAPACITY`] warnings and errors, shared by its clones.
+#[derive(Debug, Clone, Default)]
+pub struct RecentEvents(Arc<Mutex<VecDeque<RecentEvent>>>);
+
+impl RecentEvents {
+    /// Keep `event`, dropping the oldest one if the ring is full.
+    pub fn push(&self, event: RecentEvent) {
+        let mut events = self.0.lock();
+        if events.len() == CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// The kept events, the most recent first.
+    #[must_use]
+    pub fn recent(&self) -> Vec<RecentEvent> {
+        self.0.lock().iter().rev().cloned().collect()
+    }
+
+    /// A layer recording the warnings and errors of a subscriber in here.
+    #[must_use]
+    pub fn layer(&self) -> RecentEventsLayer {
+        RecentEventsLayer(self.clone())
+    }
+}
+
+/// Records `WARN` and `ERROR` events in [`RecentEvents`].
+#[derive(Debug, Clone)]
+pub struct RecentEventsLayer(RecentEvents);
+
+impl<S: Subscriber> Layer<S> for RecentEventsLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        // more verbose levels are greater
+        if *metadata.level() > Level::WARN {
+            return;
+        }
+        let mut message = MessageVisitor::default();
+        event.record(&mut message);
+        self.0.push(RecentEvent {
+            at: SystemTime::now(),
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            message: message.0,
+        });
+    }
+}
+
+/// Formats the fields of an event like the fmt layer does, the message first.
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let separator = if self.0.is_empty() { "" } else { " " };
+        if field.name() == "message" {
+            self.0.insert_str(0, &format!("{value:?}{separator}"));
+        } else {
+            write!(self.0, "{separator}{}={value:?}", field.name())
+                .expect("Failed to write to string");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{error, info, warn};
+    use tracing_subscriber::layer::SubscriberExt as _;
+
+    use super::*;
+
+    #[test]
+    fn test_recent_events() {
+        let events = RecentEvents::default();
+        let subscriber = tracing_subscriber::registry().with(events.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            for i in 0..=CAPACITY {
+                error!("error {i}");
+            }
+        });
+
+        let recent = events.recent();
+        assert_eq!(recent.len(), CAPACITY);
+        assert_eq!(recent[0].message, format!("error {CAPACITY}"));
+        assert_eq!(recent[0].level, Level::ERROR);
+        // the oldest one was dropped
+        assert_eq!(recent[CAPACITY - 1].message, "error 1");
+        assert!(recent.iter().all(|event| event.target == module_path!()));
+
+        let events = RecentEvents::default();
+        let subscriber = tracing_subscriber::registry().with(events.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            info!("not kept");
+            warn!(attempt = 2, "Failed to fetch bangs");
+        });
+        let recent = events.recent();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].message, "Failed to fetch bangs attempt=2");
+        assert_eq!(recent[0].level, Level::WARN);
+    }
+}
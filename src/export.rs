@@ -0,0 +1,246 @@
+use clap::ValueEnum;
+use serde_json::{Value, json};
+
+use crate::bang::Bang;
+
+/// Browser formats the bangs can be exported to.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExportFormat {
+    /// Firefox enterprise policy (`policies.json`) with one search engine per
+    /// bang
+    FirefoxSearchjson,
+    /// Chrome `SiteSearchSettings` policy with one site search per bang
+    Chrome,
+}
+
+/// Build the URL template a browser should use for the given trigger.
+///
+/// The browser substitutes `{searchTerms}` and the resulting query is routed
+/// back through redirector in `query_param`, so the bang is resolved the same
+/// way as if it was typed manually.
+#[must_use]
+pub fn search_url(base_url: &str, query_param: &str, trigger: &str) -> String {
+    format!(
+        "{}/?{}=%21{}%20{{searchTerms}}",
+        base_url.trim_end_matches('/'),
+        urlencoding::encode(query_param),
+        urlencoding::encode(trigger)
+    )
+}
+
+/// Export the given bangs as search engines importable by a browser.
+///
+/// Notes are kept as the engine description where the format has one.
+///
+/// # Errors
+/// If the resulting document cannot be serialized.
+pub fn export<'a, I>(
+    base_url: &str,
+    query_param: &str,
+    bangs: I,
+    format: ExportFormat,
+) -> anyhow::Result<String>
+where
+    I: IntoIterator<Item = &'a Bang>,
+{
+    let base_url = base_url.trim_end_matches('/');
+    let document = match format {
+        ExportFormat::FirefoxSearchjson => {
+            let engines: Vec<Value> = bangs
+                .into_iter()
+                .map(|bang| {
+                    let trigger = &bang.trigger;
+                    let mut engine = json!({
+                        "Name": format!("!{trigger}"),
+                        "URLTemplate": search_url(base_url, query_param, trigger),
+                        "Method": "GET",
+                        "IconURL": format!("{base_url}/favicon.ico"),
+                        "Alias": trigger,
+                    });
+                    if let Some(note) = &bang.note {
+                        engine["Description"] = Value::from(note.as_str());
+                    }
+                    engine
+                })
+                .collect();
+            json!({ "policies": { "SearchEngines": { "Add": engines } } })
+        },
+        ExportFormat::Chrome => {
+            let engines: Vec<Value> = bangs
+                .into_iter()
+                .map(|bang| {
+                    let trigger = &bang.trigger;
+                    json!({
+                        "name": format!("!{trigger}"),
+                        "shortcut": trigger,
+                        "url": search_url(base_url, query_param, trigger),
+                    })
+                })
+                .collect();
+            json!({ "SiteSearchSettings": engines })
+        },
+    };
+    Ok(serde_json::to_string_pretty(&document)?)
+}
+
+/// List the bangs for fuzzy pickers like fzf, one per line.
+///
+/// Every line holds the trigger, the short name (empty if unknown) and the
+/// URL template with `{}` in place of the search term, separated by tabs. This
+/// is an interface for scripts: no header and the column order stays as is.
+#[must_use]
+pub fn pick_lines<'a, I>(bangs: I) -> String
+where
+    I: IntoIterator<Item = &'a Bang>,
+{
+    // keep the columns intact
+    let clean = |field: &str| field.replace(['\t', '\n', '\r'], " ");
+    let mut lines = String::new();
+    for bang in bangs {
+        let template = if bang.url_template.contains("{{{s}}}") {
+            bang.url_template.replace("{{{s}}}", "{}")
+        } else {
+            format!("{}{{}}", bang.url_template)
+        };
+        lines.push_str(&clean(&bang.trigger));
+        lines.push('\t');
+        lines.push_str(&clean(bang.short_name.as_deref().unwrap_or_default()));
+        lines.push('\t');
+        lines.push_str(&clean(&template));
+        lines.push('\n');
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bang::test_bang;
+    use crate::resolver::{build_cache, sorted_bangs};
+
+    const BASE_URL: &str = "http://127.0.0.1:3000/";
+    const TRIGGERS: [&str; 3] = ["g", "gh", "c++"];
+
+    fn bangs() -> Vec<Bang> {
+        TRIGGERS
+            .iter()
+            .map(|trigger| {
+                Bang {
+                    trigger: (*trigger).to_string(),
+                    note: (*trigger == "gh").then(|| "from the CI".to_string()),
+                    ..Bang::default()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_export_firefox() {
+        let exported = export(BASE_URL, "q", &bangs(), ExportFormat::FirefoxSearchjson).unwrap();
+        let document: Value = serde_json::from_str(&exported).unwrap();
+        let engines = document["policies"]["SearchEngines"]["Add"]
+            .as_array()
+            .unwrap();
+
+        assert_eq!(
+            engines.len(),
+            TRIGGERS.len(),
+            "expected one engine per bang"
+        );
+        assert_eq!(engines[0]["Alias"], "g");
+        assert!(engines[0].get("Description").is_none(), "g has no note");
+        assert_eq!(engines[1]["Description"], "from the CI");
+        assert_eq!(
+            engines[0]["URLTemplate"],
+            "http://127.0.0.1:3000/?q=%21g%20{searchTerms}"
+        );
+        assert_eq!(
+            engines[2]["URLTemplate"],
+            "http://127.0.0.1:3000/?q=%21c%2B%2B%20{searchTerms}"
+        );
+    }
+
+    #[test]
+    fn test_export_chrome() {
+        let exported = export(BASE_URL, "q", &bangs(), ExportFormat::Chrome).unwrap();
+        let document: Value = serde_json::from_str(&exported).unwrap();
+        let engines = document["SiteSearchSettings"].as_array().unwrap();
+
+        assert_eq!(
+            engines.len(),
+            TRIGGERS.len(),
+            "expected one engine per bang"
+        );
+        assert_eq!(engines[1]["shortcut"], "gh");
+        assert_eq!(
+            engines[1]["url"],
+            "http://127.0.0.1:3000/?q=%21gh%20{searchTerms}"
+        );
+
+        let exported = export(BASE_URL, "search", &bangs(), ExportFormat::Chrome).unwrap();
+        let document: Value = serde_json::from_str(&exported).unwrap();
+        assert_eq!(
+            document["SiteSearchSettings"][1]["url"],
+            "http://127.0.0.1:3000/?search=%21gh%20{searchTerms}",
+            "the search term goes into query_param"
+        );
+    }
+
+    #[test]
+    fn test_stable_order() {
+        let bang = |trigger: &str| {
+            test_bang(
+                trigger,
+                &format!("https://{trigger}.example.com/?q={{{{{{s}}}}}}"),
+            )
+        };
+        let remote = ["yt", "a", "w", "gh", "zz"].map(bang);
+        let configured = ["z", "b", "gh"].map(bang);
+        let mut reversed_remote = remote.to_vec();
+        reversed_remote.reverse();
+        let mut reversed_configured = configured.to_vec();
+        reversed_configured.reverse();
+
+        let export_all = |cache: &crate::BangCache, format| {
+            let bangs = sorted_bangs(cache)
+                .into_iter()
+                .map(|(_, cached)| &cached.bang);
+            export(BASE_URL, "q", bangs, format).unwrap()
+        };
+        let cache = build_cache(remote.to_vec(), &configured);
+        let reordered = build_cache(reversed_remote, &reversed_configured);
+        let triggers: Vec<&str> = sorted_bangs(&cache)
+            .into_iter()
+            .map(|(trigger, _)| trigger)
+            .collect();
+        assert_eq!(triggers, ["b", "gh", "z", "a", "w", "yt", "zz"]);
+        for format in [ExportFormat::FirefoxSearchjson, ExportFormat::Chrome] {
+            assert_eq!(
+                export_all(&cache, format),
+                export_all(&reordered, format),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_pick_lines() {
+        let bangs = [
+            Bang {
+                short_name: Some("GitHub".to_string()),
+                ..test_bang("gh", "https://github.com/search?q={{{s}}}&type=code")
+            },
+            test_bang("w", "https://en.wikipedia.org/?search="),
+            Bang {
+                short_name: Some("Tab\tand\nnewline".to_string()),
+                ..test_bang("odd", "https://odd.example.com/{{{s}}}")
+            },
+        ];
+        assert_eq!(
+            pick_lines(&bangs),
+            "gh\tGitHub\thttps://github.com/search?q={}&type=code\nw\t\thttps://en.wikipedia.\
+             org/?search={}\nodd\tTab and newline\thttps://odd.example.com/{}\n"
+        );
+    }
+}
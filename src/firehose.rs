@@ -0,0 +1,220 @@
+//! Live events of the instance, streamed by `/api/events`.
+//!
+//! Handlers publish what happens, like redirects and bangs being added, to a
+//! broadcast channel every subscriber gets a copy from. A subscriber that
+//! can't keep up misses events instead of slowing down the server, and is told
+//! so by a [`FirehoseEvent::Gap`].
+
+use std::time::{Duration, SystemTime};
+
+use futures_util::Stream;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::Resolution;
+use crate::source::unix_seconds;
+
+/// Events buffered per subscriber, a subscriber falling further behind misses
+/// the oldest ones.
+pub const CAPACITY: usize = 1024;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum FirehoseEvent {
+    /// A search was redirected. The query and URL are left out with
+    /// `redact_queries`.
+    Redirect {
+        /// Unix timestamp of the redirect.
+        timestamp: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        query: Option<String>,
+        /// The trigger of the matched bang, without the leading '!'.
+        trigger: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+        /// How long resolving the query took in microseconds.
+        latency_us: u64,
+        /// Whether no bang matched and the default search was used.
+        fallback: bool,
+    },
+    /// A bang was added to the configured bangs.
+    BangAdded { timestamp: u64, trigger: String },
+    /// A bang was removed from the configured bangs.
+    BangRemoved { timestamp: u64, trigger: String },
+    /// The bang list was updated.
+    UpdateCompleted {
+        timestamp: u64,
+        /// URL or path the bangs were loaded from.
+        source: String,
+        entries: usize,
+        /// Whether the list differs from the one loaded before.
+        changed: bool,
+    },
+    /// The subscriber fell behind and missed `missed` events here.
+    Gap { missed: u64 },
+}
+
+impl FirehoseEvent {
+    /// The redirect of `query` to `resolution` that was resolved in `latency`,
+    /// happening now.
+    #[must_use]
+    pub fn redirect(
+        query: &str,
+        resolution: &Resolution,
+        latency: Duration,
+        redact_queries: bool,
+    ) -> Self {
+        Self::Redirect {
+            timestamp: unix_seconds(SystemTime::now()),
+            query: (!redact_queries).then(|| query.to_string()),
+            trigger: resolution.trigger.clone(),
+            url: (!redact_queries).then(|| resolution.url.clone()),
+            latency_us: u64::try_from(latency.as_micros()).unwrap_or(u64::MAX),
+            fallback: resolution.fallback,
+        }
+    }
+
+    #[must_use]
+    pub fn bang_added(trigger: &str) -> Self {
+        Self::BangAdded {
+            timestamp: unix_seconds(SystemTime::now()),
+            trigger: trigger.to_string(),
+        }
+    }
+
+    #[must_use]
+    pub fn bang_removed(trigger: &str) -> Self {
+        Self::BangRemoved {
+            timestamp: unix_seconds(SystemTime::now()),
+            trigger: trigger.to_string(),
+        }
+    }
+
+    #[must_use]
+    pub fn update_completed(source: &str, entries: usize, changed: bool) -> Self {
+        Self::UpdateCompleted {
+            timestamp: unix_seconds(SystemTime::now()),
+            source: source.to_string(),
+            entries,
+            changed,
+        }
+    }
+
+    /// The event as a line of newline delimited JSON.
+    #[must_use]
+    pub fn to_ndjson(&self) -> String {
+        let mut line = serde_json::to_string(self).expect("Failed to serialize event");
+        line.push('\n');
+        line
+    }
+
+    /// The event as a message of a `text/event-stream`, for `EventSource`.
+    #[must_use]
+    pub fn to_sse(&self) -> String {
+        format!(
+            "data: {}\n\n",
+            serde_json::to_string(self).expect("Failed to serialize event")
+        )
+    }
+}
+
+/// The channel events are published to, shared by its clones.
+#[derive(Debug, Clone)]
+pub struct Firehose(broadcast::Sender<FirehoseEvent>);
+
+impl Default for Firehose {
+    fn default() -> Self {
+        Self::with_capacity(CAPACITY)
+    }
+}
+
+impl Firehose {
+    /// A channel buffering `capacity` events per subscriber.
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(broadcast::channel(capacity).0)
+    }
+
+    /// Whether anyone is subscribed, events don't need to be built otherwise.
+    #[must_use]
+    pub fn is_watched(&self) -> bool {
+        self.0.receiver_count() > 0
+    }
+
+    /// Send `event` to every subscriber.
+    pub fn publish(&self, event: FirehoseEvent) {
+        // fails only if nobody is subscribed
+        _ = self.0.send(event);
+    }
+
+    /// The events published from now on, with a [`FirehoseEvent::Gap`] where
+    /// the subscriber fell behind.
+    pub fn subscribe(&self) -> impl Stream<Item = FirehoseEvent> + use<> {
+        futures_util::stream::unfold(self.0.subscribe(), |mut receiver| {
+            async move {
+                match receiver.recv().await {
+                    Ok(event) => Some((event, receiver)),
+                    Err(RecvError::Lagged(missed)) => {
+                        Some((FirehoseEvent::Gap { missed }, receiver))
+                    },
+                    Err(RecvError::Closed) => None,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt as _;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_slow_subscriber() {
+        let firehose = Firehose::with_capacity(2);
+        assert!(!firehose.is_watched());
+        firehose.publish(FirehoseEvent::bang_added("unseen"));
+
+        let mut events = std::pin::pin!(firehose.subscribe());
+        assert!(firehose.is_watched());
+        for trigger in ["a", "b", "c", "d"] {
+            firehose.publish(FirehoseEvent::bang_added(trigger));
+        }
+        assert_eq!(events.next().await, Some(FirehoseEvent::Gap { missed: 2 }));
+        for trigger in ["c", "d"] {
+            let Some(FirehoseEvent::BangAdded { trigger: added, .. }) = events.next().await else {
+                panic!("expected the bang {trigger} to be added");
+            };
+            assert_eq!(added, trigger);
+        }
+    }
+
+    #[test]
+    fn test_redacted_redirect() {
+        let resolution = Resolution {
+            url: "https://en.wikipedia.org/?search=secret".to_string(),
+            trigger: Some("w".to_string()),
+            origin: None,
+            note: None,
+            post: None,
+            fallback: false,
+        };
+        let latency = Duration::from_micros(42);
+        let event = FirehoseEvent::redirect("!w secret", &resolution, latency, false);
+        let line = event.to_ndjson();
+        assert!(line.ends_with("}\n"), "{line}");
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["event"], "redirect");
+        assert_eq!(json["query"], "!w secret");
+        assert_eq!(json["trigger"], "w");
+        assert_eq!(json["latency_us"], 42);
+
+        let redacted = FirehoseEvent::redirect("!w secret", &resolution, latency, true).to_sse();
+        assert!(redacted.starts_with("data: {") && redacted.ends_with("}\n\n"));
+        assert!(!redacted.contains("secret"), "{redacted}");
+        assert!(redacted.contains(r#""trigger":"w""#), "{redacted}");
+    }
+}
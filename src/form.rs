@@ -0,0 +1,108 @@
+//! Decoding of the `q` parameter, the same way for every client.
+//!
+//! Clients disagree on how a search term ends up in the URL: Chrome sends
+//! `?q=!g+c%2B%2B` for `!g c++`, while some webviews send `?q=!g%20c++`.
+//! Everything is decoded the way forms are, with a `+` as a space, unless
+//! `plus_as_space` is turned off for clients that send literal pluses.
+
+/// Decode a component of an `application/x-www-form-urlencoded` string.
+///
+/// Percent signs not followed by two hex digits are kept as they are, and
+/// bytes that aren't UTF-8 become replacement characters.
+#[must_use]
+pub fn decode(raw: &str, plus_as_space: bool) -> String {
+    let bytes = raw.as_bytes();
+    if !bytes.iter().any(|&byte| byte == b'%' || byte == b'+') {
+        return raw.to_string();
+    }
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+                if let Some(byte) = hex {
+                    decoded.push(byte);
+                    i += 2;
+                } else {
+                    decoded.push(b'%');
+                }
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded)
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+/// The decoded value of the first `name` parameter of `encoded`, a query
+/// string or form body.
+///
+/// A parameter without a `=` has an empty value.
+#[must_use]
+pub fn form_value(encoded: &str, name: &str, plus_as_space: bool) -> Option<String> {
+    encoded
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .find_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key, plus_as_space) == name).then(|| decode(value, plus_as_space))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form_value() {
+        // query string as sent, term with pluses as spaces, term with literal
+        // pluses
+        let captured: &[(&str, Option<&str>, Option<&str>)] = &[
+            // Chrome, Edge
+            ("q=!g+c%2B%2B", Some("!g c++"), Some("!g+c++")),
+            // webviews encoding spaces but not pluses
+            ("q=!g%20c++", Some("!g c  "), Some("!g c++")),
+            // Firefox
+            (
+                "client=firefox-b-d&q=%21g+rust+lang",
+                Some("!g rust lang"),
+                Some("!g+rust+lang"),
+            ),
+            // Safari
+            ("q=!w%20caf%C3%A9", Some("!w café"), Some("!w café")),
+            // curl --data-urlencode
+            (
+                "q=!gh%20tokio%2Brt",
+                Some("!gh tokio+rt"),
+                Some("!gh tokio+rt"),
+            ),
+            ("q=%e2%9c%93+done", Some("✓ done"), Some("✓+done")),
+            ("q=100%+sure", Some("100% sure"), Some("100%+sure")),
+            ("q=50%2", Some("50%2"), Some("50%2")),
+            ("q=caf%E9", Some("caf\u{FFFD}"), Some("caf\u{FFFD}")),
+            ("q=!g+a%26b&ie=UTF-8", Some("!g a&b"), Some("!g+a&b")),
+            ("q=!g+a=b%3Dc", Some("!g a=b=c"), Some("!g+a=b=c")),
+            ("ie=UTF-8&q=first&q=second", Some("first"), Some("first")),
+            ("q=", Some(""), Some("")),
+            ("&q&", Some(""), Some("")),
+            ("ie=UTF-8&query=rust", None, None),
+        ];
+        for &(encoded, spaces, pluses) in captured {
+            assert_eq!(
+                form_value(encoded, "q", true).as_deref(),
+                spaces,
+                "{encoded}"
+            );
+            assert_eq!(
+                form_value(encoded, "q", false).as_deref(),
+                pluses,
+                "{encoded}"
+            );
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Bounded decompression of gzipped bang lists.
+//!
+//! Exported lists compress very well, so imports and file sources may be
+//! gzipped. A few kilobytes of gzip can expand to gigabytes, so everything is
+//! decompressed up to [`MAX_DECOMPRESSED_SIZE`] only.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Read as _};
+
+use flate2::read::MultiGzDecoder;
+
+/// Largest size imports and file sources may have after decompression.
+pub const MAX_DECOMPRESSED_SIZE: usize = 50 * 1024 * 1024;
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum GunzipError {
+    /// The data decompresses to more than the limit.
+    TooLarge { limit: usize },
+    /// The data is not valid gzip.
+    Corrupt(io::Error),
+}
+
+impl fmt::Display for GunzipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { limit } => {
+                write!(
+                    f,
+                    "Too large after decompression, the limit is {limit} bytes"
+                )
+            },
+            Self::Corrupt(e) => write!(f, "Corrupt gzip data: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for GunzipError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::TooLarge { .. } => None,
+            Self::Corrupt(e) => Some(e),
+        }
+    }
+}
+
+/// Whether `data` starts like gzip.
+#[must_use]
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&GZIP_MAGIC)
+}
+
+/// Decompress `data` to at most `limit` bytes.
+///
+/// # Errors
+/// If `data` isn't gzip or decompresses to more than `limit` bytes.
+pub fn gunzip(data: &[u8], limit: usize) -> Result<Vec<u8>, GunzipError> {
+    let mut decompressed = Vec::new();
+    MultiGzDecoder::new(data)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(GunzipError::Corrupt)?;
+    if decompressed.len() > limit {
+        return Err(GunzipError::TooLarge { limit });
+    }
+    Ok(decompressed)
+}
+
+/// Decompress `data` if it starts like gzip, regardless of how it was
+/// labeled, and return it as is otherwise.
+///
+/// # Errors
+/// Like [`gunzip`].
+pub fn maybe_gunzip(data: &[u8], limit: usize) -> Result<Cow<'_, [u8]>, GunzipError> {
+    if is_gzip(data) {
+        gunzip(data, limit).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use super::*;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_gunzip() {
+        let json = br#"[{"t":"w","u":"https://en.wikipedia.org/w/index.php?search={{{s}}}"}]"#;
+        let gzipped = gzip(json);
+        assert!(is_gzip(&gzipped) && !is_gzip(json));
+        assert_eq!(gunzip(&gzipped, 1024).unwrap(), json);
+        assert_eq!(maybe_gunzip(&gzipped, 1024).unwrap(), &json[..]);
+        // plain data, even if it was labeled as gzip, is passed through
+        assert!(matches!(maybe_gunzip(json, 1024), Ok(Cow::Borrowed(_))));
+
+        let bomb = gzip(&vec![0; 1024 * 1024]);
+        assert!(bomb.len() < 4096);
+        assert!(matches!(
+            gunzip(&bomb, 1024 * 1024 - 1),
+            Err(GunzipError::TooLarge { .. })
+        ));
+        assert_eq!(gunzip(&bomb, 1024 * 1024).unwrap().len(), 1024 * 1024);
+
+        let mut truncated = gzipped.clone();
+        truncated.truncate(gzipped.len() / 2);
+        for corrupt in [&json[..], &truncated, &[0x1F, 0x8B, 0, 0]] {
+            assert!(
+                matches!(gunzip(corrupt, 1024), Err(GunzipError::Corrupt(_))),
+                "{corrupt:?}"
+            );
+        }
+    }
+}
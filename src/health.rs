@@ -0,0 +1,234 @@
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::SourceMetadata;
+use crate::supervisor::TaskStatus;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// The bang cache was not loaded yet, because the first download is still
+    /// running.
+    Starting,
+    /// The bang cache was never loaded, because its first update failed.
+    Failed,
+    Healthy,
+    /// The bang cache was loaded, but its last update failed, it is older
+    /// than `max_cache_age` or a background task is down.
+    Degraded,
+}
+
+impl HealthStatus {
+    /// Derive the health from the age of the bang cache and whether its last
+    /// update failed.
+    #[must_use]
+    pub fn from_cache(
+        cache_age: Option<Duration>,
+        max_cache_age: Duration,
+        update_failed: bool,
+    ) -> Self {
+        match cache_age {
+            None if update_failed => Self::Failed,
+            None => Self::Starting,
+            Some(age) if age <= max_cache_age && !update_failed => Self::Healthy,
+            Some(_) => Self::Degraded,
+        }
+    }
+
+    #[must_use]
+    pub const fn is_healthy(self) -> bool {
+        matches!(self, Self::Healthy)
+    }
+
+    /// Whether `/healthz` answers with `200`, while healthy or still starting.
+    #[must_use]
+    pub const fn is_available(self) -> bool {
+        matches!(self, Self::Healthy | Self::Starting)
+    }
+
+    /// The name of the status, as serialized.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Starting => "starting",
+            Self::Failed => "failed",
+            Self::Healthy => "healthy",
+            Self::Degraded => "degraded",
+        }
+    }
+}
+
+/// Health information served by `/healthz` and `/metrics`.
+#[derive(Serialize, Debug, Clone)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    /// Seconds since the bang cache was last updated successfully.
+    pub cache_age_seconds: Option<u64>,
+    pub max_cache_age_seconds: u64,
+    /// Why the last update of the bang cache failed, if it did.
+    pub last_update_error: Option<String>,
+    /// Searches are answered with a 503 page.
+    pub maintenance: bool,
+    /// Where the bangs in the cache were loaded from.
+    pub sources: Vec<SourceMetadata>,
+    /// The supervised background tasks.
+    pub tasks: Vec<TaskStatus>,
+}
+
+/// Render the health report in the Prometheus text exposition format.
+#[must_use]
+pub fn render_metrics(report: &HealthReport, cache_entries: usize) -> String {
+    let mut metrics = String::new();
+    writeln!(
+        metrics,
+        "# HELP redirector_healthy Whether the bang cache is fresh enough.\n# TYPE \
+         redirector_healthy gauge\nredirector_healthy {}",
+        u8::from(report.status.is_healthy())
+    )
+    .expect("Failed to write to metrics string");
+    metrics.push_str(
+        "# HELP redirector_health Whether the health is starting, failed, healthy or degraded.\n# \
+         TYPE redirector_health gauge\n",
+    );
+    for status in [
+        HealthStatus::Starting,
+        HealthStatus::Failed,
+        HealthStatus::Healthy,
+        HealthStatus::Degraded,
+    ] {
+        writeln!(
+            metrics,
+            "redirector_health{{status=\"{}\"}} {}",
+            status.as_str(),
+            u8::from(report.status == status)
+        )
+        .expect("Failed to write to metrics string");
+    }
+    writeln!(
+        metrics,
+        "# HELP redirector_bang_cache_entries Number of bangs in the cache.\n# TYPE \
+         redirector_bang_cache_entries gauge\nredirector_bang_cache_entries {cache_entries}"
+    )
+    .expect("Failed to write to metrics string");
+    writeln!(
+        metrics,
+        "# HELP redirector_maintenance Whether searches are answered with a maintenance page.\n# \
+         TYPE redirector_maintenance gauge\nredirector_maintenance {}",
+        u8::from(report.maintenance)
+    )
+    .expect("Failed to write to metrics string");
+    if !report.tasks.is_empty() {
+        metrics.push_str(
+            "# HELP redirector_task_restarts_total How often a background task was restarted.\n# \
+             TYPE redirector_task_restarts_total counter\n",
+        );
+        for task in &report.tasks {
+            writeln!(
+                metrics,
+                "redirector_task_restarts_total{{task=\"{}\"}} {}",
+                task.name, task.restarts
+            )
+            .expect("Failed to write to metrics string");
+        }
+    }
+    if let Some(age) = report.cache_age_seconds {
+        writeln!(
+            metrics,
+            "# HELP redirector_bang_cache_age_seconds Seconds since the bang cache was last \
+             updated successfully.\n# TYPE redirector_bang_cache_age_seconds \
+             gauge\nredirector_bang_cache_age_seconds {age}"
+        )
+        .expect("Failed to write to metrics string");
+    }
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::config::{AppConfig, AppState};
+
+    #[test]
+    fn test_staleness_flips_health() {
+        let max_cache_age = Duration::from_hours(1);
+        assert_eq!(
+            HealthStatus::from_cache(Some(Duration::from_mins(59)), max_cache_age, false),
+            HealthStatus::Healthy
+        );
+        assert_eq!(
+            HealthStatus::from_cache(Some(Duration::from_mins(61)), max_cache_age, false),
+            HealthStatus::Degraded
+        );
+        assert_eq!(
+            HealthStatus::from_cache(Some(Duration::from_mins(1)), max_cache_age, true),
+            HealthStatus::Degraded
+        );
+        assert_eq!(
+            HealthStatus::from_cache(None, max_cache_age, false),
+            HealthStatus::Starting
+        );
+        assert_eq!(
+            HealthStatus::from_cache(None, max_cache_age, true),
+            HealthStatus::Failed
+        );
+
+        let app_state = AppState::new(AppConfig {
+            max_cache_age: 60 * 60,
+            ..AppConfig::default()
+        });
+        assert!(
+            !app_state.health_report().status.is_healthy(),
+            "a never loaded cache isn't healthy"
+        );
+        app_state.record_update();
+        assert!(app_state.health_report().status.is_healthy());
+
+        *app_state.last_update.write() = Instant::now().checked_sub(Duration::from_hours(2));
+        let report = app_state.health_report();
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert!(report.cache_age_seconds.unwrap() >= 2 * 60 * 60);
+        assert!(render_metrics(&report, 0).contains("redirector_healthy 0"));
+    }
+
+    #[test]
+    fn test_update_transitions() {
+        let app_state = AppState::new(AppConfig {
+            max_cache_age: 60 * 60,
+            ..AppConfig::default()
+        });
+        let report = app_state.health_report();
+        assert_eq!(report.status, HealthStatus::Starting);
+        assert_eq!(report.last_update_error, None);
+
+        // the first download failed, nothing was ever loaded
+        app_state.record_update_failure(&anyhow::anyhow!("offline"));
+        let report = app_state.health_report();
+        assert_eq!(report.status, HealthStatus::Failed);
+        assert_eq!(report.last_update_error.as_deref(), Some("offline"));
+        let metrics = render_metrics(&report, 0);
+        assert!(metrics.contains("redirector_health{status=\"failed\"} 1"));
+        assert!(metrics.contains("redirector_health{status=\"starting\"} 0"));
+
+        app_state.record_update();
+        let report = app_state.health_report();
+        assert_eq!(report.status, HealthStatus::Healthy);
+        assert_eq!(report.last_update_error, None);
+
+        // a refresh failed, the cache loaded before is still served
+        app_state.record_update_failure(&anyhow::anyhow!("timed out"));
+        let report = app_state.health_report();
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.last_update_error.as_deref(), Some("timed out"));
+        assert!(report.cache_age_seconds.is_some());
+        let metrics = render_metrics(&report, 0);
+        assert!(metrics.contains("redirector_health{status=\"degraded\"} 1"));
+        assert!(metrics.contains("redirector_health{status=\"starting\"} 0"));
+
+        app_state.record_update();
+        assert_eq!(app_state.health_report().status, HealthStatus::Healthy);
+    }
+}
@@ -0,0 +1,134 @@
+//! The languages a browser prefers, for localized bangs and default searches.
+//!
+//! Bangs and the default search can have templates by language tag, like a
+//! `de` one leading to de.wikipedia.org. Which one a request gets is decided
+//! by its `Accept-Language` header, unless a `lang` cookie pins a language,
+//! for people whose browser language isn't the one they search in.
+
+/// Most languages taken from a header, more are ignored.
+const MAX_LANGUAGES: usize = 16;
+
+/// Whether `tag` looks like a language tag, like `de` or `zh-Hant-TW`.
+fn is_language_tag(tag: &str) -> bool {
+    (1..=35).contains(&tag.len())
+        && tag.split('-').all(|subtag| {
+            (1..=8).contains(&subtag.len())
+                && subtag.bytes().all(|byte| byte.is_ascii_alphanumeric())
+        })
+}
+
+/// The languages of an `Accept-Language` header, best first.
+///
+/// Languages are ordered by their `q` value, equally weighted ones as listed.
+/// Languages with `q=0`, the `*` wildcard and anything malformed are left
+/// out.
+#[must_use]
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = Vec::new();
+    for entry in header.split(',').take(MAX_LANGUAGES) {
+        let mut parts = entry.split(';').map(str::trim);
+        let tag = parts.next().unwrap_or_default();
+        if !is_language_tag(tag) {
+            continue;
+        }
+        let mut weight = Some(1.0);
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=')
+                && name.trim().eq_ignore_ascii_case("q")
+            {
+                weight = value
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|weight| (0.0..=1.0).contains(weight));
+            }
+        }
+        let Some(weight) = weight.filter(|weight| *weight > 0.0) else {
+            continue;
+        };
+        let tag = tag.to_ascii_lowercase();
+        if !languages.iter().any(|(language, _)| *language == tag) {
+            languages.push((tag, weight));
+        }
+    }
+    // stable, so equally weighted languages keep their order
+    languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    languages
+        .into_iter()
+        .map(|(language, _)| language)
+        .collect()
+}
+
+/// The languages of a request, the one of its `lang` cookie if it has a
+/// valid one and those of its `Accept-Language` header otherwise.
+#[must_use]
+pub fn preferred_languages(cookie: Option<&str>, accept_language: Option<&str>) -> Vec<String> {
+    let pinned = cookie
+        .into_iter()
+        .flat_map(|cookie| cookie.split(';'))
+        .find_map(|pair| {
+            match pair.trim().split_once('=') {
+                Some(("lang", language)) if is_language_tag(language) => {
+                    Some(language.to_ascii_lowercase())
+                },
+                _ => None,
+            }
+        });
+    pinned.map_or_else(
+        || {
+            accept_language
+                .map(parse_accept_language)
+                .unwrap_or_default()
+        },
+        |language| vec![language],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_accept_language() {
+        for (header, languages) in [
+            ("de", &["de"][..]),
+            ("de-DE,de;q=0.9,en;q=0.8", &["de-de", "de", "en"]),
+            // ordered by weight, not position
+            ("en;q=0.5, fr;q=0.7, de", &["de", "fr", "en"]),
+            ("en;q=0.8,de;q=0.8,fr", &["fr", "en", "de"]),
+            ("en;q=1.000", &["en"]),
+            ("EN-us", &["en-us"]),
+            // unwanted, wildcard and malformed entries are left out
+            ("fr;q=0, de", &["de"]),
+            ("*, de;q=0.5", &["de"]),
+            ("de;q=2, en;q=abc, fr;q=-1, it", &["it"]),
+            ("<script>, de_DE, toolongsubtag-x, nl", &["nl"]),
+            ("de, de;q=0.1, en", &["de", "en"]),
+            ("", &[]),
+            (" , ;q=1", &[]),
+        ] {
+            assert_eq!(parse_accept_language(header), languages, "{header}");
+        }
+        let many = vec!["en"; 100].join(",") + ",de";
+        assert_eq!(parse_accept_language(&many), ["en"]);
+    }
+
+    #[test]
+    fn test_preferred_languages() {
+        let header = Some("en-US,en;q=0.9");
+        assert_eq!(preferred_languages(None, header), ["en-us", "en"]);
+        assert_eq!(
+            preferred_languages(Some("mobile=1; lang=DE"), header),
+            ["de"],
+            "the cookie overrides the header"
+        );
+        assert_eq!(
+            preferred_languages(Some("lang=; theme=dark"), header),
+            ["en-us", "en"]
+        );
+        assert_eq!(
+            preferred_languages(Some("lang=<b>"), None),
+            Vec::<String>::new()
+        );
+    }
+}
@@ -0,0 +1,543 @@
+//! Checking whether the configured bangs still lead somewhere.
+//!
+//! Sites change their search URLs over the years, so every configured bang is
+//! resolved with a harmless probe term and requested once. Only the status and
+//! where redirects end up are looked at, the pages themselves are not
+//! inspected, and nothing is changed automatically. The bang list fetched from
+//! `bangs_url` isn't checked, but bangs of it for the same domain are
+//! suggested as replacements for the failing ones.
+//!
+//! Some sites moved their search behind JavaScript or a form, a plain GET
+//! then is answered with a redirect to the homepage or `405 Method Not
+//! Allowed`. Those bangs are reported as [`LikelyBroken`], apart from bangs
+//! whose page is gone.
+
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::bail;
+use reqwest::{Client, Method, StatusCode};
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::bang::Bang;
+use crate::config::AppConfig;
+use crate::outbound::OutboundPolicy;
+use crate::resolver::{BangCache, BangOrigin, build_cache, resolve_trigger};
+
+/// Search term substituted into the templates.
+const PROBE_TERM: &str = "redirector";
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of bangs checked at the same time.
+const CHECK_CONCURRENCY: usize = 4;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BangCheck {
+    pub trigger: String,
+    /// The URL requested.
+    pub url: String,
+    /// Status of the final response, if there was one.
+    pub status: Option<u16>,
+    /// Where the redirects ended up, if the bang was redirected.
+    pub redirected_to: Option<String>,
+    /// The final response was a `404 Not Found` or `410 Gone`.
+    pub not_found: bool,
+    /// Why the template likely doesn't search anymore, even though the site
+    /// answers.
+    pub likely_broken: Option<LikelyBroken>,
+    pub error: Option<String>,
+    /// A bang of the downloaded list for the same domain, for failed checks.
+    pub replacement: Option<Replacement>,
+}
+
+/// Why a template likely doesn't search anymore.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LikelyBroken {
+    /// Redirected to a URL without the probe term, like the homepage.
+    RedirectedWithoutTerm,
+    /// `405 Method Not Allowed`, the search likely needs a POST or
+    /// JavaScript.
+    MethodNotAllowed,
+}
+
+/// A bang of the downloaded list suggested instead of a failing one.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Replacement {
+    pub trigger: String,
+    pub url_template: String,
+}
+
+impl BangCheck {
+    /// Whether the bang led to a successful response that likely shows
+    /// search results.
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.likely_broken.is_none()
+            && self
+                .status
+                .is_some_and(|status| (200..300).contains(&status))
+    }
+}
+
+/// The bangs of `cache` downloaded from `bangs_url`, the candidates for
+/// [`Replacement`]s.
+#[must_use]
+pub fn remote_bangs(cache: &BangCache) -> Vec<Bang> {
+    cache
+        .values()
+        .filter(|cached| cached.origin == BangOrigin::Remote)
+        .map(|cached| cached.bang.clone())
+        .collect()
+}
+
+/// Check all configured bangs of `app_config`, in their configured order,
+/// suggesting bangs of `remote` for the same domain as the failing ones.
+///
+/// URLs refused by `policy` aren't requested, `client` has to refuse the
+/// redirects and resolved addresses it doesn't allow.
+///
+/// # Errors
+/// In `offline` mode, as the checks need the network.
+pub async fn check_config_bangs(
+    client: &Client,
+    policy: &OutboundPolicy,
+    app_config: &AppConfig,
+    remote: &[Bang],
+) -> anyhow::Result<Vec<BangCheck>> {
+    if app_config.offline {
+        bail!("Refusing to check bangs in offline mode");
+    }
+    let bangs = app_config.bangs.as_deref().unwrap_or_default();
+    let cache = build_cache(Vec::new(), bangs);
+    let options = app_config.resolve_options();
+    let targets: Vec<(String, String)> = bangs
+        .iter()
+        .filter_map(|bang| {
+            resolve_trigger(&cache, &options, &bang.trigger, PROBE_TERM)
+                .map(|resolution| (bang.trigger.clone(), resolution.url))
+        })
+        .collect();
+    let mut checks = check_targets(client, policy, targets).await;
+    for check in checks.iter_mut().filter(|check| !check.passed()) {
+        check.replacement = bangs
+            .iter()
+            .find(|bang| bang.trigger == check.trigger)
+            .and_then(|bang| replacement(bang, remote));
+    }
+    Ok(checks)
+}
+
+/// The domain of `bang` without `www.`, from its `domain` or its template.
+fn bang_domain(bang: &Bang) -> Option<String> {
+    let domain = bang.domain.clone().or_else(|| {
+        reqwest::Url::parse(&bang.url_template.replace("{{{s}}}", ""))
+            .ok()?
+            .host_str()
+            .map(str::to_string)
+    })?;
+    let domain = domain.to_ascii_lowercase();
+    Some(
+        domain
+            .strip_prefix("www.")
+            .map_or_else(|| domain.clone(), str::to_string),
+    )
+}
+
+/// The most relevant bang of `remote` for the domain of `bang`, with another
+/// template.
+fn replacement(bang: &Bang, remote: &[Bang]) -> Option<Replacement> {
+    let domain = bang_domain(bang)?;
+    remote
+        .iter()
+        .filter(|candidate| {
+            candidate.url_template != bang.url_template
+                && bang_domain(candidate).as_deref() == Some(domain.as_str())
+        })
+        .max_by(|a, b| {
+            a.relevance
+                .cmp(&b.relevance)
+                .then_with(|| b.trigger.len().cmp(&a.trigger.len()))
+                .then_with(|| b.trigger.cmp(&a.trigger))
+        })
+        .map(|candidate| {
+            Replacement {
+                trigger: candidate.trigger.clone(),
+                url_template: candidate.url_template.clone(),
+            }
+        })
+}
+
+/// Request every `(trigger, url)`, at most [`CHECK_CONCURRENCY`] at a time.
+async fn check_targets(
+    client: &Client,
+    policy: &OutboundPolicy,
+    targets: Vec<(String, String)>,
+) -> Vec<BangCheck> {
+    let semaphore = Arc::new(Semaphore::new(CHECK_CONCURRENCY));
+    let mut tasks = JoinSet::new();
+    for (index, (trigger, url)) in targets.into_iter().enumerate() {
+        let client = client.clone();
+        let policy = policy.clone();
+        let semaphore = Arc::clone(&semaphore);
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            (index, check_url(&client, &policy, trigger, url).await)
+        });
+    }
+    let mut checks: Vec<(usize, BangCheck)> = tasks.join_all().await;
+    checks.sort_unstable_by_key(|(index, _)| *index);
+    checks.into_iter().map(|(_, check)| check).collect()
+}
+
+async fn check_url(
+    client: &Client,
+    policy: &OutboundPolicy,
+    trigger: String,
+    url: String,
+) -> BangCheck {
+    let mut check = BangCheck {
+        trigger,
+        url,
+        status: None,
+        redirected_to: None,
+        not_found: false,
+        likely_broken: None,
+        error: None,
+        replacement: None,
+    };
+    if let Err(e) = policy.check_url(&check.url) {
+        check.error = Some(e.to_string());
+        return check;
+    }
+    let mut response = request(client, Method::HEAD, &check.url).await;
+    // plenty of sites don't implement HEAD
+    if response.as_ref().is_ok_and(|response| {
+        matches!(
+            response.status(),
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+        )
+    }) {
+        response = request(client, Method::GET, &check.url).await;
+    }
+    match response {
+        Ok(response) => {
+            let status = response.status();
+            check.status = Some(status.as_u16());
+            check.not_found = matches!(status, StatusCode::NOT_FOUND | StatusCode::GONE);
+            if response.url().as_str() != check.url {
+                check.redirected_to = Some(response.url().to_string());
+                if !response.url().as_str().contains(PROBE_TERM) {
+                    check.likely_broken = Some(LikelyBroken::RedirectedWithoutTerm);
+                }
+            }
+            if status == StatusCode::METHOD_NOT_ALLOWED {
+                check.likely_broken = Some(LikelyBroken::MethodNotAllowed);
+            }
+        },
+        Err(e) => check.error = Some(e.to_string()),
+    }
+    check
+}
+
+async fn request(client: &Client, method: Method, url: &str) -> reqwest::Result<reqwest::Response> {
+    client
+        .request(method, url)
+        .timeout(CHECK_TIMEOUT)
+        .send()
+        .await
+}
+
+/// Render the checks as a table for the terminal.
+#[must_use]
+pub fn render_table(checks: &[BangCheck]) -> String {
+    let mut table = String::new();
+    for check in checks {
+        let status = check
+            .status
+            .map_or_else(|| "-".to_string(), |status| status.to_string());
+        let verdict = if check.passed() {
+            "ok"
+        } else if check.not_found {
+            "NOT FOUND"
+        } else if check.likely_broken.is_some() {
+            "LIKELY BROKEN"
+        } else {
+            "FAILED"
+        };
+        let detail = match check.likely_broken {
+            Some(LikelyBroken::RedirectedWithoutTerm) => {
+                format!(
+                    "redirected without the search term to {}",
+                    check.redirected_to.as_deref().unwrap_or_default()
+                )
+            },
+            Some(LikelyBroken::MethodNotAllowed) => {
+                "GET not allowed, the search likely needs a form or JavaScript".to_string()
+            },
+            None => {
+                check
+                    .error
+                    .as_deref()
+                    .or(check.redirected_to.as_deref())
+                    .unwrap_or(&check.url)
+                    .to_string()
+            },
+        };
+        writeln!(
+            table,
+            "!{:<12} {status:>4} {verdict:<13} {detail}",
+            check.trigger
+        )
+        .expect("Failed to write to table string");
+        if let Some(replacement) = &check.replacement {
+            writeln!(
+                table,
+                "{:<32} try !{} {}",
+                "", replacement.trigger, replacement.url_template
+            )
+            .expect("Failed to write to table string");
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use axum::Router;
+    use axum::extract::RawQuery;
+    use axum::http::StatusCode as AxumStatus;
+    use axum::response::Redirect;
+    use axum::routing::{get, post};
+
+    use super::*;
+    use crate::bang::test_bang;
+    use crate::outbound::{IpFamily, guarded_client};
+
+    #[tokio::test]
+    async fn test_check_config_bangs() {
+        let app =
+            Router::new()
+                .route("/", get(|| async { "home" }))
+                .route("/search", get(|| async { "results" }))
+                .route(
+                    "/old-search",
+                    get(|RawQuery(query): RawQuery| {
+                        async move {
+                            Redirect::permanent(&format!("/search?{}", query.unwrap_or_default()))
+                        }
+                    }),
+                )
+                .route("/gone", get(|| async { AxumStatus::NOT_FOUND }))
+                // moved behind JavaScript, plain searches land on the homepage
+                .route("/js-search", get(|| async { Redirect::to("/") }))
+                // only answers the POST of a form
+                .route("/form-search", post(|| async { "results" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut app_config = AppConfig {
+            bangs: Some(vec![
+                test_bang("healthy", &format!("http://{addr}/search?q={{{{{{s}}}}}}")),
+                test_bang(
+                    "moved",
+                    &format!("http://{addr}/old-search?q={{{{{{s}}}}}}"),
+                ),
+                test_bang("rotten", &format!("http://{addr}/gone?q={{{{{{s}}}}}}")),
+                test_bang("js", &format!("http://{addr}/js-search?q={{{{{{s}}}}}}")),
+                test_bang(
+                    "form",
+                    &format!("http://{addr}/form-search?q={{{{{{s}}}}}}"),
+                ),
+            ]),
+            ..AppConfig::default()
+        };
+        let policy = OutboundPolicy::new(&["127.0.0.1".to_string()]);
+        let client = guarded_client(policy.clone(), IpFamily::Auto, None);
+        let checks = check_config_bangs(&client, &policy, &app_config, &[])
+            .await
+            .unwrap();
+
+        let triggers: Vec<&str> = checks.iter().map(|check| check.trigger.as_str()).collect();
+        assert_eq!(triggers, ["healthy", "moved", "rotten", "js", "form"]);
+        assert_eq!(checks[0].url, format!("http://{addr}/search?q=redirector"));
+        assert_eq!(checks[0].status, Some(200));
+        assert!(checks[0].passed() && checks[0].redirected_to.is_none());
+        assert!(checks[1].passed(), "{:?}", checks[1]);
+        assert_eq!(
+            checks[1].redirected_to.as_deref(),
+            Some(format!("http://{addr}/search?q=redirector").as_str())
+        );
+        assert_eq!(checks[2].status, Some(404));
+        assert!(checks[2].not_found && !checks[2].passed());
+        assert_eq!(checks[2].likely_broken, None);
+
+        assert_eq!(checks[3].status, Some(200));
+        assert_eq!(
+            checks[3].likely_broken,
+            Some(LikelyBroken::RedirectedWithoutTerm)
+        );
+        assert!(!checks[3].passed() && !checks[3].not_found);
+        assert_eq!(checks[4].status, Some(405));
+        assert_eq!(
+            checks[4].likely_broken,
+            Some(LikelyBroken::MethodNotAllowed)
+        );
+        assert!(!checks[4].passed() && !checks[4].not_found);
+        assert!(checks.iter().all(|check| check.replacement.is_none()));
+
+        let table = render_table(&checks);
+        assert!(table.contains("NOT FOUND"), "{table}");
+        assert!(
+            table.contains("LIKELY BROKEN redirected without the search term"),
+            "{table}"
+        );
+        assert!(table.contains("LIKELY BROKEN GET not allowed"), "{table}");
+
+        app_config.offline = true;
+        if let Ok(checks) = check_config_bangs(&client, &policy, &app_config, &[]).await {
+            panic!("offline mode must refuse to check, got {checks:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replacements() {
+        let app = Router::new()
+            .route("/", get(|| async { "home" }))
+            .route("/search", get(|| async { Redirect::to("/") }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let remote_bang = |trigger: &str, url_template: &str, domain: &str, relevance| {
+            Bang {
+                domain: Some(domain.to_string()),
+                relevance: Some(relevance),
+                ..test_bang(trigger, url_template)
+            }
+        };
+        let remote = [
+            remote_bang(
+                "shop",
+                "https://shop.example.com/s?k={{{s}}}",
+                "shop.example.com",
+                10,
+            ),
+            remote_bang(
+                "shopx",
+                "https://shop.example.com/x?k={{{s}}}",
+                "www.shop.example.com",
+                50,
+            ),
+            remote_bang(
+                "other",
+                "https://other.example.com/?q={{{s}}}",
+                "other.example.com",
+                90,
+            ),
+            remote_bang(
+                "same",
+                &format!("http://{addr}/search?q={{{{{{s}}}}}}"),
+                "127.0.0.1",
+                99,
+            ),
+        ];
+        let configured = Bang {
+            domain: Some("www.shop.example.com".to_string()),
+            ..test_bang("shop", &format!("http://{addr}/search?q={{{{{{s}}}}}}"))
+        };
+        assert_eq!(
+            replacement(&configured, &remote),
+            Some(Replacement {
+                trigger: "shopx".to_string(),
+                url_template: "https://shop.example.com/x?k={{{s}}}".to_string(),
+            })
+        );
+        let unknown = test_bang("unknown", "https://unknown.example.com/?q={{{s}}}");
+        assert_eq!(replacement(&unknown, &remote), None);
+
+        let app_config = AppConfig {
+            bangs: Some(vec![configured]),
+            ..AppConfig::default()
+        };
+        let policy = OutboundPolicy::new(&["127.0.0.1".to_string()]);
+        let client = guarded_client(policy.clone(), IpFamily::Auto, None);
+        let checks = check_config_bangs(&client, &policy, &app_config, &remote)
+            .await
+            .unwrap();
+        assert_eq!(
+            checks[0].likely_broken,
+            Some(LikelyBroken::RedirectedWithoutTerm)
+        );
+        assert_eq!(
+            checks[0].replacement.as_ref().map(|r| r.trigger.as_str()),
+            Some("shopx")
+        );
+        let table = render_table(&checks);
+        assert!(
+            table.contains("try !shopx https://shop.example.com/x?k={{{s}}}"),
+            "{table}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_internal_targets() {
+        let requested = Arc::new(AtomicBool::new(false));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let location = format!("http://127.0.0.1:{port}/internal?q={PROBE_TERM}");
+        let app = Router::new()
+            .route(
+                "/search",
+                get(move || {
+                    let location = location.clone();
+                    async move { Redirect::to(&location) }
+                }),
+            )
+            .route(
+                "/internal",
+                get({
+                    let requested = Arc::clone(&requested);
+                    move || {
+                        requested.store(true, Ordering::SeqCst);
+                        async { "internal" }
+                    }
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let app_config = AppConfig {
+            bangs: Some(vec![
+                test_bang(
+                    "redirected",
+                    &format!("http://localhost:{port}/search?q={{{{{{s}}}}}}"),
+                ),
+                test_bang(
+                    "direct",
+                    &format!("http://127.0.0.1:{port}/internal?q={{{{{{s}}}}}}"),
+                ),
+            ]),
+            ..AppConfig::default()
+        };
+        let policy = OutboundPolicy::new(&["localhost".to_string()]);
+        let client = guarded_client(policy.clone(), IpFamily::V4, None);
+        let checks = check_config_bangs(&client, &policy, &app_config, &[])
+            .await
+            .unwrap();
+        for check in &checks {
+            assert!(
+                check.status.is_none() && check.error.is_some() && !check.passed(),
+                "{check:?}"
+            );
+        }
+        assert!(
+            !requested.load(Ordering::SeqCst),
+            "internal addresses must not be requested, neither directly nor after a redirect"
+        );
+    }
+}
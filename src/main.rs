@@ -1,242 +1,49 @@
 #![cfg_attr(doc, doc = include_str!("../README.md"))]
 
-use std::env;
-use std::fmt::Write as _;
+use std::fs::read_to_string;
+use std::io::IsTerminal as _;
 use std::net::SocketAddr;
-use std::process::{Command, Stdio, exit};
-use std::time::{Duration, Instant};
+use std::process::exit;
 
-use axum::extract::{Query, State};
-use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
-use axum::response::{Html, IntoResponse, Redirect};
-use axum::routing::{get, post};
-use axum::{Json, Router};
+use clap::error::ErrorKind;
 use clap::{CommandFactory as _, Parser as _};
-use clap_complete::generate;
-use heck::ToTitleCase as _;
+use clap_complete::{generate, generate_to};
+use redirector::bang::Bang;
+use redirector::browser::{OpenOptions, SystemBrowser, open_resolution};
 use redirector::cli::SubCommand::Completions;
-use redirector::cli::{Cli, SubCommand};
-use redirector::config::{AppState, append_file_config, get_file_config, reload_config};
-use redirector::{BANG_CACHE, periodic_update, resolve, update_bangs};
-use reqwest::Client;
-use serde::Deserialize;
+use redirector::cli::{BangsCommand, Cli, ConfigCommand, SubCommand};
+use redirector::compact::compact_file;
+use redirector::config::{
+    AppState, FileConfig, default_config_path, get_file_config, import_file_config_bangs,
+    parse_file_config,
+};
+use redirector::events::RecentEvents;
+use redirector::export::{export, pick_lines};
+use redirector::linkcheck::{BangCheck, check_config_bangs, remote_bangs, render_table};
+use redirector::resolver::{resolve_trigger, sorted_bangs};
+use redirector::server::{router, serve};
+use redirector::setup::{self, SetupAnswers};
+use redirector::supervisor::install_panic_hook;
+use redirector::{
+    BANG_CACHE, bench, diff, load_binary_cache, periodic_expiry, periodic_update, pin_bangs,
+    popular, refresh_bangs, resolve_with_info, selftest, stats, stdio, update_bangs,
+    update_bangs_offline,
+};
+use tokio::io::BufReader;
 use tokio::net::TcpListener;
-use tokio::time::sleep;
-use tower_http::compression::CompressionLayer;
-use tower_http::trace::TraceLayer;
 use tracing::level_filters::LevelFilter;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::layer::SubscriberExt as _;
 use tracing_subscriber::util::SubscriberInitExt as _;
 use tracing_subscriber::{EnvFilter, fmt, registry};
 
-#[derive(Debug, Deserialize)]
-struct SearchParams {
-    #[serde(rename = "q")]
-    query: Option<String>,
-}
-
-const FAVICON: &[u8] = include_bytes!("../res/icon64.png");
-
-async fn favicon() -> impl IntoResponse {
-    let mut headers = HeaderMap::new();
-    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/png"));
-    (StatusCode::OK, headers, FAVICON)
-}
-
-/// Handler function that extracts the `q` parameter and redirects accordingly
-async fn handler(
-    Query(params): Query<SearchParams>,
-    State(app_state): State<AppState>,
-) -> Redirect {
-    params.query.map_or_else(
-        || Redirect::to("/bangs"),
-        |query| {
-            let start = Instant::now();
-            let redirect_url = resolve(&app_state.get_config(), &query);
-            debug!("Request completed in {:?}", start.elapsed());
-            info!("Redirecting '{query}' to '{redirect_url}'.");
-            Redirect::to(&redirect_url)
-        },
-    )
-}
-
-async fn list_bangs(State(app_state): State<AppState>) -> Html<String> {
-    let pkg_name = env!("CARGO_PKG_NAME").to_title_case();
-    let mut html = String::from(
-        "<style>:root { background: #181818; color: #ffffff; font-family: monospace; } table { \
-         border-collapse: collapse; width: auto; } table th { text-align: left; padding: 1rem 0; \
-         font-size: 1.25rem; width: auto; } table tr { border-bottom: #ffffff10 solid 2px; } \
-         table tr:nth-child(2n) { background: #161616; } table tr:nth-child(2n+1) { background: \
-         #181818; } table td, table th { padding: 0px 8px; } table td:nth-of-type(2) { word-wrap: \
-         anywhere; }</style><html>",
-    );
-    html += format!(r#"<head><meta charset="UTF-8"><meta name="viewport" content="width=device-width, initial-scale=1.0"><link rel="search" type="application/opensearchdescription+xml" title="{pkg_name}" href="/opensearch.xml"/><link rel="icon" type="image/png" href="/favicon.ico"/><title>Bang Commands</title></head><body><h1>Bang Commands</h1>"#).as_str();
-
-    if let Some(bangs) = &app_state.get_config().bangs {
-        html.push_str("<h2>Configured Bangs</h2><table><th>Abbr.</th><th>Trigger</th><th>URL</th>");
-        for bang in bangs {
-            write!(
-                html,
-                "<tr><td><strong>{:?}</strong></td><td>{}</td><td>{}</td></tr>",
-                bang.short_name, bang.trigger, bang.url_template
-            )
-            .expect("Failed to write to HTML string");
-        }
-        html.push_str("</table>");
-    }
-
-    html.push_str("<h2>Active Bangs</h2><table><th>Trigger</th><th>URL</th>");
-    for (trigger, url_template) in BANG_CACHE.read().iter() {
-        write!(
-            html,
-            "<tr><td><strong>{trigger}</strong></td><td>{url_template}</td></tr>"
-        )
-        .expect("Failed to write to HTML string");
-    }
-    html.push_str("</ul></body></html>");
-    Html(html)
-}
-
-async fn opensearch(State(app_state): State<AppState>) -> impl IntoResponse {
-    let pkg_name = env!("CARGO_PKG_NAME");
-    let pkg_description = env!("CARGO_PKG_DESCRIPTION");
-    let app_config = app_state.get_config();
-    let opensearch_xml = format!(
-        r#"<?xml version="1.0" encoding="UTF-8"?>
-<OpenSearchDescription
-  xmlns="http://a9.com/-/spec/opensearch/1.1/"
-  xmlns:moz="http://www.mozilla.org/2006/browser/search/">
-  <ShortName>{}</ShortName>
-  <Description>{}</Description>
-  <InputEncoding>UTF-8</InputEncoding>
-  <Image height="64" width="64" type="image/png">/favicon.ico</Image>
-  <Url type="text/html" method="GET" template="http://{}:{}/?q={{searchTerms}}" />
-  <Url type="application/x-suggestions+json" method="GET" template="http://{}:{}/suggest?q={{searchTerms}}" />
-</OpenSearchDescription>"#,
-        pkg_name.to_title_case(),
-        pkg_description,
-        app_config.ip,
-        app_config.port,
-        app_config.ip,
-        app_config.port
-    );
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        header::CONTENT_TYPE,
-        HeaderValue::from_static("application/opensearchdescription+xml"),
-    );
-    (StatusCode::OK, headers, opensearch_xml)
-}
-
-async fn suggestions_proxy(
-    Query(params): Query<SearchParams>,
-    State(app_state): State<AppState>,
-) -> impl IntoResponse {
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        header::CONTENT_TYPE,
-        HeaderValue::from_static("application/json"),
-    );
-
-    if let Some(query) = params.query {
-        let suggest_api_url = app_state
-            .get_config()
-            .search_suggestions
-            .replace("{}", &query);
-
-        match Client::new().get(&suggest_api_url).send().await {
-            Ok(response) => {
-                if let Ok(json) = response.json::<serde_json::Value>().await {
-                    return (StatusCode::OK, headers, Json(json));
-                }
-            },
-            Err(e) => {
-                error!("Failed to fetch suggestions from search suggestion API: {e}");
-            },
-        }
-    }
-
-    (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        headers,
-        Json(serde_json::json!([])),
-    )
-}
-
-// endpoint to add a new bang to the config file
-async fn add_bang(
-    Query(params): Query<redirector::bang::Bang>,
-    State(app_state): State<AppState>,
-) -> impl IntoResponse {
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        header::CONTENT_TYPE,
-        HeaderValue::from_static("application/json"),
-    );
-
-    let mut config = app_state.config.write();
-    if let Some(bangs) = &mut config.bangs {
-        append_file_config(params.clone());
-        bangs.push(params.clone());
-        if let Some(mut cache) = BANG_CACHE.try_write() {
-            cache.insert(params.trigger, params.url_template);
-        }
-        return (
-            StatusCode::OK,
-            headers,
-            Json(serde_json::json!({ "status": "success" })),
-        );
-    }
-    drop(config);
-
-    (
-        StatusCode::BAD_REQUEST,
-        headers,
-        Json(serde_json::json!({ "status": "failed" })),
-    )
-}
-
-async fn reload(State(app_state): State<AppState>) -> impl IntoResponse {
-    let res = reload_config(&app_state).await;
-    match res {
-        Ok(()) => (StatusCode::OK, "Reloaded successfully! (•‿•)".into()),
-        Err(e) => {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to reload: {e}"),
-            )
-        },
-    }
-}
-
-async fn restart() -> impl IntoResponse {
-    tokio::spawn(async {
-        // give the HTTP response a moment to go out
-        sleep(Duration::from_millis(50)).await;
-
-        // collect the current executable path and args
-        let exe = env::current_exe().expect("failed to get current exe");
-        let args: Vec<String> = env::args().skip(1).collect();
-        Command::new(exe)
-            .args(&args)
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()
-            .expect("failed to spawn new process");
-        exit(0);
-    });
-
-    (
-        StatusCode::OK,
-        "Server is restarting shortly, see you soon ( ´ ▽ ` )ﾉ",
-    )
-}
-
 #[tokio::main]
 async fn main() {
-    let cli_config = Cli::parse();
+    let mut cli_config = Cli::parse();
+    if let Err(e) = cli_config.apply_listen() {
+        Cli::command().error(ErrorKind::ArgumentConflict, e).exit();
+    }
 
     let level = match cli_config.verbose {
         0 => LevelFilter::WARN,
@@ -245,39 +52,140 @@ async fn main() {
         _ => LevelFilter::TRACE,
     };
 
+    let recent_events = RecentEvents::default();
     registry()
         .with(
             EnvFilter::builder()
                 .with_default_directive(level.into())
                 .from_env_lossy(),
         )
-        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(
+            fmt::layer()
+                .with_writer(std::io::stderr)
+                // how long the steps of a resolution took, see `resolver`
+                .with_span_events(if level == LevelFilter::TRACE {
+                    FmtSpan::CLOSE
+                } else {
+                    FmtSpan::NONE
+                }),
+        )
+        .with(recent_events.layer())
         .init();
+    install_panic_hook();
 
-    let file_config = get_file_config();
+    let config_path = cli_config
+        .config
+        .clone()
+        .unwrap_or_else(default_config_path);
+    let file_config = match get_file_config(&config_path) {
+        Ok(file_config) => file_config,
+        // an explicitly given file must be used, `init` writes it and
+        // `config validate` reports what's wrong with it
+        Err(e)
+            if cli_config.config.is_some()
+                && !matches!(
+                    cli_config.command,
+                    Some(
+                        SubCommand::Config {
+                            command: ConfigCommand::Validate,
+                        } | SubCommand::Init { .. }
+                    )
+                ) =>
+        {
+            error!("{e:#}");
+            exit(1);
+        },
+        Err(_) => FileConfig::default(),
+    };
 
-    let app_config = file_config
-        .unwrap_or_default()
-        .merge(cli_config.clone().into());
+    let mut app_config = file_config.merge(cli_config.clone().into());
+    app_config.persist &= !cli_config.no_persist;
 
-    let app_state = AppState::new(app_config.clone());
+    // `config validate` reports the problems itself, completions and `init`
+    // don't need a working configuration
+    let checked = !matches!(
+        cli_config.command,
+        Some(
+            SubCommand::Config {
+                command: ConfigCommand::Validate,
+            } | Completions { .. }
+                | SubCommand::Init { .. }
+        )
+    );
+    let problems = app_config.problems();
+    if checked && !problems.is_empty() {
+        for problem in &problems {
+            if cli_config.ignore_bad_config {
+                warn!("Ignoring invalid configuration: {problem}");
+            } else {
+                error!("Invalid configuration: {problem}");
+            }
+        }
+        if !cli_config.ignore_bad_config {
+            error!("Fix the configuration or pass --ignore-bad-config to start anyway");
+            exit(1);
+        }
+    }
+
+    if cli_config.pin_bangs
+        && let Err(e) = pin_bangs(&app_config).await
+    {
+        error!("Failed to pin bangs: {e:#}");
+        exit(1);
+    }
+
+    if checked {
+        for warning in app_config.quota_warnings() {
+            warn!("{warning}");
+        }
+    }
+
+    let mut app_state = AppState::new(app_config.clone());
+    app_state.recent_events = recent_events;
+    let checked = app_state
+        .suggestion_policy
+        .read()
+        .check_url(&app_config.search_suggestions.replace("{}", ""));
+    if let Err(e) = checked {
+        warn!("Suggestions will not be available: {e}");
+    }
 
     match cli_config.command {
+        Some(SubCommand::Serve { stdio: true, .. }) => {
+            if let Err(e) = update_bangs(&app_config).await {
+                error!("Failed to update bang commands: {e:#}");
+            }
+            spawn_updater(&app_state);
+
+            let stdin = BufReader::new(tokio::io::stdin());
+            if let Err(e) = stdio::run(&app_config, stdin, tokio::io::stdout()).await {
+                error!("Failed to answer stdio requests: {e}");
+                exit(1);
+            }
+        },
         Some(SubCommand::Serve { .. }) | None => {
-            tokio::spawn(periodic_update(app_config.clone()));
+            match load_binary_cache(&app_config) {
+                Ok(Some(metadata)) => {
+                    info!("Loaded {} bangs from the binary cache", metadata.entries);
+                    app_state.record_source(metadata);
+                },
+                Ok(None) => {},
+                Err(e) => warn!("Ignoring the binary bang cache: {e:#}"),
+            }
+            spawn_updater(&app_state);
+            // both do nothing until stats_persist is turned on, also by a
+            // reload
+            let stats_state = app_state.clone();
+            app_state
+                .tasks
+                .spawn("stats", move || stats::periodic_fold(stats_state.clone()));
+            let popular_state = app_state.clone();
+            app_state.tasks.spawn("popular bangs", move || {
+                popular::periodic_recompute(popular_state.clone())
+            });
 
-            let app = Router::new()
-                .route("/", get(handler))
-                .route("/bangs", get(list_bangs))
-                .route("/opensearch.xml", get(opensearch))
-                .route("/favicon.ico", get(favicon))
-                .route("/suggest", get(suggestions_proxy))
-                .route("/add_bang", post(add_bang))
-                .route("/reload", get(reload))
-                .route("/restart", get(restart))
-                .layer(TraceLayer::new_for_http())
-                .layer(CompressionLayer::new())
-                .with_state(app_state);
+            let shutdown_state = app_state.clone();
+            let app = router(app_state);
             let addr = SocketAddr::new(app_config.ip, app_config.port);
             let listener = match TcpListener::bind(addr).await {
                 Ok(listener) => listener,
@@ -290,21 +198,444 @@ async fn main() {
                 "Server running on '{}'",
                 listener.local_addr().unwrap_or(addr)
             );
-            axum::serve(listener, app).await.unwrap();
+            tokio::select! {
+                () = serve(listener, app, app_config.http2) => {},
+                () = shutdown_signal() => {
+                    info!("Shutting down");
+                    stats::fold(&shutdown_state);
+                },
+            }
         },
-        Some(SubCommand::Resolve { query }) => {
-            if let Err(e) = update_bangs(&app_config).await {
-                error!("Failed to update bang commands: {e}");
+        Some(SubCommand::Resolve {
+            queries,
+            trigger,
+            json,
+        }) => {
+            let resolutions: Vec<_> = if let Some(trigger) = trigger {
+                if let Err(e) = update_bangs_offline(&app_config) {
+                    error!("Failed to load bang commands: {e:#}");
+                    exit(1);
+                }
+                let cache = BANG_CACHE.read();
+                let options = app_config.resolve_options();
+                let resolutions = queries
+                    .iter()
+                    .map(|query| resolve_trigger(&cache, &options, &trigger, query))
+                    .collect::<Option<_>>();
+                let Some(resolutions) = resolutions else {
+                    error!("Unknown bang '{trigger}'");
+                    exit(2);
+                };
+                resolutions
+            } else {
+                if let Err(e) = update_bangs(&app_config).await {
+                    error!("Failed to update bang commands: {e:#}");
+                }
+                queries
+                    .iter()
+                    .map(|query| resolve_with_info(&app_config, query))
+                    .collect()
+            };
+            if json {
+                let resolved: Vec<_> = queries
+                    .iter()
+                    .zip(resolutions)
+                    .map(|(query, resolution)| {
+                        serde_json::json!({
+                            "query": query,
+                            "target": resolution.url,
+                            "matched_trigger": resolution.trigger,
+                        })
+                    })
+                    .collect();
+                match serde_json::to_string_pretty(&resolved) {
+                    Ok(json) => println!("{json}"),
+                    Err(e) => {
+                        error!("Failed to serialize the resolutions: {e}");
+                        exit(1);
+                    },
+                }
+            } else {
+                for resolution in resolutions {
+                    println!("{}", resolution.url);
+                }
             }
-            println!("{}", resolve(&app_config, &query));
         },
-        Some(Completions { shell }) => {
-            generate(
-                shell,
-                &mut Cli::command(),
-                env!("CARGO_PKG_NAME"),
+        Some(SubCommand::Open {
+            query,
+            print,
+            dry_run,
+        }) => {
+            if let Err(e) = update_bangs_offline(&app_config) {
+                debug!("No cached bangs, downloading them: {e:#}");
+                if let Err(e) = update_bangs(&app_config).await {
+                    error!("Failed to update bang commands: {e:#}");
+                }
+            }
+            let resolution = resolve_with_info(&app_config, &query);
+            if let Err(e) = open_resolution(
+                &resolution,
+                OpenOptions { print, dry_run },
+                &SystemBrowser,
                 &mut std::io::stdout(),
+            ) {
+                error!("{e:#}");
+                exit(1);
+            }
+        },
+        Some(Completions { shell, output }) => {
+            if let Some(dir) = output {
+                match generate_to(shell, &mut Cli::command(), env!("CARGO_PKG_NAME"), &dir) {
+                    Ok(path) => println!("{}", path.display()),
+                    Err(e) => {
+                        error!("Failed to write completions to '{}': {e}", dir.display());
+                        exit(1);
+                    },
+                }
+            } else {
+                generate(
+                    shell,
+                    &mut Cli::command(),
+                    env!("CARGO_PKG_NAME"),
+                    &mut std::io::stdout(),
+                );
+            }
+        },
+        Some(SubCommand::Export { format, output }) => {
+            if let Err(e) = update_bangs(&app_config).await {
+                error!("Failed to update bang commands: {e:#}");
+            }
+            let bangs = cached_bangs();
+            let exported = match export(
+                &app_config.base_url(),
+                &app_config.query_param,
+                &bangs,
+                format,
+            ) {
+                Ok(exported) => exported,
+                Err(e) => {
+                    error!("Failed to export bangs: {e}");
+                    exit(1);
+                },
+            };
+            match output {
+                Some(path) => {
+                    if let Err(e) = std::fs::write(&path, exported) {
+                        error!("Failed to write export to '{}': {e}", path.display());
+                        exit(1);
+                    }
+                    info!("Exported {} bangs to '{}'", bangs.len(), path.display());
+                },
+                None => println!("{exported}"),
+            }
+        },
+        Some(SubCommand::Bangs {
+            command: BangsCommand::List { pick_format },
+        }) => {
+            if let Err(e) = update_bangs_offline(&app_config) {
+                error!("Failed to load bang commands: {e:#}");
+                exit(1);
+            }
+            let bangs = cached_bangs();
+            if pick_format {
+                print!("{}", pick_lines(&bangs));
+            } else {
+                for bang in &bangs {
+                    println!("!{:<12} {}", bang.trigger, bang.url_template);
+                }
+            }
+        },
+        Some(SubCommand::Update) => {
+            let metadata = match refresh_bangs(&app_config).await {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    error!("Failed to update bang commands: {e:#}");
+                    exit(1);
+                },
+            };
+            let unknown = || "unknown".to_string();
+            println!("source: {}", metadata.source);
+            println!("fetched: {}", metadata.fetched_at_date());
+            println!(
+                "last modified: {}",
+                metadata.last_modified.unwrap_or_else(unknown)
+            );
+            println!("etag: {}", metadata.etag.unwrap_or_else(unknown));
+            println!(
+                "content length: {}",
+                metadata
+                    .content_length
+                    .map_or_else(unknown, |length| length.to_string())
             );
+            println!("entries: {}", metadata.entries);
+            println!("content hash: {}", metadata.content_hash);
+        },
+        Some(SubCommand::Selftest) => {
+            if let Err(e) = update_bangs(&app_config).await {
+                error!("Failed to update bang commands: {e:#}");
+            }
+            let report = selftest::run(&app_state).await;
+            match serde_json::to_string_pretty(&report) {
+                Ok(json) => println!("{json}"),
+                Err(e) => error!("Failed to serialize the selftest report: {e}"),
+            }
+            if !report.passed {
+                exit(1);
+            }
+        },
+        Some(SubCommand::Bangs {
+            command: BangsCommand::Check { json },
+        }) => {
+            // only for suggesting replacements, they are left out without the list
+            if let Err(e) = update_bangs_offline(&app_config) {
+                debug!("No bang list to suggest replacements from: {e:#}");
+            }
+            let remote = remote_bangs(&BANG_CACHE.read());
+            let client = app_state.suggestion_client.read().clone();
+            let policy = app_state.suggestion_policy.read().clone();
+            let checks = match check_config_bangs(&client, &policy, &app_config, &remote).await {
+                Ok(checks) => checks,
+                Err(e) => {
+                    error!("Failed to check bangs: {e:#}");
+                    exit(1);
+                },
+            };
+            if json {
+                match serde_json::to_string_pretty(&checks) {
+                    Ok(json) => println!("{json}"),
+                    Err(e) => error!("Failed to serialize the bang checks: {e}"),
+                }
+            } else {
+                print!("{}", render_table(&checks));
+            }
+            if !checks.iter().all(BangCheck::passed) {
+                exit(1);
+            }
+        },
+        Some(SubCommand::Bangs {
+            command: BangsCommand::Import { format, file },
+        }) => {
+            let contents = match std::fs::read(&file) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    error!("Failed to read '{}': {e}", file.display());
+                    exit(1);
+                },
+            };
+            let (mut bangs, skipped) = match format.parse(&contents) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    error!("Invalid bang list in '{}': {e}", file.display());
+                    exit(1);
+                },
+            };
+            for bang in &mut bangs {
+                bang.normalize();
+            }
+            let imported = bangs.len();
+            if let Err(e) =
+                import_file_config_bangs(&config_path, bangs, app_config.bang_collisions)
+            {
+                error!("Failed to import bangs: {e:#}");
+                exit(1);
+            }
+            println!("Imported {imported} bangs, skipped {skipped} entries");
+        },
+        Some(SubCommand::Bangs {
+            command:
+                BangsCommand::Diff {
+                    source,
+                    format,
+                    json,
+                    filter,
+                    full,
+                },
+        }) => {
+            if let Err(e) = update_bangs_offline(&app_config) {
+                error!("Failed to load bang commands: {e:#}");
+                exit(1);
+            }
+            let other = match diff::load_other(&app_config, &source, format).await {
+                Ok(other) => other,
+                Err(e) => {
+                    error!("Failed to load the bangs to compare with: {e:#}");
+                    exit(1);
+                },
+            };
+            let diff = diff::diff(&BANG_CACHE.read(), &other, filter.as_deref());
+            if json {
+                match serde_json::to_string_pretty(&diff) {
+                    Ok(json) => println!("{json}"),
+                    Err(e) => error!("Failed to serialize the bang diff: {e}"),
+                }
+            } else {
+                print!("{}", diff::render_diff(&diff, &source, full));
+            }
         },
+        Some(SubCommand::Bench {
+            queries,
+            iterations,
+        }) => {
+            if let Err(e) = update_bangs_offline(&app_config) {
+                error!("Failed to load bang commands: {e:#}");
+                exit(1);
+            }
+            let contents = match std::fs::read_to_string(&queries) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    error!("Failed to read queries from '{}': {e}", queries.display());
+                    exit(1);
+                },
+            };
+            let Some(report) = bench::run(
+                &BANG_CACHE.read(),
+                &app_config.resolve_options(),
+                &bench::parse_queries(&contents),
+                iterations,
+            ) else {
+                error!("No queries in '{}'", queries.display());
+                exit(1);
+            };
+            print!("{}", bench::render_table(&report));
+        },
+        Some(SubCommand::Init { defaults, force }) => {
+            let path = &config_path;
+            if path.exists() && !force {
+                error!(
+                    "There already is a configuration file at {}, pass --force to replace it",
+                    path.display()
+                );
+                exit(1);
+            }
+            let answers = if defaults {
+                SetupAnswers::default()
+            } else if std::io::stdin().is_terminal() {
+                let answers = setup::ask(&mut std::io::stdin().lock(), &mut std::io::stdout());
+                match answers {
+                    Ok(answers) => answers,
+                    Err(e) => {
+                        error!("Failed to read the answers: {e}");
+                        exit(1);
+                    },
+                }
+            } else {
+                error!("redirector init asks on a terminal, pass --defaults to write the defaults");
+                exit(1);
+            };
+            match setup::write_config(path, &answers, force) {
+                Ok(opensearch_url) => {
+                    println!("Wrote '{}'", path.display());
+                    println!(
+                        "Start the server with `redirector serve` and add {opensearch_url} to the \
+                         browser as a search engine"
+                    );
+                },
+                Err(e) => {
+                    error!("Failed to write the configuration: {e:#}");
+                    exit(1);
+                },
+            }
+        },
+        Some(SubCommand::Config {
+            command: ConfigCommand::Show,
+        }) => {
+            match app_config.to_toml() {
+                Ok(toml) => print!("{toml}"),
+                Err(e) => {
+                    error!("Failed to show the configuration: {e:#}");
+                    exit(1);
+                },
+            }
+        },
+        Some(SubCommand::Config {
+            command: ConfigCommand::Validate,
+        }) => {
+            let path = &config_path;
+            let contents = match read_to_string(path) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    eprintln!("Failed to read '{}': {e}", path.display());
+                    exit(1);
+                },
+            };
+            if let Err(e) = parse_file_config(&contents) {
+                eprintln!("'{}' is invalid: {e}", path.display());
+                exit(1);
+            }
+            let problems = app_config.problems();
+            if !problems.is_empty() {
+                eprintln!("'{}' is invalid:", path.display());
+                for problem in &problems {
+                    eprintln!("{problem}");
+                }
+                exit(1);
+            }
+            println!("'{}' is valid", path.display());
+        },
+        Some(SubCommand::Config {
+            command: ConfigCommand::Compact { dry_run },
+        }) => {
+            let path = &config_path;
+            match compact_file(path, dry_run) {
+                Ok(removed) => {
+                    for removal in &removed {
+                        println!("{:?}: !{}", removal.reason, removal.trigger);
+                    }
+                    let verb = if dry_run { "Would remove" } else { "Removed" };
+                    println!("{verb} {} entries from '{}'", removed.len(), path.display());
+                },
+                Err(e) => {
+                    error!("Failed to compact configuration file: {e:#}");
+                    exit(1);
+                },
+            }
+        },
+    }
+}
+
+/// The bangs of the cache in the stable order of [`sorted_bangs`].
+fn cached_bangs() -> Vec<Bang> {
+    sorted_bangs(&BANG_CACHE.read())
+        .into_iter()
+        .map(|(_, cached)| cached.bang.clone())
+        .collect()
+}
+
+fn spawn_updater(app_state: &AppState) {
+    let updater_state = app_state.clone();
+    app_state.tasks.spawn("bang updater", move || {
+        periodic_update(updater_state.clone())
+    });
+    let expiry_state = app_state.clone();
+    app_state
+        .tasks
+        .spawn("bang expiry", move || periodic_expiry(expiry_state.clone()));
+}
+
+/// Resolves on Ctrl-C and, on Unix, on SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            },
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            },
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => {},
+        () = terminate => {},
     }
 }
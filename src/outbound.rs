@@ -0,0 +1,454 @@
+//! Policy for outbound requests to user configured targets.
+//!
+//! Templates like `search_suggestions` come from the config file, which can be
+//! appended to over HTTP. Without a policy the suggestion proxy could be used
+//! to read internal services (e.g. cloud metadata endpoints) through the
+//! server, so requests to loopback, private and link-local addresses are
+//! refused unless the host is explicitly allowed. Hostnames are resolved
+//! through [`GuardedResolver`], which validates the resolved addresses and
+//! hands only those to the connector, so a DNS answer can't change between
+//! validation and connection.
+
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{ClientBuilder, Url, redirect};
+use serde::{Deserialize, Serialize};
+
+/// Whether requests to `ip` are refused by default.
+#[must_use]
+pub fn is_forbidden_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // shared address space (RFC 6598)
+                || (a == 100 && (b & 0b1100_0000) == 64)
+        },
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // unique local (fc00::/7) and link-local (fe80::/10)
+                || (first & 0xFE00) == 0xFC00
+                || (first & 0xFFC0) == 0xFE80
+                || ip
+                    .to_ipv4_mapped()
+                    .is_some_and(|ip| is_forbidden_ip(IpAddr::V4(ip)))
+        },
+    }
+}
+
+/// IP family outbound connections are made with, for dual-stack hosts where
+/// only one of them actually works.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum IpFamily {
+    /// Whatever the addresses resolve to, trying both.
+    #[default]
+    Auto,
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    /// The unspecified local address connections are bound to, which makes
+    /// the connector skip remote addresses of the other family.
+    #[must_use]
+    pub const fn local_address(self) -> Option<IpAddr> {
+        match self {
+            Self::Auto => None,
+            Self::V4 => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            Self::V6 => Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        }
+    }
+
+    /// Restrict the connections of a client to this family.
+    pub fn apply(self, builder: ClientBuilder) -> ClientBuilder {
+        builder.local_address(self.local_address())
+    }
+}
+
+/// Lowest TLS version outbound connections negotiate, connections to servers
+/// offering only older ones fail.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl TlsVersion {
+    #[must_use]
+    pub const fn to_reqwest(self) -> reqwest::tls::Version {
+        match self {
+            Self::Tls12 => reqwest::tls::Version::TLS_1_2,
+            Self::Tls13 => reqwest::tls::Version::TLS_1_3,
+        }
+    }
+
+    /// Restrict the connections of a client to this version and newer ones.
+    pub fn apply(self, builder: ClientBuilder) -> ClientBuilder {
+        builder.tls_version_min(self.to_reqwest())
+    }
+}
+
+/// A client builder for outbound connections made with the IP `family` and
+/// at least TLS `min_tls_version`, the TLS library's default if `None`.
+pub fn outbound_builder(family: IpFamily, min_tls_version: Option<TlsVersion>) -> ClientBuilder {
+    let builder = family.apply(reqwest::Client::builder());
+    match min_tls_version {
+        Some(version) => version.apply(builder),
+        None => builder,
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OutboundPolicy {
+    allow_hosts: Arc<[String]>,
+}
+
+impl OutboundPolicy {
+    /// Create a policy that additionally allows the given hosts, even if they
+    /// resolve to internal addresses.
+    #[must_use]
+    pub fn new(allow_hosts: &[String]) -> Self {
+        Self {
+            allow_hosts: allow_hosts
+                .iter()
+                .map(|host| host.trim_matches(['[', ']']).to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn allows_host(&self, host: &str) -> bool {
+        let host = host.trim_matches(['[', ']']);
+        self.allow_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    }
+
+    /// Check a URL before requesting it.
+    ///
+    /// Hostnames can only be fully validated once resolved, so this only
+    /// rejects unsupported schemes and forbidden IP literals.
+    pub fn check_url(&self, url: &str) -> Result<Url> {
+        let parsed = Url::parse(url)?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            bail!("Unsupported URL scheme '{}' in '{url}'", parsed.scheme());
+        }
+        let Some(host) = parsed.host_str() else {
+            bail!("Missing host in '{url}'");
+        };
+        if let Ok(ip) = host.trim_matches(['[', ']']).parse::<IpAddr>()
+            && is_forbidden_ip(ip)
+            && !self.allows_host(host)
+        {
+            bail!("Refusing to connect to internal address '{ip}'");
+        }
+        Ok(parsed)
+    }
+
+    /// A redirect policy checking every hop with [`Self::check_url`], since
+    /// redirects to IP literals never reach the [`GuardedResolver`].
+    #[must_use]
+    pub fn redirect_policy(&self) -> redirect::Policy {
+        let policy = self.clone();
+        redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            match policy.check_url(attempt.url().as_str()) {
+                Ok(_) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        })
+    }
+
+    /// Check the addresses `host` resolved to.
+    ///
+    /// A name resolving to any forbidden address is refused as a whole, as a
+    /// mix of public and internal addresses is a common DNS rebinding setup.
+    pub fn check_addrs(&self, host: &str, addrs: Vec<SocketAddr>) -> Result<Vec<SocketAddr>> {
+        if self.allows_host(host) {
+            return Ok(addrs);
+        }
+        if let Some(addr) = addrs.iter().find(|addr| is_forbidden_ip(addr.ip())) {
+            bail!(
+                "Refusing to connect to '{host}', it resolves to internal address '{}'",
+                addr.ip()
+            );
+        }
+        if addrs.is_empty() {
+            bail!("'{host}' did not resolve to any address");
+        }
+        Ok(addrs)
+    }
+}
+
+/// Redirects followed per request, like reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
+pub type LookupFuture = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send>>;
+
+/// Resolves hostnames to addresses.
+pub trait Lookup: Send + Sync + 'static {
+    fn lookup(&self, host: String) -> LookupFuture;
+}
+
+/// Resolves hostnames using the system resolver.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemLookup;
+
+impl Lookup for SystemLookup {
+    fn lookup(&self, host: String) -> LookupFuture {
+        Box::pin(async move { Ok(tokio::net::lookup_host((host, 0)).await?.collect()) })
+    }
+}
+
+/// DNS resolver only handing out addresses allowed by the [`OutboundPolicy`].
+#[derive(Clone, Debug)]
+pub struct GuardedResolver<L = SystemLookup> {
+    policy: OutboundPolicy,
+    lookup: Arc<L>,
+}
+
+impl GuardedResolver {
+    #[must_use]
+    pub fn new(policy: OutboundPolicy) -> Self {
+        Self::with_lookup(policy, SystemLookup)
+    }
+}
+
+impl<L: Lookup> GuardedResolver<L> {
+    #[must_use]
+    pub fn with_lookup(policy: OutboundPolicy, lookup: L) -> Self {
+        Self {
+            policy,
+            lookup: Arc::new(lookup),
+        }
+    }
+}
+
+impl<L: Lookup> Resolve for GuardedResolver<L> {
+    fn resolve(&self, name: Name) -> Resolving {
+        let policy = self.policy.clone();
+        let lookup = Arc::clone(&self.lookup);
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs = lookup.lookup(host.clone()).await?;
+            let addrs: Addrs = Box::new(policy.check_addrs(&host, addrs)?.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Build an HTTP client whose connections and redirects are restricted by
+/// `policy`, see [`outbound_builder`] for `family` and `min_tls_version`.
+///
+/// # Panics
+/// If the TLS backend cannot be initialized.
+#[must_use]
+pub fn guarded_client(
+    policy: OutboundPolicy,
+    family: IpFamily,
+    min_tls_version: Option<TlsVersion>,
+) -> reqwest::Client {
+    outbound_builder(family, min_tls_version)
+        .redirect(policy.redirect_policy())
+        .dns_resolver(GuardedResolver::new(policy))
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::str::FromStr as _;
+
+    use super::*;
+
+    /// Resolves names from a fixed table, like a DNS server under attacker
+    /// control would.
+    struct MockLookup(HashMap<&'static str, Vec<IpAddr>>);
+
+    impl Lookup for MockLookup {
+        fn lookup(&self, host: String) -> LookupFuture {
+            let addrs = self.0.get(host.as_str()).cloned().unwrap_or_default();
+            Box::pin(
+                async move { Ok(addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect()) },
+            )
+        }
+    }
+
+    fn resolver(allow_hosts: &[String]) -> GuardedResolver<MockLookup> {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        GuardedResolver::with_lookup(
+            OutboundPolicy::new(allow_hosts),
+            MockLookup(HashMap::from([
+                ("search.example.com", vec![ip("93.184.216.34")]),
+                ("127.0.0.1.nip.io", vec![ip("127.0.0.1")]),
+                ("metadata.example.com", vec![ip("169.254.169.254")]),
+                (
+                    "rebind.example.com",
+                    vec![ip("93.184.216.34"), ip("10.0.0.1")],
+                ),
+                ("mapped.example.com", vec![ip("::ffff:192.168.0.1")]),
+                ("internal.example.com", vec![ip("fd00::1")]),
+            ])),
+        )
+    }
+
+    async fn resolve(resolver: &GuardedResolver<MockLookup>, host: &str) -> Option<Vec<IpAddr>> {
+        let addrs = resolver.resolve(Name::from_str(host).unwrap()).await.ok()?;
+        Some(addrs.map(|addr| addr.ip()).collect())
+    }
+
+    #[tokio::test]
+    async fn test_guarded_resolver() {
+        let resolver = resolver(&[]);
+        assert_eq!(
+            resolve(&resolver, "search.example.com").await,
+            Some(vec!["93.184.216.34".parse().unwrap()])
+        );
+        for host in [
+            "127.0.0.1.nip.io",
+            "metadata.example.com",
+            "rebind.example.com",
+            "mapped.example.com",
+            "internal.example.com",
+            "unknown.example.com",
+        ] {
+            assert_eq!(
+                resolve(&resolver, host).await,
+                None,
+                "{host} must be refused"
+            );
+        }
+
+        let resolver = self::resolver(&["Internal.example.com".to_string()]);
+        assert_eq!(
+            resolve(&resolver, "internal.example.com").await,
+            Some(vec!["fd00::1".parse().unwrap()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ip_family() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new().route("/", axum::routing::get(|| async { "ok" }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let policy = OutboundPolicy::new(&["127.0.0.1".to_string()]);
+        for family in [IpFamily::Auto, IpFamily::V4] {
+            let client = guarded_client(policy.clone(), family, None);
+            let response = client.get(format!("http://{addr}/")).send().await;
+            assert!(response.is_ok(), "{family:?}: {response:?}");
+        }
+        let client = guarded_client(policy, IpFamily::V6, None);
+        if let Ok(response) = client.get(format!("http://{addr}/")).send().await {
+            panic!("an IPv6 client must not connect to {addr}, got {response:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redirect_to_internal_address() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let location = format!("http://127.0.0.1:{port}/secret");
+        let app = axum::Router::new()
+            .route(
+                "/",
+                axum::routing::get(move || {
+                    let location = location.clone();
+                    async move { axum::response::Redirect::to(&location) }
+                }),
+            )
+            .route("/secret", axum::routing::get(|| async { "secret" }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = guarded_client(
+            OutboundPolicy::new(&["localhost".to_string()]),
+            IpFamily::V4,
+            None,
+        );
+        let response = client
+            .get(format!("http://localhost:{port}/secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.text().await.unwrap(),
+            "secret",
+            "the host is allowed"
+        );
+        let error = client
+            .get(format!("http://localhost:{port}/"))
+            .send()
+            .await
+            .expect_err("the redirect to 127.0.0.1 must be refused");
+        assert!(error.is_redirect(), "{error:?}");
+    }
+
+    #[test]
+    fn test_min_tls_version() {
+        let builder = outbound_builder(IpFamily::Auto, None);
+        assert!(
+            !format!("{builder:?}").contains("tls_version_min"),
+            "{builder:?}"
+        );
+        for (version, expected) in [
+            (TlsVersion::Tls12, reqwest::tls::Version::TLS_1_2),
+            (TlsVersion::Tls13, reqwest::tls::Version::TLS_1_3),
+        ] {
+            let builder = outbound_builder(IpFamily::V4, Some(version));
+            let debug = format!("{builder:?}");
+            assert!(
+                debug.contains(&format!("tls_version_min: {expected:?}")),
+                "{debug}"
+            );
+            builder.build().unwrap();
+        }
+        let version: TlsVersion = serde_json::from_str(r#""1.2""#).unwrap();
+        assert_eq!(version, TlsVersion::Tls12);
+        serde_json::from_str::<TlsVersion>(r#""1.1""#).unwrap_err();
+    }
+
+    #[test]
+    fn test_check_url() {
+        let policy = OutboundPolicy::new(&["[::1]".to_string()]);
+        for url in [
+            "https://search.example.com/?q=",
+            "http://[::1]:8080/suggest",
+        ] {
+            if let Err(e) = policy.check_url(url) {
+                panic!("{url} must be allowed: {e}");
+            }
+        }
+        for url in [
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.1.2.3/",
+            "http://100.64.0.1/",
+            "file:///etc/passwd",
+            "not a url",
+        ] {
+            if let Ok(parsed) = policy.check_url(url) {
+                panic!("{url} must be refused, got {parsed}");
+            }
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! The most used bangs, shown as quick links on the landing page.
+//!
+//! They are computed from the [stats](crate::stats) of all retained months,
+//! so they are only known with `stats_persist`. Summing and sorting the
+//! counts takes too long to be done per request, so the list is recomputed
+//! every [`RECOMPUTE_INTERVAL`] and kept in [`AppState`].
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use parking_lot::RwLock;
+use serde::Serialize;
+use tokio::time::interval;
+
+use crate::BANG_CACHE;
+use crate::config::AppState;
+use crate::resolver::BangCache;
+use crate::server::escape_html;
+use crate::stats::StatsStore;
+
+/// How often the popular bangs are recomputed.
+pub const RECOMPUTE_INTERVAL: Duration = Duration::from_mins(5);
+/// Number of bangs shown.
+pub const POPULAR_BANGS: usize = 8;
+/// Redirects a bang needs to be shown, so a single use doesn't make it
+/// popular.
+pub const MIN_COUNT: u64 = 3;
+/// Marker in the landing page that is replaced by the links.
+pub const PLACEHOLDER: &str = "<!-- popular bangs -->";
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PopularBang {
+    pub trigger: String,
+    pub short_name: Option<String>,
+    pub domain: Option<String>,
+    pub count: u64,
+}
+
+/// The `limit` bangs of `cache` with the most redirects in `store`, at least
+/// `min_count`, the most used first and alphabetically among equally used
+/// ones.
+///
+/// Triggers that aren't in the cache anymore are left out.
+#[must_use]
+pub fn compute(
+    store: &StatsStore,
+    cache: &BangCache,
+    limit: usize,
+    min_count: u64,
+) -> Vec<PopularBang> {
+    let mut totals: HashMap<&str, u64> = HashMap::new();
+    for month in store.months.values() {
+        for (trigger, count) in &month.triggers {
+            *totals.entry(trigger).or_default() += count;
+        }
+    }
+    let mut popular: Vec<PopularBang> = totals
+        .into_iter()
+        .filter(|&(_, count)| count >= min_count)
+        .filter_map(|(trigger, count)| {
+            let cached = cache.get(&trigger.to_lowercase())?;
+            Some(PopularBang {
+                trigger: trigger.to_string(),
+                short_name: cached.bang.short_name.clone(),
+                domain: cached.bang.domain.clone(),
+                count,
+            })
+        })
+        .collect();
+    popular.sort_unstable_by(|a, b| {
+        Reverse(a.count)
+            .cmp(&Reverse(b.count))
+            .then_with(|| a.trigger.cmp(&b.trigger))
+    });
+    popular.truncate(limit);
+    popular
+}
+
+/// Render the bangs as a list of links to their domains, nothing if there
+/// are none.
+///
+/// # Panics
+/// If writing to the string fails, which it can't.
+#[must_use]
+pub fn render(popular: &[PopularBang]) -> String {
+    if popular.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from(r#"<nav class="popular-bangs"><ul>"#);
+    for bang in popular {
+        let label = format!("!{}", escape_html(&bang.trigger));
+        match &bang.domain {
+            Some(domain) => {
+                write!(
+                    html,
+                    r#"<li><a href="https://{}">{label}</a>"#,
+                    escape_html(domain)
+                )
+            },
+            None => write!(html, "<li>{label}"),
+        }
+        .expect("Failed to write to HTML string");
+        if let Some(short_name) = &bang.short_name {
+            write!(html, " {}", escape_html(short_name)).expect("Failed to write to HTML string");
+        }
+        html.push_str("</li>");
+    }
+    html.push_str("</ul></nav>");
+    html
+}
+
+/// Recompute the popular bangs of `cache`, none without `ui.popular_bangs` or
+/// `stats_persist`.
+pub fn recompute(app_state: &AppState, cache: &RwLock<BangCache>) {
+    let config = app_state.config.read();
+    let enabled = config.ui.popular_bangs && config.stats_persist;
+    drop(config);
+    let popular = if enabled {
+        compute(
+            &app_state.stats.lock().store,
+            &cache.read(),
+            POPULAR_BANGS,
+            MIN_COUNT,
+        )
+    } else {
+        Vec::new()
+    };
+    *app_state.popular_bangs.write() = popular;
+}
+
+/// Recompute the popular bangs every [`RECOMPUTE_INTERVAL`].
+pub async fn periodic_recompute(app_state: AppState) {
+    let mut interval = interval(RECOMPUTE_INTERVAL);
+    #[expect(
+        clippy::infinite_loop,
+        reason = "This is intended to run indefinitely in the background"
+    )]
+    loop {
+        interval.tick().await;
+        recompute(&app_state, &BANG_CACHE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bang::{Bang, test_bang};
+    use crate::config::{AppConfig, UiConfig};
+    use crate::resolver::build_cache;
+
+    fn cache() -> BangCache {
+        let bang = |trigger: &str| {
+            Bang {
+                domain: Some(format!("{trigger}.example")),
+                short_name: Some(trigger.to_uppercase()),
+                ..test_bang(
+                    trigger,
+                    &format!("https://{trigger}.example/?q={{{{{{s}}}}}}"),
+                )
+            }
+        };
+        build_cache(
+            ["a", "b", "c", "d"].map(bang).to_vec(),
+            &[Bang {
+                domain: None,
+                ..bang("mine")
+            }],
+        )
+    }
+
+    #[test]
+    fn test_threshold() {
+        let mut store = StatsStore::default();
+        let mut record = |trigger, times, timestamp| {
+            for _ in 0..times {
+                store.record_at(Some(trigger), timestamp);
+            }
+        };
+        // January and February 2025
+        record("a", 2, 1_736_000_000);
+        record("a", 2, 1_739_000_000);
+        record("b", 4, 1_739_000_000);
+        record("c", 2, 1_739_000_000);
+        record("mine", 5, 1_739_000_000);
+        record("gone", 9, 1_739_000_000);
+
+        let popular = compute(&store, &cache(), POPULAR_BANGS, MIN_COUNT);
+        let triggers: Vec<(&str, u64)> = popular
+            .iter()
+            .map(|bang| (bang.trigger.as_str(), bang.count))
+            .collect();
+        // c is below the threshold, gone isn't a bang anymore
+        assert_eq!(triggers, [("mine", 5), ("a", 4), ("b", 4)]);
+        assert_eq!(compute(&store, &cache(), 1, MIN_COUNT).len(), 1);
+        assert_eq!(compute(&store, &cache(), POPULAR_BANGS, 10), []);
+
+        let html = render(&popular);
+        assert!(html.contains(r#"<li><a href="https://a.example">!a</a> A</li>"#));
+        assert!(html.contains("<li>!mine MINE</li>"));
+        assert_eq!(render(&[]), "");
+    }
+
+    #[test]
+    fn test_disabled() {
+        let enabled = AppConfig {
+            stats_persist: true,
+            persist: false,
+            ..AppConfig::default()
+        };
+        for (config, shown) in [
+            (enabled.clone(), true),
+            (
+                AppConfig {
+                    ui: UiConfig {
+                        popular_bangs: false,
+                        ..UiConfig::default()
+                    },
+                    ..enabled.clone()
+                },
+                false,
+            ),
+            (
+                AppConfig {
+                    stats_persist: false,
+                    ..enabled
+                },
+                false,
+            ),
+        ] {
+            let app_state = AppState::new(config);
+            {
+                let mut stats = app_state.stats.lock();
+                for _ in 0..MIN_COUNT {
+                    stats.store.record(Some("a"));
+                }
+            }
+            recompute(&app_state, &RwLock::new(cache()));
+            assert_eq!(!app_state.popular_bangs.read().is_empty(), shown);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! The most recent redirects, shown on the `/debug/recent` page.
+//!
+//! To find out what a search did after the fact without debug logs, the last
+//! `debug_recent_size` resolutions are kept in memory with their query. None
+//! are kept with `redact_queries`.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::Resolution;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecentResolution {
+    /// Unix timestamp of the redirect.
+    pub timestamp: u64,
+    pub query: String,
+    /// The trigger of the matched bang, without the leading '!'.
+    pub trigger: Option<String>,
+    pub url: String,
+    /// How long resolving the query took in microseconds.
+    pub latency_us: u64,
+    /// Whether no bang matched and the default search was used.
+    pub fallback: bool,
+}
+
+impl RecentResolution {
+    /// The redirect of `query` to `resolution` that was resolved in `latency`,
+    /// happening now.
+    #[must_use]
+    pub fn new(query: &str, resolution: &Resolution, latency: Duration) -> Self {
+        Self {
+            timestamp: unix_now(),
+            query: query.to_string(),
+            trigger: resolution.trigger.clone(),
+            url: resolution.url.clone(),
+            latency_us: u64::try_from(latency.as_micros()).unwrap_or(u64::MAX),
+            fallback: resolution.fallback,
+        }
+    }
+
+    /// When the redirect happened, as an HTTP date.
+    #[must_use]
+    pub fn date(&self) -> String {
+        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(self.timestamp))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Ring of the last resolutions, shared by its clones.
+#[derive(Debug, Clone, Default)]
+pub struct RecentResolutions(Arc<Mutex<VecDeque<RecentResolution>>>);
+
+impl RecentResolutions {
+    /// Keep `resolution`, dropping the oldest ones so at most `capacity` are
+    /// kept.
+    pub fn push(&self, resolution: RecentResolution, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        let mut resolutions = self.0.lock();
+        // the capacity may have shrunk through a reload
+        while resolutions.len() >= capacity {
+            resolutions.pop_front();
+        }
+        resolutions.push_back(resolution);
+    }
+
+    /// The kept resolutions, the most recent first.
+    #[must_use]
+    pub fn recent(&self) -> Vec<RecentResolution> {
+        self.0.lock().iter().rev().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded() {
+        let recent = RecentResolutions::default();
+        let resolution = |query: &str| {
+            RecentResolution {
+                timestamp: 0,
+                query: query.to_string(),
+                trigger: None,
+                url: format!("https://duckduckgo.com/?q={query}"),
+                latency_us: 1,
+                fallback: true,
+            }
+        };
+        for i in 0..10 {
+            recent.push(resolution(&i.to_string()), 3);
+        }
+        let queries: Vec<String> = recent.recent().into_iter().map(|r| r.query).collect();
+        assert_eq!(queries, ["9", "8", "7"]);
+
+        recent.push(resolution("shrunk"), 1);
+        assert_eq!(recent.recent(), [resolution("shrunk")]);
+        recent.push(resolution("disabled"), 0);
+        assert_eq!(recent.recent(), [resolution("shrunk")]);
+
+        recent.clear();
+        assert!(recent.recent().is_empty(), "cleared");
+    }
+}
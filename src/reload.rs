@@ -0,0 +1,183 @@
+//! Rate limit for reloading the configuration.
+//!
+//! Every reload downloads the bang list and rebuilds the cache, so a burst of
+//! `/reload` requests would hammer the upstream and hold the cache lock over
+//! and over. Reloads run at most once per `reload_interval_ms`, requests in
+//! between wait for the next one and share its result. `/bangs/reload` has a
+//! limiter of its own, as its result doesn't answer a full reload.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tokio::time::{Instant, sleep_until};
+
+/// The outcome of a reload, shared with every request it answered.
+pub type ReloadResult<T = ()> = Result<T, Arc<str>>;
+
+#[derive(Debug)]
+struct LastReload<T> {
+    finished: Option<Instant>,
+    /// The number of the reload `result` is of, see `ReloadLimiter::started`.
+    generation: u64,
+    result: Option<ReloadResult<T>>,
+}
+
+/// Coalesces reloads, shared by its clones.
+#[derive(Debug)]
+pub struct ReloadLimiter<T = ()> {
+    last: Arc<Mutex<LastReload<T>>>,
+    /// How many reloads were started, to tell whether one started after a
+    /// request came in.
+    started: Arc<AtomicU64>,
+}
+
+impl<T> Clone for ReloadLimiter<T> {
+    fn clone(&self) -> Self {
+        Self {
+            last: Arc::clone(&self.last),
+            started: Arc::clone(&self.started),
+        }
+    }
+}
+
+impl<T> Default for ReloadLimiter<T> {
+    fn default() -> Self {
+        Self {
+            last: Arc::new(Mutex::new(LastReload {
+                finished: None,
+                generation: 0,
+                result: None,
+            })),
+            started: Arc::default(),
+        }
+    }
+}
+
+impl<T: Clone> ReloadLimiter<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `reload` unless a reload that started after this call answers it
+    /// too, waiting until `interval` passed since the last one.
+    pub async fn run<F, Fut>(&self, interval: Duration, reload: F) -> ReloadResult<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = ReloadResult<T>>,
+    {
+        let requested = self.started.load(Ordering::SeqCst);
+        let mut last = self.last.lock().await;
+        if last.generation > requested
+            && let Some(result) = &last.result
+        {
+            // it read the configuration after the request, so it's as good
+            return result.clone();
+        }
+        if let Some(finished) = last.finished {
+            // holding the lock, so requests coming in meanwhile join this one
+            sleep_until(finished + interval).await;
+        }
+        let generation = self.started.fetch_add(1, Ordering::SeqCst) + 1;
+        let result = reload().await;
+        // only now, a reload dropped halfway answers nobody
+        last.finished = Some(Instant::now());
+        last.generation = generation;
+        last.result = Some(result.clone());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_coalesce_reloads() {
+        let limiter = ReloadLimiter::new();
+        let interval = Duration::from_secs(5);
+        let reloads = Arc::new(AtomicUsize::new(0));
+        let reload = || {
+            let limiter = limiter.clone();
+            let reloads = Arc::clone(&reloads);
+            async move {
+                limiter
+                    .run(interval, || {
+                        async move {
+                            reloads.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            Ok(())
+                        }
+                    })
+                    .await
+            }
+        };
+
+        let started = Instant::now();
+        reload().await.unwrap();
+        assert_eq!(reloads.load(Ordering::SeqCst), 1, "the first reload runs");
+        assert!(started.elapsed() < interval, "right away");
+
+        let results = tokio::join!(reload(), reload(), reload(), reload(), reload());
+        assert_eq!(results, (Ok(()), Ok(()), Ok(()), Ok(()), Ok(())));
+        assert_eq!(
+            reloads.load(Ordering::SeqCst),
+            2,
+            "the burst is coalesced into a single reload"
+        );
+        assert!(started.elapsed() >= interval, "after the interval");
+
+        // failures are shared as well
+        let failing = || {
+            let limiter = limiter.clone();
+            let reloads = Arc::clone(&reloads);
+            async move {
+                limiter
+                    .run(interval, || {
+                        async move {
+                            reloads.fetch_add(1, Ordering::SeqCst);
+                            Err(Arc::from("broken config"))
+                        }
+                    })
+                    .await
+            }
+        };
+        let (first, second) = tokio::join!(failing(), failing());
+        assert_eq!(&*first.unwrap_err(), "broken config");
+        assert_eq!(&*second.unwrap_err(), "broken config");
+        assert_eq!(reloads.load(Ordering::SeqCst), 3);
+    }
+    #[tokio::test(start_paused = true)]
+    async fn test_dropped_reload() {
+        let limiter = ReloadLimiter::<usize>::new();
+        let interval = Duration::from_secs(5);
+        assert_eq!(limiter.run(interval, || async { Ok(1) }).await, Ok(1));
+
+        let hanging = tokio::spawn({
+            let limiter = limiter.clone();
+            async move {
+                limiter
+                    .run(interval, std::future::pending::<ReloadResult<usize>>)
+                    .await
+            }
+        });
+        // the hanging reload waits for the interval, holding the lock
+        tokio::task::yield_now().await;
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.run(interval, || async { Ok(2) }).await }
+        });
+        tokio::task::yield_now().await;
+        tokio::time::sleep(interval + Duration::from_millis(1)).await;
+        hanging.abort();
+        assert_eq!(
+            waiting.await.unwrap(),
+            Ok(2),
+            "a dropped reload doesn't answer the requests waiting for it"
+        );
+    }
+}
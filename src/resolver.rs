@@ -0,0 +1,1985 @@
+//! Resolution of queries to URLs.
+//!
+//! This module does no I/O and only depends on `serde`, `urlencoding` and
+//! `idna`, the latter for internationalized domain names, so it builds for
+//! `wasm32-unknown-unknown` with `--no-default-features --features wasm`. That
+//! way a browser extension can resolve bangs locally from a
+//! [snapshot](parse_snapshot) with exactly the semantics of the server, which
+//! uses this module as well.
+//!
+//! With the `server` feature, every step of a resolution is a trace-level
+//! `tracing` span, `tokenize`, `lookup` and `build_url` within `resolve`, to
+//! see where the time goes on large caches. With `-vvv` the time spent in
+//! each is logged, below the trace level they are disabled and cost a single
+//! check each.
+//!
+//! Templates substitute the search term for `{{{s}}}` or have it appended if
+//! they don't contain the placeholder, `default_search` substitutes the whole
+//! query for `{}`. In both, `{{}}` stands for a literal `{}` that is never
+//! substituted.
+
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Write as _};
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aliases;
+use crate::bang::{Bang, BangMethod, TermEncoding};
+
+/// Enter a trace-level span for a step of the resolution until the end of
+/// the block, nothing without the `server` feature.
+macro_rules! trace_step {
+    ($($span:tt)+) => {
+        #[cfg(feature = "server")]
+        let _step = tracing::trace_span!($($span)+).entered();
+    };
+}
+
+/// Where a cached bang was loaded from.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BangOrigin {
+    /// The bang list downloaded from `bangs_url`.
+    Remote,
+    /// The `[[bangs]]` of the configuration file.
+    Config,
+}
+
+impl Display for BangOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Remote => write!(f, "remote"),
+            Self::Config => write!(f, "config"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedBang {
+    pub bang: Bang,
+    pub origin: BangOrigin,
+}
+
+/// Bangs by their lowercase trigger.
+pub type BangCache = HashMap<String, CachedBang>;
+
+/// The bang with the lowercase trigger `key_lower`, or the one an alias of
+/// `options` stands for if no bang has that trigger, along with its trigger.
+fn lookup<'c>(
+    cache: &'c BangCache,
+    options: &ResolveOptions<'_>,
+    key_lower: String,
+) -> Option<(&'c CachedBang, String)> {
+    if let Some(cached) = cache.get(&key_lower) {
+        return Some((cached, key_lower));
+    }
+    let trigger = aliases::alias_target(&key_lower, options.builtin_aliases, options.aliases)?;
+    cache.get(&trigger).map(|cached| (cached, trigger))
+}
+
+/// Form field POST bangs send the search term in unless configured otherwise.
+const DEFAULT_POST_FIELD: &str = "q";
+
+/// The settings resolution depends on.
+#[derive(Debug, Clone, Copy)]
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "These are independent settings, not states"
+)]
+pub struct ResolveOptions<'a> {
+    /// URL for queries without a known bang, `{}` is replaced with the query.
+    pub default_search: &'a str,
+    /// Strip zero-width and bidi control characters, see [`sanitize_query`].
+    pub sanitize_query: bool,
+    /// Post the term to bangs with `method = "post"` instead of redirecting.
+    pub post_bangs: bool,
+    /// Never treat tokens inside a `"double quoted"` phrase as bangs.
+    pub ignore_quoted_bangs: bool,
+    /// Resolve unknown bangs that look like a domain, see [`is_domain`].
+    pub domain_bangs: bool,
+    /// Redirect queries that are a bare URL to it, see [`direct_url`].
+    pub direct_urls: bool,
+    /// Hosts [`direct_url`] accepts, with their subdomains, any if empty.
+    pub direct_url_hosts: &'a [String],
+    /// Whether queries are percent-decoded first, see [`decode_incoming`].
+    pub decode_incoming: DecodeIncoming,
+    /// Use the `mobile_url_template` of bangs that have one.
+    pub mobile: bool,
+    /// Language tags the client prefers, best first, to pick the `localized`
+    /// templates of bangs.
+    pub languages: &'a [String],
+    /// Put in front of every search term, after looking for the bang.
+    pub query_prefix: &'a str,
+    /// Appended to every search term, after looking for the bang.
+    pub query_suffix: &'a str,
+    /// Whether the curated aliases of [`crate::aliases::BUILTIN_ALIASES`]
+    /// resolve to their bangs.
+    pub builtin_aliases: bool,
+    /// The configured aliases, see [`crate::aliases::alias_target`].
+    pub aliases: &'a BTreeMap<String, String>,
+}
+
+/// What a query resolves to and how.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    /// The URL the query redirects to.
+    pub url: String,
+    /// The trigger of the matched bang, without the leading '!'.
+    pub trigger: Option<String>,
+    pub origin: Option<BangOrigin>,
+    /// The note of the matched bang.
+    pub note: Option<String>,
+    /// Set if the term has to be posted to `url` instead of redirecting.
+    pub post: Option<PostForm>,
+    /// Whether no bang matched and the default search is used.
+    pub fallback: bool,
+}
+
+/// Form submission a POST bang resolves to.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PostForm {
+    /// Name of the form field holding the search term.
+    pub field: String,
+    /// The search term, not URL encoded.
+    pub term: String,
+    /// Hidden fields to post along with the term.
+    pub hidden: BTreeMap<String, String>,
+}
+
+impl Resolution {
+    const fn fallback(url: String) -> Self {
+        Self {
+            url,
+            trigger: None,
+            origin: None,
+            note: None,
+            post: None,
+            fallback: true,
+        }
+    }
+}
+
+/// A whitespace delimited token of a query starting with '!', which is a
+/// bang if the rest of it is a known trigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BangToken<'q> {
+    /// Byte offset of the '!' in the query.
+    pub start: usize,
+    /// Byte offset right after the token.
+    pub end: usize,
+    /// The token without the leading '!', empty for a lone '!'.
+    pub trigger: &'q str,
+    /// Whether the token is inside a `"double quoted"` phrase.
+    pub quoted: bool,
+}
+
+/// Iterator over the [`BangToken`]s of a query, see [`bang_tokens`].
+#[derive(Debug, Clone)]
+pub struct BangTokens<'q> {
+    query: &'q str,
+    offset: usize,
+    quoted: bool,
+}
+
+impl<'q> Iterator for BangTokens<'q> {
+    type Item = BangToken<'q>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let rest = &self.query[self.offset..];
+            let start = self.offset + rest.find(|c: char| !c.is_whitespace())?;
+            let end = self.query[start..]
+                .find(char::is_whitespace)
+                .map_or(self.query.len(), |len| start + len);
+            let token = &self.query[start..end];
+            let quoted = self.quoted;
+            if token.bytes().filter(|&b| b == b'"').count() % 2 == 1 {
+                self.quoted = !self.quoted;
+            }
+            self.offset = end;
+            if let Some(trigger) = token.strip_prefix('!') {
+                return Some(BangToken {
+                    start,
+                    end,
+                    trigger,
+                    quoted,
+                });
+            }
+        }
+    }
+}
+
+/// The tokens of `query` that may be bangs, in order.
+///
+/// These are the rules the server, the CLI and completion all share:
+/// - Tokens are delimited by whitespace, a '!' only starts a bang at the
+///   beginning of a token, so `100!g` or `wow!!` never contain one.
+/// - The rest of the token is the trigger, `!g!w` has the trigger `g!w`.
+/// - A lone '!' has an empty trigger and is never a bang.
+/// - Tokens after an unmatched '"' are quoted, whether quoted tokens can be
+///   bangs depends on [`ResolveOptions::ignore_quoted_bangs`].
+#[must_use]
+pub const fn bang_tokens(query: &str) -> BangTokens<'_> {
+    BangTokens {
+        query,
+        offset: 0,
+        quoted: false,
+    }
+}
+
+/// The first token of `query` that is a known bang.
+#[must_use]
+pub fn find_bang<'q>(
+    cache: &BangCache,
+    options: &ResolveOptions<'_>,
+    query: &'q str,
+) -> Option<BangToken<'q>> {
+    bang_tokens(query).find(|token| {
+        !token.trigger.is_empty()
+            && (!options.ignore_quoted_bangs || !token.quoted)
+            && lookup(cache, options, token.trigger.to_ascii_lowercase()).is_some()
+    })
+}
+
+/// The bang being typed at the end of `query`, if any.
+#[must_use]
+pub fn partial_bang<'q>(options: &ResolveOptions<'_>, query: &'q str) -> Option<BangToken<'q>> {
+    bang_tokens(query)
+        .last()
+        .filter(|token| token.end == query.len() && (!options.ignore_quoted_bangs || !token.quoted))
+}
+
+/// Get the first token of the query that looks like a bang, whether it is a
+/// known one or not. See [`bang_tokens`] for the rules.
+#[must_use]
+pub fn get_bang(query: &str) -> Option<&str> {
+    bang_tokens(query)
+        .find(|token| !token.trigger.is_empty())
+        .map(|token| &query[token.start..token.end])
+}
+
+/// Complete the bang typed at the end of `query` to the known triggers
+/// starting with it, keeping the rest of the query.
+///
+/// Shortest (closest) matches come first, alphabetically among equally long
+/// ones.
+#[must_use]
+pub fn complete(
+    cache: &BangCache,
+    options: &ResolveOptions<'_>,
+    query: &str,
+    limit: usize,
+) -> Vec<String> {
+    let query = query.trim_start();
+    let Some(token) = partial_bang(options, query) else {
+        return Vec::new();
+    };
+    let partial = token.trigger.to_ascii_lowercase();
+    let before = &query[..token.start];
+
+    let mut matches: Vec<&String> = cache
+        .keys()
+        .filter(|trigger| trigger.starts_with(&partial))
+        .collect();
+    matches.sort_unstable_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+    matches
+        .into_iter()
+        .take(limit)
+        .map(|trigger| format!("{before}!{trigger}"))
+        .collect()
+}
+
+/// Whether `c` is invisible formatting that queries pasted from chat apps
+/// often carry along: zero-width characters, byte order marks and bidi
+/// controls.
+const fn is_invisible_control(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}'..='\u{200F}'
+            | '\u{FEFF}'
+            | '\u{061C}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2066}'..='\u{2069}'
+    )
+}
+
+/// Strip zero-width and bidi control characters from the query.
+#[must_use]
+pub fn sanitize_query(query: &str) -> Cow<'_, str> {
+    if query.is_ascii() || !query.chars().any(is_invisible_control) {
+        return Cow::Borrowed(query);
+    }
+    Cow::Owned(
+        query
+            .chars()
+            .filter(|&c| !is_invisible_control(c))
+            .collect(),
+    )
+}
+
+/// How percent-escapes in incoming queries are treated.
+///
+/// Some clients encode the query themselves before it is encoded as a
+/// parameter again, so it would end up encoded twice, like `%2520` for a
+/// space.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum DecodeIncoming {
+    /// Take queries as they are.
+    #[default]
+    Never,
+    /// Decode queries that look encoded as a whole, see [`decode_incoming`].
+    Auto,
+    /// Decode every escape of two hex digits once, other `%` are kept.
+    Always,
+}
+
+/// Whether encoders always escape `byte`: `%` itself, whitespace, controls
+/// and the bytes of non-ASCII characters.
+const fn must_escape(byte: u8) -> bool {
+    byte == b'%' || byte.is_ascii_whitespace() || byte.is_ascii_control() || !byte.is_ascii()
+}
+
+/// Decode the percent-escapes of `query` once, depending on `mode`.
+///
+/// [`DecodeIncoming::Auto`] only decodes queries that a client must have
+/// encoded as a whole, so legitimate uses of `%` are never touched:
+/// 1. Every `%` starts an escape of two hex digits, so `50%`, `50% off` or
+///    `%zz` are kept as they are.
+/// 2. No other character an encoder always escapes, `%`, whitespace, controls
+///    and non-ASCII characters, is left raw, so the half encoded `a%20b c` or
+///    `café%20` are kept.
+/// 3. At least one escape stands for such a character, like `%20` or `%25`.
+///    Escapes of characters like `/` are left alone when they are all there is,
+///    since `%2F` may well be meant literally.
+/// 4. The decoded bytes are UTF-8, so the Latin-1 `caf%E9` is kept.
+#[must_use]
+pub fn decode_incoming(query: &str, mode: DecodeIncoming) -> Cow<'_, str> {
+    if mode == DecodeIncoming::Never || !query.contains('%') {
+        return Cow::Borrowed(query);
+    }
+    let hex = |byte: u8| char::from(byte).to_digit(16);
+    let bytes = query.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    // rules 1 and 2
+    let mut consistent = true;
+    // rule 3
+    let mut needed = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i..i + 3) {
+            Some(&[b'%', high, low]) => hex(high).zip(hex(low)),
+            _ => None,
+        };
+        if let Some((high, low)) = escaped {
+            #[expect(clippy::cast_possible_truncation, reason = "both digits are below 16")]
+            let byte = (high * 16 + low) as u8;
+            needed |= must_escape(byte);
+            decoded.push(byte);
+            i += 3;
+        } else {
+            consistent &= !must_escape(bytes[i]);
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    match mode {
+        DecodeIncoming::Always => Cow::Owned(String::from_utf8_lossy(&decoded).into_owned()),
+        DecodeIncoming::Auto if consistent && needed => {
+            String::from_utf8(decoded).map_or(Cow::Borrowed(query), Cow::Owned)
+        },
+        DecodeIncoming::Auto | DecodeIncoming::Never => Cow::Borrowed(query),
+    }
+}
+
+/// Resolve the query against `cache`, recording which bang was used.
+#[must_use]
+pub fn resolve(cache: &BangCache, options: &ResolveOptions<'_>, query: &str) -> Resolution {
+    trace_step!("resolve", cache_entries = cache.len());
+    // decoded first, so no invisible characters are decoded into it
+    let decoded = decode_incoming(query, options.decode_incoming);
+    let query = if options.sanitize_query {
+        sanitize_query(&decoded)
+    } else {
+        Cow::Borrowed(decoded.as_ref())
+    };
+    let query = query.as_ref();
+
+    if query.is_empty() {
+        return Resolution::fallback(fill_template(options.default_search, "{}", ""));
+    }
+
+    if options.direct_urls
+        && let Some(url) = direct_url(query, options.direct_url_hosts)
+    {
+        return Resolution {
+            url,
+            trigger: None,
+            origin: None,
+            note: None,
+            post: None,
+            fallback: false,
+        };
+    }
+
+    // Fastest path for the most common case, queries without any '!' can't
+    // contain a bang, which a single `memchr` tells
+    if !query.contains('!') {
+        return Resolution::fallback(fill_template(
+            options.default_search,
+            "{}",
+            &urlencoding::encode(&affixed(options, query)),
+        ));
+    }
+
+    // Single-word queries can't contain a bang after the first word either
+    if !query.starts_with('!') && !query.contains(char::is_whitespace) {
+        return Resolution::fallback(fill_template(
+            options.default_search,
+            "{}",
+            &urlencoding::encode(&affixed(options, query)),
+        ));
+    }
+
+    let token = {
+        trace_step!("tokenize");
+        find_bang(cache, options, query)
+    };
+    if let Some(token) = token {
+        let found = {
+            trace_step!("lookup");
+            lookup(cache, options, token.trigger.to_ascii_lowercase())
+        };
+        if let Some((cached, key_lower)) = found {
+            trace_step!("build_url");
+            let term = format!("{}{}", &query[..token.start], &query[token.end..]);
+            return expand(cached, key_lower, term.trim(), options);
+        }
+    }
+
+    if options.domain_bangs
+        && let Some(token) = bang_tokens(query).find(|token| {
+            (!options.ignore_quoted_bangs || !token.quoted) && is_domain(token.trigger)
+        })
+    {
+        let term = format!("{}{}", &query[..token.start], &query[token.end..]);
+        return resolve_domain(&token.trigger.to_ascii_lowercase(), term.trim(), options);
+    }
+
+    // Default fallback
+    Resolution::fallback(fill_template(
+        options.default_search,
+        "{}",
+        &urlencoding::encode(&affixed(options, query)),
+    ))
+}
+
+/// Whether `trigger` looks like a domain, like `github.com` or
+/// `docs.rs`: dot separated labels of letters, digits and inner hyphens
+/// ending in an alphabetic or punycode top-level domain.
+#[must_use]
+pub fn is_domain(trigger: &str) -> bool {
+    let Some((_, tld)) = trigger.rsplit_once('.') else {
+        return false;
+    };
+    tld.len() >= 2
+        && (tld.bytes().all(|b| b.is_ascii_alphabetic())
+            || tld
+                .get(..4)
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case("xn--")))
+        && trigger.len() <= 253
+        && trigger.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+/// The URL to go to if `query` is a bare URL, like `example.com/foo` or
+/// `http://example.com`, with `https://` prefixed if it has no scheme.
+///
+/// Only `http` and `https` URLs of `hosts` or their subdomains are accepted,
+/// of any host if `hosts` is empty. Without a scheme the host has to look
+/// like a domain, see [`is_domain`], so `localhost:3000` is searched, and
+/// has to start with `www.` or be followed by a path, so file and package
+/// names like `README.md` or `node.js` are searched as well.
+/// Internationalized hosts like `müller.example` are accepted and redirected
+/// to in punycode.
+#[must_use]
+pub fn direct_url(query: &str, hosts: &[String]) -> Option<String> {
+    let query = query.trim();
+    if query.is_empty() || query.contains(char::is_whitespace) {
+        return None;
+    }
+    let (has_scheme, rest) = match query.split_once("://") {
+        Some((scheme, rest))
+            if scheme.eq_ignore_ascii_case("https") || scheme.eq_ignore_ascii_case("http") =>
+        {
+            (true, rest)
+        },
+        Some(_) => return None,
+        None => (false, query),
+    };
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let authority = &rest[..authority_end];
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        Some(_) => return None,
+        None => authority,
+    };
+    let host = ascii_domain(host)?;
+    if !(is_domain(&host) || has_scheme && host.parse::<Ipv4Addr>().is_ok()) {
+        return None;
+    }
+    if !has_scheme && !host.starts_with("www.") && !rest[authority_end..].starts_with('/') {
+        return None;
+    }
+    let allowed = hosts.is_empty()
+        || hosts.iter().any(|allowed| {
+            let Some(allowed) = ascii_domain(allowed) else {
+                return false;
+            };
+            host.strip_suffix(&allowed)
+                .is_some_and(|subdomain| subdomain.is_empty() || subdomain.ends_with('.'))
+        });
+    if !allowed {
+        return None;
+    }
+    let url = if has_scheme {
+        Cow::Borrowed(query)
+    } else {
+        Cow::Owned(format!("https://{query}"))
+    };
+    // only what can't be in a URL at all is encoded, after the host is
+    // converted, which must not be percent-encoded
+    let url = punycode_host(&url);
+    Some(percent_encode_except(&url, |byte| byte.is_ascii_graphic()).into_owned())
+}
+
+/// The lowercase ASCII form of a domain, punycode for internationalized
+/// ones, or nothing if it isn't a valid one.
+fn ascii_domain(domain: &str) -> Option<String> {
+    if domain.is_ascii() {
+        return Some(domain.to_ascii_lowercase());
+    }
+    idna::domain_to_ascii(domain).ok()
+}
+
+/// Where the host of the absolute `url` is, without user info and port.
+/// Nothing for IP literals like `[::1]`, which have nothing to convert.
+fn host_range(url: &str) -> Option<std::ops::Range<usize>> {
+    let authority_start = url.find("://")? + 3;
+    let rest = &url[authority_start..];
+    let authority_end = authority_start + rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let authority = &url[authority_start..authority_end];
+    let start = authority_start + authority.rfind('@').map_or(0, |at| at + 1);
+    let host_and_port = &url[start..authority_end];
+    if host_and_port.starts_with('[') {
+        return None;
+    }
+    let end = match host_and_port.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => start + host.len(),
+        _ => authority_end,
+    };
+    Some(start..end)
+}
+
+/// `url` with an internationalized host in punycode.
+///
+/// Some clients refuse other hosts in a `Location`, so
+/// `https://müller.example/` becomes `https://xn--mller-kva.example/`. Hosts
+/// that are no valid domain are left as they are.
+#[must_use]
+pub fn punycode_host(url: &str) -> Cow<'_, str> {
+    if url.is_ascii() {
+        return Cow::Borrowed(url);
+    }
+    let Some(range) = host_range(url) else {
+        return Cow::Borrowed(url);
+    };
+    let host = &url[range.clone()];
+    if host.is_ascii() {
+        return Cow::Borrowed(url);
+    }
+    idna::domain_to_ascii(host).map_or(Cow::Borrowed(url), |ascii| {
+        Cow::Owned(format!(
+            "{}{ascii}{}",
+            &url[..range.start],
+            &url[range.end..]
+        ))
+    })
+}
+
+/// `url` with a punycode host in Unicode, like `https://müller.example/`, for
+/// showing it to people. The opposite of [`punycode_host`].
+#[must_use]
+pub fn unicode_host(url: &str) -> Cow<'_, str> {
+    let Some(range) = host_range(url) else {
+        return Cow::Borrowed(url);
+    };
+    let host = &url[range.clone()];
+    if !host.to_ascii_lowercase().contains("xn--") {
+        return Cow::Borrowed(url);
+    }
+    match idna::domain_to_unicode(host) {
+        (unicode, Ok(())) => {
+            Cow::Owned(format!(
+                "{}{unicode}{}",
+                &url[..range.start],
+                &url[range.end..]
+            ))
+        },
+        (_, Err(_)) => Cow::Borrowed(url),
+    }
+}
+
+/// Search within `domain` with the default search, or visit it without a
+/// search term.
+fn resolve_domain(domain: &str, search_term: &str, options: &ResolveOptions<'_>) -> Resolution {
+    let url = if search_term.is_empty() {
+        format!("https://{domain}/")
+    } else {
+        fill_template(
+            options.default_search,
+            "{}",
+            &urlencoding::encode(&format!("site:{domain} {}", affixed(options, search_term))),
+        )
+    };
+    Resolution {
+        url,
+        trigger: None,
+        origin: None,
+        note: None,
+        post: None,
+        fallback: false,
+    }
+}
+
+/// Resolve the search term with the bang `trigger` (with or without the
+/// leading '!'), skipping the parsing of a query. Returns nothing if the bang
+/// is unknown.
+#[must_use]
+pub fn resolve_trigger(
+    cache: &BangCache,
+    options: &ResolveOptions<'_>,
+    trigger: &str,
+    term: &str,
+) -> Option<Resolution> {
+    let decoded = decode_incoming(term, options.decode_incoming);
+    let term = if options.sanitize_query {
+        sanitize_query(&decoded)
+    } else {
+        Cow::Borrowed(decoded.as_ref())
+    };
+    let key_lower = trigger.trim().trim_start_matches('!').to_ascii_lowercase();
+    let (cached, key_lower) = lookup(cache, options, key_lower)?;
+    Some(expand(cached, key_lower, term.trim(), options))
+}
+
+/// `term` between the `query_prefix` and the `query_suffix` of `options`,
+/// separated by spaces. Empty terms stay empty, so bangs without one still
+/// open the site.
+fn affixed<'t>(options: &ResolveOptions<'_>, term: &'t str) -> Cow<'t, str> {
+    if term.is_empty() || (options.query_prefix.is_empty() && options.query_suffix.is_empty()) {
+        return Cow::Borrowed(term);
+    }
+    let affixed = [options.query_prefix, term, options.query_suffix]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    Cow::Owned(affixed)
+}
+
+/// Substitute the search term into the template of the matched bang.
+fn expand(
+    cached: &CachedBang,
+    key_lower: String,
+    search_term: &str,
+    options: &ResolveOptions<'_>,
+) -> Resolution {
+    // the language matters more than the layout of the site
+    let url_template = cached
+        .bang
+        .localized
+        .as_ref()
+        .and_then(|variants| localized(variants, options.languages))
+        .or_else(|| {
+            cached
+                .bang
+                .mobile_url_template
+                .as_deref()
+                .filter(|_| options.mobile)
+        })
+        .unwrap_or(&cached.bang.url_template);
+    let search_term = &affixed(options, search_term);
+    let encoded_term = encode_term(search_term, cached.bang.term_encoding.unwrap_or_default());
+
+    let post = (options.post_bangs && cached.bang.method == Some(BangMethod::Post)).then(|| {
+        PostForm {
+            field: cached
+                .bang
+                .post_field
+                .clone()
+                .unwrap_or_else(|| DEFAULT_POST_FIELD.to_string()),
+            term: search_term.to_string(),
+            hidden: cached
+                .bang
+                .post_fields
+                .iter()
+                .flatten()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        }
+    });
+
+    // Template handling
+    let url = if url_template.contains("{{{s}}}") {
+        fill_template(url_template, "{{{s}}}", &encoded_term)
+    } else if post.is_some() {
+        // the term is sent in the form instead
+        fill_template(url_template, "{{{s}}}", "")
+    } else {
+        // Simple append case
+        let mut result = fill_template(url_template, "{{{s}}}", "");
+        result.push_str(&encoded_term);
+        result
+    };
+    Resolution {
+        url,
+        trigger: Some(key_lower),
+        origin: Some(cached.origin),
+        note: cached.bang.note.clone(),
+        post,
+        fallback: false,
+    }
+}
+
+/// The variant for the first, most preferred, of `languages`.
+///
+/// It is matched by the whole tag and then by the language alone, so `de-AT`
+/// gets the `de` variant if there is none for `de-AT`. Without one the base
+/// template is used, even if there is a variant for a language liked less,
+/// as the base template may well be in the preferred language.
+#[must_use]
+pub fn localized<'v>(
+    variants: &'v BTreeMap<String, String>,
+    languages: &[String],
+) -> Option<&'v str> {
+    let find = |tag: &str| {
+        variants
+            .iter()
+            .find(|(variant, _)| variant.eq_ignore_ascii_case(tag))
+            .map(|(_, template)| template.as_str())
+    };
+    let language = languages.first()?;
+    find(language).or_else(|| {
+        language
+            .split_once('-')
+            .and_then(|(primary, _)| find(primary))
+    })
+}
+
+/// Encode the search term of a bang for its template.
+fn encode_term(term: &str, encoding: TermEncoding) -> Cow<'_, str> {
+    match encoding {
+        TermEncoding::Component => {
+            let encoded = urlencoding::encode(term);
+            // slashes are kept, for paths in the term
+            if encoded.contains("%2F") {
+                Cow::from(encoded.replace("%2F", "/"))
+            } else {
+                encoded
+            }
+        },
+        TermEncoding::None => percent_encode_except(term, |byte| byte.is_ascii_graphic()),
+        TermEncoding::Path => {
+            percent_encode_except(term, |byte| {
+                byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@/".contains(&byte)
+            })
+        },
+    }
+}
+
+/// Percent-encode the bytes of `term` that aren't `kept`.
+fn percent_encode_except(term: &str, kept: impl Fn(u8) -> bool) -> Cow<'_, str> {
+    if term.bytes().all(&kept) {
+        return Cow::Borrowed(term);
+    }
+    let mut encoded = String::with_capacity(term.len() * 3);
+    for byte in term.bytes() {
+        if kept(byte) {
+            encoded.push(char::from(byte));
+        } else {
+            write!(encoded, "%{byte:02X}").expect("Failed to write to string");
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+/// Escaped braces in templates, which become a literal `{}`.
+pub(crate) const LITERAL_BRACES: &str = "{{}}";
+
+/// Substitute `value` for every `placeholder` in `template` and turn every
+/// [`LITERAL_BRACES`] into `{}`, which isn't substituted. An
+/// internationalized host ends up in punycode, see [`punycode_host`].
+fn fill_template(template: &str, placeholder: &str, value: &str) -> String {
+    let template = punycode_host(template);
+    if !template.contains(LITERAL_BRACES) {
+        return template.replace(placeholder, value);
+    }
+    template
+        .split(LITERAL_BRACES)
+        .map(|part| part.replace(placeholder, value))
+        .collect::<Vec<_>>()
+        .join("{}")
+}
+
+/// What to do with triggers that differ only in case but lead to different
+/// URLs, like `!W` and `!w`. Triggers are matched case-insensitively, so only
+/// one of them can be kept.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum CollisionPolicy {
+    /// Keep the bang listed first.
+    #[default]
+    KeepFirst,
+    /// Keep the bang with the highest relevance, the first among equally
+    /// relevant ones.
+    HighestRelevance,
+    /// Refuse to load the bangs.
+    Error,
+}
+
+impl CollisionPolicy {
+    /// Whether the downloaded bang `remote` is kept over the configured bang
+    /// `configured` with the same trigger. Configured bangs override
+    /// downloaded ones, unless the policy is [`Self::HighestRelevance`] and
+    /// `offsets` are given: then the downloaded one is kept if it is more
+    /// relevant after the offsets are added.
+    #[must_use]
+    pub fn keeps_remote(
+        self,
+        offsets: Option<RelevanceOffsets>,
+        remote: &Bang,
+        configured: &Bang,
+    ) -> bool {
+        self == Self::HighestRelevance
+            && offsets.is_some_and(|offsets| {
+                offsets.relevance(remote, BangOrigin::Remote)
+                    > offsets.relevance(configured, BangOrigin::Config)
+            })
+    }
+}
+
+/// Relevance added to all bangs of a source when bangs of different sources
+/// compete for a trigger, to prefer one source over the other wholesale.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct RelevanceOffsets {
+    /// Added to the bangs downloaded from `bangs_url`.
+    pub remote: i64,
+    /// Added to the `[[bangs]]` of the configuration file.
+    pub config: i64,
+}
+
+impl RelevanceOffsets {
+    /// The relevance of `bang` from `origin` with the offset of its source,
+    /// counting an unset relevance as 0.
+    #[must_use]
+    pub fn relevance(self, bang: &Bang, origin: BangOrigin) -> i64 {
+        let offset = match origin {
+            BangOrigin::Remote => self.remote,
+            BangOrigin::Config => self.config,
+        };
+        i64::try_from(bang.relevance.unwrap_or(0))
+            .unwrap_or(i64::MAX)
+            .saturating_add(offset)
+    }
+}
+
+/// Two bangs of the same origin whose triggers differ only in case.
+#[derive(Debug, Clone)]
+pub struct Collision {
+    pub origin: BangOrigin,
+    pub kept: Bang,
+    pub dropped: Bang,
+}
+
+impl Display for Collision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bangs !{} ({}) and !{} ({}) differ only in case",
+            self.origin,
+            self.kept.trigger,
+            self.kept.url_template,
+            self.dropped.trigger,
+            self.dropped.url_template
+        )
+    }
+}
+
+/// Insert `bang` under its lowercase trigger.
+///
+/// A bang replaces one with the same trigger or URL, as before, and a
+/// configured bang replaces a downloaded one unless
+/// [`CollisionPolicy::keeps_remote`] says otherwise. Bangs of the same origin
+/// whose triggers only differ in case collide and `policy` decides which one
+/// is kept, the collision is returned. Internationalized hosts of its
+/// templates are converted to punycode, see [`punycode_host`].
+///
+/// # Errors
+/// With [`CollisionPolicy::Error`], if the triggers collide. The cache is left
+/// unchanged then.
+pub fn insert_bang(
+    cache: &mut BangCache,
+    mut bang: Bang,
+    origin: BangOrigin,
+    policy: CollisionPolicy,
+    offsets: Option<RelevanceOffsets>,
+) -> Result<Option<Collision>, Box<Collision>> {
+    // cached as they are sent, pages showing them convert back
+    let templates = std::iter::once(&mut bang.url_template)
+        .chain(&mut bang.mobile_url_template)
+        .chain(bang.localized.iter_mut().flat_map(BTreeMap::values_mut));
+    for template in templates {
+        if let Cow::Owned(ascii) = punycode_host(template) {
+            *template = ascii;
+        }
+    }
+    let cached = CachedBang { bang, origin };
+    let mut entry = match cache.entry(cached.bang.trigger.to_ascii_lowercase()) {
+        Entry::Vacant(entry) => {
+            entry.insert(cached);
+            return Ok(None);
+        },
+        Entry::Occupied(entry) => entry,
+    };
+    let existing = entry.get();
+    if existing.origin == BangOrigin::Remote
+        && origin == BangOrigin::Config
+        && policy.keeps_remote(offsets, &existing.bang, &cached.bang)
+    {
+        return Ok(None);
+    }
+    if existing.origin != origin
+        || existing.bang.trigger == cached.bang.trigger
+        || existing.bang.url_template == cached.bang.url_template
+    {
+        entry.insert(cached);
+        return Ok(None);
+    }
+    let replace = match policy {
+        CollisionPolicy::KeepFirst => false,
+        CollisionPolicy::HighestRelevance => {
+            cached.bang.relevance.unwrap_or(0) > existing.bang.relevance.unwrap_or(0)
+        },
+        CollisionPolicy::Error => {
+            return Err(Box::new(Collision {
+                origin,
+                kept: existing.bang.clone(),
+                dropped: cached.bang,
+            }));
+        },
+    };
+    let collision = if replace {
+        let dropped = entry.insert(cached).bang;
+        Collision {
+            origin,
+            kept: entry.get().bang.clone(),
+            dropped,
+        }
+    } else {
+        Collision {
+            origin,
+            kept: existing.bang.clone(),
+            dropped: cached.bang,
+        }
+    };
+    Ok(Some(collision))
+}
+
+/// Build a cache from the downloaded bangs and the configured ones, which
+/// take precedence, keeping the first of colliding triggers.
+#[must_use]
+pub fn build_cache(remote: Vec<Bang>, configured: &[Bang]) -> BangCache {
+    match build_cache_with(remote, configured, CollisionPolicy::KeepFirst, None) {
+        Ok((cache, _)) => cache,
+        Err(_) => unreachable!("keeping the first bang never fails"),
+    }
+}
+
+/// Build a cache like [`build_cache`], resolving colliding triggers with
+/// `policy` and the relevance `offsets` of the sources, see [`insert_bang`].
+/// Returns the cache and the collisions.
+///
+/// # Errors
+/// With [`CollisionPolicy::Error`], on the first collision.
+pub fn build_cache_with(
+    remote: Vec<Bang>,
+    configured: &[Bang],
+    policy: CollisionPolicy,
+    offsets: Option<RelevanceOffsets>,
+) -> Result<(BangCache, Vec<Collision>), Box<Collision>> {
+    let mut cache = HashMap::with_capacity(remote.len() + configured.len());
+    let mut collisions = Vec::new();
+    for bang in remote {
+        collisions.extend(insert_bang(
+            &mut cache,
+            bang,
+            BangOrigin::Remote,
+            policy,
+            offsets,
+        )?);
+    }
+    for bang in configured {
+        collisions.extend(insert_bang(
+            &mut cache,
+            bang.clone(),
+            BangOrigin::Config,
+            policy,
+            offsets,
+        )?);
+    }
+    Ok((cache, collisions))
+}
+
+/// The bangs of `cache` in a stable order: the configured ones first, then
+/// the downloaded ones, each alphabetically by trigger. Only references are
+/// sorted, the cache isn't copied.
+#[must_use]
+pub fn sorted_bangs(cache: &BangCache) -> Vec<(&str, &CachedBang)> {
+    let mut bangs: Vec<(&str, &CachedBang)> = cache
+        .iter()
+        .map(|(trigger, cached)| (trigger.as_str(), cached))
+        .collect();
+    bangs.sort_unstable_by(|(a_trigger, a), (b_trigger, b)| {
+        (a.origin != BangOrigin::Config, a_trigger)
+            .cmp(&(b.origin != BangOrigin::Config, b_trigger))
+    });
+    bangs
+}
+
+/// Load a snapshot of bangs, a JSON array in the format of the
+/// [DuckDuckGo bang list](https://duckduckgo.com/bang.js).
+#[cfg(any(feature = "server", feature = "wasm"))]
+pub fn parse_snapshot(json: &str) -> serde_json::Result<BangCache> {
+    Ok(build_cache(serde_json::from_str(json)?, &[]))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::bang::test_bang;
+
+    /// The options the tests resolve with, unless they override some.
+    fn base_options() -> ResolveOptions<'static> {
+        ResolveOptions {
+            default_search: "https://search.example.com/?q={}",
+            sanitize_query: true,
+            post_bangs: true,
+            ignore_quoted_bangs: true,
+            domain_bangs: false,
+            direct_urls: false,
+            direct_url_hosts: &[],
+            decode_incoming: DecodeIncoming::Never,
+            mobile: false,
+            languages: &[],
+            query_prefix: "",
+            query_suffix: "",
+            builtin_aliases: false,
+            aliases: const { &BTreeMap::new() },
+        }
+    }
+
+    #[test]
+    fn test_get_bang() {
+        // Valid bang queries
+        assert_eq!(get_bang("!gh search term"), Some("!gh"));
+        assert_eq!(get_bang("search !gh term"), Some("!gh"));
+        assert_eq!(get_bang("!gh"), Some("!gh"));
+        assert_eq!(get_bang("!multi-word"), Some("!multi-word"));
+        assert_eq!(get_bang("  !gh search"), Some("!gh"));
+        assert_eq!(get_bang("!g rust programming"), Some("!g"));
+
+        // Invalid bang queries
+        assert_eq!(get_bang("search!gh term"), None); // No space before !
+        assert_eq!(get_bang("search! gh term"), None); // Space after !
+        assert_eq!(get_bang("!"), None); // Single ! is not a bang
+        assert_eq!(get_bang(""), None); // Empty string
+        assert_eq!(get_bang("no bang here"), None); // No bang
+        assert_eq!(get_bang("a!!gh"), None); // No space before !
+    }
+
+    fn tokens(query: &str) -> Vec<(&str, bool)> {
+        bang_tokens(query)
+            .map(|token| (token.trigger, token.quoted))
+            .collect()
+    }
+
+    #[test]
+    fn test_bang_tokens() {
+        assert_eq!(tokens("!g rust"), [("g", false)]);
+        assert_eq!(tokens("rust !g"), [("g", false)]);
+        assert_eq!(tokens("  rust\t!g\n"), [("g", false)]);
+        assert_eq!(tokens("rust !g !w"), [("g", false), ("w", false)]);
+        assert_eq!(tokens("!g!w"), [("g!w", false)]);
+        assert_eq!(tokens("!!g"), [("!g", false)]);
+        assert_eq!(tokens("!"), [("", false)]);
+        assert_eq!(tokens("rust ! lang"), [("", false)]);
+
+        // a '!' inside a word never starts a bang
+        for query in [
+            "foo!g",
+            "100!g",
+            "wow!!",
+            "a!!gh",
+            "'!g' quoted",
+            "\"!g\"",
+            "",
+        ] {
+            assert_eq!(tokens(query), [], "{query:?}");
+        }
+
+        // tokens after an unmatched '"' are quoted
+        assert_eq!(tokens("\"rust !g\" !w"), [("g\"", true), ("w", false)]);
+        assert_eq!(tokens("\"rust !g"), [("g", true)]);
+        assert_eq!(tokens("say \"hi\" !g"), [("g", false)]);
+
+        let token = bang_tokens("rust !gh lang").next().unwrap();
+        assert_eq!((token.start, token.end), (5, 8));
+    }
+
+    #[test]
+    fn test_find_and_complete_bangs() {
+        let cache = build_cache(
+            ["g", "gh", "gi", "w"]
+                .map(|trigger| test_bang(trigger, "https://example.com/?q={{{s}}}"))
+                .to_vec(),
+            &[],
+        );
+        let mut options = base_options();
+        let found = |options: &ResolveOptions<'_>, query| {
+            find_bang(&cache, options, query).map(|token| token.trigger)
+        };
+
+        assert_eq!(found(&options, "!nope !G rust"), Some("G"));
+        assert_eq!(found(&options, "!g!w"), None);
+        assert_eq!(found(&options, "!"), None);
+        assert_eq!(found(&options, "\"rust !g\""), None);
+        assert_eq!(complete(&cache, &options, "!g", 2), ["!g", "!gh"]);
+        assert_eq!(
+            complete(&cache, &options, "  rust !G", 5),
+            ["rust !g", "rust !gh", "rust !gi"]
+        );
+        assert_eq!(complete(&cache, &options, "!", 1), ["!g"]);
+        for query in ["rust !g ", "rust!g", "\"rust !g"] {
+            let completions = complete(&cache, &options, query, 5);
+            assert!(completions.is_empty(), "{query:?}: {completions:?}");
+        }
+
+        options.ignore_quoted_bangs = false;
+        // the closing quote is part of the token
+        assert_eq!(found(&options, "\"rust !g\""), None);
+        assert_eq!(found(&options, "\"rust !g tutorial\""), Some("g"));
+        assert_eq!(complete(&cache, &options, "\"rust !w", 5), ["\"rust !w"]);
+    }
+
+    /// Queries with the URL they must resolve to, shared with the test suite
+    /// of the browser extension.
+    #[derive(Deserialize)]
+    struct TestVectors {
+        default_search: String,
+        bangs: Vec<Bang>,
+        vectors: Vec<TestVector>,
+    }
+
+    #[derive(Deserialize)]
+    struct TestVector {
+        query: String,
+        url: String,
+        trigger: Option<String>,
+    }
+
+    #[test]
+    fn test_vectors() {
+        let TestVectors {
+            default_search,
+            bangs,
+            vectors,
+        } = serde_json::from_str(include_str!("../res/resolve-vectors.json")).unwrap();
+        let cache = build_cache(bangs, &[]);
+        let options = ResolveOptions {
+            default_search: &default_search,
+            ..base_options()
+        };
+
+        for vector in vectors {
+            let resolution = resolve(&cache, &options, &vector.query);
+            assert_eq!(resolution.url, vector.url, "{:?}", vector.query);
+            assert_eq!(resolution.trigger, vector.trigger, "{:?}", vector.query);
+        }
+    }
+
+    #[test]
+    fn test_resolve_trigger() {
+        let cache = build_cache(
+            vec![test_bang("gh", "https://github.com/search?q={{{s}}}")],
+            &[],
+        );
+        let options = base_options();
+
+        for trigger in ["gh", "!gh", "GH"] {
+            let resolution = resolve_trigger(&cache, &options, trigger, " !g rust ").unwrap();
+            assert_eq!(
+                resolution.url, "https://github.com/search?q=%21g%20rust",
+                "the term must not be parsed for bangs"
+            );
+            assert_eq!(resolution.trigger.as_deref(), Some("gh"));
+        }
+        assert_eq!(resolve_trigger(&cache, &options, "nope", "rust"), None);
+    }
+
+    #[test]
+    fn test_query_affixes() {
+        let cache = build_cache(
+            vec![
+                test_bang("gh", "https://github.com/search?q={{{s}}}"),
+                test_bang("w", "https://en.wikipedia.org/?search={{{s}}}"),
+            ],
+            &[],
+        );
+        let options = ResolveOptions {
+            domain_bangs: true,
+            direct_urls: true,
+            query_suffix: "lang:en",
+            ..base_options()
+        };
+
+        for (query, url) in [
+            ("rust", "https://search.example.com/?q=rust%20lang%3Aen"),
+            (
+                "rust book",
+                "https://search.example.com/?q=rust%20book%20lang%3Aen",
+            ),
+            ("!gh rust", "https://github.com/search?q=rust%20lang%3Aen"),
+            ("rust !gh", "https://github.com/search?q=rust%20lang%3Aen"),
+            (
+                "!docs.rs serde",
+                "https://search.example.com/?q=site%3Adocs.rs%20serde%20lang%3Aen",
+            ),
+            // no term to add to
+            ("!gh", "https://github.com/search?q="),
+            ("", "https://search.example.com/?q="),
+            ("https://example.com/", "https://example.com/"),
+        ] {
+            assert_eq!(resolve(&cache, &options, query).url, url, "{query:?}");
+        }
+        assert_eq!(
+            resolve_trigger(&cache, &options, "w", "rust").unwrap().url,
+            "https://en.wikipedia.org/?search=rust%20lang%3Aen"
+        );
+
+        // the bang is looked for before the affixes are added
+        let options = ResolveOptions {
+            query_prefix: "!w",
+            query_suffix: "",
+            ..options
+        };
+        assert_eq!(
+            resolve(&cache, &options, "rust").url,
+            "https://search.example.com/?q=%21w%20rust"
+        );
+        assert_eq!(
+            resolve(&cache, &options, "!gh rust").url,
+            "https://github.com/search?q=%21w%20rust"
+        );
+    }
+
+    #[test]
+    fn test_trigger_collisions() {
+        let bang = |trigger: &str, url_template: &str, relevance| {
+            Bang {
+                relevance: Some(relevance),
+                ..test_bang(trigger, url_template)
+            }
+        };
+        let remote = vec![
+            bang("W", "https://w.example.com/?q=", 1),
+            bang("w", "https://wikipedia.example.com/?q=", 5),
+            // the same URL in another case isn't a collision
+            bang("G", "https://google.example.com/?q=", 1),
+            bang("g", "https://google.example.com/?q=", 1),
+        ];
+        // configured bangs override downloaded ones in any case
+        let configured = [bang("Gh", "https://github.example.com/?q=", 1)];
+        let url = |cache: &BangCache, trigger: &str| cache[trigger].bang.url_template.clone();
+
+        let (cache, collisions) = build_cache_with(
+            remote.clone(),
+            &configured,
+            CollisionPolicy::KeepFirst,
+            None,
+        )
+        .unwrap();
+        assert_eq!(url(&cache, "w"), "https://w.example.com/?q=");
+        assert_eq!(url(&cache, "gh"), "https://github.example.com/?q=");
+        assert_eq!(cache.len(), 3);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(
+            (
+                collisions[0].kept.trigger.as_str(),
+                collisions[0].dropped.trigger.as_str()
+            ),
+            ("W", "w")
+        );
+        assert_eq!(
+            collisions[0].to_string(),
+            "remote bangs !W (https://w.example.com/?q=) and !w \
+             (https://wikipedia.example.com/?q=) differ only in case"
+        );
+
+        let (cache, collisions) = build_cache_with(
+            remote.clone(),
+            &configured,
+            CollisionPolicy::HighestRelevance,
+            None,
+        )
+        .unwrap();
+        assert_eq!(url(&cache, "w"), "https://wikipedia.example.com/?q=");
+        assert_eq!(collisions[0].kept.trigger, "w");
+
+        let collision =
+            build_cache_with(remote, &configured, CollisionPolicy::Error, None).unwrap_err();
+        assert_eq!(collision.dropped.trigger, "w");
+
+        let options = base_options();
+        assert_eq!(
+            resolve(&build_cache(Vec::new(), &configured), &options, "!gH rust").url,
+            "https://github.example.com/?q=rust"
+        );
+
+        // bangs of different sources only compete with offsets
+        let remote = vec![bang("ddg", "https://duckduckgo.example.com/?q=", 10)];
+        let configured = [Bang {
+            relevance: None,
+            ..bang("DDG", "https://mine.example.com/?q=", 0)
+        }];
+        let winner = |policy, offsets| {
+            let (cache, collisions) =
+                build_cache_with(remote.clone(), &configured, policy, offsets).unwrap();
+            assert!(collisions.is_empty());
+            cache["ddg"].origin
+        };
+        let offsets = |remote, config| Some(RelevanceOffsets { remote, config });
+        assert_eq!(
+            winner(CollisionPolicy::HighestRelevance, None),
+            BangOrigin::Config
+        );
+        assert_eq!(
+            winner(CollisionPolicy::HighestRelevance, offsets(0, 0)),
+            BangOrigin::Remote
+        );
+        assert_eq!(
+            winner(CollisionPolicy::HighestRelevance, offsets(0, 20)),
+            BangOrigin::Config
+        );
+        assert_eq!(
+            winner(CollisionPolicy::HighestRelevance, offsets(-10, 0)),
+            BangOrigin::Config,
+            "ties go to the configured bang"
+        );
+        assert_eq!(
+            winner(CollisionPolicy::KeepFirst, offsets(100, 0)),
+            BangOrigin::Config
+        );
+    }
+
+    #[test]
+    fn test_literal_braces() {
+        assert_eq!(fill_template("a{}b", "{}", "x"), "axb");
+        assert_eq!(fill_template("a{{}}b{}", "{}", "x"), "a{}bx");
+        assert_eq!(fill_template("{{}}{{}}", "{}", "x"), "{}{}");
+        assert_eq!(fill_template("{{{s}}}{{}}", "{{{s}}}", "x"), "x{}");
+
+        let options = ResolveOptions {
+            default_search: "https://search.example.com/?filter={{}}&q={}",
+            ..base_options()
+        };
+        let cache = build_cache(
+            Vec::new(),
+            &[
+                test_bang("jq", "https://jqplay.example.com/?filter={{}}&q={{{s}}}"),
+                test_bang("append", "https://example.com/{{}}/?q="),
+                test_bang("plain", "https://example.com/?json={}&q={{{s}}}"),
+            ],
+        );
+        let url = |query| resolve(&cache, &options, query).url;
+        assert_eq!(url("rust"), "https://search.example.com/?filter={}&q=rust");
+        assert_eq!(url(""), "https://search.example.com/?filter={}&q=");
+        // braces in the search term are encoded, never substituted
+        assert_eq!(
+            url("!jq {{}} {}"),
+            "https://jqplay.example.com/?filter={}&q=%7B%7B%7D%7D%20%7B%7D"
+        );
+        assert_eq!(url("!append rust"), "https://example.com/{}/?q=rust");
+        // a lone {} isn't a placeholder of bangs
+        assert_eq!(url("!plain rust"), "https://example.com/?json={}&q=rust");
+    }
+
+    #[test]
+    fn test_mobile_url_template() {
+        let desktop_options = base_options();
+        let mobile_options = ResolveOptions {
+            mobile: true,
+            ..desktop_options
+        };
+        let cache = build_cache(
+            Vec::new(),
+            &[
+                Bang {
+                    mobile_url_template: Some(
+                        "vnd.youtube://results?search_query={{{s}}}".to_string(),
+                    ),
+                    ..test_bang("yt", "https://www.youtube.com/results?search_query={{{s}}}")
+                },
+                test_bang("w", "https://en.wikipedia.org/w/index.php?search={{{s}}}"),
+            ],
+        );
+        let mobile = |query| resolve(&cache, &mobile_options, query).url;
+        let desktop = |query| resolve(&cache, &desktop_options, query).url;
+        assert_eq!(
+            mobile("!yt rust talks"),
+            "vnd.youtube://results?search_query=rust%20talks"
+        );
+        assert_eq!(
+            desktop("!yt rust talks"),
+            "https://www.youtube.com/results?search_query=rust%20talks"
+        );
+        // without a mobile template the regular one is used
+        assert_eq!(
+            mobile("!w rust"),
+            "https://en.wikipedia.org/w/index.php?search=rust"
+        );
+        assert_eq!(mobile("rust"), desktop("rust"));
+    }
+
+    #[test]
+    fn test_localized() {
+        let cache = build_cache(
+            Vec::new(),
+            &[
+                Bang {
+                    mobile_url_template: Some(
+                        "https://en.m.wikipedia.org/?search={{{s}}}".to_string(),
+                    ),
+                    localized: Some(BTreeMap::from([
+                        (
+                            "de".to_string(),
+                            "https://de.wikipedia.org/wiki/Special:Search?search={{{s}}}"
+                                .to_string(),
+                        ),
+                        (
+                            "pt-BR".to_string(),
+                            "https://pt.wikipedia.org/w/index.php?search={{{s}}}".to_string(),
+                        ),
+                    ])),
+                    ..test_bang("w", "https://en.wikipedia.org/w/index.php?search={{{s}}}")
+                },
+                test_bang("g", "https://www.google.com/search?q={{{s}}}"),
+            ],
+        );
+        let resolve_in = |languages: &[&str], mobile: bool, query: &str| {
+            let languages: Vec<String> = languages.iter().map(ToString::to_string).collect();
+            let options = ResolveOptions {
+                mobile,
+                languages: &languages,
+                ..base_options()
+            };
+            resolve(&cache, &options, query).url
+        };
+        let german = "https://de.wikipedia.org/wiki/Special:Search?search=rust";
+        let english = "https://en.wikipedia.org/w/index.php?search=rust";
+        assert_eq!(resolve_in(&["de"], false, "!w rust"), german);
+        assert_eq!(resolve_in(&[], false, "!w rust"), english);
+        assert_eq!(resolve_in(&["en"], false, "!w rust"), english);
+        // the first language decides, by tag and then language
+        assert_eq!(resolve_in(&["de-at", "en"], false, "!w rust"), german);
+        assert_eq!(
+            resolve_in(&["fr", "de-at", "en"], false, "!w rust"),
+            english
+        );
+        assert_eq!(
+            resolve_in(&["en-us", "en", "de"], false, "!w rust"),
+            english
+        );
+        assert_eq!(
+            resolve_in(&["pt-br"], false, "!w rust"),
+            "https://pt.wikipedia.org/w/index.php?search=rust"
+        );
+        assert_eq!(resolve_in(&["pt"], false, "!w rust"), english);
+        // localized templates go before mobile ones
+        assert_eq!(resolve_in(&["de"], true, "!w rust"), german);
+        assert_eq!(
+            resolve_in(&["fr"], true, "!w rust"),
+            "https://en.m.wikipedia.org/?search=rust"
+        );
+        assert_eq!(
+            resolve_in(&["de"], false, "!g rust"),
+            "https://www.google.com/search?q=rust"
+        );
+    }
+
+    #[test]
+    fn test_idn_hosts() {
+        for (url, ascii) in [
+            (
+                "https://müller.example/?q={}",
+                "https://xn--mller-kva.example/?q={}",
+            ),
+            (
+                "https://user@Bücher.example:8443/süß?q=ä",
+                "https://user@xn--bcher-kva.example:8443/süß?q=ä",
+            ),
+            ("https://example.com/süß", "https://example.com/süß"),
+            ("https://[::1]/ä", "https://[::1]/ä"),
+            ("not a url ä", "not a url ä"),
+        ] {
+            assert_eq!(punycode_host(url), ascii, "{url}");
+        }
+        assert_eq!(
+            unicode_host("https://xn--mller-kva.example/?q={{{s}}}"),
+            "https://müller.example/?q={{{s}}}"
+        );
+        assert_eq!(
+            unicode_host("https://example.com/xn--a"),
+            "https://example.com/xn--a"
+        );
+
+        let cache = build_cache(
+            Vec::new(),
+            &[Bang {
+                localized: Some(BTreeMap::from([(
+                    "ru".to_string(),
+                    "https://пример.рф/?q={{{s}}}".to_string(),
+                )])),
+                ..test_bang("m", "https://müller.example/suche?q={{{s}}}")
+            }],
+        );
+        assert_eq!(
+            cache["m"].bang.url_template, "https://xn--mller-kva.example/suche?q={{{s}}}",
+            "converted when cached"
+        );
+        let languages = ["ru".to_string()];
+        let options = ResolveOptions {
+            default_search: "https://süche.example/?q={}",
+            sanitize_query: false,
+            post_bangs: false,
+            ignore_quoted_bangs: false,
+            ..base_options()
+        };
+        assert_eq!(
+            resolve(&cache, &options, "!m grüße").url,
+            "https://xn--mller-kva.example/suche?q=gr%C3%BC%C3%9Fe"
+        );
+        assert_eq!(
+            resolve(&cache, &options, "grüße").url,
+            "https://xn--sche-0ra.example/?q=gr%C3%BC%C3%9Fe"
+        );
+        let localized = ResolveOptions {
+            languages: &languages,
+            ..options
+        };
+        assert_eq!(
+            resolve(&cache, &localized, "!m rust").url,
+            "https://xn--e1afmkfd.xn--p1ai/?q=rust"
+        );
+    }
+
+    #[test]
+    fn test_term_encoding() {
+        let options = ResolveOptions {
+            sanitize_query: false,
+            post_bangs: false,
+            ignore_quoted_bangs: false,
+            ..base_options()
+        };
+        let bang = |trigger: &str, url_template: &str, term_encoding| {
+            Bang {
+                term_encoding,
+                ..test_bang(trigger, url_template)
+            }
+        };
+        let bangs = [
+            bang("q", "https://example.com/?q={{{s}}}", None),
+            bang(
+                "component",
+                "https://example.com/?q={{{s}}}",
+                Some(TermEncoding::Component),
+            ),
+            bang(
+                "wayback",
+                "https://web.archive.org/web/{{{s}}}",
+                Some(TermEncoding::None),
+            ),
+            bang("raw", "https://example.com/?url=", Some(TermEncoding::None)),
+            bang(
+                "wiki",
+                "https://wiki.example.com/wiki/{{{s}}}",
+                Some(TermEncoding::Path),
+            ),
+        ];
+        let cache = build_cache(Vec::new(), &bangs);
+        let url = |query: &str| resolve(&cache, &options, query).url;
+
+        let term = "https://example.com/post/123?a=1&b=x y#top";
+        for (trigger, expected) in [
+            (
+                "q",
+                "https://example.com/?q=https%3A//example.com/post/123%3Fa%3D1%26b%3Dx%20y%23top",
+            ),
+            (
+                "component",
+                "https://example.com/?q=https%3A//example.com/post/123%3Fa%3D1%26b%3Dx%20y%23top",
+            ),
+            (
+                "wayback",
+                "https://web.archive.org/web/https://example.com/post/123?a=1&b=x%20y#top",
+            ),
+            (
+                "raw",
+                "https://example.com/?url=https://example.com/post/123?a=1&b=x%20y#top",
+            ),
+            (
+                "wiki",
+                "https://wiki.example.com/wiki/https://example.com/post/123%3Fa=1&b=x%20y%23top",
+            ),
+        ] {
+            assert_eq!(url(&format!("!{trigger} {term}")), expected, "{trigger}");
+        }
+        // percent signs are kept as typed only without encoding
+        assert_eq!(
+            url("!wayback https://example.com/caf%C3%A9 ✓"),
+            "https://web.archive.org/web/https://example.com/caf%C3%A9%20%E2%9C%93"
+        );
+        assert_eq!(url("!wiki 100%"), "https://wiki.example.com/wiki/100%25");
+
+        let warned: Vec<&str> = bangs
+            .iter()
+            .filter(|bang| bang.raw_term_in_query())
+            .map(|bang| bang.trigger.as_str())
+            .collect();
+        assert_eq!(warned, ["raw"]);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_trace_spans() {
+        use std::sync::{Arc, Mutex};
+
+        use tracing::span::{Attributes, Id};
+        use tracing::{Level, Subscriber};
+        use tracing_subscriber::layer::{Context, SubscriberExt as _};
+        use tracing_subscriber::{Layer, filter};
+
+        /// Records the names of the spans created, in order.
+        #[derive(Clone, Default)]
+        struct SpanNames(Arc<Mutex<Vec<&'static str>>>);
+
+        impl<S: Subscriber> Layer<S> for SpanNames {
+            fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+                self.0.lock().unwrap().push(attrs.metadata().name());
+            }
+        }
+
+        let options = base_options();
+        let cache = build_cache(
+            Vec::new(),
+            &[test_bang(
+                "w",
+                "https://en.wikipedia.org/w/index.php?search={{{s}}}",
+            )],
+        );
+        let spans = |level, query| {
+            let names = SpanNames::default();
+            let subscriber = tracing_subscriber::registry().with(
+                names
+                    .clone()
+                    .with_filter(filter::LevelFilter::from_level(level)),
+            );
+            tracing::subscriber::with_default(subscriber, || resolve(&cache, &options, query));
+            names.0.lock().unwrap().clone()
+        };
+
+        assert_eq!(
+            spans(Level::TRACE, "!w rust"),
+            ["resolve", "tokenize", "lookup", "build_url"]
+        );
+        assert_eq!(spans(Level::TRACE, "rust !nope"), ["resolve", "tokenize"]);
+        assert_eq!(spans(Level::TRACE, "rust"), ["resolve"]);
+        assert_eq!(spans(Level::DEBUG, "!w rust"), [] as [&str; 0]);
+    }
+
+    #[test]
+    fn test_resolve_without_bang() {
+        let options = base_options();
+        let mut cache = build_cache(Vec::new(), &[]);
+        for query in ["rust lang", "rust", "rust !new", "rust!new lang"] {
+            let resolution = resolve(&cache, &options, query);
+            assert!(resolution.fallback, "{query:?}");
+            assert_eq!(
+                resolution.url,
+                format!(
+                    "https://search.example.com/?q={}",
+                    urlencoding::encode(query)
+                )
+            );
+        }
+
+        // a bang added after it was unknown is picked up right away
+        insert_bang(
+            &mut cache,
+            test_bang("new", "https://new.example.com/?q="),
+            BangOrigin::Config,
+            CollisionPolicy::KeepFirst,
+            None,
+        )
+        .unwrap();
+        let resolution = resolve(&cache, &options, "rust !new");
+        assert_eq!(resolution.url, "https://new.example.com/?q=rust");
+        assert!(resolve(&cache, &options, "rust!new lang").fallback);
+    }
+
+    #[test]
+    fn test_domain_bangs() {
+        for domain in [
+            "github.com",
+            "docs.rs",
+            "en.wikipedia.org",
+            "my-site.co.uk",
+            "x1.io",
+        ] {
+            assert!(is_domain(domain), "{domain}");
+        }
+        for trigger in [
+            "g",
+            "gh",
+            "co.u",
+            "1.23",
+            "github.",
+            ".com",
+            "a..com",
+            "-a.com",
+            "a-.com",
+            "git hub.com",
+            "github.com/x",
+            "a.c0m",
+        ] {
+            assert!(!is_domain(trigger), "{trigger}");
+        }
+
+        let cache = build_cache(
+            vec![test_bang(
+                "docs.rs",
+                "https://docs.rs/releases/search?query={{{s}}}",
+            )],
+            &[],
+        );
+        let mut options = base_options();
+        assert!(
+            resolve(&cache, &options, "!github.com rust").fallback,
+            "opt-in only"
+        );
+
+        options.domain_bangs = true;
+        let resolution = resolve(&cache, &options, "async !GitHub.com rust");
+        assert_eq!(
+            resolution.url,
+            "https://search.example.com/?q=site%3Agithub.com%20async%20%20rust"
+        );
+        assert!(!resolution.fallback && resolution.trigger.is_none());
+        assert_eq!(
+            resolve(&cache, &options, "!github.com").url,
+            "https://github.com/"
+        );
+        // known bangs win, even after the domain
+        assert_eq!(
+            resolve(&cache, &options, "!github.com !docs.rs serde").url,
+            "https://docs.rs/releases/search?query=%21github.com%20%20serde"
+        );
+        assert!(resolve(&cache, &options, "\"!github.com rust\"").fallback);
+        assert!(resolve(&cache, &options, "!nodomain rust").fallback);
+    }
+
+    #[test]
+    fn test_decode_incoming() {
+        // query, decoded with auto, decoded with always
+        let inputs = [
+            ("50%", "50%", "50%"),
+            ("50% off", "50% off", "50% off"),
+            ("100%25", "100%", "100%"),
+            ("a%20b", "a b", "a b"),
+            ("a b", "a b", "a b"),
+            ("%zz", "%zz", "%zz"),
+            ("%2", "%2", "%2"),
+            ("%", "%", "%"),
+            ("%+1", "%+1", "%+1"),
+            ("!g%20rust%20lang", "!g rust lang", "!g rust lang"),
+            ("caf%C3%A9", "café", "café"),
+            ("caf%c3%a9", "café", "café"),
+            ("caf%E9", "caf%E9", "caf\u{FFFD}"),
+            // half encoded
+            ("a%20b c", "a%20b c", "a b c"),
+            ("café%20au%20lait", "café%20au%20lait", "café au lait"),
+            ("a%20b%zz", "a%20b%zz", "a b%zz"),
+            ("50%25 off", "50%25 off", "50% off"),
+            // only escapes that weren't needed
+            ("a%2Fb", "a%2Fb", "a/b"),
+            ("%41%42", "%41%42", "AB"),
+            ("a%2Fb%20c", "a/b c", "a/b c"),
+            // encoded twice, decoded only once
+            ("a%2520b", "a%20b", "a%20b"),
+            ("a%0Ab", "a\nb", "a\nb"),
+            ("", "", ""),
+        ];
+        for (query, auto, always) in inputs {
+            assert_eq!(decode_incoming(query, DecodeIncoming::Never), query);
+            assert_eq!(
+                decode_incoming(query, DecodeIncoming::Auto),
+                auto,
+                "{query}"
+            );
+            assert_eq!(
+                decode_incoming(query, DecodeIncoming::Always),
+                always,
+                "{query}"
+            );
+        }
+
+        let cache = build_cache(
+            vec![test_bang("g", "https://google.example.com/?q={{{s}}}")],
+            &[],
+        );
+        let mut options = base_options();
+        assert_eq!(
+            resolve(&cache, &options, "rust%20lang").url,
+            "https://search.example.com/?q=rust%2520lang"
+        );
+        options.decode_incoming = DecodeIncoming::Auto;
+        assert_eq!(
+            resolve(&cache, &options, "rust%20lang").url,
+            "https://search.example.com/?q=rust%20lang"
+        );
+        assert_eq!(
+            resolve(&cache, &options, "%21g%20rust").url,
+            "https://google.example.com/?q=rust"
+        );
+        assert_eq!(
+            resolve(&cache, &options, "!g 50% off").url,
+            "https://google.example.com/?q=50%25%20off"
+        );
+        assert_eq!(
+            resolve(&cache, &options, "!g%20a%E2%80%8Bb").url,
+            "https://google.example.com/?q=ab"
+        );
+        assert_eq!(
+            resolve_trigger(&cache, &options, "g", "a%20b").unwrap().url,
+            "https://google.example.com/?q=a%20b"
+        );
+    }
+
+    #[test]
+    fn test_direct_url() {
+        for (query, url) in [
+            ("example.com/foo", "https://example.com/foo"),
+            ("  docs.rs/ ", "https://docs.rs/"),
+            ("www.example.com", "https://www.example.com"),
+            (
+                "en.wikipedia.org/wiki/Rust?action=history#top",
+                "https://en.wikipedia.org/wiki/Rust?action=history#top",
+            ),
+            ("example.com:8443/a", "https://example.com:8443/a"),
+            ("http://example.com", "http://example.com"),
+            ("HTTPS://Example.com/x", "HTTPS://Example.com/x"),
+            ("https://127.0.0.1:3000/", "https://127.0.0.1:3000/"),
+            ("example.com/café", "https://example.com/caf%C3%A9"),
+            (
+                "müller.example/café",
+                "https://xn--mller-kva.example/caf%C3%A9",
+            ),
+            (
+                "http://Bücher.example:8080/",
+                "http://xn--bcher-kva.example:8080/",
+            ),
+            ("www.пример.рф", "https://www.xn--e1afmkfd.xn--p1ai"),
+            ("https://docs.rs", "https://docs.rs"),
+        ] {
+            assert_eq!(direct_url(query, &[]).as_deref(), Some(url), "{query}");
+        }
+        for query in [
+            "",
+            "rust",
+            "rust lang",
+            "example.com foo",
+            "1.5",
+            "localhost:3000",
+            "127.0.0.1",
+            "example.com:port",
+            "ftp://example.com",
+            "javascript://example.com/%0Aalert(1)",
+            "https://",
+            "https://user@evil.example",
+            "!github.com",
+            "what?",
+            // names that only look like a host
+            "node.js",
+            "README.md",
+            "docs.rs",
+            "example.com?q=rust",
+        ] {
+            assert_eq!(direct_url(query, &[]), None, "{query}");
+        }
+
+        let hosts = ["example.com".to_string(), "Docs.RS".to_string()];
+        assert!(direct_url("example.com/a", &hosts).is_some());
+        assert!(direct_url("www.example.com/a", &hosts).is_some());
+        assert!(direct_url("https://docs.rs/serde", &hosts).is_some());
+        assert_eq!(direct_url("notexample.com/a", &hosts), None);
+        assert_eq!(direct_url("example.com.evil.example", &hosts), None);
+        let hosts = ["müller.example".to_string()];
+        assert!(direct_url("xn--mller-kva.example/a", &hosts).is_some());
+        assert!(direct_url("www.müller.example/a", &hosts).is_some());
+        assert_eq!(direct_url("mueller.example/a", &hosts), None);
+        let hosts = ["example.com".to_string(), "Docs.RS".to_string()];
+
+        let cache = build_cache(Vec::new(), &[]);
+        let mut options = ResolveOptions {
+            direct_url_hosts: &hosts,
+            ..base_options()
+        };
+        assert!(
+            resolve(&cache, &options, "example.com/foo").fallback,
+            "opt-in only"
+        );
+        options.direct_urls = true;
+        let resolution = resolve(&cache, &options, "example.com/foo");
+        assert_eq!(resolution.url, "https://example.com/foo");
+        assert!(!resolution.fallback && resolution.trigger.is_none());
+        assert_eq!(
+            resolve(&cache, &options, "github.com/rust-lang").url,
+            "https://search.example.com/?q=github.com%2Frust-lang"
+        );
+    }
+}
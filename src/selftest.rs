@@ -0,0 +1,247 @@
+//! Smoke test of a running configuration, served by `/selftest` and run by
+//! `redirector selftest`.
+//!
+//! None of the checks contact the targets of the bangs, the only outbound
+//! request is the one to the suggestion upstream, which is skipped in
+//! `offline` mode.
+
+use std::time::Duration;
+
+use reqwest::Url;
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+
+use crate::config::{AppConfig, AppState};
+use crate::resolver::{self, BangCache};
+use crate::server::{capabilities, opensearch_xml};
+use crate::suggest::suggestions;
+use crate::{BANG_CACHE, Resolution};
+
+/// Search terms used to resolve queries.
+const PROBE_TERMS: &str = "redirector selftest";
+/// How long the suggestion upstream may take to answer.
+const SUGGEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<String, String>) -> Self {
+        let passed = result.is_ok();
+        Self {
+            name,
+            passed,
+            detail: result.unwrap_or_else(|e| e),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SelftestReport {
+    /// Whether all checks passed.
+    pub passed: bool,
+    pub checks: Vec<Check>,
+}
+
+/// Run all checks against the global bang cache.
+pub async fn run(app_state: &AppState) -> SelftestReport {
+    let mut checks = check_resolution(&app_state.get_config(), &BANG_CACHE.read());
+    checks.extend(check_suggestions(app_state).await);
+    SelftestReport {
+        passed: checks.iter().all(|check| check.passed),
+        checks,
+    }
+}
+
+/// The checks not needing any I/O: the cache, resolving with and without a
+/// bang and the `OpenSearch` description.
+#[must_use]
+pub fn check_resolution(app_config: &AppConfig, cache: &BangCache) -> Vec<Check> {
+    let options = app_config.resolve_options();
+    let trigger = &app_config.selftest_trigger;
+
+    let bang = Check::new("bang", {
+        let resolution = resolver::resolve(cache, &options, &format!("!{trigger} {PROBE_TERMS}"));
+        if resolution.trigger.as_deref() == Some(trigger.as_str()) {
+            check_url(&resolution)
+        } else {
+            Err(format!("!{trigger} is not loaded"))
+        }
+    });
+    let default_search = Check::new("default_search", {
+        let resolution = resolver::resolve(cache, &options, PROBE_TERMS);
+        check_url(&resolution)
+    });
+
+    vec![
+        Check::new(
+            "cache",
+            if cache.is_empty() {
+                Err("the bang cache is empty".to_string())
+            } else {
+                Ok(format!("{} bangs loaded", cache.len()))
+            },
+        ),
+        bang,
+        default_search,
+        Check::new(
+            "opensearch",
+            check_xml(&opensearch_xml(app_config)).map(|()| "well-formed".to_string()),
+        ),
+        check_capabilities(app_config),
+    ]
+}
+
+/// Whether the capabilities document lists the search route and itself.
+fn check_capabilities(app_config: &AppConfig) -> Check {
+    let capabilities = capabilities(app_config);
+    let missing: Vec<&str> = ["/", "/.well-known/redirector.json"]
+        .into_iter()
+        .filter(|path| !capabilities.routes.iter().any(|route| route.path == *path))
+        .collect();
+    Check::new(
+        "capabilities",
+        if missing.is_empty() {
+            Ok(format!("{} routes", capabilities.routes.len()))
+        } else {
+            Err(format!("missing routes {missing:?}"))
+        },
+    )
+}
+
+/// Whether `/suggest` answers for the probe terms in time, `None` if it is
+/// disabled and there is nothing to check.
+async fn check_suggestions(app_state: &AppState) -> Option<Check> {
+    if !app_state.get_config().routes.suggest {
+        return None;
+    }
+    let answer = tokio::time::timeout(
+        SUGGEST_TIMEOUT,
+        suggestions(app_state, PROBE_TERMS, &HeaderMap::new()),
+    )
+    .await;
+    Some(Check::new(
+        "suggest",
+        match answer {
+            Ok(suggestions) if suggestions[0] == PROBE_TERMS && suggestions[1].is_array() => {
+                Ok(format!(
+                    "answered with {} suggestions",
+                    suggestions[1].as_array().map_or(0, Vec::len)
+                ))
+            },
+            Ok(suggestions) => Err(format!("unexpected suggestions {suggestions}")),
+            Err(_) => Err(format!("no answer within {SUGGEST_TIMEOUT:?}")),
+        },
+    ))
+}
+
+/// Whether the resolved URL is an absolute http(s) URL.
+fn check_url(resolution: &Resolution) -> Result<String, String> {
+    let url = Url::parse(&resolution.url).map_err(|e| format!("'{}': {e}", resolution.url))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(format!("'{url}' is not an http(s) URL"));
+    }
+    Ok(resolution.url.clone())
+}
+
+/// Check that every element of `xml` is closed in order and there is a single
+/// root. Enough to catch broken templates, not a validating parser.
+fn check_xml(xml: &str) -> Result<(), String> {
+    let mut open = Vec::new();
+    let mut roots = 0;
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        let end = rest[start..]
+            .find('>')
+            .ok_or_else(|| "unterminated tag".to_string())?
+            + start;
+        let tag = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            match open.pop() {
+                Some(opened) if opened == name.trim() => {},
+                opened => return Err(format!("</{name}> closes {opened:?}")),
+            }
+            continue;
+        }
+        if open.is_empty() {
+            roots += 1;
+        }
+        if !tag.ends_with('/') {
+            open.push(tag.split_whitespace().next().unwrap_or_default());
+        }
+    }
+    if let Some(opened) = open.last() {
+        return Err(format!("<{opened}> is never closed"));
+    }
+    if roots != 1 {
+        return Err(format!("expected a single root element, found {roots}"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bang::test_bang;
+
+    #[test]
+    fn test_check_resolution() {
+        let app_config = AppConfig::default();
+        let checks = check_resolution(&app_config, &BangCache::new());
+        let failed: Vec<&str> = checks
+            .iter()
+            .filter(|check| !check.passed)
+            .map(|check| check.name)
+            .collect();
+        assert_eq!(failed, ["cache", "bang"], "{checks:?}");
+
+        let cache = resolver::build_cache(
+            vec![test_bang(
+                "w",
+                "https://en.wikipedia.org/w/index.php?search={{{s}}}",
+            )],
+            &[],
+        );
+        let checks = check_resolution(&app_config, &cache);
+        assert!(checks.iter().all(|check| check.passed), "{checks:?}");
+
+        let broken = AppConfig {
+            default_search: "search for {}".to_string(),
+            base_url: Some("http://example.com/?a=<b>".to_string()),
+            ..AppConfig::default()
+        };
+        let failed: Vec<&str> = check_resolution(&broken, &cache)
+            .into_iter()
+            .filter(|check| !check.passed)
+            .map(|check| check.name)
+            .collect();
+        // the base URL is escaped, so the description stays well-formed
+        assert_eq!(failed, ["default_search"]);
+    }
+
+    #[tokio::test]
+    async fn test_check_suggestions() {
+        let app_state = AppState::new(AppConfig {
+            offline: true,
+            ..AppConfig::default()
+        });
+        let check = check_suggestions(&app_state).await.unwrap();
+        assert!(check.passed, "{check:?}");
+        assert!(check.detail.starts_with("answered with "), "{check:?}");
+
+        app_state.config.write().routes.suggest = false;
+        assert_eq!(
+            check_suggestions(&app_state).await,
+            None,
+            "nothing is checked without /suggest"
+        );
+    }
+}
@@ -0,0 +1,4305 @@
+//! HTTP interface of redirector.
+
+use std::borrow::Cow;
+use std::convert::Infallible;
+use std::env;
+use std::fmt::Write as _;
+use std::process::{Command, Stdio, exit};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, FromRequest, Path, Query, Request, State};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, header};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::{MethodRouter, get, post};
+use axum::{Json, Router};
+use clap::ValueEnum as _;
+use futures_util::{Stream, StreamExt as _};
+use heck::ToTitleCase as _;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::time::sleep;
+use tower_http::compression::predicate::{NotForContentType, Predicate as _};
+use tower_http::compression::{CompressionLayer, DefaultPredicate};
+use tower_http::trace::TraceLayer;
+use tracing::{debug, error, info, warn};
+
+use crate::bang_groups::{GroupCache, group_names};
+use crate::bookmarks::ImportFormat;
+use crate::cache::cache_generation;
+use crate::compact::compact_file;
+use crate::config::{
+    AppConfig, AppState, QuotaExceeded, TemplateCheck, TimeoutsConfig, append_file_config,
+    append_file_config_bangs, check_url_template, reload_bangs, reload_bangs_now, reload_config,
+};
+use crate::events::RecentEvent;
+use crate::export::ExportFormat;
+use crate::firehose::FirehoseEvent;
+use crate::form::form_value;
+use crate::gzip::{GunzipError, MAX_DECOMPRESSED_SIZE, gunzip};
+use crate::health::{HealthReport, render_metrics};
+use crate::linkcheck::{BangCheck, check_config_bangs, remote_bangs};
+use crate::recent::RecentResolution;
+use crate::resolver::{self, sorted_bangs, unicode_host};
+use crate::stats::MonthReport;
+use crate::suggest::{SuggestionProvider, suggestions};
+use crate::usage::{RETENTION, TriggerCount, parse_window};
+use crate::{
+    BANG_CACHE, BangCache, BangOrigin, CachedBang, PostForm, Resolution, SourceMetadata, assets,
+    get_bang, language, popular, replace_config_bangs, resolve_with_info,
+};
+
+/// The `query_param` parameter of the query string, or of the form body of
+/// POST requests, decoded with [`form_value`] and `plus_as_space`.
+#[derive(Debug)]
+struct SearchQuery(Option<String>);
+
+impl FromRequest<AppState> for SearchQuery {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &AppState) -> Result<Self, Response> {
+        let (query_param, plus_as_space) = {
+            let config = state.config.read();
+            (config.query_param.clone(), config.plus_as_space)
+        };
+        if matches!(*req.method(), Method::GET | Method::HEAD) {
+            let query = req.uri().query().unwrap_or_default();
+            return Ok(Self(form_value(query, &query_param, plus_as_space)));
+        }
+        let is_form = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| {
+                content_type.starts_with("application/x-www-form-urlencoded")
+            });
+        if !is_form {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected an application/x-www-form-urlencoded body",
+            )
+                .into_response());
+        }
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(Self(form_value(
+            &String::from_utf8_lossy(&body),
+            &query_param,
+            plus_as_space,
+        )))
+    }
+}
+
+/// Like [`SearchQuery`], but falling back to a plain `q` in the query string,
+/// for API clients that don't know about `query_param`.
+#[derive(Debug)]
+struct ApiQuery(Option<String>);
+
+impl FromRequest<AppState> for ApiQuery {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &AppState) -> Result<Self, Response> {
+        let plus_as_space = state.config.read().plus_as_space;
+        let plain_q = form_value(req.uri().query().unwrap_or_default(), "q", plus_as_space);
+        let SearchQuery(query) = SearchQuery::from_request(req, state).await?;
+        Ok(Self(query.or(plain_q)))
+    }
+}
+
+async fn favicon(State(app_state): State<AppState>, headers: HeaderMap) -> Response {
+    static_file(
+        State(app_state),
+        Path("icon64.png".to_string()),
+        Uri::from_static("/static/icon64.png"),
+        headers,
+    )
+    .await
+}
+
+/// Files of `ui.static_dir`, or the built-in ones, with the `ETag` and
+/// precompressed variants of [`assets::serve`].
+async fn static_file(
+    State(app_state): State<AppState>,
+    Path(path): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    // the wildcard matches trailing slashes too, redirect like other routes
+    if path.ends_with('/') {
+        return strip_trailing_slash(&["/static/{*path}"], &uri);
+    }
+    let static_dir = app_state.config.read().ui.static_dir.clone();
+    assets::serve(static_dir.as_deref(), &path, &headers).await
+}
+
+/// Served as `/robots.txt` unless configured otherwise, searches and the
+/// bang list shouldn't be crawled.
+const DEFAULT_ROBOTS_TXT: &str = "User-agent: *\nDisallow: /\n";
+
+async fn robots_txt(State(app_state): State<AppState>) -> impl IntoResponse {
+    let config = app_state.get_config();
+    let mut robots_txt = None;
+    if let Some(path) = &config.robots_txt_path {
+        match tokio::fs::read_to_string(path).await {
+            Ok(contents) => robots_txt = Some(contents),
+            Err(e) => error!("Failed to read robots.txt at {}: {e}", path.display()),
+        }
+    }
+    let robots_txt = robots_txt
+        .or(config.robots_txt)
+        .unwrap_or_else(|| DEFAULT_ROBOTS_TXT.to_string());
+    (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        robots_txt,
+    )
+}
+
+fn log_redirect(query: &str, resolution: &Resolution, redact_queries: bool) {
+    if redact_queries {
+        info!(
+            trigger = resolution.trigger.as_deref(),
+            note = resolution.note.as_deref(),
+            "Redirecting a query."
+        );
+    } else {
+        info!(
+            trigger = resolution.trigger.as_deref(),
+            note = resolution.note.as_deref(),
+            "Redirecting '{query}' to '{}'.",
+            resolution.url
+        );
+    }
+}
+
+/// Handler function that extracts the `q` parameter and redirects accordingly
+///
+/// POST bangs are answered with a form submitting the term to the target,
+/// also for POST requests, whose body holds the query rather than the field
+/// the target expects.
+async fn handler(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    SearchQuery(query): SearchQuery,
+) -> Response {
+    if app_state.maintenance.load(Ordering::Relaxed) {
+        return maintenance_response();
+    }
+    let Some(query) = query else {
+        let landing_page = app_state.landing_page.read().clone();
+        return landing_page.map_or_else(
+            || Redirect::to("/bangs").into_response(),
+            |page| {
+                if page.contains(popular::PLACEHOLDER) {
+                    let links = popular::render(&app_state.popular_bangs.read());
+                    Html(page.replacen(popular::PLACEHOLDER, &links, 1)).into_response()
+                } else {
+                    Html(page.to_string()).into_response()
+                }
+            },
+        );
+    };
+    let start = Instant::now();
+    let config = app_state.get_config();
+    let languages = request_languages(&headers);
+    let resolution = resolver::resolve(
+        &BANG_CACHE.read(),
+        &request_resolve_options(&config, &headers, &languages),
+        &query,
+    );
+    let latency = start.elapsed();
+    debug!("Request completed in {latency:?}");
+    redirect(&app_state, &query, &resolution, latency)
+}
+
+/// The languages the client of a request with `headers` prefers, see
+/// [`language::preferred_languages`].
+fn request_languages(headers: &HeaderMap) -> Vec<String> {
+    language::preferred_languages(
+        request_cookies(headers).as_deref(),
+        headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok()),
+    )
+}
+
+/// All `Cookie` headers of a request as one, HTTP/2 clients may send a header
+/// per cookie.
+fn request_cookies(headers: &HeaderMap) -> Option<String> {
+    let cookies: Vec<&str> = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    (!cookies.is_empty()).then(|| cookies.join("; "))
+}
+
+/// The resolve options for a request with `headers` from a client preferring
+/// `languages`, the mobile ones for mobile browsers.
+///
+/// A `localized_default_search` for the languages replaces `default_search`,
+/// while `mobile_default_search` still takes precedence on phones.
+fn request_resolve_options<'a>(
+    config: &'a AppConfig,
+    headers: &HeaderMap,
+    languages: &'a [String],
+) -> resolver::ResolveOptions<'a> {
+    let mobile = config.is_mobile(
+        request_cookies(headers).as_deref(),
+        headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok()),
+    );
+    let mut options = if mobile {
+        config.mobile_resolve_options()
+    } else {
+        config.resolve_options()
+    };
+    options.languages = languages;
+    if !(mobile && config.mobile_default_search.is_some())
+        && let Some(default_search) =
+            resolver::localized(&config.localized_default_search, languages)
+    {
+        options.default_search = default_search;
+    }
+    options
+}
+
+/// Record the resolution of `query`, which took `latency`, and answer with
+/// where it leads.
+fn redirect(
+    app_state: &AppState,
+    query: &str,
+    resolution: &Resolution,
+    latency: Duration,
+) -> Response {
+    let (redact_queries, recent_capacity, stats_persist) = {
+        let config = app_state.config.read();
+        (
+            config.redact_queries,
+            config.debug_recent_capacity(),
+            config.stats_persist,
+        )
+    };
+    log_redirect(query, resolution, redact_queries);
+    if app_state.firehose.is_watched() {
+        app_state.firehose.publish(FirehoseEvent::redirect(
+            query,
+            resolution,
+            latency,
+            redact_queries,
+        ));
+    }
+    if let Some(trigger) = &resolution.trigger {
+        app_state.usage.lock().record(trigger);
+    }
+    if recent_capacity > 0 {
+        app_state.recent_resolutions.push(
+            RecentResolution::new(query, resolution, latency),
+            recent_capacity,
+        );
+    }
+    if stats_persist {
+        app_state
+            .stats
+            .lock()
+            .store
+            .record(resolution.trigger.as_deref());
+    }
+    resolution.post.as_ref().map_or_else(
+        || Redirect::to(&resolution.url).into_response(),
+        |post| Html(render_post_form(&resolution.url, post)).into_response(),
+    )
+}
+
+fn maintenance_response() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, "300")],
+        Html(render_error_page(
+            "Down for maintenance",
+            "Searches are not redirected right now, please try again in a few minutes.",
+        )),
+    )
+        .into_response()
+}
+
+/// Redirect the search term to the bang `trigger`, whatever the term
+/// contains, for tools deep-linking into a specific bang. Unknown triggers
+/// are answered with a `404`, the caller named the bang explicitly.
+async fn go(
+    Path(trigger): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    SearchQuery(term): SearchQuery,
+) -> Response {
+    if app_state.maintenance.load(Ordering::Relaxed) {
+        return maintenance_response();
+    }
+    go_in(
+        &BANG_CACHE.read(),
+        &app_state,
+        &headers,
+        &trigger,
+        &term.unwrap_or_default(),
+    )
+}
+
+/// [`go`] with the bangs of `cache`.
+fn go_in(
+    cache: &BangCache,
+    app_state: &AppState,
+    headers: &HeaderMap,
+    trigger: &str,
+    term: &str,
+) -> Response {
+    let start = Instant::now();
+    let config = app_state.get_config();
+    let trigger = trigger.trim_start_matches('!');
+    let languages = request_languages(headers);
+    let resolution = resolver::resolve_trigger(
+        cache,
+        &request_resolve_options(&config, headers, &languages),
+        trigger,
+        term,
+    );
+    let Some(resolution) = resolution else {
+        return (
+            StatusCode::NOT_FOUND,
+            Html(render_error_page(
+                "Unknown bang",
+                &format!("There is no bang !{trigger}, see /bangs for all of them."),
+            )),
+        )
+            .into_response();
+    };
+    redirect(
+        app_state,
+        format!("!{trigger} {term}").trim_end(),
+        &resolution,
+        start.elapsed(),
+    )
+}
+
+/// Render a plain page for errors, like being down for maintenance.
+fn render_error_page(title: &str, message: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><meta name="viewport" content="width=device-width, initial-scale=1.0"><title>{title}</title><style>:root {{ background: #181818; color: #ffffff; font-family: monospace; }} body {{ max-width: 40rem; margin: 4rem auto; padding: 0 1rem; }}</style></head><body><h1>{title}</h1><p>{}</p></body></html>"#,
+        escape_html(message),
+        title = escape_html(title),
+    )
+}
+
+/// Render a page submitting `post` to `action` as soon as it is loaded.
+fn render_post_form(action: &str, post: &PostForm) -> String {
+    let mut inputs = String::new();
+    for (name, value) in &post.hidden {
+        // the term takes precedence over a hidden field of the same name
+        if *name != post.field {
+            write!(
+                inputs,
+                r#"<input type="hidden" name="{}" value="{}"/>"#,
+                escape_html(name),
+                escape_html(value)
+            )
+            .expect("Failed to write to HTML string");
+        }
+    }
+    format!(
+        r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Redirecting...</title></head><body onload="document.forms[0].submit()"><form method="post" action="{}">{inputs}<input type="hidden" name="{}" value="{}"/><noscript><button type="submit">Continue</button></noscript></form></body></html>"#,
+        escape_html(action),
+        escape_html(&post.field),
+        escape_html(&post.term),
+    )
+}
+
+/// Escape text for use in HTML content and attribute values.
+pub(crate) fn escape_html(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len() + 16);
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[derive(Debug, Deserialize)]
+struct ListParams {
+    #[serde(rename = "try")]
+    try_query: Option<String>,
+    /// Show all active bangs in one table instead of grouped by category.
+    #[serde(default)]
+    flat: bool,
+    /// Show only a page of this category, together with `subcategory`.
+    category: Option<String>,
+    subcategory: Option<String>,
+    page: Option<usize>,
+}
+
+/// The grouping of [`BANG_CACHE`] for the `/bangs` page.
+static BANG_GROUPS: GroupCache = GroupCache::new();
+
+/// A search box submitting to `/`, so the instance can be used without
+/// adding it as a search engine. As `/` redirects to `/bangs` without a
+/// landing page, it is the landing page as well.
+fn render_search_box(query_param: &str) -> String {
+    format!(
+        r#"<form action="/" method="get" class="search"><input type="search" name="{}" placeholder="!w rust" size="40" autofocus/> <button type="submit">Search</button></form>"#,
+        escape_html(query_param)
+    )
+}
+
+/// Render the "try it" box of the `/bangs` page, with the resolution of the
+/// query if one was submitted.
+fn render_try(query: Option<&str>, resolution: Option<&Resolution>) -> String {
+    let mut html = format!(
+        r#"<form action="/bangs" method="get"><input type="text" name="try" value="{}" placeholder="!gh redirector" size="40"/> <button type="submit">Try</button></form>"#,
+        escape_html(query.unwrap_or_default())
+    );
+    if let Some(resolution) = resolution {
+        let url = escape_html(&resolution.url);
+        // only offer links the browser would follow as a redirect as well
+        let link =
+            if resolution.url.starts_with("https://") || resolution.url.starts_with("http://") {
+                format!(r#" <a href="{url}">open</a>"#)
+            } else {
+                String::new()
+            };
+        write!(
+            html,
+            "<table><tr><th>URL</th><td><code>{url}</code>{link}</td></tr><tr><th>Trigger</\
+             th><td>{}</td></tr><tr><th>Source</th><td>{}</td></tr><tr><th>Fallback</th><td>{}</\
+             td></tr></table>",
+            resolution
+                .trigger
+                .as_deref()
+                .map_or_else(|| "-".into(), |trigger| escape_html(trigger).into_owned()),
+            resolution
+                .origin
+                .map_or_else(|| "-".to_string(), |origin| origin.to_string()),
+            if resolution.fallback { "yes" } else { "no" },
+        )
+        .expect("Failed to write to HTML string");
+    }
+    html
+}
+
+/// Styling of the pages with tables, like `/bangs` and `/admin`, also served
+/// as `/static/style.css`.
+const TABLE_STYLE: &str = include_str!("../res/style.css");
+
+/// The `<style>` element with the stylesheet of `ui.custom_css_path`, if
+/// there is one. It can't end the element early.
+fn custom_style(custom_css: Option<&str>) -> String {
+    custom_css.map_or_else(String::new, |css| {
+        format!("<style>{}</style>", css.replace("</", "<\\/"))
+    })
+}
+
+async fn list_bangs(
+    Query(params): Query<ListParams>,
+    State(app_state): State<AppState>,
+) -> Html<String> {
+    let pkg_name = env!("CARGO_PKG_NAME").to_title_case();
+    let mut html = format!(
+        "<style>{TABLE_STYLE}</style>{}<html>",
+        custom_style(app_state.custom_css.read().as_deref())
+    );
+    html += format!(r#"<head><meta charset="UTF-8"><meta name="viewport" content="width=device-width, initial-scale=1.0"><link rel="search" type="application/opensearchdescription+xml" title="{pkg_name}" href="/opensearch.xml"/><link rel="icon" type="image/png" href="/favicon.ico"/><title>Bang Commands</title></head><body><h1>Bang Commands</h1>"#).as_str();
+    html.push_str(&render_search_box(&app_state.config.read().query_param));
+
+    let resolution = params
+        .try_query
+        .as_deref()
+        .map(|query| resolve_with_info(&app_state.get_config(), query));
+    html.push_str(&render_try(
+        params.try_query.as_deref(),
+        resolution.as_ref(),
+    ));
+    html.push_str(&render_sources(&app_state.sources.read()));
+
+    if let Some(bangs) = &app_state.get_config().bangs {
+        html.push_str("<h2>Configured Bangs</h2><table><th>Abbr.</th><th>Trigger</th><th>URL</th>");
+        for bang in bangs {
+            write!(
+                html,
+                "<tr><td><strong>{}</strong></td><td>{}</td><td>{}</td></tr>",
+                escape_html(&format!("{:?}", bang.short_name)),
+                escape_html(&bang.trigger),
+                escape_html(&bang.url_template)
+            )
+            .expect("Failed to write to HTML string");
+        }
+        html.push_str("</table>");
+    }
+
+    let page_size = app_state.config.read().ui.page_size;
+    let cache = BANG_CACHE.read();
+    if params.flat || cache.is_empty() {
+        html.push_str(&render_active_bangs(
+            &cache,
+            app_state.last_update_error.read().as_deref(),
+        ));
+    } else {
+        let grouped = BANG_GROUPS.get(&cache, cache_generation(), page_size);
+        drop(cache);
+        html.push_str(
+            r#"<h2>Active Bangs</h2><p><a href="/bangs?flat=true">Show all in one table</a></p>"#,
+        );
+        let page = params
+            .category
+            .as_deref()
+            .zip(params.subcategory.as_deref())
+            .map(|(category, subcategory)| {
+                grouped
+                    .render_group_page(category, subcategory, params.page.unwrap_or(1))
+                    .unwrap_or_else(|| "<p>There is no such page of bangs.</p>".to_string())
+            });
+        html.push_str(page.as_deref().unwrap_or(&grouped.html));
+    }
+    html.push_str("</body></html>");
+    Html(html)
+}
+
+/// The table of all bangs in the cache, or why there are none.
+fn render_active_bangs(cache: &crate::BangCache, last_update_error: Option<&str>) -> String {
+    let mut html = String::from("<h2>Active Bangs</h2>");
+    if cache.is_empty() {
+        match last_update_error {
+            Some(error) => {
+                write!(
+                    html,
+                    "<p>No bangs are loaded, the bang list is unreachable: <code>{}</code>. It is \
+                     retried in the background.</p>",
+                    escape_html(error)
+                )
+                .expect("Failed to write to HTML string");
+            },
+            None => {
+                html.push_str(
+                    "<p>No bangs are loaded yet, the bang list is still loading. Reload this page \
+                     in a moment.</p>",
+                );
+            },
+        }
+        return html;
+    }
+    html.push_str("<table><th>Trigger</th><th>URL</th>");
+    for (trigger, cached) in crate::resolver::sorted_bangs(cache) {
+        write!(
+            html,
+            "<tr><td><strong>{}</strong></td><td>{}</td></tr>",
+            escape_html(trigger),
+            escape_html(&unicode_host(&cached.bang.url_template))
+        )
+        .expect("Failed to write to HTML string");
+    }
+    html.push_str("</table>");
+    html
+}
+
+/// Describe where the bang list was loaded from.
+fn render_sources(sources: &[SourceMetadata]) -> String {
+    let mut html = String::new();
+    for source in sources {
+        write!(
+            html,
+            "<p>{} bangs from <code>{}</code>",
+            source.entries,
+            escape_html(&source.source),
+        )
+        .expect("Failed to write to HTML string");
+        if let Some(final_url) = &source.final_url
+            && *final_url != source.source
+        {
+            write!(
+                html,
+                " (redirected to <code>{}</code>)",
+                escape_html(final_url)
+            )
+            .expect("Failed to write to HTML string");
+        }
+        write!(html, ", fetched {}", source.fetched_at_date())
+            .expect("Failed to write to HTML string");
+        if let Some(last_modified) = &source.last_modified {
+            write!(html, ", last modified {}", escape_html(last_modified))
+                .expect("Failed to write to HTML string");
+        }
+        if let Some(etag) = &source.etag {
+            write!(html, ", ETag <code>{}</code>", escape_html(etag))
+                .expect("Failed to write to HTML string");
+        }
+        write!(html, " (hash <code>{}</code>)</p>", source.content_hash)
+            .expect("Failed to write to HTML string");
+    }
+    html
+}
+
+#[derive(Serialize, Debug)]
+struct BangsInfo {
+    sources: Vec<SourceMetadata>,
+    /// Number of bangs in the cache, including configured ones.
+    entries: usize,
+}
+
+async fn api_bangs(State(app_state): State<AppState>) -> Json<BangsInfo> {
+    Json(BangsInfo {
+        sources: app_state.sources.read().clone(),
+        entries: BANG_CACHE.read().len(),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct BangsJsonParams {
+    /// Only bangs of this category, named like on `/bangs`.
+    category: Option<String>,
+    subcategory: Option<String>,
+    /// At most `bangs_json_limit`, which is also the default.
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct BangEntry {
+    trigger: String,
+    url_template: String,
+    category: String,
+    subcategory: String,
+    origin: BangOrigin,
+}
+
+#[derive(Serialize, Debug)]
+struct BangsPage {
+    /// Number of bangs matching the filters, on all pages.
+    total: usize,
+    offset: usize,
+    limit: usize,
+    bangs: Vec<BangEntry>,
+}
+
+/// The page of the bangs of `cache` selected by `params`, in the order of
+/// the flat `/bangs` table and filtered like its groups.
+fn bangs_page(cache: &BangCache, params: &BangsJsonParams, max_limit: usize) -> BangsPage {
+    let limit = params.limit.unwrap_or(max_limit).min(max_limit);
+    let matching: Vec<(&str, &CachedBang, String, &str)> = sorted_bangs(cache)
+        .into_iter()
+        .map(|(trigger, cached)| {
+            let (category, subcategory) = group_names(&cached.bang);
+            (trigger, cached, category, subcategory)
+        })
+        .filter(|(_, _, category, subcategory)| {
+            params
+                .category
+                .as_ref()
+                .is_none_or(|wanted| wanted == category)
+                && params
+                    .subcategory
+                    .as_deref()
+                    .is_none_or(|wanted| wanted == *subcategory)
+        })
+        .collect();
+    let bangs = matching
+        .iter()
+        .skip(params.offset)
+        .take(limit)
+        .map(|(trigger, cached, category, subcategory)| {
+            BangEntry {
+                trigger: (*trigger).to_string(),
+                url_template: cached.bang.url_template.clone(),
+                category: category.clone(),
+                subcategory: (*subcategory).to_string(),
+                origin: cached.origin,
+            }
+        })
+        .collect();
+    BangsPage {
+        total: matching.len(),
+        offset: params.offset,
+        limit,
+        bangs,
+    }
+}
+
+async fn bangs_json(
+    Query(params): Query<BangsJsonParams>,
+    State(app_state): State<AppState>,
+) -> Json<BangsPage> {
+    let max_limit = app_state.config.read().bangs_json_limit;
+    Json(bangs_page(&BANG_CACHE.read(), &params, max_limit))
+}
+
+async fn api_resolve(
+    State(app_state): State<AppState>,
+    ApiQuery(query): ApiQuery,
+) -> Json<Resolution> {
+    Json(resolve_with_info(
+        &app_state.get_config(),
+        query.as_deref().unwrap_or_default(),
+    ))
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ResolveFormat {
+    /// The [`Resolution`], like `/api/resolve`.
+    #[default]
+    Json,
+    /// `204 No Content` with the target in the `Location` header.
+    Location,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResolveParams {
+    #[serde(default)]
+    format: ResolveFormat,
+}
+
+async fn resolve(
+    Query(params): Query<ResolveParams>,
+    State(app_state): State<AppState>,
+    ApiQuery(query): ApiQuery,
+) -> Response {
+    let resolution = resolve_with_info(
+        &app_state.get_config(),
+        query.as_deref().unwrap_or_default(),
+    );
+    if params.format == ResolveFormat::Json {
+        return Json(resolution).into_response();
+    }
+    // the search term would be lost, the form is only part of the JSON
+    if resolution.post.is_some() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The query resolves to a form submission, use format=json",
+        )
+            .into_response();
+    }
+    (StatusCode::NO_CONTENT, [(header::LOCATION, resolution.url)]).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct ValidateTemplate {
+    url_template: String,
+}
+
+/// Check the template of a bang before it is added, for immediate feedback in
+/// front-ends, see [`check_url_template`].
+async fn validate_template(
+    State(app_state): State<AppState>,
+    Json(body): Json<ValidateTemplate>,
+) -> Json<TemplateCheck> {
+    let config = app_state.get_config();
+    Json(check_url_template(
+        &config.resolve_options(),
+        &body.url_template,
+    ))
+}
+
+/// Whether `a` and `b` are equal, taking the same time wherever they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Only let requests with the `admin_token` through. Admin routes aren't
+/// mounted without one, see [`route_table`].
+async fn require_admin(
+    State(app_state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let admin_token = app_state.config.read().admin_token.clone();
+    let authorized = admin_token.is_some_and(|admin_token| {
+        request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), admin_token.as_bytes()))
+    });
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "Admin token required",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+#[derive(Debug, Deserialize)]
+struct TopParams {
+    window: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+struct TopBangs {
+    window_seconds: u64,
+    top: Vec<TriggerCount>,
+}
+
+async fn metrics_top(
+    Query(params): Query<TopParams>,
+    State(app_state): State<AppState>,
+) -> Response {
+    let window = params.window.as_deref().unwrap_or("24h");
+    let Some(window) = parse_window(window) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid window '{window}', expected e.g. 30m, 24h or 7d"),
+        )
+            .into_response();
+    };
+    let window = window.min(RETENTION);
+    let top = app_state
+        .usage
+        .lock()
+        .top(window, params.limit.unwrap_or(10));
+    Json(TopBangs {
+        window_seconds: window.as_secs(),
+        top,
+    })
+    .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsParams {
+    /// Like `2025-01`, the current month if unset.
+    month: Option<String>,
+}
+
+async fn api_stats(
+    Query(params): Query<StatsParams>,
+    State(app_state): State<AppState>,
+) -> Response {
+    let month = params.month.unwrap_or_else(crate::stats::current_month);
+    if !crate::stats::is_month(&month) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid month '{month}', expected e.g. 2025-01"),
+        )
+            .into_response();
+    }
+    let report = app_state.stats.lock().store.month(&month);
+    Json(report.unwrap_or(MonthReport {
+        month,
+        redirects: 0,
+        top: Vec::new(),
+    }))
+    .into_response()
+}
+
+async fn stats_page(State(app_state): State<AppState>) -> Html<String> {
+    let table = crate::stats::render_table(&app_state.stats.lock().store);
+    Html(format!(
+        r#"<html><head><meta charset="UTF-8"><title>Redirect Stats</title></head><body><h1>Redirects per Month</h1>{table}</body></html>"#
+    ))
+}
+
+async fn admin_page(State(app_state): State<AppState>) -> Html<String> {
+    let top = app_state
+        .usage
+        .lock()
+        .top(ADMIN_TOP_WINDOW, ADMIN_TOP_BANGS);
+    Html(render_admin(&AdminOverview {
+        report: app_state.health_report(),
+        entries: BANG_CACHE.read().len(),
+        generation: cache_generation(),
+        top,
+        events: app_state.recent_events.recent(),
+        reload: app_state.get_config().routes.reload,
+        custom_css: app_state.custom_css.read().clone(),
+    }))
+}
+
+/// Window and number of the bangs on `/admin` that were used the most.
+const ADMIN_TOP_WINDOW: Duration = Duration::from_hours(24);
+const ADMIN_TOP_BANGS: usize = 10;
+
+/// Everything shown on `/admin`.
+struct AdminOverview {
+    report: HealthReport,
+    /// Size of the bang cache.
+    entries: usize,
+    /// See [`cache_generation`].
+    generation: u64,
+    top: Vec<TriggerCount>,
+    events: Vec<RecentEvent>,
+    /// Whether `/reload` and `/bangs/reload` are enabled, to offer buttons.
+    reload: bool,
+    /// Contents of `ui.custom_css_path`.
+    custom_css: Option<Arc<str>>,
+}
+
+fn render_admin(overview: &AdminOverview) -> String {
+    let report = &overview.report;
+    let mut html = format!(
+        r#"<html><head><meta charset="UTF-8"><meta name="viewport" content="width=device-width, initial-scale=1.0"><style>{TABLE_STYLE}</style>{}<title>Admin</title></head><body><h1>Admin</h1><table><tr><th>Status</th><td>{}</td></tr><tr><th>Bang cache</th><td>{} bangs, generation {}, "#,
+        custom_style(overview.custom_css.as_deref()),
+        report.status.as_str(),
+        overview.entries,
+        overview.generation,
+    );
+    match report.cache_age_seconds {
+        Some(age) => write!(html, "updated {age} seconds ago"),
+        None => write!(html, "never updated"),
+    }
+    .expect("Failed to write to HTML string");
+    if let Some(e) = &report.last_update_error {
+        write!(
+            html,
+            "</td></tr><tr><th>Last update</th><td>failed: {}",
+            escape_html(e)
+        )
+        .expect("Failed to write to HTML string");
+    }
+    write!(
+        html,
+        "</td></tr><tr><th>Maintenance</th><td>{}</td></tr>",
+        if report.maintenance { "on" } else { "off" }
+    )
+    .expect("Failed to write to HTML string");
+    for task in &report.tasks {
+        write!(
+            html,
+            "<tr><th>Task {}</th><td>{}, {} restarts",
+            escape_html(task.name),
+            if task.running { "running" } else { "stopped" },
+            task.restarts
+        )
+        .expect("Failed to write to HTML string");
+        if let Some(last_exit) = &task.last_exit {
+            write!(html, ", last exit: {}", escape_html(last_exit))
+                .expect("Failed to write to HTML string");
+        }
+        html.push_str("</td></tr>");
+    }
+    html.push_str("</table><h2>Sources</h2>");
+    html.push_str(&render_sources(&report.sources));
+
+    html.push_str("<h2>Top Bangs (24h)</h2>");
+    if overview.top.is_empty() {
+        html.push_str("<p>No redirects through bangs yet.</p>");
+    } else {
+        html.push_str("<table><tr><th>Trigger</th><th>Redirects</th></tr>");
+        for TriggerCount { trigger, count } in &overview.top {
+            write!(
+                html,
+                "<tr><td>!{}</td><td>{count}</td></tr>",
+                escape_html(trigger)
+            )
+            .expect("Failed to write to HTML string");
+        }
+        html.push_str("</table>");
+    }
+
+    html.push_str("<h2>Recent Warnings and Errors</h2>");
+    if overview.events.is_empty() {
+        html.push_str("<p>None so far.</p>");
+    } else {
+        html.push_str("<table><tr><th>Time</th><th>Level</th><th>Module</th><th>Message</th></tr>");
+        for event in &overview.events {
+            write!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                httpdate::fmt_http_date(event.at),
+                event.level,
+                escape_html(&event.target),
+                escape_html(&event.message)
+            )
+            .expect("Failed to write to HTML string");
+        }
+        html.push_str("</table>");
+    }
+
+    if overview.reload {
+        html.push_str(
+            r#"<h2>Actions</h2><form method="get" action="/reload"><button>Reload the configuration and bangs</button></form><form method="post" action="/bangs/reload"><button>Reload the configured bangs</button></form>"#,
+        );
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+#[derive(Serialize, Debug)]
+struct BangChecks {
+    /// Whether all bangs passed.
+    passed: bool,
+    bangs: Vec<BangCheck>,
+}
+
+async fn check_bangs(State(app_state): State<AppState>) -> Response {
+    let remote = remote_bangs(&BANG_CACHE.read());
+    let client = app_state.suggestion_client.read().clone();
+    let policy = app_state.suggestion_policy.read().clone();
+    match check_config_bangs(&client, &policy, &app_state.get_config(), &remote).await {
+        Ok(bangs) => {
+            Json(BangChecks {
+                passed: bangs.iter().all(BangCheck::passed),
+                bangs,
+            })
+            .into_response()
+        },
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, format!("{e:#}")).into_response(),
+    }
+}
+
+/// Stream the events of the instance as they happen, as newline delimited
+/// JSON or as server-sent events for clients accepting those, like
+/// `EventSource` in browsers.
+async fn api_events(State(app_state): State<AppState>, headers: HeaderMap) -> Response {
+    let sse = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(EVENT_STREAM));
+    let events = app_state.firehose.subscribe();
+    let (content_type, body) = if sse {
+        let frames = with_keepalive(events.map(|event| event.to_sse()), SSE_KEEPALIVE);
+        (
+            EVENT_STREAM,
+            Body::from_stream(frames.map(Ok::<_, Infallible>)),
+        )
+    } else {
+        let frames = events.map(|event| Ok::<_, Infallible>(event.to_ndjson()));
+        (NDJSON, Body::from_stream(frames))
+    };
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Interleave `frames` with a server-sent events comment every `period`, so
+/// proxies don't close a stream that is quiet for a while.
+fn with_keepalive(
+    frames: impl Stream<Item = String>,
+    period: Duration,
+) -> impl Stream<Item = String> {
+    let start = tokio::time::Instant::now() + period;
+    let keepalive =
+        futures_util::stream::unfold(tokio::time::interval_at(start, period), |mut interval| {
+            async move {
+                interval.tick().await;
+                Some((": keepalive\n\n".to_string(), interval))
+            }
+        });
+    futures_util::stream::select(frames, keepalive)
+}
+
+/// How often a quiet server-sent events stream of `/api/events` gets a
+/// keepalive comment.
+const SSE_KEEPALIVE: Duration = Duration::from_secs(15);
+const NDJSON: &str = "application/x-ndjson";
+const EVENT_STREAM: &str = "text/event-stream";
+
+async fn api_debug_recent(State(app_state): State<AppState>) -> Json<Vec<RecentResolution>> {
+    Json(app_state.recent_resolutions.recent())
+}
+
+async fn debug_recent_page(State(app_state): State<AppState>) -> Html<String> {
+    Html(render_debug_recent(
+        &app_state.recent_resolutions.recent(),
+        app_state.custom_css.read().as_deref(),
+    ))
+}
+
+fn render_debug_recent(resolutions: &[RecentResolution], custom_css: Option<&str>) -> String {
+    let mut html = format!(
+        r#"<html><head><meta charset="UTF-8"><meta name="viewport" content="width=device-width, initial-scale=1.0"><style>{TABLE_STYLE}</style>{}<title>Recent Redirects</title></head><body><h1>Recent Redirects</h1><form method="post" action="/debug/recent/clear"><button>Clear</button></form>"#,
+        custom_style(custom_css),
+    );
+    if resolutions.is_empty() {
+        html.push_str("<p>None so far.</p>");
+    } else {
+        html.push_str(
+            "<table><tr><th>Time</th><th>Query</th><th>Bang</th><th>URL</th><th>Latency</\
+             th><th>Fallback</th></tr>",
+        );
+        for resolution in resolutions {
+            write!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} µs</td><td>{}</td></tr>",
+                resolution.date(),
+                escape_html(&resolution.query),
+                resolution
+                    .trigger
+                    .as_ref()
+                    .map_or_else(String::new, |trigger| format!("!{}", escape_html(trigger))),
+                escape_html(&resolution.url),
+                resolution.latency_us,
+                if resolution.fallback { "yes" } else { "no" },
+            )
+            .expect("Failed to write to HTML string");
+        }
+        html.push_str("</table>");
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+/// Forget the recent redirects, back to `/debug/recent` for the button on it.
+async fn clear_debug_recent(State(app_state): State<AppState>) -> Redirect {
+    app_state.recent_resolutions.clear();
+    info!("Cleared the recent redirects");
+    Redirect::to("/debug/recent")
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintenanceParams {
+    /// Toggled if not given.
+    enabled: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+struct MaintenanceState {
+    maintenance: bool,
+}
+
+async fn maintenance(
+    Query(params): Query<MaintenanceParams>,
+    State(app_state): State<AppState>,
+) -> Json<MaintenanceState> {
+    let maintenance = params.enabled.map_or_else(
+        || !app_state.maintenance.fetch_xor(true, Ordering::Relaxed),
+        |enabled| {
+            app_state.maintenance.store(enabled, Ordering::Relaxed);
+            enabled
+        },
+    );
+    info!(
+        "Maintenance mode {}",
+        if maintenance { "enabled" } else { "disabled" }
+    );
+    Json(MaintenanceState { maintenance })
+}
+
+async fn selftest(State(app_state): State<AppState>) -> impl IntoResponse {
+    let report = crate::selftest::run(&app_state).await;
+    let status = if report.passed {
+        StatusCode::OK
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    (status, Json(report))
+}
+
+/// The `OpenSearch` description of this instance.
+///
+/// The URLs only depend on the configuration, never on the `Host` or
+/// forwarded headers of the request, so the document is the same for every
+/// client and well-formed whatever they send.
+#[must_use]
+pub fn opensearch_xml(app_config: &AppConfig) -> String {
+    let pkg_name = env!("CARGO_PKG_NAME");
+    let pkg_description = env!("CARGO_PKG_DESCRIPTION");
+    let base_url = app_config.base_url();
+    let base_url = escape_html(&base_url);
+    let query_param = urlencoding::encode(&app_config.query_param);
+    let query_param = escape_html(&query_param);
+    let suggestions = if app_config.routes.suggest && app_config.advertise_suggestions {
+        format!(
+            r#"
+  <Url type="application/x-suggestions+json" method="GET" template="{base_url}/suggest?{query_param}={{searchTerms}}" />"#
+        )
+    } else {
+        String::new()
+    };
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<OpenSearchDescription
+  xmlns="http://a9.com/-/spec/opensearch/1.1/"
+  xmlns:moz="http://www.mozilla.org/2006/browser/search/">
+  <ShortName>{}</ShortName>
+  <Description>{}</Description>
+  <InputEncoding>UTF-8</InputEncoding>
+  <Image height="64" width="64" type="image/png">/favicon.ico</Image>
+  <Url type="text/html" method="GET" template="{base_url}/?{query_param}={{searchTerms}}" />{suggestions}
+</OpenSearchDescription>"#,
+        escape_html(&pkg_name.to_title_case()),
+        escape_html(pkg_description),
+    )
+}
+
+async fn opensearch(State(app_state): State<AppState>) -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/opensearchdescription+xml"),
+    );
+    (
+        StatusCode::OK,
+        headers,
+        opensearch_xml(&app_state.get_config()),
+    )
+}
+
+async fn suggestions_proxy(
+    State(app_state): State<AppState>,
+    request_headers: HeaderMap,
+    ApiQuery(query): ApiQuery,
+) -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+
+    if let Some(query) = query {
+        return (
+            StatusCode::OK,
+            headers,
+            Json(suggestions(&app_state, &query, &request_headers).await),
+        );
+    }
+
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        headers,
+        Json(serde_json::json!([])),
+    )
+}
+
+// endpoint to add a new bang to the config file
+async fn add_bang(
+    Query(mut params): Query<crate::bang::Bang>,
+    State(app_state): State<AppState>,
+) -> impl IntoResponse {
+    params.normalize();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+
+    let mut config = app_state.config.write();
+    let persist = config.persist;
+    let config_path = config.config_path.clone();
+    let (_, exceeded) = config.fit_quota(std::slice::from_ref(&params));
+    if let Some(bangs) = &mut config.bangs {
+        if let Some(exceeded) = exceeded {
+            warn!("Refusing to add the bang '{}': {exceeded}", params.trigger);
+            return (
+                quota_status(exceeded),
+                headers,
+                Json(serde_json::json!({ "status": "failed", "error": exceeded.to_string() })),
+            );
+        }
+        if persist {
+            append_file_config(&config_path, params.clone());
+        }
+        bangs.push(params.clone());
+        app_state
+            .firehose
+            .publish(FirehoseEvent::bang_added(&params.trigger));
+        if let Some(mut cache) = BANG_CACHE.try_write() {
+            cache.insert(
+                params.trigger.to_ascii_lowercase(),
+                CachedBang {
+                    bang: params,
+                    origin: BangOrigin::Config,
+                },
+            );
+            crate::cache::cache_changed();
+        }
+        return (
+            StatusCode::OK,
+            headers,
+            Json(serde_json::json!({ "status": "success" })),
+        );
+    }
+    drop(config);
+
+    (
+        StatusCode::BAD_REQUEST,
+        headers,
+        Json(serde_json::json!({ "status": "failed" })),
+    )
+}
+
+/// `507 Insufficient Storage` for a full configuration file, `422` for too
+/// many bangs.
+const fn quota_status(exceeded: QuotaExceeded) -> StatusCode {
+    match exceeded {
+        QuotaExceeded::CustomBangs { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        QuotaExceeded::ConfigFileSize { .. } => StatusCode::INSUFFICIENT_STORAGE,
+    }
+}
+
+/// Parse a bang list to import in `format`, decompressing it if sent with
+/// `Content-Encoding: gzip`.
+///
+/// Returns the bangs and the number of entries that were skipped, because
+/// they aren't bangs.
+fn parse_import(
+    headers: &HeaderMap,
+    body: &[u8],
+    format: ImportFormat,
+) -> Result<(Vec<crate::bang::Bang>, usize), (StatusCode, String)> {
+    let body = match headers
+        .get(header::CONTENT_ENCODING)
+        .map(|encoding| encoding.to_str().unwrap_or_default().trim())
+    {
+        None | Some("identity") => Cow::Borrowed(body),
+        Some(encoding) if encoding.eq_ignore_ascii_case("gzip") => {
+            match gunzip(body, MAX_DECOMPRESSED_SIZE) {
+                Ok(body) => Cow::Owned(body),
+                Err(e @ GunzipError::TooLarge { .. }) => {
+                    return Err((StatusCode::PAYLOAD_TOO_LARGE, e.to_string()));
+                },
+                Err(e @ GunzipError::Corrupt(_)) => {
+                    return Err((StatusCode::BAD_REQUEST, e.to_string()));
+                },
+            }
+        },
+        Some(encoding) => {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("Unsupported Content-Encoding '{encoding}', use gzip"),
+            ));
+        },
+    };
+    format
+        .parse(&body)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid bang list: {e}")))
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportParams {
+    #[serde(default)]
+    format: ImportFormat,
+}
+
+// endpoint to add many bangs to the config file at once
+async fn import_bangs(
+    Query(params): Query<ImportParams>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let (mut bangs, skipped) = match parse_import(&headers, &body, params.format) {
+        Ok(parsed) => parsed,
+        Err((status, error)) => {
+            return (
+                status,
+                Json(serde_json::json!({ "status": "failed", "error": error })),
+            );
+        },
+    };
+    for bang in &mut bangs {
+        bang.normalize();
+    }
+    let mut config = app_state.config.write();
+    let (fitting, exceeded) = config.fit_quota(&bangs);
+    let over_quota: Vec<String> = bangs
+        .split_off(fitting)
+        .into_iter()
+        .map(|bang| bang.trigger)
+        .collect();
+    if let Some(exceeded) = exceeded
+        && bangs.is_empty()
+    {
+        drop(config);
+        return (
+            quota_status(exceeded),
+            Json(serde_json::json!({
+                "status": "failed",
+                "error": exceeded.to_string(),
+                "skipped": skipped,
+                "over_quota": over_quota,
+            })),
+        );
+    }
+    let imported = bangs.len();
+    let mut config_bangs = config.bangs.clone().unwrap_or_default();
+    config_bangs.extend(bangs.iter().cloned());
+    let replaced = replace_config_bangs(
+        &mut BANG_CACHE.write(),
+        &config_bangs,
+        config.bang_collisions,
+        config.relevance_offset,
+    );
+    if let Err(e) = replaced {
+        drop(config);
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({ "status": "failed", "error": e.to_string() })),
+        );
+    }
+    for bang in &bangs {
+        app_state
+            .firehose
+            .publish(FirehoseEvent::bang_added(&bang.trigger));
+    }
+    if config.persist {
+        append_file_config_bangs(&config.config_path, bangs);
+    }
+    config.bangs = Some(config_bangs);
+    drop(config);
+    info!("Imported {imported} bangs, skipped {skipped} entries");
+    let Some(exceeded) = exceeded else {
+        return (
+            StatusCode::OK,
+            Json(
+                serde_json::json!({ "status": "success", "imported": imported, "skipped": skipped }),
+            ),
+        );
+    };
+    warn!("Left out {} imported bangs: {exceeded}", over_quota.len());
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "status": "partial",
+            "error": exceeded.to_string(),
+            "imported": imported,
+            "skipped": skipped,
+            "over_quota": over_quota,
+        })),
+    )
+}
+
+async fn healthz(State(app_state): State<AppState>) -> impl IntoResponse {
+    let report = app_state.health_report();
+    let status = if report.status.is_available() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+async fn metrics(State(app_state): State<AppState>) -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    (
+        StatusCode::OK,
+        headers,
+        render_metrics(&app_state.health_report(), BANG_CACHE.read().len()),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct CompactParams {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+async fn compact_config(
+    Query(params): Query<CompactParams>,
+    State(app_state): State<AppState>,
+) -> impl IntoResponse {
+    if !params.dry_run && !app_state.config.read().persist {
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "status": "failed",
+                "error": "Persisting is turned off, the configuration file isn't written"
+            })),
+        );
+    }
+    let config_path = app_state.config.read().config_path.clone();
+    match compact_file(&config_path, params.dry_run) {
+        Ok(removed) => {
+            if !params.dry_run
+                && !removed.is_empty()
+                && let Err(e) = reload_bangs_now(&app_state)
+            {
+                error!("Failed to reload the configured bangs after compacting them: {e:#}");
+            }
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({ "dry_run": params.dry_run, "removed": removed })),
+            )
+        },
+        Err(e) => {
+            error!("Failed to compact configuration file: {e:#}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "status": "failed", "error": format!("{e:#}") })),
+            )
+        },
+    }
+}
+
+async fn reload(State(app_state): State<AppState>) -> impl IntoResponse {
+    let res = reload_config(&app_state).await;
+    match res {
+        Ok(()) => (StatusCode::OK, "Reloaded successfully! (•‿•)".into()),
+        Err(e) => {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to reload: {e}"),
+            )
+        },
+    }
+}
+
+async fn reload_bangs_only(State(app_state): State<AppState>) -> impl IntoResponse {
+    match reload_bangs(&app_state).await {
+        Ok(count) => {
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({ "status": "success", "bangs": count })),
+            )
+        },
+        Err(e) => {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "status": "failed", "error": format!("{e:#}") })),
+            )
+        },
+    }
+}
+
+async fn restart(State(app_state): State<AppState>) -> impl IntoResponse {
+    tokio::spawn(async move {
+        // give the HTTP response a moment to go out
+        sleep(Duration::from_millis(50)).await;
+        crate::stats::fold(&app_state);
+
+        // collect the current executable path and args
+        let exe = env::current_exe().expect("failed to get current exe");
+        let args: Vec<String> = env::args().skip(1).collect();
+        Command::new(exe)
+            .args(&args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to spawn new process");
+        exit(0);
+    });
+
+    (
+        StatusCode::OK,
+        "Server is restarting shortly, see you soon ( ´ ▽ ` )ﾉ",
+    )
+}
+
+/// Serve `app` on `listener` until the process exits.
+///
+/// HTTP/1.1 is always served, with `http2` cleartext HTTP/2 (h2c, prior
+/// knowledge) is accepted on the same listener as well.
+pub async fn serve(listener: TcpListener, app: Router, http2: bool) {
+    #[expect(
+        clippy::infinite_loop,
+        reason = "The server is intended to run until the process exits"
+    )]
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                // usually running out of file descriptors, give it a moment
+                error!("Failed to accept connection: {e}");
+                sleep(Duration::from_millis(100)).await;
+                continue;
+            },
+        };
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            let mut builder = auto::Builder::new(TokioExecutor::new());
+            if !http2 {
+                builder = builder.http1_only();
+            }
+            if let Err(e) = builder
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Connection from '{remote_addr}' closed with an error: {e}");
+            }
+        });
+    }
+}
+
+/// An endpoint served by the router.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteInfo {
+    pub path: &'static str,
+    pub methods: &'static [&'static str],
+    /// Whether the route requires the `admin_token`, it isn't mounted without
+    /// one.
+    pub admin: bool,
+    /// Whether the route changes the configuration or state of the instance.
+    pub write: bool,
+    /// Which of the `[timeouts]` applies to the route, if any.
+    #[serde(skip)]
+    budget: Option<Budget>,
+}
+
+/// The `[timeouts]` budgets routes are limited by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Budget {
+    Redirect,
+    Suggest,
+    BangsPage,
+    Admin,
+}
+
+fn timeout_page() -> String {
+    render_error_page(
+        "Timed out",
+        "This took too long to answer, please try again.",
+    )
+}
+
+impl Budget {
+    const fn limit(self, timeouts: TimeoutsConfig) -> Duration {
+        Duration::from_millis(match self {
+            Self::Redirect => timeouts.redirect_ms,
+            Self::Suggest => timeouts.suggest_ms,
+            Self::BangsPage => timeouts.bangs_page_ms,
+            Self::Admin => timeouts.admin_ms,
+        })
+    }
+
+    /// The answer to a request to `uri`, with the search term in
+    /// `query_param`, that took longer than `limit`.
+    fn timed_out(
+        self,
+        uri: &Uri,
+        query_param: &str,
+        redact_queries: bool,
+        limit: Duration,
+    ) -> Response {
+        let query =
+            form_value(uri.query().unwrap_or_default(), query_param, true).unwrap_or_default();
+        match self {
+            Self::Redirect => {
+                // only the bang, to find the slow component without logging
+                // the search terms, and not even that while queries are
+                // redacted
+                warn!(
+                    trigger = get_bang(&query).filter(|_| !redact_queries),
+                    "Resolving a search took longer than {limit:?}"
+                );
+                (StatusCode::SERVICE_UNAVAILABLE, Html(timeout_page())).into_response()
+            },
+            Self::Suggest => {
+                warn!("Answering suggestions took longer than {limit:?}");
+                Json(serde_json::json!([query, []])).into_response()
+            },
+            Self::BangsPage => {
+                warn!("Rendering the bangs page took longer than {limit:?}");
+                (StatusCode::SERVICE_UNAVAILABLE, Html(timeout_page())).into_response()
+            },
+            Self::Admin => {
+                warn!("'{}' took longer than {limit:?}", uri.path());
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    Json(serde_json::json!({
+                        "status": "failed",
+                        "error": format!("Timed out after {limit:?}"),
+                    })),
+                )
+                    .into_response()
+            },
+        }
+    }
+}
+
+/// Answer requests taking longer than their budget with an error.
+async fn enforce_budget(
+    State((app_state, budget)): State<(AppState, Budget)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (limit, query_param, redact_queries) = {
+        let config = app_state.config.read();
+        (
+            budget.limit(config.timeouts),
+            config.query_param.clone(),
+            config.redact_queries,
+        )
+    };
+    let uri = request.uri().clone();
+    tokio::time::timeout(limit, next.run(request))
+        .await
+        .unwrap_or_else(|_| budget.timed_out(&uri, &query_param, redact_queries, limit))
+}
+
+/// Limit `method_router` to the `budget`.
+fn with_budget(
+    method_router: MethodRouter<AppState>,
+    app_state: &AppState,
+    budget: Budget,
+) -> MethodRouter<AppState> {
+    method_router.route_layer(middleware::from_fn_with_state(
+        (app_state.clone(), budget),
+        enforce_budget,
+    ))
+}
+
+impl RouteInfo {
+    const fn new(path: &'static str, methods: &'static [&'static str]) -> Self {
+        Self {
+            path,
+            methods,
+            admin: false,
+            write: false,
+            budget: None,
+        }
+    }
+
+    /// Require the `admin_token`, admin routes are limited by the admin
+    /// budget.
+    const fn admin(mut self) -> Self {
+        self.admin = true;
+        self.budget = Some(Budget::Admin);
+        self
+    }
+
+    const fn budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Lift the budget, for responses that stream as long as the client
+    /// listens.
+    const fn unbounded(mut self) -> Self {
+        self.budget = None;
+        self
+    }
+
+    const fn write(mut self) -> Self {
+        self.write = true;
+        self
+    }
+
+    /// The `Allow` header of the route, in the order axum lists the methods
+    /// of a method router: `HEAD` after `GET`, and `OPTIONS` last.
+    fn allow(&self) -> String {
+        let mut allow = Vec::with_capacity(self.methods.len() + 2);
+        for &method in self.methods {
+            allow.push(method);
+            if method == "GET" {
+                allow.push("HEAD");
+            }
+        }
+        allow.push("OPTIONS");
+        allow.join(",")
+    }
+}
+
+/// The endpoints enabled by `[routes]` with their handlers. Both the router
+/// and the capabilities document are built from this, so they always agree.
+fn route_table(app_config: &AppConfig) -> Vec<(RouteInfo, MethodRouter<AppState>)> {
+    const GET: &[&str] = &["GET"];
+    const POST: &[&str] = &["POST"];
+    let routes = app_config.routes;
+    // fail closed, the admin endpoints show queries and change the instance
+    let admin = routes.admin && app_config.admin_token.is_some();
+    let admin_metrics = admin && routes.metrics;
+    let admin_stats = admin && app_config.stats_persist;
+    let debug_recent = admin && app_config.debug_recent_capacity() > 0;
+    [
+        (
+            true,
+            RouteInfo::new("/", &["GET", "POST"]).budget(Budget::Redirect),
+            get(handler).post(handler),
+        ),
+        (
+            true,
+            RouteInfo::new("/search", &["GET", "POST"]).budget(Budget::Redirect),
+            get(handler).post(handler),
+        ),
+        (
+            true,
+            RouteInfo::new("/go/{trigger}", &["GET", "POST"]).budget(Budget::Redirect),
+            get(go).post(go),
+        ),
+        (
+            true,
+            RouteInfo::new("/bangs", GET).budget(Budget::BangsPage),
+            get(list_bangs),
+        ),
+        (
+            routes.api,
+            RouteInfo::new("/api/bangs", GET),
+            get(api_bangs),
+        ),
+        (
+            routes.api,
+            RouteInfo::new("/bangs.json", GET).budget(Budget::BangsPage),
+            get(bangs_json),
+        ),
+        (
+            routes.api,
+            RouteInfo::new("/api/resolve", GET),
+            get(api_resolve),
+        ),
+        (routes.api, RouteInfo::new("/resolve", GET), get(resolve)),
+        (
+            routes.api,
+            RouteInfo::new("/validate_template", POST),
+            post(validate_template),
+        ),
+        (
+            true,
+            RouteInfo::new("/opensearch.xml", GET),
+            get(opensearch),
+        ),
+        (true, RouteInfo::new("/favicon.ico", GET), get(favicon)),
+        (
+            true,
+            RouteInfo::new("/static/{*path}", GET),
+            get(static_file),
+        ),
+        (true, RouteInfo::new("/robots.txt", GET), get(robots_txt)),
+        (
+            routes.suggest,
+            RouteInfo::new("/suggest", GET).budget(Budget::Suggest),
+            get(suggestions_proxy),
+        ),
+        (
+            routes.add_bang,
+            RouteInfo::new("/add_bang", POST).write(),
+            post(add_bang),
+        ),
+        (
+            routes.add_bang,
+            RouteInfo::new("/api/bangs/import", POST)
+                .write()
+                .budget(Budget::Admin),
+            // the limit applies before decompression, but plain lists may be
+            // just as large
+            post(import_bangs).layer(DefaultBodyLimit::max(MAX_DECOMPRESSED_SIZE)),
+        ),
+        (
+            admin && routes.compact,
+            RouteInfo::new("/api/config/compact", POST).admin().write(),
+            post(compact_config),
+        ),
+        (true, RouteInfo::new("/healthz", GET), get(healthz)),
+        (
+            routes.metrics,
+            RouteInfo::new("/metrics", GET),
+            get(metrics),
+        ),
+        (
+            routes.reload,
+            RouteInfo::new("/bangs/reload", POST).write(),
+            post(reload_bangs_only),
+        ),
+        (
+            routes.reload,
+            RouteInfo::new("/reload", GET).write(),
+            get(reload),
+        ),
+        (
+            routes.restart,
+            RouteInfo::new("/restart", GET).write(),
+            get(restart),
+        ),
+        (
+            admin,
+            RouteInfo::new("/admin", GET).admin(),
+            get(admin_page),
+        ),
+        (
+            admin,
+            RouteInfo::new("/selftest", GET).admin(),
+            get(selftest),
+        ),
+        (
+            admin_metrics,
+            RouteInfo::new("/metrics/top", GET).admin(),
+            get(metrics_top),
+        ),
+        (
+            admin_stats,
+            RouteInfo::new("/api/stats", GET).admin(),
+            get(api_stats),
+        ),
+        (
+            admin_stats,
+            RouteInfo::new("/stats", GET).admin(),
+            get(stats_page),
+        ),
+        (
+            admin,
+            RouteInfo::new("/api/bangs/check", POST).admin(),
+            post(check_bangs),
+        ),
+        (
+            admin,
+            RouteInfo::new("/maintenance", POST).admin().write(),
+            post(maintenance),
+        ),
+        (
+            admin,
+            RouteInfo::new("/api/events", GET).admin().unbounded(),
+            get(api_events),
+        ),
+        (
+            debug_recent,
+            RouteInfo::new("/debug/recent", GET).admin(),
+            get(debug_recent_page),
+        ),
+        (
+            debug_recent,
+            RouteInfo::new("/api/debug/recent", GET).admin(),
+            get(api_debug_recent),
+        ),
+        (
+            debug_recent,
+            RouteInfo::new("/debug/recent/clear", POST).admin().write(),
+            post(clear_debug_recent),
+        ),
+        (
+            true,
+            RouteInfo::new(CAPABILITIES_PATH, GET),
+            get(capabilities_document),
+        ),
+    ]
+    .into_iter()
+    .filter(|(enabled, ..)| *enabled)
+    .map(|(_, info, method_router)| (info, method_router))
+    .collect()
+}
+
+const CAPABILITIES_PATH: &str = "/.well-known/redirector.json";
+
+/// What this instance supports, for companion tools to feature-detect.
+#[derive(Serialize, Debug, Clone)]
+pub struct Capabilities {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub base_url: String,
+    pub bang_prefix: &'static str,
+    pub routes: Vec<RouteInfo>,
+    /// Whether every route changing the instance requires the `admin_token`.
+    pub auth_required_for_writes: bool,
+    pub export_formats: Vec<String>,
+    /// Where `/suggest` fetches suggestions from, if it is enabled.
+    pub suggestion_provider: Option<SuggestionProvider>,
+}
+
+/// The capabilities of an instance running with `app_config`.
+#[must_use]
+pub fn capabilities(app_config: &AppConfig) -> Capabilities {
+    let routes: Vec<RouteInfo> = route_table(app_config)
+        .into_iter()
+        .map(|(info, _)| info)
+        .collect();
+    Capabilities {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        base_url: app_config.base_url(),
+        bang_prefix: "!",
+        auth_required_for_writes: app_config.admin_token.is_some()
+            && routes
+                .iter()
+                .filter(|route| route.write)
+                .all(|route| route.admin),
+        routes,
+        export_formats: ExportFormat::value_variants()
+            .iter()
+            .filter_map(|format| Some(format.to_possible_value()?.get_name().to_string()))
+            .collect(),
+        suggestion_provider: app_config
+            .routes
+            .suggest
+            .then_some(app_config.suggestion_provider),
+    }
+}
+
+async fn capabilities_document(State(app_state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(capabilities(&app_state.get_config())),
+    )
+}
+
+/// Answer requests no route matched: redirect paths like `/bangs/` to the
+/// route without the trailing slashes, keeping the query, with a `308` so
+/// the method and body are kept as well. Anything else doesn't exist.
+fn strip_trailing_slash(paths: &[&str], uri: &Uri) -> Response {
+    let path = uri.path();
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.len() == path.len() || !paths.iter().any(|pattern| route_matches(pattern, trimmed)) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let location = uri
+        .query()
+        .map_or_else(|| trimmed.to_string(), |query| format!("{trimmed}?{query}"));
+    Redirect::permanent(&location).into_response()
+}
+
+/// Whether `path` is served by the route `pattern`, where `{name}` stands
+/// for one segment and `{*name}` for the rest of the path.
+fn route_matches(pattern: &str, path: &str) -> bool {
+    let mut segments = path.split('/');
+    for expected in pattern.split('/') {
+        if expected.starts_with("{*") {
+            return segments.any(|segment| !segment.is_empty());
+        }
+        match segments.next() {
+            Some(segment) if expected.starts_with('{') => {
+                if segment.is_empty() {
+                    return false;
+                }
+            },
+            Some(segment) if segment == expected => {},
+            _ => return false,
+        }
+    }
+    segments.next().is_none()
+}
+
+/// Identify the crate and the instance in the headers of every response,
+/// if `instance_headers` is turned on.
+async fn instance_headers(State(app_state): State<AppState>, req: Request, next: Next) -> Response {
+    let mut response = next.run(req).await;
+    let (enabled, instance_name) = {
+        let config = app_state.config.read();
+        (config.instance_headers, config.instance_name.clone())
+    };
+    if !enabled {
+        return response;
+    }
+    let headers = response.headers_mut();
+    headers.insert(
+        header::SERVER,
+        HeaderValue::from_static(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        )),
+    );
+    if let Some(value) = instance_name.and_then(|name| HeaderValue::try_from(name).ok()) {
+        headers.insert("x-instance", value);
+    }
+    response
+}
+
+/// Build the router serving all HTTP endpoints enabled by `[routes]`.
+pub fn router(app_state: AppState) -> Router {
+    let app_config = app_state.get_config();
+    let mut router = Router::new();
+    let mut paths = Vec::new();
+    for (info, method_router) in route_table(&app_config) {
+        paths.push(info.path);
+        let method_router = match info.budget {
+            Some(budget) => with_budget(method_router, &app_state, budget),
+            None => method_router,
+        };
+        let method_router = if info.admin {
+            method_router.route_layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                require_admin,
+            ))
+        } else {
+            method_router
+        };
+        // added after the layers, so preflight requests need no admin token;
+        // other methods are answered with a 405 and the same `Allow` by axum
+        let allow = info.allow();
+        let method_router = method_router.options(move || {
+            std::future::ready((StatusCode::NO_CONTENT, [(header::ALLOW, allow.clone())]))
+        });
+        router = router.route(info.path, method_router);
+    }
+    router
+        .fallback(move |uri: Uri| std::future::ready(strip_trailing_slash(&paths, &uri)))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            instance_headers,
+        ))
+        .layer(TraceLayer::new_for_http())
+        // compressed, events would be held back until enough piled up
+        .layer(
+            CompressionLayer::new()
+                .compress_when(DefaultPredicate::new().and(NotForContentType::const_new(NDJSON))),
+        )
+        .with_state(app_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::Read as _;
+    use std::net::SocketAddr;
+
+    use reqwest::Version;
+    use tokio::time::timeout;
+    use tracing_subscriber::layer::SubscriberExt as _;
+    use tracing_subscriber::registry;
+
+    use super::*;
+    use crate::bang::test_bang;
+    use crate::config::{RoutesConfig, UiConfig};
+    use crate::firehose::Firehose;
+
+    async fn spawn_app(app_state: AppState, http2: bool) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, router(app_state), http2));
+        addr
+    }
+
+    async fn spawn_server(http2: bool) -> SocketAddr {
+        spawn_app(AppState::new(AppConfig::default()), http2).await
+    }
+
+    #[tokio::test]
+    async fn test_landing_page() {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        let addr = spawn_server(false).await;
+        let response = client.get(format!("http://{addr}/")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/bangs");
+
+        let dir = tempfile::tempdir().unwrap();
+        let landing_path = dir.path().join("landing.html");
+        std::fs::write(&landing_path, "<h1>Search</h1>").unwrap();
+        let css_path = dir.path().join("custom.css");
+        std::fs::write(&css_path, "h1 { color: red; } /* </style> */").unwrap();
+        let app_state = AppState::new(AppConfig {
+            ui: UiConfig {
+                landing_html_path: Some(landing_path.clone()),
+                custom_css_path: Some(css_path),
+                ..UiConfig::default()
+            },
+            ..AppConfig::default()
+        });
+        let addr = spawn_app(app_state.clone(), false).await;
+        let response = client.get(format!("http://{addr}/")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "<h1>Search</h1>");
+        let bangs = reqwest::get(format!("http://{addr}/bangs"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(
+            bangs.contains(r"</style><style>h1 { color: red; } /* <\/style> */</style>"),
+            "{bangs}"
+        );
+
+        // queries are still redirected
+        let response = client
+            .get(format!("http://{addr}/?q=rust"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        std::fs::write(&landing_path, "<h1>Updated</h1>").unwrap();
+        app_state.load_ui_files();
+        let response = client.get(format!("http://{addr}/")).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "<h1>Updated</h1>");
+
+        std::fs::write(&landing_path, "<h1>Search</h1><!-- popular bangs -->").unwrap();
+        app_state.load_ui_files();
+        *app_state.popular_bangs.write() = vec![popular::PopularBang {
+            trigger: "gh".to_string(),
+            short_name: Some("GitHub".to_string()),
+            domain: Some("github.com".to_string()),
+            count: 12,
+        }];
+        let response = client.get(format!("http://{addr}/")).send().await.unwrap();
+        assert_eq!(
+            response.text().await.unwrap(),
+            r#"<h1>Search</h1><nav class="popular-bangs"><ul><li><a href="https://github.com">!gh</a> GitHub</li></ul></nav>"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_static_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let static_dir = dir.path().join("static");
+        std::fs::create_dir_all(&static_dir).unwrap();
+        std::fs::write(static_dir.join("icon.svg"), "<svg/>").unwrap();
+        std::fs::write(dir.path().join("secret.txt"), "hunter2").unwrap();
+        let addr = spawn_app(
+            AppState::new(AppConfig {
+                ui: UiConfig {
+                    static_dir: Some(static_dir),
+                    ..UiConfig::default()
+                },
+                ..AppConfig::default()
+            }),
+            false,
+        )
+        .await;
+        let client = reqwest::Client::builder()
+            .no_gzip()
+            .no_brotli()
+            .build()
+            .unwrap();
+        let get = |path: &str, accept_encoding: &str, if_none_match: Option<&str>| {
+            let mut request = client
+                .get(format!("http://{addr}{path}"))
+                .header(header::ACCEPT_ENCODING, accept_encoding);
+            if let Some(etag) = if_none_match {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            request.send()
+        };
+
+        let response = get("/static/style.css", "gzip, br", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "public, max-age=31536000, immutable"
+        );
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let response = get("/static/style.css", "gzip, br;q=0", None)
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_ne!(response.headers()[header::ETAG], etag.as_str());
+        let mut css = String::new();
+        flate2::read::GzDecoder::new(&*response.bytes().await.unwrap())
+            .read_to_string(&mut css)
+            .unwrap();
+        assert_eq!(css, TABLE_STYLE);
+        let response = get("/static/style.css", "identity", None).await.unwrap();
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(response.text().await.unwrap(), TABLE_STYLE);
+
+        // any variant is as good as the one the client has
+        let response = get("/static/style.css", "identity", Some(&etag))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(response.bytes().await.unwrap().is_empty());
+        let response = get("/static/style.css", "br", Some("\"0\"")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // PNGs aren't compressed again
+        let response = get("/favicon.ico", "br", None).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+
+        // files of the static directory replace the built-in ones
+        let response = get("/static/icon.svg", "identity", None).await.unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(response.text().await.unwrap(), "<svg/>");
+        let response = get("/static/icon.svg", "identity", Some(&etag))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        for path in [
+            "/static/..%2Fsecret.txt",
+            "/static/%2E%2E/secret.txt",
+            "/static/%2Fetc%2Fpasswd",
+            "/static/missing.css",
+        ] {
+            let response = get(path, "identity", None).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_box() {
+        let addr = spawn_app(
+            AppState::new(AppConfig {
+                query_param: "query".to_string(),
+                ..AppConfig::default()
+            }),
+            false,
+        )
+        .await;
+        let page = reqwest::get(format!("http://{addr}/bangs"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let form = r#"<h1>Bang Commands</h1><form action="/" method="get" class="search"><input type="search" name="query""#;
+        assert!(page.contains(form), "{page}");
+
+        // the form submits to a search
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!("http://{addr}/?query=rust"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_ne!(response.headers()[header::LOCATION], "/bangs");
+    }
+
+    #[tokio::test]
+    async fn test_allow() {
+        let app_config = AppConfig {
+            stats_persist: true,
+            persist: false,
+            admin_token: Some("hunter2".to_string()),
+            ..AppConfig::default()
+        };
+        let expected = [
+            ("/", "GET,HEAD,POST,OPTIONS"),
+            ("/search", "GET,HEAD,POST,OPTIONS"),
+            ("/go/{trigger}", "GET,HEAD,POST,OPTIONS"),
+            ("/bangs", "GET,HEAD,OPTIONS"),
+            ("/api/bangs", "GET,HEAD,OPTIONS"),
+            ("/bangs.json", "GET,HEAD,OPTIONS"),
+            ("/api/resolve", "GET,HEAD,OPTIONS"),
+            ("/resolve", "GET,HEAD,OPTIONS"),
+            ("/validate_template", "POST,OPTIONS"),
+            ("/opensearch.xml", "GET,HEAD,OPTIONS"),
+            ("/favicon.ico", "GET,HEAD,OPTIONS"),
+            ("/static/{*path}", "GET,HEAD,OPTIONS"),
+            ("/robots.txt", "GET,HEAD,OPTIONS"),
+            ("/suggest", "GET,HEAD,OPTIONS"),
+            ("/add_bang", "POST,OPTIONS"),
+            ("/api/bangs/import", "POST,OPTIONS"),
+            ("/api/config/compact", "POST,OPTIONS"),
+            ("/healthz", "GET,HEAD,OPTIONS"),
+            ("/metrics", "GET,HEAD,OPTIONS"),
+            ("/bangs/reload", "POST,OPTIONS"),
+            ("/reload", "GET,HEAD,OPTIONS"),
+            ("/restart", "GET,HEAD,OPTIONS"),
+            ("/admin", "GET,HEAD,OPTIONS"),
+            ("/selftest", "GET,HEAD,OPTIONS"),
+            ("/metrics/top", "GET,HEAD,OPTIONS"),
+            ("/api/stats", "GET,HEAD,OPTIONS"),
+            ("/stats", "GET,HEAD,OPTIONS"),
+            ("/api/bangs/check", "POST,OPTIONS"),
+            ("/maintenance", "POST,OPTIONS"),
+            ("/api/events", "GET,HEAD,OPTIONS"),
+            ("/debug/recent", "GET,HEAD,OPTIONS"),
+            ("/api/debug/recent", "GET,HEAD,OPTIONS"),
+            ("/debug/recent/clear", "POST,OPTIONS"),
+            (CAPABILITIES_PATH, "GET,HEAD,OPTIONS"),
+        ];
+        let paths: Vec<&str> = route_table(&app_config)
+            .iter()
+            .map(|(info, _)| info.path)
+            .collect();
+        assert_eq!(
+            paths,
+            expected.map(|(path, _)| path),
+            "every route is listed"
+        );
+
+        let addr = spawn_app(AppState::new(app_config), false).await;
+        let client = reqwest::Client::new();
+        for (path, allow) in expected {
+            let response = client
+                .request(Method::OPTIONS, format!("http://{addr}{path}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT, "{path}");
+            assert_eq!(response.headers()[header::ALLOW], allow, "{path}");
+
+            // the router itself lists the same methods
+            let response = client
+                .request(Method::PATCH, format!("http://{addr}{path}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{path}");
+            assert_eq!(response.headers()[header::ALLOW], allow, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash() {
+        let app_config = AppConfig::default();
+        let addr = spawn_app(AppState::new(app_config.clone()), false).await;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let request = |method, path: String| {
+            client
+                .request(method, format!("http://{addr}{path}"))
+                .send()
+        };
+
+        for (info, _) in route_table(&app_config) {
+            if info.path == "/" {
+                continue;
+            }
+            let path = info
+                .path
+                .replace("{trigger}", "gh")
+                .replace("{*path}", "style.css");
+            assert!(!path.contains('{'), "no value for {path}");
+            let method = Method::from_bytes(info.methods[0].as_bytes()).unwrap();
+            for (slashed, location) in [
+                (format!("{path}/"), path.clone()),
+                (format!("{path}//?q=a%2Fb&x"), format!("{path}?q=a%2Fb&x")),
+            ] {
+                let response = request(method.clone(), slashed).await.unwrap();
+                assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT, "{path}");
+                assert_eq!(response.headers()[header::LOCATION], location, "{path}");
+            }
+            // the canonical form is served
+            let response = request(Method::OPTIONS, path.clone()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT, "{path}");
+        }
+
+        for path in [
+            "/nope/",
+            "//",
+            "/bangs/nope/",
+            "/%62angs/",
+            "/go//",
+            "/static/",
+        ] {
+            let response = request(Method::GET, path.to_string()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_param() {
+        let app_config = AppConfig {
+            query_param: "search".to_string(),
+            ..AppConfig::default()
+        };
+        let addr = spawn_app(AppState::new(app_config.clone()), false).await;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        let response = client
+            .get(format!("http://{addr}/?search=rust+lang"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            resolve_with_info(&app_config, "rust lang").url.as_str()
+        );
+        let response = client
+            .post(format!("http://{addr}/"))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body("search=rust+lang")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            resolve_with_info(&app_config, "rust lang").url.as_str()
+        );
+
+        // `q` is just another parameter now
+        let response = client
+            .get(format!("http://{addr}/?q=rust"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::LOCATION], "/bangs");
+
+        // but API clients may always send it
+        for (query, expected) in [
+            ("q=rust+lang", "rust lang"),
+            ("search=%21gh+x&q=rust", "!gh x"),
+        ] {
+            let resolution: serde_json::Value = client
+                .get(format!("http://{addr}/api/resolve?{query}"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(
+                resolution["url"],
+                resolve_with_info(&app_config, expected).url,
+                "{query}"
+            );
+        }
+        let response = client
+            .get(format!("http://{addr}/resolve?format=location&q=rust"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            resolve_with_info(&app_config, "rust").url.as_str()
+        );
+
+        let opensearch = opensearch_xml(&app_config);
+        assert!(
+            opensearch.contains("/?search={searchTerms}"),
+            "{opensearch}"
+        );
+        assert!(
+            opensearch.contains("/suggest?search={searchTerms}"),
+            "{opensearch}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mobile_default_search() {
+        let app_config = AppConfig {
+            mobile_default_search: Some("https://m.search.example.com/?q={}".to_string()),
+            ..AppConfig::default()
+        };
+        let addr = spawn_app(AppState::new(app_config), false).await;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) Mobile/15E148";
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+
+        for (user_agent, cookies, location) in [
+            (iphone, &[][..], "https://m.search.example.com/?q=rust"),
+            (firefox, &[], "https://www.qwant.com/?q=rust"),
+            (iphone, &["mobile=0"], "https://www.qwant.com/?q=rust"),
+            (
+                firefox,
+                &["mobile=1"],
+                "https://m.search.example.com/?q=rust",
+            ),
+            // one header per cookie
+            (
+                firefox,
+                &["theme=dark", "mobile=1"],
+                "https://m.search.example.com/?q=rust",
+            ),
+        ] {
+            let mut request = client
+                .get(format!("http://{addr}/?q=rust"))
+                .header(header::USER_AGENT, user_agent);
+            for cookie in cookies {
+                request = request.header(header::COOKIE, *cookie);
+            }
+            let response = request.send().await.unwrap();
+            assert_eq!(
+                response.headers()[header::LOCATION],
+                location,
+                "{user_agent} {cookies:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_localized_default_search() {
+        let app_config = AppConfig {
+            localized_default_search: BTreeMap::from([
+                (
+                    "de".to_string(),
+                    "https://de.search.example.com/?q={}".to_string(),
+                ),
+                (
+                    "fr".to_string(),
+                    "https://fr.search.example.com/?q={}".to_string(),
+                ),
+            ]),
+            mobile_default_search: Some("https://m.search.example.com/?q={}".to_string()),
+            ..AppConfig::default()
+        };
+        let addr = spawn_app(AppState::new(app_config), false).await;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        for (accept_language, cookies, location) in [
+            (None, &[][..], "https://www.qwant.com/?q=rust"),
+            (
+                Some("de-DE,de;q=0.9"),
+                &[],
+                "https://de.search.example.com/?q=rust",
+            ),
+            (
+                Some("fr-CA,en;q=0.9,de;q=0.7"),
+                &[],
+                "https://fr.search.example.com/?q=rust",
+            ),
+            // the preferred language decides, not one liked less
+            (
+                Some("en-US,en;q=0.9,fr;q=0.8,de;q=0.7"),
+                &[],
+                "https://www.qwant.com/?q=rust",
+            ),
+            (Some("it, es;q=0.5"), &[], "https://www.qwant.com/?q=rust"),
+            (Some("de;q=0"), &[], "https://www.qwant.com/?q=rust"),
+            // the cookie pins a language whatever the browser sends
+            (
+                Some("de"),
+                &["lang=fr"],
+                "https://fr.search.example.com/?q=rust",
+            ),
+            (Some("de"), &["lang=en"], "https://www.qwant.com/?q=rust"),
+            // in any of the Cookie headers
+            (
+                Some("de"),
+                &["theme=dark", "lang=fr"],
+                "https://fr.search.example.com/?q=rust",
+            ),
+            // the mobile default search still goes first on phones
+            (
+                Some("de"),
+                &["mobile=1"],
+                "https://m.search.example.com/?q=rust",
+            ),
+        ] {
+            let mut request = client.get(format!("http://{addr}/?q=rust"));
+            if let Some(accept_language) = accept_language {
+                request = request.header(header::ACCEPT_LANGUAGE, accept_language);
+            }
+            for cookie in cookies {
+                request = request.header(header::COOKIE, *cookie);
+            }
+            let response = request.send().await.unwrap();
+            assert_eq!(
+                response.headers()[header::LOCATION],
+                location,
+                "{accept_language:?} {cookies:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bangs_page() {
+        let bang = |trigger: String, category| {
+            crate::bang::Bang {
+                category,
+                subcategory: Some("Programming".to_string()),
+                ..test_bang(&trigger, "https://example.com/?q={{{s}}}")
+            }
+        };
+        let remote: Vec<_> = (0..5)
+            .map(|i| bang(format!("t{i}"), Some(crate::bang::Category::Tech)))
+            .chain([bang("x".to_string(), None)])
+            .collect();
+        let cache = crate::resolver::build_cache(remote, &[bang("mine".to_string(), None)]);
+        let params = |category: Option<&str>, limit, offset| {
+            BangsJsonParams {
+                category: category.map(str::to_string),
+                subcategory: None,
+                limit,
+                offset,
+            }
+        };
+        let triggers = |page: &BangsPage| -> Vec<String> {
+            page.bangs.iter().map(|bang| bang.trigger.clone()).collect()
+        };
+
+        let page = bangs_page(&cache, &params(None, None, 0), 3);
+        assert_eq!((page.total, page.offset, page.limit), (7, 0, 3));
+        assert_eq!(triggers(&page), ["mine", "t0", "t1"]);
+        assert_eq!(page.bangs[0].origin, BangOrigin::Config);
+        assert_eq!(page.bangs[0].category, "Other");
+
+        // the cap applies to larger limits too
+        let page = bangs_page(&cache, &params(None, Some(100), 5), 3);
+        assert_eq!((page.total, page.limit), (7, 3));
+        assert_eq!(triggers(&page), ["t4", "x"]);
+
+        let page = bangs_page(&cache, &params(Some("Tech"), Some(2), 2), 500);
+        assert_eq!((page.total, page.limit), (5, 2));
+        assert_eq!(triggers(&page), ["t2", "t3"]);
+        assert_eq!(page.bangs[0].subcategory, "Programming");
+        let beyond = bangs_page(&cache, &params(Some("Tech"), None, 10), 500);
+        assert_eq!((beyond.total, beyond.bangs.len()), (5, 0));
+
+        let addr = spawn_server(false).await;
+        let page: serde_json::Value = reqwest::get(format!("http://{addr}/bangs.json?limit=1000"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(page["limit"], 500);
+        assert_eq!(page["offset"], 0);
+        assert!(page["total"].is_u64() && page["bangs"].is_array());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_location() {
+        let addr = spawn_server(false).await;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        let response = client
+            .get(format!("http://{addr}/resolve?q=rust+lang&format=location"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            resolve_with_info(&AppConfig::default(), "rust lang")
+                .url
+                .as_str()
+        );
+        assert_eq!(response.text().await.unwrap(), "");
+
+        let response = client
+            .get(format!("http://{addr}/resolve?q=rust+lang"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(header::LOCATION));
+        let resolution: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(resolution["fallback"], true);
+
+        let response = client
+            .get(format!("http://{addr}/resolve?q=rust&format=xml"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let addr = spawn_app(
+            AppState::new(AppConfig {
+                plus_as_space: false,
+                ..AppConfig::default()
+            }),
+            false,
+        )
+        .await;
+        let response = client
+            .get(format!(
+                "http://{addr}/resolve?q=c++%20lang&format=location"
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            resolve_with_info(&AppConfig::default(), "c++ lang")
+                .url
+                .as_str()
+        );
+    }
+
+    #[tokio::test]
+    #[expect(
+        clippy::literal_string_with_formatting_args,
+        reason = "route paths have parameters in braces"
+    )]
+    async fn test_search_url_routes() {
+        let app_config = AppConfig::default();
+        let app_state = AppState::new(app_config.clone());
+        let addr = spawn_app(app_state.clone(), false).await;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let get = |path: &str| client.get(format!("http://{addr}{path}")).send();
+
+        let response = get("/search?q=rust+lang").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            resolve_with_info(&app_config, "rust lang").url.as_str(),
+            "/search is an alias of /"
+        );
+
+        let mut cache = crate::cache::fixture_cache();
+        cache.insert(
+            "gotest".to_string(),
+            CachedBang {
+                bang: test_bang("gotest", "https://go.example.com/?q={{{s}}}"),
+                origin: BangOrigin::Config,
+            },
+        );
+        for (trigger, term, location) in [
+            (
+                "gotest",
+                "rust lang",
+                "https://go.example.com/?q=rust%20lang",
+            ),
+            ("gotest", "!w rust", "https://go.example.com/?q=%21w%20rust"),
+            ("!GoTest", "rust", "https://go.example.com/?q=rust"),
+            ("gotest", "", "https://go.example.com/?q="),
+        ] {
+            let response = go_in(&cache, &app_state, &HeaderMap::new(), trigger, term);
+            assert_eq!(response.status(), StatusCode::SEE_OTHER, "{trigger} {term}");
+            assert_eq!(
+                response.headers()[header::LOCATION],
+                location,
+                "{trigger} {term}"
+            );
+        }
+
+        let response = get("/go/nope-not-a-bang?q=rust").await.unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::NOT_FOUND,
+            "named bangs don't fall back"
+        );
+        assert!(!response.headers().contains_key(header::LOCATION));
+        let html = response.text().await.unwrap();
+        assert!(html.contains("<h1>Unknown bang</h1>"), "{html}");
+        assert!(html.contains("!nope-not-a-bang"), "{html}");
+
+        let paths: Vec<_> = capabilities(&app_config)
+            .routes
+            .iter()
+            .map(|route| route.path)
+            .collect();
+        assert!(paths.contains(&"/search") && paths.contains(&"/go/{trigger}"));
+    }
+
+    #[tokio::test]
+    async fn test_instance_headers() {
+        let addr = spawn_app(
+            AppState::new(AppConfig {
+                instance_headers: true,
+                instance_name: Some("eu-1".to_string()),
+                ..AppConfig::default()
+            }),
+            false,
+        )
+        .await;
+        for path in ["/healthz", "/robots.txt", "/nope"] {
+            let response = reqwest::get(format!("http://{addr}{path}")).await.unwrap();
+            assert_eq!(
+                response.headers()[header::SERVER],
+                concat!("redirector/", env!("CARGO_PKG_VERSION")),
+                "{path}"
+            );
+            assert_eq!(response.headers()["x-instance"], "eu-1", "{path}");
+        }
+
+        let addr = spawn_app(
+            AppState::new(AppConfig {
+                instance_name: Some("eu-1".to_string()),
+                ..AppConfig::default()
+            }),
+            false,
+        )
+        .await;
+        let response = reqwest::get(format!("http://{addr}/healthz"))
+            .await
+            .unwrap();
+        assert!(
+            !response.headers().contains_key(header::SERVER),
+            "off by default"
+        );
+        assert!(!response.headers().contains_key("x-instance"));
+    }
+
+    /// Send a raw HTTP/1.0 request to `addr`, returning the status line, the
+    /// headers and the body.
+    async fn http10(addr: SocketAddr, request: &str) -> (String, String) {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.to_string(), body.to_string())
+    }
+
+    /// Check that `xml` is a well-formed description searching `base_url`.
+    fn assert_opensearch(xml: &str, base_url: &str) {
+        let document = roxmltree::Document::parse(xml).unwrap();
+        let root = document.root_element();
+        assert_eq!(root.tag_name().name(), "OpenSearchDescription");
+        let templates: Vec<&str> = root
+            .children()
+            .filter(|node| node.has_tag_name("Url"))
+            .map(|node| node.attribute("template").unwrap())
+            .collect();
+        assert_eq!(templates[0], format!("{base_url}/?q={{searchTerms}}"));
+        for template in templates {
+            assert_eq!(template.matches("{searchTerms}").count(), 1, "{template}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_template() {
+        let addr = spawn_app(AppState::new(AppConfig::default()), false).await;
+        let client = reqwest::Client::new();
+        let validate = |url_template: &str| {
+            let request = client
+                .post(format!("http://{addr}/validate_template"))
+                .json(&serde_json::json!({ "url_template": url_template }));
+            async move {
+                let response = request.send().await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                response.json::<serde_json::Value>().await.unwrap()
+            }
+        };
+
+        assert_eq!(
+            validate("https://crates.io/search?q={{{s}}}").await,
+            serde_json::json!({
+                "placeholder": true,
+                "url": "https://crates.io/search?q=sample%20term",
+                "valid_url": true,
+                "error": null,
+                "warnings": [],
+            })
+        );
+        let check = validate("https://example.com/?a={{{s}}}&b={{{s}}}").await;
+        assert_eq!(check["valid_url"], true);
+        assert_eq!(
+            check["warnings"],
+            serde_json::json!(["2 {{{s}}} placeholders, the term is inserted at each"])
+        );
+        let check = validate("https://example.com/?q=%s").await;
+        assert_eq!(check["placeholder"], false);
+        assert_eq!(check["url"], "https://example.com/?q=%ssample%20term");
+        assert_eq!(
+            check["warnings"],
+            serde_json::json!([
+                "No {{{s}}} placeholder, the term is appended",
+                "%s is no placeholder, use {{{s}}}"
+            ])
+        );
+        let check = validate("example.com/?q={}").await;
+        assert_eq!(check["valid_url"], false);
+        assert_eq!(check["url"], "example.com/?q={}sample%20term");
+        assert_eq!(check["error"], "needs an http:// or https:// scheme");
+        assert!(
+            check["warnings"]
+                .as_array()
+                .unwrap()
+                .contains(&"{} is kept as it is, use {{{s}}} for the term".into())
+        );
+        let check = validate("https://exa mple.com/?q={{{s}}}").await;
+        assert_eq!(check["valid_url"], false);
+        assert!(
+            check["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("is not a valid URL")
+        );
+
+        let response = client
+            .post(format!("http://{addr}/validate_template"))
+            .json(&serde_json::json!({ "template": "https://example.com/" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_opensearch_without_host() {
+        let addr = spawn_server(false).await;
+        let base_url = AppConfig::default().base_url();
+        for request in [
+            "GET /opensearch.xml HTTP/1.0\r\n\r\n",
+            "GET /opensearch.xml HTTP/1.0\r\nX-Forwarded-Host: evil.example\r\nX-Forwarded-Proto: \
+             https\r\nForwarded: host=\"a\\\"b\"\r\n\r\n",
+            "GET /opensearch.xml HTTP/1.1\r\nHost: <not a host>\r\nConnection: close\r\n\r\n",
+        ] {
+            let (head, body) = http10(addr, request).await;
+            assert!(head.starts_with("HTTP/1."), "{head}");
+            assert!(head.contains(" 200 OK"), "{head}");
+            assert!(
+                head.contains("content-type: application/opensearchdescription+xml"),
+                "{head}"
+            );
+            assert_opensearch(&body, &base_url);
+        }
+        let (head, body) = http10(addr, "HEAD /opensearch.xml HTTP/1.0\r\n\r\n").await;
+        assert!(head.contains(" 200 OK"), "{head}");
+        assert_eq!(body, "");
+
+        // configured values are escaped
+        let app_config = AppConfig {
+            base_url: Some("https://search.example.com/a&b\"c/".to_string()),
+            ..AppConfig::default()
+        };
+        assert_opensearch(
+            &opensearch_xml(&app_config),
+            "https://search.example.com/a&b\"c",
+        );
+        let app_config = AppConfig {
+            ip: "::1".parse().unwrap(),
+            ..AppConfig::default()
+        };
+        assert_opensearch(&opensearch_xml(&app_config), "http://[::1]:3000");
+    }
+
+    #[tokio::test]
+    async fn test_robots_txt() {
+        let addr = spawn_server(false).await;
+        let response = reqwest::get(format!("http://{addr}/robots.txt"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            response.text().await.unwrap(),
+            "User-agent: *\nDisallow: /\n"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let robots_path = dir.path().join("robots.txt");
+        let app_state = AppState::new(AppConfig {
+            robots_txt: Some("User-agent: *\nAllow: /bangs\n".to_string()),
+            ..AppConfig::default()
+        });
+        let addr = spawn_app(app_state.clone(), false).await;
+        let robots_txt = || {
+            async {
+                reqwest::get(format!("http://{addr}/robots.txt"))
+                    .await
+                    .unwrap()
+                    .text()
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(robots_txt().await, "User-agent: *\nAllow: /bangs\n");
+
+        // a file takes precedence, the configured content is the fallback
+        app_state.config.write().robots_txt_path = Some(robots_path.clone());
+        assert_eq!(robots_txt().await, "User-agent: *\nAllow: /bangs\n");
+        std::fs::write(&robots_path, "User-agent: *\nAllow: /\n").unwrap();
+        assert_eq!(robots_txt().await, "User-agent: *\nAllow: /\n");
+    }
+
+    #[tokio::test]
+    async fn test_compact_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        let contents = "[[bangs]]\ntrigger = \"gh\"\nurl_template = \"https://github.com/search?q={{{s}}}\"\n\n[[bangs]]\ntrigger = \"gh\"\nurl_template = \"https://github.com/search?q={{{s}}}\"\n";
+        std::fs::write(&config_path, contents).unwrap();
+        let client = reqwest::Client::new();
+        let compact = |addr: SocketAddr, token: Option<&'static str>| {
+            let mut request = client.post(format!("http://{addr}/api/config/compact?dry_run=true"));
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            request.send()
+        };
+
+        let addr = spawn_app(
+            AppState::new(AppConfig {
+                config_path: config_path.clone(),
+                ..AppConfig::default()
+            }),
+            false,
+        )
+        .await;
+        let response = compact(addr, Some("hunter2")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "fails closed");
+
+        let addr = spawn_app(
+            AppState::new(AppConfig {
+                config_path: config_path.clone(),
+                admin_token: Some("hunter2".to_string()),
+                ..AppConfig::default()
+            }),
+            false,
+        )
+        .await;
+        let response = compact(addr, None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = compact(addr, Some("hunter2")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let json: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(json["removed"].as_array().unwrap().len(), 1);
+        assert_eq!(std::fs::read_to_string(&config_path).unwrap(), contents);
+    }
+
+    #[tokio::test]
+    async fn test_selftest() {
+        // a bang that is never loaded fails the selftest, whatever else is in
+        // the global cache
+        let app_config = AppConfig {
+            offline: true,
+            admin_token: Some("hunter2".to_string()),
+            selftest_trigger: "no-such-bang".to_string(),
+            ..AppConfig::default()
+        };
+        let addr = spawn_app(AppState::new(app_config), false).await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("http://{addr}/selftest"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = client
+            .get(format!("http://{addr}/selftest"))
+            .bearer_auth("hunter3")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client
+            .get(format!("http://{addr}/selftest"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let report: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(report["passed"], false, "{report}");
+        let names: Vec<&str> = report["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|check| check["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "cache",
+                "bang",
+                "default_search",
+                "opensearch",
+                "capabilities",
+                "suggest"
+            ]
+        );
+        assert_eq!(report["checks"][1]["passed"], false, "{report}");
+        assert_eq!(report["checks"][2]["passed"], true, "{report}");
+        assert_eq!(report["checks"][5]["passed"], true, "{report}");
+    }
+
+    #[tokio::test]
+    async fn test_admin_page() {
+        let app_state = AppState::new(AppConfig {
+            admin_token: Some("hunter2".to_string()),
+            ..AppConfig::default()
+        });
+        app_state.usage.lock().record("gh");
+        let subscriber = registry().with(app_state.recent_events.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            error!("Failed to fetch bangs from <https://example.com>");
+        });
+        let addr = spawn_app(app_state, false).await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("http://{addr}/admin"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = client
+            .get(format!("http://{addr}/admin"))
+            .bearer_auth("wrong")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client
+            .get(format!("http://{addr}/admin"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let html = response.text().await.unwrap();
+        assert!(
+            html.contains(
+                "<td>ERROR</td><td>redirector::server::tests</td><td>Failed to fetch bangs from \
+                 &lt;https://example.com&gt;</td>"
+            ),
+            "{html}"
+        );
+        assert!(html.contains("<tr><td>!gh</td><td>1</td></tr>"), "{html}");
+        assert!(html.contains("<th>Status</th><td>starting</td>"), "{html}");
+        assert!(html.contains(r#"action="/bangs/reload""#), "{html}");
+
+        let addr = spawn_app(
+            AppState::new(AppConfig {
+                routes: RoutesConfig {
+                    admin: false,
+                    ..RoutesConfig::default()
+                },
+                ..AppConfig::default()
+            }),
+            false,
+        )
+        .await;
+        let response = client
+            .get(format!("http://{addr}/admin"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_top() {
+        let app_state = AppState::new(AppConfig {
+            admin_token: Some("hunter2".to_string()),
+            ..AppConfig::default()
+        });
+        {
+            let mut usage = app_state.usage.lock();
+            for trigger in ["gh", "w", "gh", "g", "gh", "w"] {
+                usage.record(trigger);
+            }
+        }
+        let addr = spawn_app(app_state, false).await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("http://{addr}/metrics/top"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let top: serde_json::Value = client
+            .get(format!("http://{addr}/metrics/top?window=1h&limit=2"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            top,
+            serde_json::json!({
+                "window_seconds": 3600,
+                "top": [{"trigger": "gh", "count": 3}, {"trigger": "w", "count": 2}],
+            })
+        );
+
+        let response = client
+            .get(format!("http://{addr}/metrics/top?window=yesterday"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_healthz_phases() {
+        let app_state = AppState::new(AppConfig::default());
+        let addr = spawn_app(app_state.clone(), false).await;
+        let healthz = async || {
+            let response = reqwest::get(format!("http://{addr}/healthz"))
+                .await
+                .unwrap();
+            let status = response.status();
+            let health: serde_json::Value = response.json().await.unwrap();
+            (
+                status,
+                health["status"].clone(),
+                health["last_update_error"].clone(),
+            )
+        };
+
+        assert_eq!(
+            healthz().await,
+            (StatusCode::OK, "starting".into(), serde_json::Value::Null),
+            "a fresh instance is still loading its bangs"
+        );
+        app_state.record_update_failure(&anyhow::anyhow!("offline"));
+        assert_eq!(
+            healthz().await,
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "failed".into(),
+                "offline".into()
+            )
+        );
+        app_state.record_update();
+        assert_eq!(
+            healthz().await,
+            (StatusCode::OK, "healthy".into(), serde_json::Value::Null)
+        );
+        app_state.record_update_failure(&anyhow::anyhow!("timed out"));
+        assert_eq!(
+            healthz().await,
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "degraded".into(),
+                "timed out".into()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_debug_recent() {
+        let app_state = AppState::new(AppConfig {
+            admin_token: Some("hunter2".to_string()),
+            debug_recent_size: 3,
+            ..AppConfig::default()
+        });
+        let addr = spawn_app(app_state, false).await;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let recent = || {
+            async {
+                let response = client
+                    .get(format!("http://{addr}/api/debug/recent"))
+                    .bearer_auth("hunter2")
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                response.json::<Vec<serde_json::Value>>().await.unwrap()
+            }
+        };
+
+        for i in 0..5 {
+            let response = client
+                .get(format!("http://{addr}/?q=weird+search+{i}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        }
+        let resolutions = recent().await;
+        let queries: Vec<&str> = resolutions
+            .iter()
+            .map(|resolution| resolution["query"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            queries,
+            ["weird search 4", "weird search 3", "weird search 2"],
+            "only the last debug_recent_size are kept"
+        );
+        assert_eq!(resolutions[0]["fallback"], true);
+        assert!(
+            resolutions[0]["url"]
+                .as_str()
+                .unwrap()
+                .contains("weird%20search%204"),
+            "{resolutions:?}"
+        );
+
+        let response = client
+            .get(format!("http://{addr}/debug/recent"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let page = client
+            .get(format!("http://{addr}/debug/recent"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(page.contains("<td>weird search 4</td>"), "{page}");
+
+        let response = client
+            .post(format!("http://{addr}/debug/recent/clear"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/debug/recent");
+        assert!(recent().await.is_empty(), "cleared");
+
+        assert_eq!(AppConfig::default().debug_recent_capacity(), 200);
+
+        // nothing is recorded, or offered, while queries are redacted
+        let app_state = AppState::new(AppConfig {
+            admin_token: Some("hunter2".to_string()),
+            redact_queries: true,
+            ..AppConfig::default()
+        });
+        let addr = spawn_app(app_state.clone(), false).await;
+        let response = client
+            .get(format!("http://{addr}/?q=secret"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert!(
+            app_state.recent_resolutions.recent().is_empty(),
+            "redacted queries are not kept"
+        );
+        let response = client
+            .get(format!("http://{addr}/api/debug/recent"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_events_stream() {
+        let app_state = AppState::new(AppConfig {
+            admin_token: Some("hunter2".to_string()),
+            ..AppConfig::default()
+        });
+        let addr = spawn_app(app_state.clone(), false).await;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!("http://{addr}/api/events"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let next_event = |mut response: reqwest::Response| {
+            async move {
+                let chunk = timeout(Duration::from_secs(5), response.chunk())
+                    .await
+                    .expect("an event in time")
+                    .unwrap()
+                    .unwrap();
+                String::from_utf8(chunk.to_vec()).unwrap()
+            }
+        };
+        for (accept, content_type, prefix, suffix) in [
+            (None, NDJSON, "{", "}\n"),
+            (Some(EVENT_STREAM), EVENT_STREAM, "data: {", "}\n\n"),
+        ] {
+            let mut request = client
+                .get(format!("http://{addr}/api/events"))
+                .bearer_auth("hunter2");
+            if let Some(accept) = accept {
+                request = request.header(header::ACCEPT, accept);
+            }
+            let response = request.send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], content_type);
+            assert!(app_state.firehose.is_watched());
+
+            let redirect = client
+                .get(format!("http://{addr}/?q=weird+search"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(redirect.status(), StatusCode::SEE_OTHER);
+            let event = next_event(response).await;
+            assert!(
+                event.starts_with(prefix) && event.ends_with(suffix),
+                "{event:?}"
+            );
+            let json: serde_json::Value =
+                serde_json::from_str(event.trim_start_matches("data: ")).unwrap();
+            assert_eq!(json["event"], "redirect");
+            assert_eq!(json["query"], "weird search");
+            assert_eq!(json["fallback"], true);
+        }
+
+        app_state.config.write().redact_queries = true;
+        let response = client
+            .get(format!("http://{addr}/api/events"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap();
+        client
+            .get(format!("http://{addr}/?q=secret"))
+            .send()
+            .await
+            .unwrap();
+        let event = next_event(response).await;
+        assert!(!event.contains("secret"), "{event}");
+        assert!(event.contains(r#""event":"redirect""#), "{event}");
+    }
+
+    #[tokio::test]
+    async fn test_events_need_admin_token() {
+        let addr = spawn_app(AppState::new(AppConfig::default()), false).await;
+        let client = reqwest::Client::new();
+        for token in [None, Some("hunter2")] {
+            let mut request = client.get(format!("http://{addr}/api/events"));
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            let response = request.send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{token:?}");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_events_keepalive() {
+        let firehose = Firehose::default();
+        let frames = with_keepalive(
+            firehose.subscribe().map(|event| event.to_sse()),
+            Duration::from_secs(15),
+        );
+        let mut frames = std::pin::pin!(frames);
+        firehose.publish(FirehoseEvent::bang_added("gh"));
+        let frame = frames.next().await.unwrap();
+        assert!(frame.starts_with("data: {"), "{frame:?}");
+        let started = tokio::time::Instant::now();
+        assert_eq!(frames.next().await.unwrap(), ": keepalive\n\n");
+        assert_eq!(started.elapsed(), Duration::from_secs(15));
+        assert_eq!(frames.next().await.unwrap(), ": keepalive\n\n");
+    }
+
+    #[tokio::test]
+    async fn test_maintenance() {
+        let app_state = AppState::new(AppConfig {
+            max_cache_age: 60,
+            admin_token: Some("hunter2".to_string()),
+            ..AppConfig::default()
+        });
+        app_state.record_update();
+        let addr = spawn_app(app_state, false).await;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let toggle = |query: &'static str| {
+            client
+                .post(format!("http://{addr}/maintenance{query}"))
+                .bearer_auth("hunter2")
+                .send()
+        };
+
+        let response = client
+            .post(format!("http://{addr}/maintenance"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let state: serde_json::Value = toggle("").await.unwrap().json().await.unwrap();
+        assert_eq!(state["maintenance"], true);
+
+        let response = client
+            .get(format!("http://{addr}/?q=rust"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.text().await.unwrap().contains("maintenance"));
+        let response = client
+            .get(format!("http://{addr}/healthz"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let health: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(health["maintenance"], true);
+        let response = client
+            .get(format!("http://{addr}/metrics"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let state: serde_json::Value = toggle("?enabled=false")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(state["maintenance"], false);
+        let response = client
+            .get(format!("http://{addr}/?q=rust"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    }
+
+    #[tokio::test]
+    async fn test_source_metadata_exposed() {
+        let app_state = AppState::new(AppConfig::default());
+        let metadata = SourceMetadata {
+            source: "https://duckduckgo.com/bang.js".to_string(),
+            final_url: Some("https://cdn.duckduckgo.com/bang.js".to_string()),
+            fetched_at: 1_791_964_800,
+            last_modified: Some("Tue, 13 Oct 2026 08:00:00 GMT".to_string()),
+            etag: Some("\"v42\"".to_string()),
+            content_length: Some(1234),
+            entries: 13_000,
+            content_hash: "0123456789abcdef".to_string(),
+        };
+        assert!(app_state.record_source(metadata.clone()));
+        assert!(
+            !app_state.record_source(metadata.clone()),
+            "the same content is unchanged"
+        );
+        assert_eq!(app_state.sources.read().len(), 1, "one entry per source");
+
+        let addr = spawn_app(app_state, false).await;
+        let healthz: serde_json::Value = reqwest::get(format!("http://{addr}/healthz"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let api: serde_json::Value = reqwest::get(format!("http://{addr}/api/bangs"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let expected = serde_json::json!({
+            "source": "https://duckduckgo.com/bang.js",
+            "final_url": "https://cdn.duckduckgo.com/bang.js",
+            "fetched_at": 1_791_964_800,
+            "last_modified": "Tue, 13 Oct 2026 08:00:00 GMT",
+            "etag": "\"v42\"",
+            "content_length": 1234,
+            "entries": 13_000,
+            "content_hash": "0123456789abcdef",
+        });
+        assert_eq!(healthz["sources"], serde_json::json!([expected]));
+        assert_eq!(api["sources"], serde_json::json!([expected]));
+        assert!(api["entries"].is_u64());
+
+        let html = reqwest::get(format!("http://{addr}/bangs"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(html.contains(
+            "13000 bangs from <code>https://duckduckgo.com/bang.js</code> (redirected to \
+             <code>https://cdn.duckduckgo.com/bang.js</code>)"
+        ));
+        assert!(html.contains("0123456789abcdef"));
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let app_config = AppConfig {
+            stats_persist: true,
+            stats_path: Some(dir.path().join("stats.json")),
+            admin_token: Some("hunter2".to_string()),
+            ..AppConfig::default()
+        };
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let app_state = AppState::new(app_config.clone());
+        let addr = spawn_app(app_state.clone(), false).await;
+        for _ in 0..3 {
+            client
+                .get(format!("http://{addr}/?q=rust+lang"))
+                .send()
+                .await
+                .unwrap();
+        }
+        crate::stats::fold(&app_state);
+
+        // after a restart
+        let addr = spawn_app(AppState::new(app_config), false).await;
+        let report: serde_json::Value = client
+            .get(format!("http://{addr}/api/stats"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(report["month"], crate::stats::current_month());
+        assert_eq!(report["redirects"], 3);
+        let response = client
+            .get(format!("http://{addr}/api/stats?month=2025-13"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let page = client
+            .get(format!("http://{addr}/stats"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(page.contains("<td>3</td>"), "{page}");
+
+        // without stats_persist nothing is counted nor served
+        let addr = spawn_server(false).await;
+        let response = client
+            .get(format!("http://{addr}/api/stats"))
+            .bearer_auth("hunter2")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_advertise_suggestions() {
+        let suggestions_url = r#"<Url type="application/x-suggestions+json""#;
+        assert!(opensearch_xml(&AppConfig::default()).contains(suggestions_url));
+        let app_config = AppConfig {
+            advertise_suggestions: false,
+            offline: true,
+            ..AppConfig::default()
+        };
+        let opensearch = opensearch_xml(&app_config);
+        assert!(!opensearch.contains(suggestions_url), "{opensearch}");
+        assert!(!opensearch.contains("/suggest"), "{opensearch}");
+        assert!(
+            opensearch.contains(r#"<Url type="text/html""#),
+            "{opensearch}"
+        );
+
+        // only the advertisement is gone, the endpoint still answers
+        let addr = spawn_app(AppState::new(app_config), false).await;
+        let body = reqwest::get(format!("http://{addr}/opensearch.xml"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(!body.contains("/suggest"), "{body}");
+        let response = reqwest::get(format!("http://{addr}/suggest?q=!"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_capabilities() {
+        let app_config = AppConfig {
+            admin_token: Some("hunter2".to_string()),
+            routes: RoutesConfig {
+                add_bang: false,
+                suggest: false,
+                ..RoutesConfig::default()
+            },
+            ..AppConfig::default()
+        };
+        assert!(!opensearch_xml(&app_config).contains("/suggest"));
+        let addr = spawn_app(AppState::new(app_config), false).await;
+
+        let response = reqwest::get(format!("http://{addr}/.well-known/redirector.json"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "public, max-age=300"
+        );
+        let document: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(document["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(document["bang_prefix"], "!");
+        assert_eq!(
+            document["export_formats"],
+            serde_json::json!(["firefox-searchjson", "chrome"])
+        );
+        // the token only guards the admin routes, not e.g. /restart
+        assert_eq!(document["auth_required_for_writes"], false);
+        assert_eq!(document["suggestion_provider"], serde_json::Value::Null);
+        assert_eq!(
+            serde_json::to_value(capabilities(&AppConfig::default())).unwrap()
+                ["suggestion_provider"],
+            "brave"
+        );
+        let paths: Vec<&str> = document["routes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|route| route["path"].as_str().unwrap())
+            .collect();
+        for path in ["/", "/api/resolve", "/maintenance", CAPABILITIES_PATH] {
+            assert!(paths.contains(&path), "{path} must be listed in {paths:?}");
+        }
+        let route = |path: &str| {
+            document["routes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|route| route["path"] == path)
+                .cloned()
+        };
+        for expected in [
+            serde_json::json!({"path": "/", "methods": ["GET", "POST"], "admin": false, "write": false}),
+            serde_json::json!({"path": "/go/{trigger}", "methods": ["GET", "POST"], "admin": false, "write": false}),
+            serde_json::json!({"path": "/restart", "methods": ["GET"], "admin": false, "write": true}),
+            serde_json::json!({"path": "/api/bangs/check", "methods": ["POST"], "admin": true, "write": false}),
+            serde_json::json!({"path": "/maintenance", "methods": ["POST"], "admin": true, "write": true}),
+            serde_json::json!({"path": CAPABILITIES_PATH, "methods": ["GET"], "admin": false, "write": false}),
+        ] {
+            let path = expected["path"].as_str().unwrap().to_string();
+            assert_eq!(route(&path), Some(expected), "{path}");
+        }
+        for path in ["/add_bang", "/suggest"] {
+            assert!(!paths.contains(&path), "{path} must not be listed");
+            let response = reqwest::get(format!("http://{addr}{path}")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+        }
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        use std::io::Write as _;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_import_bangs() {
+        let list = r#"[{"t":"!gh","u":"https://github.com/search?q={{{s}}}"},{"t":"w","u":"https://en.wikipedia.org/w/index.php?search={{{s}}}"}]"#;
+        let gzipped_headers =
+            HeaderMap::from_iter([(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"))]);
+        let (bangs, skipped) =
+            parse_import(&gzipped_headers, &gzip(list.as_bytes()), ImportFormat::Json).unwrap();
+        assert_eq!((bangs.len(), skipped), (2, 0));
+        assert_eq!(bangs[1].trigger, "w");
+        assert_eq!(
+            parse_import(&HeaderMap::new(), list.as_bytes(), ImportFormat::Json)
+                .unwrap()
+                .0
+                .len(),
+            2
+        );
+        let bookmarks = include_bytes!("../res/bookmarks.html");
+        let (bangs, skipped) = parse_import(
+            &gzipped_headers,
+            &gzip(bookmarks),
+            ImportFormat::BookmarksHtml,
+        )
+        .unwrap();
+        assert_eq!((bangs.len(), skipped), (5, 4));
+        let (status, _) =
+            parse_import(&HeaderMap::new(), bookmarks, ImportFormat::Json).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let addr = spawn_app(AppState::new(AppConfig::default()), false).await;
+        let client = reqwest::Client::new();
+        let import = |body: Vec<u8>, encoding: &'static str| {
+            client
+                .post(format!("http://{addr}/api/bangs/import"))
+                .header(header::CONTENT_ENCODING, encoding)
+                .body(body)
+                .send()
+        };
+        let bomb = gzip(&vec![b' '; MAX_DECOMPRESSED_SIZE + 1]);
+        assert!(bomb.len() < 1024 * 1024);
+        let response = import(bomb, "gzip").await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let response: serde_json::Value = response.json().await.unwrap();
+        assert!(
+            response["error"]
+                .as_str()
+                .unwrap()
+                .contains("Too large after decompression"),
+            "{response}"
+        );
+
+        // a plain list labeled as gzip
+        let response = import(list.as_bytes().to_vec(), "gzip").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response: serde_json::Value = response.json().await.unwrap();
+        assert!(
+            response["error"].as_str().unwrap().contains("Corrupt gzip"),
+            "{response}"
+        );
+
+        let response = import(list.as_bytes().to_vec(), "br").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_bang_quota() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(&config_path, "").unwrap();
+        let app_config = AppConfig {
+            bangs: Some(Vec::new()),
+            max_custom_bangs: 3,
+            config_path: config_path.clone(),
+            ..AppConfig::default()
+        };
+        let addr = spawn_app(AppState::new(app_config.clone()), false).await;
+        let client = reqwest::Client::new();
+        let add = |addr: SocketAddr, trigger: &str| {
+            client
+                .post(format!(
+                    "http://{addr}/add_bang?trigger={trigger}&url_template=https%3A%2F%2Fquota.example.com%2F%3Fq%3D"
+                ))
+                .send()
+        };
+        let import = |addr: SocketAddr, triggers: &[&str]| {
+            let list: Vec<_> = triggers
+                .iter()
+                .map(|trigger| serde_json::json!({ "t": trigger, "u": "https://quota.example.com/?q=" }))
+                .collect();
+            client
+                .post(format!("http://{addr}/api/bangs/import"))
+                .json(&list)
+                .send()
+        };
+
+        for trigger in ["quota1", "quota2"] {
+            assert_eq!(add(addr, trigger).await.unwrap().status(), StatusCode::OK);
+        }
+        let response = import(addr, &["quota3", "quota4", "quota5"]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(response["status"], "partial");
+        assert_eq!(response["imported"], 1, "the third bang still fits");
+        assert_eq!(response["skipped"], 0);
+        assert_eq!(
+            response["over_quota"],
+            serde_json::json!(["quota4", "quota5"])
+        );
+
+        let response = add(addr, "quota6").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response: serde_json::Value = response.json().await.unwrap();
+        assert!(
+            response["error"]
+                .as_str()
+                .unwrap()
+                .contains("max_custom_bangs = 3"),
+            "{response}"
+        );
+        let response = import(addr, &["quota7"]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(response["over_quota"], serde_json::json!(["quota7"]));
+        let contents = std::fs::read_to_string(&config_path).unwrap();
+        assert_eq!(contents.matches("[[bangs]]").count(), 3, "{contents}");
+
+        // the file may grow up to exactly max_config_file_size
+        let size = std::fs::metadata(&config_path).unwrap().len();
+        let per_bang = size / 3;
+        let addr = spawn_app(
+            AppState::new(AppConfig {
+                max_custom_bangs: 100,
+                max_config_file_size: size + per_bang,
+                ..app_config
+            }),
+            false,
+        )
+        .await;
+        assert_eq!(add(addr, "quota8").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            std::fs::metadata(&config_path).unwrap().len(),
+            size + per_bang
+        );
+        let response = add(addr, "quota9").await.unwrap();
+        assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+        let response = import(addr, &["quota9"]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+        assert_eq!(
+            std::fs::metadata(&config_path).unwrap().len(),
+            size + per_bang,
+            "nothing is written beyond the quota"
+        );
+    }
+
+    #[test]
+    fn test_render_empty_cache() {
+        let empty = crate::BangCache::new();
+        assert!(render_active_bangs(&empty, None).contains("still loading"));
+        let html = render_active_bangs(&empty, Some("error sending request for <url>"));
+        assert!(html.contains("unreachable"), "{html}");
+        assert!(html.contains("error sending request for &lt;url&gt;"));
+        assert!(!html.contains("<table>"));
+
+        let cache = crate::resolver::build_cache(
+            Vec::new(),
+            &[test_bang(
+                "w",
+                "https://en.wikipedia.org/w/index.php?search={{{s}}}",
+            )],
+        );
+        let html = render_active_bangs(&cache, Some("stale error"));
+        assert!(html.contains("<table>") && html.contains("<strong>w</strong>"));
+        assert!(!html.contains("stale error"));
+
+        let app_state = AppState::new(AppConfig::default());
+        app_state.record_update_failure(&anyhow::anyhow!("offline"));
+        assert_eq!(
+            app_state.last_update_error.read().as_deref(),
+            Some("offline")
+        );
+        app_state.record_update();
+        assert_eq!(*app_state.last_update_error.read(), None);
+    }
+
+    #[tokio::test]
+    async fn test_route_budgets() {
+        let app_state = AppState::new(AppConfig {
+            timeouts: TimeoutsConfig {
+                redirect_ms: 50,
+                suggest_ms: 50,
+                bangs_page_ms: 50,
+                admin_ms: 50,
+            },
+            ..AppConfig::default()
+        });
+        // stands in for a stuck handler, e.g. an upstream that never answers
+        let slow = || get(|| async { sleep(Duration::from_secs(5)).await });
+        let app = Router::new()
+            .route(
+                "/redirect",
+                with_budget(slow(), &app_state, Budget::Redirect),
+            )
+            .route("/suggest", with_budget(slow(), &app_state, Budget::Suggest))
+            .route("/bangs", with_budget(slow(), &app_state, Budget::BangsPage))
+            .route("/admin", with_budget(slow(), &app_state, Budget::Admin))
+            .route(
+                "/fast",
+                with_budget(get(|| async { "done" }), &app_state, Budget::Redirect),
+            )
+            .with_state(app_state);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, app, false));
+        let get = |path: &str| reqwest::get(format!("http://{addr}{path}"));
+
+        let start = Instant::now();
+        for path in ["/redirect?q=!g+rust", "/bangs"] {
+            let response = get(path).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE, "{path}");
+            assert!(
+                response
+                    .text()
+                    .await
+                    .unwrap()
+                    .contains("<h1>Timed out</h1>")
+            );
+        }
+        let response = get("/suggest?q=ru").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let suggestions: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(suggestions, serde_json::json!(["ru", []]));
+        let response = get("/admin").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(response["status"], "failed");
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "no request may wait for the handler"
+        );
+
+        let response = get("/fast").await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "done");
+    }
+
+    #[test]
+    fn test_post_form() {
+        let cache = crate::BangCache::from([(
+            "wiki".to_string(),
+            CachedBang {
+                bang: crate::bang::Bang {
+                    method: Some(crate::bang::BangMethod::Post),
+                    post_field: Some("search\"term".to_string()),
+                    post_fields: Some(std::collections::HashMap::from([
+                        ("csrf".to_string(), "<token>".to_string()),
+                        ("scope".to_string(), "all".to_string()),
+                        ("search\"term".to_string(), "shadowed".to_string()),
+                    ])),
+                    ..test_bang("wiki", "https://wiki.internal/search?lang=en&full=1")
+                },
+                origin: BangOrigin::Config,
+            },
+        )]);
+        let config = AppConfig::default();
+
+        let resolution =
+            crate::resolve_in(&cache, &config, r#"!wiki "><script>alert('x')</script>"#);
+        let post = resolution.post.as_ref().unwrap();
+        assert_eq!(post.term, r#""><script>alert('x')</script>"#);
+
+        let html = render_post_form(&resolution.url, post);
+        assert!(
+            html.contains(
+                r#"<form method="post" action="https://wiki.internal/search?lang=en&amp;full=1">"#
+            ),
+            "{html}"
+        );
+        assert!(
+            html.contains(
+                r#"<input type="hidden" name="search&quot;term" value="&quot;&gt;&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"/>"#
+            ),
+            "{html}"
+        );
+        assert!(
+            html.contains(
+                r#"<input type="hidden" name="csrf" value="&lt;token&gt;"/><input type="hidden" name="scope" value="all"/>"#
+            ),
+            "hidden fields must be posted as well: {html}"
+        );
+        assert!(
+            !html.contains("shadowed"),
+            "the term must win over hidden fields"
+        );
+        assert!(!html.contains("<script>"), "the term must be escaped");
+
+        let config = AppConfig {
+            post_bangs: false,
+            ..AppConfig::default()
+        };
+        let resolution = crate::resolve_in(&cache, &config, "!wiki rust");
+        assert_eq!(resolution.post, None, "POST bangs can be disabled");
+        assert_eq!(
+            resolution.url,
+            "https://wiki.internal/search?lang=en&full=1rust"
+        );
+    }
+
+    #[test]
+    fn test_render_try() {
+        let cache = crate::BangCache::from([(
+            "gh".to_string(),
+            CachedBang {
+                bang: test_bang("gh", "https://github.com/search?q={{{s}}}&type=code"),
+                origin: BangOrigin::Config,
+            },
+        )]);
+        let config = AppConfig::default();
+
+        let query = "!gh <script>";
+        let resolution = crate::resolve_in(&cache, &config, query);
+        let html = render_try(Some(query), Some(&resolution));
+        assert!(html.contains(r#"value="!gh &lt;script&gt;""#));
+        assert!(
+            html.contains(
+                r#"<code>https://github.com/search?q=%3Cscript%3E&amp;type=code</code> <a href="https://github.com/search?q=%3Cscript%3E&amp;type=code">"#
+            ),
+            "the URL must be shown escaped and linked: {html}"
+        );
+        assert!(html.contains("<th>Trigger</th><td>gh</td>"));
+        assert!(html.contains("<th>Source</th><td>config</td>"));
+        assert!(html.contains("<th>Fallback</th><td>no</td>"));
+
+        let resolution = crate::resolve_in(&cache, &config, "!nope rust");
+        assert!(resolution.fallback);
+        let html = render_try(Some("!nope rust"), Some(&resolution));
+        assert!(html.contains(&escape_html(&resolution.url).into_owned()));
+        assert!(html.contains("<th>Trigger</th><td>-</td>"));
+        assert!(html.contains("<th>Source</th><td>-</td>"));
+        assert!(html.contains("<th>Fallback</th><td>yes</td>"));
+
+        assert!(
+            !render_try(None, None).contains("<table>"),
+            "nothing to show without a query"
+        );
+    }
+
+    /// Log output collected by a test subscriber.
+    #[derive(Clone, Default)]
+    struct LogBuffer(std::sync::Arc<parking_lot::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_note_in_log() {
+        let cache = crate::BangCache::from([(
+            "ci".to_string(),
+            CachedBang {
+                bang: crate::bang::Bang {
+                    note: Some("nightly pipeline".to_string()),
+                    ..test_bang("ci", "https://ci.example.com/?q=")
+                },
+                origin: BangOrigin::Config,
+            },
+        )]);
+        let resolution = crate::resolve_in(&cache, &AppConfig::default(), "!ci build");
+        assert_eq!(resolution.note.as_deref(), Some("nightly pipeline"));
+
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            log_redirect("!ci build", &resolution, false);
+        });
+
+        let logs = String::from_utf8(logs.0.lock().clone()).unwrap();
+        assert!(
+            logs.contains(r#"note="nightly pipeline""#),
+            "the note must be logged: {logs}"
+        );
+        assert!(logs.contains("trigger=\"ci\""), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn test_h2c() {
+        let h2c_client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        let addr = spawn_server(true).await;
+        let response = h2c_client
+            .get(format!("http://{addr}/?q=rust"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), Version::HTTP_2);
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        // HTTP/1.1 keeps working on the same listener
+        let response = reqwest::get(format!("http://{addr}/favicon.ico"))
+            .await
+            .unwrap();
+        assert_eq!(response.version(), Version::HTTP_11);
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let addr = spawn_server(false).await;
+        if let Ok(response) = h2c_client
+            .get(format!("http://{addr}/favicon.ico"))
+            .send()
+            .await
+        {
+            panic!("h2c must be refused unless enabled, got {response:?}");
+        }
+    }
+}
@@ -0,0 +1,394 @@
+//! The questions of `redirector init`, which writes a first configuration
+//! file for new users.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use anyhow::{Context as _, Result, bail};
+
+use crate::bang::Bang;
+use crate::cli::parse_listen;
+use crate::config::{
+    AppConfig, Config, check_url_template, parse_file_config, template_problem, url_problem,
+};
+use crate::suggest::SuggestionProvider;
+
+/// Search engines offered as `default_search`, the first one is the default.
+const SEARCH_PRESETS: [(&str, &str); 5] = [
+    ("Qwant", "https://www.qwant.com/?q={}"),
+    ("DuckDuckGo", "https://duckduckgo.com/?q={}"),
+    ("Brave Search", "https://search.brave.com/search?q={}"),
+    ("Startpage", "https://www.startpage.com/do/search?q={}"),
+    ("Google", "https://www.google.com/search?q={}"),
+];
+
+/// Suggestion providers offered, the first one is the default.
+const SUGGESTION_PRESETS: [(&str, SuggestionProvider); 4] = [
+    ("Brave", SuggestionProvider::Brave),
+    ("DuckDuckGo", SuggestionProvider::DuckDuckGo),
+    ("Google", SuggestionProvider::Google),
+    ("Qwant", SuggestionProvider::Qwant),
+];
+
+/// Bang offered as the example of a custom bang.
+const EXAMPLE_TRIGGER: &str = "crates";
+const EXAMPLE_URL_TEMPLATE: &str = "https://crates.io/search?q={{{s}}}";
+
+/// What the configuration written by `redirector init` is made of.
+#[derive(Debug, Clone)]
+pub struct SetupAnswers {
+    pub listen: SocketAddr,
+    pub default_search: String,
+    pub suggestion_provider: SuggestionProvider,
+    pub bangs_url: String,
+    pub example_bang: Option<Bang>,
+}
+
+impl Default for SetupAnswers {
+    /// The answer to every question is its default, as with `--defaults`.
+    fn default() -> Self {
+        let default = AppConfig::default();
+        Self {
+            listen: SocketAddr::new(default.ip, default.port),
+            default_search: SEARCH_PRESETS[0].1.to_string(),
+            suggestion_provider: SUGGESTION_PRESETS[0].1,
+            bangs_url: default.bangs_url,
+            example_bang: None,
+        }
+    }
+}
+
+impl SetupAnswers {
+    /// The configuration file with these answers.
+    ///
+    /// # Panics
+    /// If a suggestion provider isn't a TOML string, which it always is.
+    #[must_use]
+    pub fn to_toml(&self) -> String {
+        let value = |value: &str| toml::Value::from(value).to_string();
+        let mut contents = format!(
+            "# written by `redirector init`, see the README for all settings\nlisten = \
+             {}\ndefault_search = {} # {{}} gets replaced with the query\nsuggestion_provider = \
+             {}\nbangs_url = {}\n",
+            value(&self.listen.to_string()),
+            value(&self.default_search),
+            toml::Value::try_from(self.suggestion_provider)
+                .expect("Suggestion providers are strings"),
+            value(&self.bangs_url),
+        );
+        if let Some(bang) = &self.example_bang {
+            write!(
+                contents,
+                "\n[[bangs]]\ntrigger = {}\nurl_template = {} # {{{{{{s}}}}}} gets replaced with \
+                 the search term\n",
+                value(&bang.trigger),
+                value(&bang.url_template)
+            )
+            .expect("Failed to write to TOML string");
+        }
+        contents
+    }
+
+    /// The configuration the file written with these answers loads as.
+    ///
+    /// # Errors
+    /// If it doesn't load or values of it can't work, which the questions
+    /// should have prevented.
+    pub fn app_config(&self) -> Result<AppConfig> {
+        let app_config = parse_file_config(&self.to_toml())?.merge(Config::default());
+        if let Some(problem) = app_config.problems().first() {
+            bail!("Invalid configuration: {problem}");
+        }
+        Ok(app_config)
+    }
+}
+
+/// Write the configuration with `answers` to `path`, along with the
+/// directories leading to it. Returns the URL of the `OpenSearch` description
+/// to add redirector to the browser with.
+///
+/// # Errors
+/// If there already is a file at `path` and not `force`, or it can't be
+/// written.
+pub fn write_config(path: &Path, answers: &SetupAnswers, force: bool) -> Result<String> {
+    if path.exists() && !force {
+        bail!(
+            "There already is a configuration file at {}, pass --force to replace it",
+            path.display()
+        );
+    }
+    let app_config = answers.app_config()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, answers.to_toml())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(format!("{}/opensearch.xml", app_config.base_url()))
+}
+
+/// Ask the questions of `redirector init` on `output`, reading the answers
+/// line by line from `input`. An empty answer takes the default in brackets,
+/// invalid ones are asked again.
+///
+/// # Errors
+/// If `input` ends before all questions are answered, or either fails.
+pub fn ask<R, W>(input: &mut R, output: &mut W) -> io::Result<SetupAnswers>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut prompt = Prompt { input, output };
+    let default = SetupAnswers::default();
+
+    let listen = prompt.ask_valid(
+        "Address to listen on",
+        &default.listen.to_string(),
+        parse_listen,
+    )?;
+
+    let mut engines: Vec<&str> = SEARCH_PRESETS.iter().map(|(name, _)| *name).collect();
+    engines.push("Other");
+    let engine = prompt.choose("Search engine for queries without a bang", &engines)?;
+    let default_search = match SEARCH_PRESETS.get(engine) {
+        Some((_, template)) => (*template).to_string(),
+        None => {
+            prompt.ask_valid(
+                "Search URL, {} is replaced with the query",
+                "",
+                |template| {
+                    template_problem(template, false).map_or_else(
+                        || Ok(template.to_string()),
+                        |problem| Err(format!("'{template}' {problem}")),
+                    )
+                },
+            )?
+        },
+    };
+
+    let providers: Vec<&str> = SUGGESTION_PRESETS.iter().map(|(name, _)| *name).collect();
+    let provider = prompt.choose("Provider of search suggestions", &providers)?;
+    let suggestion_provider = SUGGESTION_PRESETS[provider].1;
+
+    let bangs_url = if prompt.confirm("Fetch the bang list of DuckDuckGo?", true)? {
+        default.bangs_url
+    } else {
+        prompt.ask_valid("URL of the bang list to fetch instead", "", |url| {
+            url_problem(url).map_or_else(
+                || Ok(url.to_string()),
+                |problem| Err(format!("'{url}' {problem}")),
+            )
+        })?
+    };
+
+    let example_bang = if prompt.confirm("Add an example custom bang?", false)? {
+        let trigger =
+            prompt.ask_valid("Trigger, typed as !trigger", EXAMPLE_TRIGGER, |trigger| {
+                let trigger = trigger.trim_start_matches('!');
+                if trigger.is_empty() || trigger.contains(char::is_whitespace) {
+                    Err("The trigger must be a single word".to_string())
+                } else {
+                    Ok(trigger.to_string())
+                }
+            })?;
+        let options = AppConfig::default();
+        let url_template = prompt.ask_valid(
+            "URL template, {{{s}}} is replaced with the search term",
+            EXAMPLE_URL_TEMPLATE,
+            |template| {
+                let check = check_url_template(&options.resolve_options(), template);
+                match check.error {
+                    Some(problem) => Err(format!("'{}' {problem}", check.url)),
+                    None if !check.placeholder => {
+                        Err(format!(
+                            "'{template}' has no {{{{{{s}}}}}} placeholder for the search term"
+                        ))
+                    },
+                    None => Ok(template.to_string()),
+                }
+            },
+        )?;
+        Some(Bang {
+            trigger,
+            url_template,
+            ..Bang::default()
+        })
+    } else {
+        None
+    };
+
+    Ok(SetupAnswers {
+        listen,
+        default_search,
+        suggestion_provider,
+        bangs_url,
+        example_bang,
+    })
+}
+
+struct Prompt<'a, R, W> {
+    input: &'a mut R,
+    output: &'a mut W,
+}
+
+impl<R: BufRead, W: Write> Prompt<'_, R, W> {
+    /// Ask `question`, an empty answer is `default`.
+    fn ask(&mut self, question: &str, default: &str) -> io::Result<String> {
+        if default.is_empty() {
+            write!(self.output, "{question}: ")?;
+        } else {
+            write!(self.output, "{question} [{default}]: ")?;
+        }
+        self.output.flush()?;
+        let mut answer = String::new();
+        if self.input.read_line(&mut answer)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The input ended before all questions were answered",
+            ));
+        }
+        let answer = answer.trim();
+        Ok(if answer.is_empty() { default } else { answer }.to_string())
+    }
+
+    /// Ask `question` until `parse` accepts the answer.
+    fn ask_valid<T>(
+        &mut self,
+        question: &str,
+        default: &str,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> io::Result<T> {
+        loop {
+            let answer = self.ask(question, default)?;
+            match parse(&answer) {
+                Ok(value) => return Ok(value),
+                Err(problem) => writeln!(self.output, "{problem}")?,
+            }
+        }
+    }
+
+    /// Ask a yes or no `question`.
+    fn confirm(&mut self, question: &str, default: bool) -> io::Result<bool> {
+        let default = if default { "yes" } else { "no" };
+        self.ask_valid(question, default, |answer| {
+            match answer.to_ascii_lowercase().as_str() {
+                "y" | "yes" => Ok(true),
+                "n" | "no" => Ok(false),
+                _ => Err("Answer yes or no".to_string()),
+            }
+        })
+    }
+
+    /// Let the user pick one of `options` by its number, the first one is
+    /// the default. Returns its index.
+    fn choose(&mut self, question: &str, options: &[&str]) -> io::Result<usize> {
+        writeln!(self.output, "{question}:")?;
+        for (number, option) in (1..).zip(options) {
+            writeln!(self.output, "  {number}) {option}")?;
+        }
+        self.ask_valid("Number", "1", |answer| {
+            answer
+                .parse::<usize>()
+                .ok()
+                .filter(|number| (1..=options.len()).contains(number))
+                .map(|number| number - 1)
+                .ok_or_else(|| format!("Pick a number from 1 to {}", options.len()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn ask_scripted(input: &str) -> (io::Result<SetupAnswers>, String) {
+        let mut output = Vec::new();
+        let answers = ask(&mut Cursor::new(input), &mut output);
+        (answers, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_defaults() {
+        let (answers, output) = ask_scripted("\n\n\n\n\n");
+        assert_eq!(
+            answers.unwrap().to_toml(),
+            SetupAnswers::default().to_toml(),
+            "taking every default is the same as --defaults"
+        );
+        assert!(output.starts_with("Address to listen on [127.0.0.1:3000]: "));
+        assert!(output.contains("  6) Other\n"), "{output}");
+
+        let app_config = SetupAnswers::default().app_config().unwrap();
+        assert_eq!(app_config.default_search, "https://www.qwant.com/?q={}");
+        assert_eq!(app_config.bangs_url, "https://duckduckgo.com/bang.js");
+    }
+
+    #[test]
+    fn test_scripted_answers() {
+        let (answers, output) = ask_scripted(
+            "unix:/run/redirector.sock\n[::1]:8080\n9\n6\nhttps://search.example.com/?q=\n\
+             https://search.example.com/?q={}\n2\nmaybe\nno\nduckduckgo.com/bang.js\n\
+             https://bangs.example.com/bang.js\nyes\n!rs docs\n!rs\nhttps://docs.rs/{}\n\
+             https://docs.rs/releases/search?query={{{s}}}\n",
+        );
+        let answers = answers.unwrap();
+        for problem in [
+            "Can't listen on 'unix:/run/redirector.sock'",
+            "Pick a number from 1 to 6",
+            "'https://search.example.com/?q=' has no {} placeholder for the query",
+            "Answer yes or no",
+            "'duckduckgo.com/bang.js' needs an http:// or https:// scheme",
+            "The trigger must be a single word",
+            "'https://docs.rs/{}' has no {{{s}}} placeholder for the search term",
+        ] {
+            assert!(
+                output.contains(problem),
+                "{problem} must be shown: {output}"
+            );
+        }
+        assert_eq!(
+            answers.to_toml(),
+            "# written by `redirector init`, see the README for all settings\nlisten = \
+             \"[::1]:8080\"\ndefault_search = \"https://search.example.com/?q={}\" # {} gets \
+             replaced with the query\nsuggestion_provider = \"duckduckgo\"\nbangs_url = \
+             \"https://bangs.example.com/bang.js\"\n\n[[bangs]]\ntrigger = \"rs\"\nurl_template \
+             = \"https://docs.rs/releases/search?query={{{s}}}\" # {{{s}}} gets replaced with \
+             the search term\n"
+        );
+        assert_eq!(
+            answers.app_config().unwrap().bangs.unwrap()[0].trigger,
+            "rs"
+        );
+
+        let (answers, _) = ask_scripted("\n\n\n\nyes\nrs\nexample.com/{{{s}}}\n");
+        assert_eq!(
+            answers.unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof,
+            "invalid templates are asked for again"
+        );
+    }
+
+    #[test]
+    fn test_write_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("redirector").join("config.toml");
+        let answers = SetupAnswers::default();
+
+        assert_eq!(
+            write_config(&path, &answers, false).unwrap(),
+            "http://127.0.0.1:3000/opensearch.xml"
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), answers.to_toml());
+
+        fs::write(&path, "port = 4000\n").unwrap();
+        let e = write_config(&path, &answers, false).unwrap_err();
+        assert!(e.to_string().contains("--force"), "{e}");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "port = 4000\n");
+        write_config(&path, &answers, true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), answers.to_toml());
+    }
+}
@@ -0,0 +1,974 @@
+//! Where the bang list comes from.
+//!
+//! The list is downloaded from `bangs_url` and kept on disk for a day, or
+//! read from a pinned snapshot instead. Downloads are bounded by
+//! `[bang_limits]`, and lists at https URLs are never redirected to plain http.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fmt, fs};
+
+use anyhow::{Context as _, anyhow, bail};
+use bincode::{Decode, Encode};
+use parking_lot::Mutex;
+use reqwest::{Url, header};
+use serde::de::{self, DeserializeSeed, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::bang::Bang;
+use crate::config::{AppConfig, BangLimitsConfig, default_config_path};
+use crate::gzip::{MAX_DECOMPRESSED_SIZE, maybe_gunzip};
+use crate::outbound::{IpFamily, TlsVersion, outbound_builder};
+
+/// Where and when the bang list was loaded from.
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct SourceMetadata {
+    /// URL or path the bangs were loaded from.
+    pub source: String,
+    /// URL the list was downloaded from in the end, after redirects of
+    /// `source`. Not set for lists read from a file.
+    #[serde(default)]
+    pub final_url: Option<String>,
+    /// Unix timestamp of when the list was fetched.
+    pub fetched_at: u64,
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+    pub content_length: Option<u64>,
+    pub entries: usize,
+    /// Hash of the raw bang list, it only changes when the list does.
+    pub content_hash: String,
+}
+
+impl SourceMetadata {
+    /// Metadata of a bang list that was read from a file.
+    pub(crate) fn from_file(path: &Path, contents: &str, entries: usize) -> Self {
+        let fetched_at = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or_else(|_| SystemTime::now());
+        Self {
+            source: path.display().to_string(),
+            final_url: None,
+            fetched_at: unix_seconds(fetched_at),
+            last_modified: None,
+            etag: None,
+            content_length: None,
+            entries,
+            content_hash: content_hash(contents.as_bytes()),
+        }
+    }
+
+    /// When the list was fetched, as an HTTP date.
+    #[must_use]
+    pub fn fetched_at_date(&self) -> String {
+        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(self.fetched_at))
+    }
+}
+
+pub(crate) fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// 64 bit FNV-1a hash, which unlike `DefaultHasher` is stable across builds.
+pub(crate) fn content_hash(content: &[u8]) -> String {
+    let hash = content
+        .iter()
+        .fold(0xCBF2_9CE4_8422_2325_u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3)
+        });
+    format!("{hash:016x}")
+}
+
+/// Where the bang list of the instance with `app_config` is cached, with
+/// `extension`.
+///
+/// The files are called `bang-cache` in the temporary directory, followed by
+/// a hash of the configuration path unless it is the default one, so
+/// instances with their own configuration don't share a cache.
+pub(crate) fn cache_file_path(app_config: &AppConfig, extension: &str) -> PathBuf {
+    let name = if app_config.config_path == default_config_path() {
+        format!("bang-cache.{extension}")
+    } else {
+        let config_path = app_config.config_path.as_os_str().as_encoded_bytes();
+        format!("bang-cache-{}.{extension}", content_hash(config_path))
+    };
+    std::env::temp_dir().join(name)
+}
+
+/// Where the downloaded bang list is cached.
+pub(crate) fn disk_cache_path(app_config: &AppConfig) -> PathBuf {
+    cache_file_path(app_config, "json")
+}
+
+/// Where the metadata of the bang list cached at `cache_path` is kept.
+fn disk_cache_metadata_path(cache_path: &Path) -> PathBuf {
+    cache_path.with_extension("meta.json")
+}
+
+pub(crate) fn load_disk_cache(
+    cache_path: &Path,
+    contents: &str,
+) -> anyhow::Result<(Vec<Bang>, SourceMetadata)> {
+    let bang_entries: Vec<Bang> = serde_json::from_str(contents)?;
+    let content_hash = content_hash(contents.as_bytes());
+    // only trust the metadata if it belongs to this very list
+    let metadata = fs::read_to_string(disk_cache_metadata_path(cache_path))
+        .ok()
+        .and_then(|metadata| serde_json::from_str::<SourceMetadata>(&metadata).ok())
+        .filter(|metadata| metadata.content_hash == content_hash)
+        .unwrap_or_else(|| SourceMetadata::from_file(cache_path, contents, bang_entries.len()));
+    Ok((bang_entries, metadata))
+}
+
+/// Load a pinned bang list, which may be gzipped.
+pub(crate) fn load_pinned_bangs(pinned_path: &Path) -> anyhow::Result<(Vec<Bang>, SourceMetadata)> {
+    let data = fs::read(pinned_path).with_context(|| {
+        format!(
+            "Failed to read pinned bangs at {}, pin them with --pin-bangs",
+            pinned_path.display()
+        )
+    })?;
+    debug!("Loading pinned bangs from {}", pinned_path.display());
+    let data = maybe_gunzip(&data, MAX_DECOMPRESSED_SIZE)
+        .with_context(|| format!("Failed to read pinned bangs at {}", pinned_path.display()))?;
+    let contents = std::str::from_utf8(&data)?;
+    let bang_entries: Vec<Bang> = serde_json::from_str(contents)?;
+    let metadata = SourceMetadata::from_file(pinned_path, contents, bang_entries.len());
+    Ok((bang_entries, metadata))
+}
+
+/// Load the bang list, from the pinned snapshot if there is one and from
+/// `bangs_url` otherwise. With `allow_disk_cache` a download from less than a
+/// day ago is reused.
+pub(crate) async fn load_bangs(
+    app_config: &AppConfig,
+    allow_disk_cache: bool,
+) -> anyhow::Result<(Vec<Bang>, SourceMetadata)> {
+    if let Some(pinned_path) = &app_config.pinned_bangs_path {
+        return load_pinned_bangs(pinned_path);
+    }
+
+    let cache_path = disk_cache_path(app_config);
+    #[expect(
+        clippy::duration_suboptimal_units,
+        reason = "unchanged from before the lint"
+    )]
+    let cache_age_limit = Duration::from_secs(24 * 60 * 60);
+
+    if allow_disk_cache
+        && let Ok(metadata) = fs::metadata(&cache_path)
+        && let Ok(modified) = metadata.modified()
+        && modified.elapsed()? < cache_age_limit
+        && let Ok(contents) = fs::read_to_string(&cache_path)
+    {
+        debug!("Bang cache is up to date.");
+        return load_disk_cache(&cache_path, &contents);
+    }
+
+    let fetched = fetch_bangs(
+        &bangs_client(app_config),
+        &app_config.bangs_url,
+        &app_config.bang_limits,
+    )
+    .await?;
+    if !app_config.persist {
+        return Ok((fetched.bangs, fetched.metadata));
+    }
+    fs::write(&cache_path, &fetched.body)?;
+    if let Err(e) = fs::write(
+        disk_cache_metadata_path(&cache_path),
+        serde_json::to_string(&fetched.metadata)?,
+    ) {
+        warn!("Failed to cache the bang list metadata: {e}");
+    }
+    Ok((fetched.bangs, fetched.metadata))
+}
+
+/// Whether the content type is one bang lists are commonly served with.
+fn is_json_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    matches!(
+        mime.as_str(),
+        "application/json" | "text/json" | "application/javascript" | "text/javascript"
+    ) || mime.ends_with("+json")
+}
+
+#[derive(Debug)]
+struct FetchedBangs {
+    /// The raw response.
+    body: String,
+    bangs: Vec<Bang>,
+    metadata: SourceMetadata,
+}
+
+/// Fetch the bang list at `url`.
+///
+/// Redirects are followed up to [`MAX_BANG_REDIRECTS`] times, except the
+/// ones [`insecure_redirect`] refuses, and compressed responses are
+/// decompressed transparently. Errors include the final URL and status, as
+/// that is usually where a misconfiguration shows. Lists over the hard
+/// `limits` are refused, bangs over the length limits skipped.
+async fn fetch_bangs(
+    client: &reqwest::Client,
+    url: &str,
+    limits: &BangLimitsConfig,
+) -> anyhow::Result<FetchedBangs> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch bangs from {url}"))?;
+    let final_url = response.url().clone();
+    let status = response.status();
+    if !status.is_success() {
+        bail!("Failed to fetch bangs from {url}: {final_url} responded with status {status}");
+    }
+
+    let headers = response.headers();
+    let header_value = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let content_type = header_value(header::CONTENT_TYPE).unwrap_or_default();
+    let last_modified = header_value(header::LAST_MODIFIED);
+    let etag = header_value(header::ETAG);
+    let content_length =
+        header_value(header::CONTENT_LENGTH).and_then(|length| length.parse().ok());
+    if !is_json_content_type(&content_type) {
+        warn!(
+            "Bangs at {final_url} are served as '{content_type}' instead of JSON, trying to parse \
+             them anyway"
+        );
+    }
+
+    let body = read_limited(response, limits.max_response_bytes)
+        .await
+        .with_context(|| format!("Failed to read bangs from {final_url} (status {status})"))?;
+    let body = String::from_utf8(body).with_context(|| {
+        format!("Invalid bang list at {final_url} (status {status}, content type '{content_type}')")
+    })?;
+    let (bangs, skipped) = parse_limited(&body, limits).with_context(|| {
+        format!("Invalid bang list at {final_url} (status {status}, content type '{content_type}')")
+    })?;
+    if skipped > 0 {
+        warn!(
+            "Skipped {skipped} bangs of {final_url} with a trigger longer than {} or a URL \
+             template longer than {} bytes, see [bang_limits]",
+            limits.max_trigger_length, limits.max_template_length
+        );
+    }
+    let metadata = SourceMetadata {
+        source: url.to_string(),
+        final_url: Some(final_url.to_string()),
+        fetched_at: unix_seconds(SystemTime::now()),
+        last_modified,
+        etag,
+        content_length,
+        entries: bangs.len(),
+        content_hash: content_hash(body.as_bytes()),
+    };
+    Ok(FetchedBangs {
+        body,
+        bangs,
+        metadata,
+    })
+}
+
+/// Download the file at `url` like `bangs_url`, within the size limit of
+/// `bang_limits`, without parsing it.
+///
+/// # Errors
+/// In `offline` mode, or if the file can't be downloaded or is too large.
+pub async fn fetch_bang_file(app_config: &AppConfig, url: &str) -> anyhow::Result<Vec<u8>> {
+    if app_config.offline {
+        bail!("Refusing to download {url} in offline mode");
+    }
+    let response = bangs_client(app_config)
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch {url}"))?;
+    let status = response.status();
+    if !status.is_success() {
+        bail!(
+            "Failed to fetch {url}: {} responded with status {status}",
+            response.url()
+        );
+    }
+    read_limited(response, app_config.bang_limits.max_response_bytes)
+        .await
+        .with_context(|| format!("Failed to read {url}"))
+}
+
+/// The body of `response`, read up to `limit` bytes after decompression.
+async fn read_limited(mut response: reqwest::Response, limit: usize) -> anyhow::Result<Vec<u8>> {
+    let too_large = || {
+        anyhow!(
+            "the list is larger than {limit} bytes, the limit of bang_limits.max_response_bytes"
+        )
+    };
+    // only known up front for uncompressed responses
+    if response
+        .content_length()
+        .is_some_and(|length| length > limit as u64)
+    {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Parse the bang list `body` within `limits`, along with the number of
+/// bangs skipped for their length.
+fn parse_limited(body: &str, limits: &BangLimitsConfig) -> anyhow::Result<(Vec<Bang>, usize)> {
+    let mut skipped = 0;
+    let mut deserializer = serde_json::Deserializer::from_str(body);
+    let bangs = LimitedBangs {
+        limits,
+        skipped: &mut skipped,
+    }
+    .deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok((bangs, skipped))
+}
+
+/// Deserializes a bang list, giving up once it has more than
+/// `max_entries`, so an entry bomb is never held in memory as a whole.
+struct LimitedBangs<'a> {
+    limits: &'a BangLimitsConfig,
+    skipped: &'a mut usize,
+}
+
+impl<'de> DeserializeSeed<'de> for LimitedBangs<'_> {
+    type Value = Vec<Bang>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Vec<Bang>, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for LimitedBangs<'_> {
+    type Value = Vec<Bang>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a list of bangs")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<Bang>, A::Error> {
+        let limits = self.limits;
+        let mut bangs = Vec::new();
+        let mut entries = 0;
+        while let Some(bang) = seq.next_element::<Bang>()? {
+            entries += 1;
+            if entries > limits.max_entries {
+                return Err(de::Error::custom(format!(
+                    "the list has more than {} bangs, the limit of bang_limits.max_entries",
+                    limits.max_entries
+                )));
+            }
+            if bang.trigger.len() > limits.max_trigger_length
+                || bang.url_template.len() > limits.max_template_length
+            {
+                *self.skipped += 1;
+                continue;
+            }
+            bangs.push(bang);
+        }
+        Ok(bangs)
+    }
+}
+
+/// Fetch `bangs_url` and save it as the snapshot at `pinned_bangs_path`,
+/// which is used from then on regardless of changes to the remote list.
+/// Returns the number of pinned bangs.
+///
+/// # Errors
+/// If no `pinned_bangs_path` is configured, in `offline` mode or if the bangs
+/// can't be fetched or written.
+pub async fn pin_bangs(app_config: &AppConfig) -> anyhow::Result<usize> {
+    let Some(pinned_path) = &app_config.pinned_bangs_path else {
+        bail!("Set pinned_bangs_path in the configuration file to pin the bangs");
+    };
+    if app_config.offline {
+        bail!("Refusing to download the bang list in offline mode");
+    }
+    let fetched = fetch_bangs(
+        &bangs_client(app_config),
+        &app_config.bangs_url,
+        &app_config.bang_limits,
+    )
+    .await?;
+
+    let tmp_path = pinned_path.with_extension("json.tmp");
+    fs::write(&tmp_path, &fetched.body)
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, pinned_path)
+        .with_context(|| format!("Failed to write pinned bangs to {}", pinned_path.display()))?;
+    info!(
+        "Pinned {} bangs from {} to {}",
+        fetched.bangs.len(),
+        app_config.bangs_url,
+        pinned_path.display()
+    );
+    Ok(fetched.bangs.len())
+}
+
+/// Redirects followed when fetching `bangs_url`, e.g. for GitHub raw URLs.
+const MAX_BANG_REDIRECTS: usize = 5;
+
+/// Why the redirect from the end of `chain` to `next` must not be followed:
+/// a list at an https URL is only fetched over https, so a captive portal or
+/// anyone else on the way can't swap it for a page of their own by
+/// redirecting to plain http. With `allow_insecure` any redirect is followed.
+fn insecure_redirect(chain: &[Url], next: &Url, allow_insecure: bool) -> Option<String> {
+    let first = chain.first()?;
+    if allow_insecure || first.scheme() != "https" || next.scheme() == "https" {
+        return None;
+    }
+    let chain = chain
+        .iter()
+        .chain([next])
+        .map(Url::as_str)
+        .collect::<Vec<_>>()
+        .join(" -> ");
+    Some(format!(
+        "refusing to leave https, set allow_insecure_bang_fetch to allow it: {chain}"
+    ))
+}
+
+/// Clients fetching `bangs_url`, one per [`IpFamily`], value of
+/// `allow_insecure_bang_fetch` and `min_tls_version`, built on first use.
+static BANGS_CLIENTS: LazyLock<Mutex<HashMap<BangsClientKey, reqwest::Client>>> =
+    LazyLock::new(Mutex::default);
+
+type BangsClientKey = (IpFamily, bool, Option<TlsVersion>);
+
+fn bangs_client(app_config: &AppConfig) -> reqwest::Client {
+    bangs_client_with(
+        app_config.outbound_ip_family,
+        app_config.allow_insecure_bang_fetch,
+        app_config.min_tls_version,
+    )
+}
+
+fn bangs_client_with(
+    family: IpFamily,
+    allow_insecure: bool,
+    min_tls_version: Option<TlsVersion>,
+) -> reqwest::Client {
+    BANGS_CLIENTS
+        .lock()
+        .entry((family, allow_insecure, min_tls_version))
+        .or_insert_with(|| {
+            outbound_builder(family, min_tls_version)
+                .redirect(bangs_redirect_policy(allow_insecure))
+                .build()
+                .expect("Failed to build HTTP client")
+        })
+        .clone()
+}
+
+/// Follow up to [`MAX_BANG_REDIRECTS`] redirects, except the ones
+/// [`insecure_redirect`] refuses.
+fn bangs_redirect_policy(allow_insecure: bool) -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() > MAX_BANG_REDIRECTS {
+            return attempt.error(format!("gave up after {MAX_BANG_REDIRECTS} redirects"));
+        }
+        match insecure_redirect(attempt.previous(), attempt.url(), allow_insecure) {
+            Some(problem) => attempt.error(problem),
+            None => attempt.follow(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use axum::Router;
+    use axum::extract::State;
+    use axum::response::Redirect;
+    use axum::routing::get;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto;
+    use hyper_util::service::TowerToHyperService;
+    use parking_lot::RwLock;
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::rustls::pki_types::pem::PemObject as _;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    use super::*;
+    use crate::cache::{BANG_CACHE, refresh_bangs};
+
+    const BANG_LIST: &str = r#"[{"t":"g","u":"https://www.google.com/search?q={{{s}}}"}]"#;
+
+    #[tokio::test]
+    async fn test_fetch_bangs() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(BANG_LIST.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let app = Router::new()
+            .route("/hop1", get(|| async { Redirect::to("/hop2") }))
+            .route("/hop2", get(|| async { Redirect::temporary("/bang.js") }))
+            .route(
+                "/bang.js",
+                get(|| async { ([(header::CONTENT_TYPE, "application/json")], BANG_LIST) }),
+            )
+            .route(
+                "/gzip-only",
+                get(|| {
+                    async move {
+                        (
+                            [
+                                (header::CONTENT_TYPE, "application/json"),
+                                (header::CONTENT_ENCODING, "gzip"),
+                            ],
+                            gzipped,
+                        )
+                    }
+                }),
+            )
+            .route(
+                "/error-page",
+                get(|| async { axum::response::Html("<html>Rate limited</html>") }),
+            )
+            .route("/loop", get(|| async { Redirect::to("/loop") }));
+        let addr = spawn_remote(app).await;
+
+        for path in ["hop1", "gzip-only"] {
+            match fetch_bangs(
+                &bangs_client_with(IpFamily::Auto, false, None),
+                &format!("http://{addr}/{path}"),
+                &BangLimitsConfig::default(),
+            )
+            .await
+            {
+                Ok(fetched) => {
+                    assert_eq!(fetched.bangs[0].trigger, "g", "{path}");
+                    assert_eq!(
+                        fetched.metadata.final_url,
+                        Some(format!(
+                            "http://{addr}/{}",
+                            if path == "hop1" { "bang.js" } else { path }
+                        )),
+                        "the end of the redirect chain must be recorded"
+                    );
+                },
+                Err(e) => panic!("{path} must be fetched: {e:#}"),
+            }
+        }
+
+        let error = fetch_bangs(
+            &bangs_client_with(IpFamily::Auto, false, None),
+            &format!("http://{addr}/error-page"),
+            &BangLimitsConfig::default(),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(
+            error.contains(&format!("http://{addr}/error-page")) && error.contains("200 OK"),
+            "final URL and status must be reported: {error}"
+        );
+
+        if let Ok(fetched) = fetch_bangs(
+            &bangs_client_with(IpFamily::Auto, false, None),
+            &format!("http://{addr}/loop"),
+            &BangLimitsConfig::default(),
+        )
+        .await
+        {
+            panic!("redirect loops must be given up on, got {}", fetched.body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bang_limits() {
+        let entries = |count: usize| {
+            let bangs: Vec<String> = (0..count)
+                .map(|i| {
+                    format!(r#"{{"t":"bomb{i}","u":"https://bomb.example.com/?q={{{{{{s}}}}}}"}}"#)
+                })
+                .collect();
+            format!("[{}]", bangs.join(","))
+        };
+        let long_trigger = format!(
+            r#"[{{"t":"{}","u":"https://long.example.com/"}},{BANG}]"#,
+            "x".repeat(101),
+            BANG = &BANG_LIST[1..BANG_LIST.len() - 1]
+        );
+        let app = Router::new()
+            .route("/huge", get(|| async { vec![b' '; 2 * 1024 * 1024] }))
+            .route(
+                "/huge-gzip",
+                get(|| {
+                    async {
+                        let mut encoder = flate2::write::GzEncoder::new(
+                            Vec::new(),
+                            flate2::Compression::default(),
+                        );
+                        encoder.write_all(&vec![b' '; 2 * 1024 * 1024]).unwrap();
+                        (
+                            [(header::CONTENT_ENCODING, "gzip")],
+                            encoder.finish().unwrap(),
+                        )
+                    }
+                }),
+            )
+            .route("/bomb", get(move || async move { entries(2000) }))
+            .route("/long", get(move || async move { long_trigger }));
+        let addr = spawn_remote(app).await;
+        let limits = BangLimitsConfig {
+            max_response_bytes: 1024 * 1024,
+            max_entries: 1000,
+            ..BangLimitsConfig::default()
+        };
+        let client = bangs_client_with(IpFamily::Auto, false, None);
+        let fetch = |path: &str| {
+            let url = format!("http://{addr}/{path}");
+            let client = &client;
+            async move { fetch_bangs(client, &url, &limits).await }
+        };
+
+        for path in ["huge", "huge-gzip"] {
+            let error = format!("{:#}", fetch(path).await.unwrap_err());
+            assert!(
+                error.contains("larger than 1048576 bytes") && error.contains("max_response_bytes"),
+                "{path}: {error}"
+            );
+        }
+        let error = format!("{:#}", fetch("bomb").await.unwrap_err());
+        assert!(
+            error.contains("more than 1000 bangs") && error.contains("max_entries"),
+            "{error}"
+        );
+
+        let fetched = fetch("long").await.unwrap();
+        let triggers: Vec<&str> = fetched
+            .bangs
+            .iter()
+            .map(|bang| bang.trigger.as_str())
+            .collect();
+        assert_eq!(triggers, ["g"], "bangs over the length limits are skipped");
+        assert_eq!(fetched.metadata.entries, 1);
+
+        // a refused list leaves the cache alone
+        let config = AppConfig {
+            bangs_url: format!("http://{addr}/bomb"),
+            bang_limits: limits,
+            persist: false,
+            ..AppConfig::default()
+        };
+        refresh_bangs(&config).await.unwrap_err();
+        assert!(
+            !BANG_CACHE.read().contains_key("bomb0"),
+            "the cache must be kept"
+        );
+    }
+
+    #[test]
+    fn test_insecure_redirect() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        let https_chain = [
+            url("https://duckduckgo.com/bang.js"),
+            url("https://cdn.example.com/bang.js"),
+        ];
+
+        assert_eq!(
+            insecure_redirect(
+                &https_chain,
+                &url("https://mirror.example.com/bang.js"),
+                false
+            ),
+            None,
+            "https to https is followed"
+        );
+        assert_eq!(
+            insecure_redirect(&https_chain, &url("http://portal.example.com/login"), false)
+                .as_deref(),
+            Some(
+                "refusing to leave https, set allow_insecure_bang_fetch to allow it: \
+                 https://duckduckgo.com/bang.js -> https://cdn.example.com/bang.js -> \
+                 http://portal.example.com/login"
+            ),
+            "a downgrade is refused with the whole chain"
+        );
+        assert_eq!(
+            insecure_redirect(&https_chain, &url("http://portal.example.com/login"), true),
+            None,
+            "allow_insecure_bang_fetch follows downgrades"
+        );
+        assert_eq!(
+            insecure_redirect(
+                &[url("http://example.com/bang.js")],
+                &url("http://example.com/bangs.json"),
+                false
+            ),
+            None,
+            "lists at http URLs may stay on http"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_source_metadata() {
+        let app = Router::new().route(
+            "/bang.js",
+            get(|| {
+                async {
+                    (
+                        [
+                            (header::CONTENT_TYPE, "application/json"),
+                            (header::ETAG, "\"v42\""),
+                            (header::LAST_MODIFIED, "Tue, 13 Oct 2026 08:00:00 GMT"),
+                        ],
+                        BANG_LIST,
+                    )
+                }
+            }),
+        );
+        let addr = spawn_remote(app).await;
+        let url = format!("http://{addr}/bang.js");
+
+        let metadata = fetch_bangs(
+            &bangs_client_with(IpFamily::Auto, false, None),
+            &url,
+            &BangLimitsConfig::default(),
+        )
+        .await
+        .unwrap()
+        .metadata;
+        assert_eq!(metadata.source, url);
+        assert_eq!(metadata.etag.as_deref(), Some("\"v42\""));
+        assert_eq!(
+            metadata.last_modified.as_deref(),
+            Some("Tue, 13 Oct 2026 08:00:00 GMT")
+        );
+        assert_eq!(metadata.content_length, Some(BANG_LIST.len() as u64));
+        assert_eq!(metadata.entries, 1);
+        assert_eq!(metadata.content_hash, content_hash(BANG_LIST.as_bytes()));
+
+        let json = serde_json::to_value(&metadata).unwrap();
+        for field in [
+            "source",
+            "fetched_at",
+            "last_modified",
+            "etag",
+            "content_length",
+            "entries",
+            "content_hash",
+        ] {
+            assert!(json.get(field).is_some(), "{field} must be exposed");
+        }
+
+        // the same list hashes the same, a changed one doesn't
+        assert_eq!(
+            fetch_bangs(
+                &bangs_client_with(IpFamily::Auto, false, None),
+                &url,
+                &BangLimitsConfig::default(),
+            )
+            .await
+            .unwrap()
+            .metadata
+            .content_hash,
+            metadata.content_hash
+        );
+        assert_ne!(content_hash(b"[]"), metadata.content_hash);
+    }
+
+    #[tokio::test]
+    async fn test_https_redirect_chain() {
+        let plain_requested = Arc::new(AtomicBool::new(false));
+        let plain = spawn_remote(Router::new().route(
+            "/bang.js",
+            get({
+                let plain_requested = Arc::clone(&plain_requested);
+                || {
+                    async move {
+                        plain_requested.store(true, Ordering::SeqCst);
+                        "<html>Log in to the Wi-Fi</html>"
+                    }
+                }
+            }),
+        ))
+        .await;
+        let tls = spawn_tls_remote(
+            Router::new()
+                .route(
+                    "/bang.js",
+                    get(|| async { ([(header::CONTENT_TYPE, "application/json")], BANG_LIST) }),
+                )
+                .route("/moved", get(|| async { Redirect::to("/mirror") }))
+                .route("/mirror", get(|| async { Redirect::to("/bang.js") }))
+                .route(
+                    "/portal",
+                    get(move || async move { Redirect::to(&format!("http://{plain}/bang.js")) }),
+                ),
+        )
+        .await;
+        let client = reqwest::Client::builder()
+            .tls_certs_only([reqwest::Certificate::from_pem(TEST_CA).unwrap()])
+            .redirect(bangs_redirect_policy(false))
+            .build()
+            .unwrap();
+        let limits = BangLimitsConfig::default();
+
+        // https to https, the list is attributed to where it was found
+        let fetched = fetch_bangs(&client, &format!("https://{tls}/moved"), &limits)
+            .await
+            .unwrap();
+        assert_eq!(fetched.bangs.len(), 1);
+        assert_eq!(
+            fetched.metadata.final_url.as_deref(),
+            Some(format!("https://{tls}/bang.js").as_str())
+        );
+
+        // a downgrade to http is refused before anything is requested there
+        let e = fetch_bangs(&client, &format!("https://{tls}/portal"), &limits)
+            .await
+            .unwrap_err();
+        let chain = format!("https://{tls}/portal -> http://{plain}/bang.js");
+        assert!(
+            format!("{e:?}").contains(&format!(
+                "refusing to leave https, set allow_insecure_bang_fetch to allow it: {chain}"
+            )),
+            "{e:?}"
+        );
+        assert!(!plain_requested.load(Ordering::SeqCst));
+    }
+
+    /// The CA of the certificate [`spawn_tls_remote`] serves.
+    const TEST_CA: &[u8] = include_bytes!("../res/test-tls/ca.pem");
+
+    /// Serve `app` over TLS, with a certificate for 127.0.0.1 issued by
+    /// [`TEST_CA`].
+    async fn spawn_tls_remote(app: Router) -> std::net::SocketAddr {
+        let cert =
+            CertificateDer::from_pem_slice(include_bytes!("../res/test-tls/cert.pem")).unwrap();
+        let key = PrivateKeyDer::from_pem_slice(include_bytes!("../res/test-tls/key.pem")).unwrap();
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert], key)
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (acceptor, app) = (acceptor.clone(), app.clone());
+                tokio::spawn(async move {
+                    let Ok(stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let _ = auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), TowerToHyperService::new(app))
+                        .await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// Serve `app` as the upstream of the bang list.
+    async fn spawn_remote(app: Router) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_pinned_bangs_ignore_remote() {
+        let remote = std::sync::Arc::new(RwLock::new(
+            r#"[{"t":"p","u":"https://before.example.com/?q={{{s}}}"}]"#.to_string(),
+        ));
+        let app = Router::new()
+            .route(
+                "/bang.js",
+                get(|State(remote): State<std::sync::Arc<RwLock<String>>>| {
+                    async move { remote.read().clone() }
+                }),
+            )
+            .with_state(std::sync::Arc::clone(&remote));
+        let addr = spawn_remote(app).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = AppConfig {
+            bangs_url: format!("http://{addr}/bang.js"),
+            pinned_bangs_path: Some(dir.path().join("bangs.json")),
+            ..AppConfig::default()
+        };
+        if let Ok((bangs, _)) = load_bangs(&config, true).await {
+            panic!("nothing is pinned yet, got {bangs:?}");
+        }
+        assert_eq!(pin_bangs(&config).await.unwrap(), 1);
+
+        *remote.write() = r#"[{"t":"p","u":"https://after.example.com/?q={{{s}}}"}]"#.to_string();
+        let (bangs, metadata) = load_bangs(&config, true).await.unwrap();
+        assert_eq!(
+            metadata.source,
+            dir.path().join("bangs.json").display().to_string()
+        );
+        assert_eq!(
+            bangs[0].url_template, "https://before.example.com/?q={{{s}}}",
+            "the pinned snapshot must be used"
+        );
+
+        // refreshing the pin picks up the change
+        pin_bangs(&config).await.unwrap();
+        let (bangs, _) = load_bangs(&config, true).await.unwrap();
+        assert_eq!(
+            bangs[0].url_template,
+            "https://after.example.com/?q={{{s}}}"
+        );
+    }
+
+    #[test]
+    fn test_gzipped_pinned_bangs() {
+        let dir = tempfile::tempdir().unwrap();
+        let gzipped_path = dir.path().join("bangs.json.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(BANG_LIST.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        fs::write(&gzipped_path, &gzipped).unwrap();
+        let (bangs, metadata) = load_pinned_bangs(&gzipped_path).unwrap();
+        assert_eq!(bangs[0].trigger, "g");
+        assert_eq!(
+            metadata.content_hash,
+            content_hash(BANG_LIST.as_bytes()),
+            "the hash is of the decompressed list"
+        );
+
+        // detected by content, not by name
+        let mislabeled_path = dir.path().join("plain.json.gz");
+        fs::write(&mislabeled_path, BANG_LIST).unwrap();
+        let (plain, _) = load_pinned_bangs(&mislabeled_path).unwrap();
+        assert_eq!(plain[0].url_template, bangs[0].url_template);
+
+        let corrupt_path = dir.path().join("corrupt.json.gz");
+        fs::write(&corrupt_path, &gzipped[..gzipped.len() / 2]).unwrap();
+        let e = load_pinned_bangs(&corrupt_path).unwrap_err();
+        assert!(format!("{e:#}").contains("Corrupt gzip"), "{e:#}");
+    }
+}
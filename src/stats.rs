@@ -0,0 +1,431 @@
+//! Redirect counts per month, kept on disk across restarts with
+//! `stats_persist = true`.
+//!
+//! Like the [usage log](crate::usage) only triggers are counted, never the
+//! search terms, and nothing leaves the machine. The counts are kept in memory
+//! and folded into the store file every [`FOLD_INTERVAL`] and on shutdown,
+//! which is written to a temporary file first and renamed, so a shutdown in
+//! the middle of it never leaves a truncated store behind.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::time::interval;
+use tracing::{debug, error};
+
+use crate::config::AppState;
+use crate::usage::TriggerCount;
+
+/// How often the counts are written to the store.
+pub const FOLD_INTERVAL: Duration = Duration::from_mins(1);
+/// Number of triggers reported per month.
+const TOP_TRIGGERS: usize = 10;
+/// Held while the store is written.
+static SAVING: Mutex<()> = Mutex::new(());
+
+/// Counts of one month.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MonthStats {
+    /// All redirects, with or without a bang.
+    pub redirects: u64,
+    /// Redirects per trigger.
+    pub triggers: BTreeMap<String, u64>,
+}
+
+/// The months counted so far, keyed like `2025-01`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatsStore {
+    pub months: BTreeMap<String, MonthStats>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MonthReport {
+    pub month: String,
+    pub redirects: u64,
+    /// The most used triggers, the most used first.
+    pub top: Vec<TriggerCount>,
+}
+
+impl StatsStore {
+    /// Count a redirect through `trigger`, or without a bang, now.
+    pub fn record(&mut self, trigger: Option<&str>) {
+        self.record_at(trigger, unix_now());
+    }
+
+    /// Count a redirect at `timestamp` seconds since the Unix epoch.
+    pub fn record_at(&mut self, trigger: Option<&str>, timestamp: u64) {
+        let month = self.months.entry(month_of(timestamp)).or_default();
+        month.redirects += 1;
+        if let Some(trigger) = trigger {
+            *month.triggers.entry(trigger.to_string()).or_default() += 1;
+        }
+    }
+
+    /// The report of `month`, like `2025-01`, if anything was counted in it.
+    #[must_use]
+    pub fn month(&self, month: &str) -> Option<MonthReport> {
+        let stats = self.months.get(month)?;
+        let mut top: Vec<TriggerCount> = stats
+            .triggers
+            .iter()
+            .map(|(trigger, &count)| {
+                TriggerCount {
+                    trigger: trigger.clone(),
+                    count,
+                }
+            })
+            .collect();
+        // stable, so equally used triggers stay alphabetical
+        top.sort_by_key(|count| Reverse(count.count));
+        top.truncate(TOP_TRIGGERS);
+        Some(MonthReport {
+            month: month.to_string(),
+            redirects: stats.redirects,
+            top,
+        })
+    }
+
+    /// Keep the last `retention` months up to the one of `timestamp`, which is
+    /// always kept, also with a `retention` of 0.
+    pub fn prune_at(&mut self, retention: u32, timestamp: u64) {
+        let Some(current) = month_index(&month_of(timestamp)) else {
+            return;
+        };
+        let retention = i64::from(retention.max(1));
+        self.months
+            .retain(|month, _| month_index(month).is_some_and(|index| index + retention > current));
+    }
+
+    /// Read the store at `path`, an empty one if there is none yet.
+    ///
+    /// # Errors
+    /// If the file exists but can't be read or parsed.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                serde_json::from_str(&contents)
+                    .with_context(|| format!("Failed to parse stats at {}", path.display()))
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read stats at {}", path.display())),
+        }
+    }
+
+    /// Write the store to `path` through a temporary file, which is renamed,
+    /// so `path` always holds a complete store.
+    ///
+    /// # Errors
+    /// If the directory can't be created or the file can't be written.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to write stats to {}", path.display()))
+    }
+}
+
+/// The persisted counts of an instance.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub store: StatsStore,
+    /// Where the store is written, nothing if `stats_persist` is off or the
+    /// existing store couldn't be read, so it isn't overwritten.
+    pub path: Option<PathBuf>,
+}
+
+impl Stats {
+    /// Load the store at `path`.
+    #[must_use]
+    pub fn open(path: PathBuf) -> Self {
+        match StatsStore::load(&path) {
+            Ok(store) => {
+                Self {
+                    store,
+                    path: Some(path),
+                }
+            },
+            Err(e) => {
+                error!("Not persisting stats: {e:#}");
+                Self::default()
+            },
+        }
+    }
+}
+
+/// Default location of the store, `$XDG_STATE_HOME/redirector/stats.json` or
+/// `~/.local/state/redirector/stats.json`.
+#[must_use]
+pub fn default_stats_path() -> PathBuf {
+    let state_dir = std::env::var("XDG_STATE_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map_or_else(
+            || {
+                let home_dir = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+                Path::new(&home_dir).join(".local").join("state")
+            },
+            PathBuf::from,
+        );
+    state_dir.join("redirector").join("stats.json")
+}
+
+/// Prune and write the store every [`FOLD_INTERVAL`].
+pub async fn periodic_fold(app_state: AppState) {
+    let mut interval = interval(FOLD_INTERVAL);
+    #[expect(
+        clippy::infinite_loop,
+        reason = "This is intended to run indefinitely in the background"
+    )]
+    loop {
+        interval.tick().await;
+        let app_state = app_state.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || fold(&app_state)).await {
+            error!("Failed to fold stats: {e}");
+        }
+    }
+}
+
+/// Prune the store to `stats_retention_months` and write it.
+///
+/// The counts are only locked to take a copy, the file is written without
+/// holding the lock.
+pub fn fold(app_state: &AppState) {
+    let retention = app_state.config.read().stats_retention_months;
+    // an older copy must not be written over a newer one
+    let _saving = SAVING.lock();
+    let (store, path) = {
+        let mut stats = app_state.stats.lock();
+        stats.store.prune_at(retention, unix_now());
+        let Some(path) = stats.path.clone() else {
+            return;
+        };
+        (stats.store.clone(), path)
+    };
+    match store.save(&path) {
+        Ok(()) => debug!("Saved stats to {}", path.display()),
+        Err(e) => error!("Failed to save stats: {e:#}"),
+    }
+}
+
+/// Open the store after `stats_persist`, `persist` or `stats_path` changed,
+/// writing the counts to the store that was open before.
+pub fn reopen(app_state: &AppState) {
+    let path = {
+        let config = app_state.config.read();
+        (config.stats_persist && config.persist)
+            .then(|| config.stats_path.clone().unwrap_or_else(default_stats_path))
+    };
+    if app_state.stats.lock().path == path {
+        return;
+    }
+    fold(app_state);
+    let stats = path.map_or_else(Stats::default, Stats::open);
+    *app_state.stats.lock() = stats;
+}
+
+/// Render the months, the newest first, as a table with the change of the
+/// redirects to the month before.
+#[must_use]
+pub fn render_table(store: &StatsStore) -> String {
+    let mut html = String::from(
+        "<table><tr><th>Month</th><th>Redirects</th><th>Change</th><th>Top bangs</th></tr>",
+    );
+    let mut previous: Option<u64> = None;
+    let mut rows = Vec::new();
+    for month in store.months.keys() {
+        let Some(report) = store.month(month) else {
+            continue;
+        };
+        let change = previous.map_or_else(
+            || "-".to_string(),
+            |previous| format!("{:+}", i128::from(report.redirects) - i128::from(previous)),
+        );
+        previous = Some(report.redirects);
+        let top: Vec<String> = report
+            .top
+            .iter()
+            .map(|count| {
+                format!(
+                    "!{} ({})",
+                    crate::server::escape_html(&count.trigger),
+                    count.count
+                )
+            })
+            .collect();
+        rows.push(format!(
+            "<tr><td>{}</td><td>{}</td><td>{change}</td><td>{}</td></tr>",
+            report.month,
+            report.redirects,
+            top.join(", ")
+        ));
+    }
+    for row in rows.iter().rev() {
+        html.push_str(row);
+    }
+    html.push_str("</table>");
+    html
+}
+
+/// The month of `timestamp` seconds since the Unix epoch, like `2025-01`.
+#[must_use]
+pub fn month_of(timestamp: u64) -> String {
+    // days to the civil date, after Howard Hinnant's `civil_from_days`
+    let days = i64::try_from(timestamp / 86_400).unwrap_or(i64::MAX) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let mut formatted = String::with_capacity(7);
+    write!(formatted, "{year:04}-{month:02}").expect("Failed to write to month string");
+    formatted
+}
+
+/// Months since year 0 of a month like `2025-01`.
+fn month_index(month: &str) -> Option<i64> {
+    let (year, month) = month.split_once('-')?;
+    let year: i64 = year.parse().ok()?;
+    let month: i64 = month.parse().ok()?;
+    (1..=12).contains(&month).then_some(year * 12 + month - 1)
+}
+
+/// Whether `month` is formatted like `2025-01`.
+#[must_use]
+pub fn is_month(month: &str) -> bool {
+    month.len() == 7 && month_index(month).is_some()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The current month, like `2025-01`.
+#[must_use]
+pub fn current_month() -> String {
+    month_of(unix_now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    /// 2025-01-31 23:59:59 UTC
+    const JANUARY: u64 = 1_738_367_999;
+    const FEBRUARY: u64 = JANUARY + 1;
+
+    #[test]
+    fn test_month_of() {
+        assert_eq!(month_of(0), "1970-01");
+        assert_eq!(month_of(JANUARY), "2025-01");
+        assert_eq!(month_of(FEBRUARY), "2025-02");
+        // leap day
+        assert_eq!(month_of(1_709_164_800), "2024-02");
+        assert_eq!(month_of(1_709_251_200), "2024-03");
+        assert!(is_month("2025-01") && !is_month("2025-13") && !is_month("2025-1"));
+    }
+
+    #[test]
+    fn test_persist_months() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("stats.json");
+        let mut store = StatsStore::load(&path).unwrap();
+        assert_eq!(store, StatsStore::default(), "a missing store is empty");
+
+        for trigger in [Some("g"), Some("w"), Some("g"), None] {
+            store.record_at(trigger, JANUARY);
+        }
+        store.record_at(Some("w"), FEBRUARY);
+        store.save(&path).unwrap();
+        assert!(!path.with_extension("json.tmp").exists());
+
+        // after a restart
+        let mut store = StatsStore::load(&path).unwrap();
+        let january = store.month("2025-01").unwrap();
+        assert_eq!(january.redirects, 4);
+        let top: Vec<(&str, u64)> = january
+            .top
+            .iter()
+            .map(|count| (count.trigger.as_str(), count.count))
+            .collect();
+        assert_eq!(top, [("g", 2), ("w", 1)]);
+        store.record_at(Some("gh"), FEBRUARY + 60);
+        assert_eq!(store.month("2025-02").unwrap().redirects, 2);
+        assert_eq!(store.month("2025-03"), None);
+
+        let table = render_table(&store);
+        assert!(
+            table.find("2025-02").unwrap() < table.find("2025-01").unwrap(),
+            "newest first: {table}"
+        );
+        assert!(
+            table.contains("<td>2025-02</td><td>2</td><td>-2</td>"),
+            "{table}"
+        );
+
+        store.prune_at(2, FEBRUARY);
+        assert_eq!(store.months.len(), 2);
+        store.prune_at(0, FEBRUARY);
+        assert_eq!(
+            store.months.keys().collect::<Vec<_>>(),
+            ["2025-02"],
+            "the current month is always kept"
+        );
+
+        std::fs::write(&path, "not json").unwrap();
+        let e = StatsStore::load(&path).unwrap_err();
+        assert!(format!("{e:#}").contains("Failed to parse stats"), "{e:#}");
+        assert_eq!(
+            Stats::open(path).path,
+            None,
+            "a broken store isn't overwritten"
+        );
+    }
+
+    #[test]
+    fn test_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+        let app_state = AppState::new(AppConfig::default());
+        assert_eq!(app_state.stats.lock().path, None);
+
+        // turned on by a reload
+        {
+            let mut config = app_state.config.write();
+            config.stats_persist = true;
+            config.stats_path = Some(path.clone());
+        }
+        reopen(&app_state);
+        assert_eq!(app_state.stats.lock().path.as_ref(), Some(&path));
+        app_state.stats.lock().store.record(Some("g"));
+
+        // and off again, the counts so far are written first
+        app_state.config.write().stats_persist = false;
+        reopen(&app_state);
+        assert_eq!(app_state.stats.lock().path, None);
+        let store = StatsStore::load(&path).unwrap();
+        assert_eq!(store.month(&current_month()).unwrap().redirects, 1);
+    }
+}
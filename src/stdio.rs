@@ -0,0 +1,170 @@
+//! Line-delimited JSON protocol for launcher integrations (rofi, wofi,
+//! Raycast, ...).
+//!
+//! Every line read from stdin is one request, every line written to stdout is
+//! the response to it, in the same order:
+//!
+//! ```text
+//! -> {"op":"resolve","q":"!gh redirector"}
+//! <- {"ok":true,"url":"https://github.com/search?q=redirector"}
+//! -> {"op":"complete","prefix":"!gi","limit":3}
+//! <- {"ok":true,"completions":["!gi","!gif","!git"]}
+//! -> {"op":"nope"}
+//! <- {"ok":false,"error":"unknown variant `nope`, ..."}
+//! ```
+//!
+//! `limit` is optional and defaults to 10. The process exits once stdin is
+//! closed.
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt as _, AsyncWrite, AsyncWriteExt as _};
+
+use crate::config::AppConfig;
+use crate::{BANG_CACHE, BangCache, resolve_in, resolver};
+
+const DEFAULT_COMPLETION_LIMIT: usize = 10;
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Request {
+    Resolve {
+        q: String,
+    },
+    Complete {
+        prefix: String,
+        limit: Option<usize>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum Response {
+    Resolved { ok: bool, url: String },
+    Completed { ok: bool, completions: Vec<String> },
+    Failed { ok: bool, error: String },
+}
+
+/// Handle a single request line and return the serialized response.
+///
+/// # Panics
+/// If the response cannot be serialized, which can't happen for plain strings.
+#[must_use]
+pub fn handle_line(app_config: &AppConfig, line: &str) -> String {
+    handle_line_in(&BANG_CACHE.read(), app_config, line)
+}
+
+/// Like [`handle_line`], answering from `cache`.
+fn handle_line_in(cache: &BangCache, app_config: &AppConfig, line: &str) -> String {
+    let response = match serde_json::from_str::<Request>(line) {
+        Ok(Request::Resolve { q }) => {
+            Response::Resolved {
+                ok: true,
+                url: resolve_in(cache, app_config, &q).url,
+            }
+        },
+        Ok(Request::Complete { prefix, limit }) => {
+            Response::Completed {
+                ok: true,
+                completions: resolver::complete(
+                    cache,
+                    &app_config.resolve_options(),
+                    &prefix,
+                    limit.unwrap_or(DEFAULT_COMPLETION_LIMIT),
+                ),
+            }
+        },
+        Err(e) => {
+            Response::Failed {
+                ok: false,
+                error: e.to_string(),
+            }
+        },
+    };
+    serde_json::to_string(&response).expect("Failed to serialize stdio response")
+}
+
+/// Answer requests from `reader` on `writer` until `reader` reaches EOF.
+///
+/// Empty lines are ignored.
+pub async fn run<R, W>(app_config: &AppConfig, reader: R, writer: W) -> anyhow::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    serve(reader, writer, |line| handle_line(app_config, line)).await
+}
+
+async fn serve<R, W>(
+    reader: R,
+    mut writer: W,
+    handle: impl Fn(&str) -> String,
+) -> anyhow::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut response = handle(&line);
+        response.push('\n');
+        writer.write_all(response.as_bytes()).await?;
+        writer.flush().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::cache::fixture_cache;
+
+    #[tokio::test]
+    async fn test_stdio_protocol() {
+        let config = AppConfig::default();
+        let cache = fixture_cache();
+
+        let input = concat!(
+            r#"{"op":"resolve","q":"!g rust"}"#,
+            "\n\n",
+            r#"{"op":"complete","prefix":"!g","limit":2}"#,
+            "\n",
+            r#"{"op":"explode"}"#,
+            "\n",
+            "not json",
+        );
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output, |line| {
+            handle_line_in(&cache, &config, line)
+        })
+        .await
+        .unwrap();
+
+        let responses: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(responses.len(), 4, "one response per non-empty line");
+
+        assert_eq!(responses[0]["ok"], true);
+        assert_eq!(
+            responses[0]["url"],
+            resolve_in(&cache, &config, "!g rust").url
+        );
+
+        assert_eq!(responses[1]["ok"], true);
+        let completions = responses[1]["completions"].as_array().unwrap();
+        assert_eq!(completions.len(), 2);
+        assert_eq!(completions[0], "!g");
+
+        for response in &responses[2..] {
+            assert_eq!(response["ok"], false);
+            assert!(response["error"].is_string(), "errors must be described");
+        }
+    }
+}
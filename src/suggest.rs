@@ -0,0 +1,934 @@
+//! Fetching and parsing responses of the search suggestion upstream.
+
+use std::borrow::Cow;
+use std::time::Instant;
+
+use anyhow::{Context as _, bail};
+use reqwest::header::{CONNECTION, HeaderMap, HeaderName};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::{debug, error, warn};
+
+use crate::BANG_CACHE;
+use crate::config::{AppConfig, AppState};
+use crate::resolver::{self, BangCache, partial_bang};
+
+/// Number of bangs suggested when the upstream can't be used.
+const LOCAL_SUGGESTION_LIMIT: usize = 10;
+
+/// Format of the responses of the `search_suggestions` upstream.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum SuggestionFormat {
+    /// Plain JSON, falling back to JSONP if that fails.
+    #[default]
+    Auto,
+    Json,
+    /// JSON wrapped in a callback, e.g. `callback([...]);`.
+    Jsonp,
+}
+
+/// Upstreams with a built-in URL template and response parser, or `custom`
+/// for the `search_suggestions` template.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum SuggestionProvider {
+    #[default]
+    Brave,
+    DuckDuckGo,
+    Google,
+    Qwant,
+    /// Answers in `suggestion_format`, passed on as it is.
+    Custom,
+}
+
+impl SuggestionProvider {
+    /// The URL template of a preset, `{}` is the query.
+    #[must_use]
+    pub const fn template(self) -> Option<&'static str> {
+        match self {
+            Self::Brave => Some("https://search.brave.com/api/suggest?q={}"),
+            Self::DuckDuckGo => Some("https://duckduckgo.com/ac/?q={}"),
+            Self::Google => {
+                Some("https://suggestqueries.google.com/complete/search?client=firefox&q={}")
+            },
+            Self::Qwant => Some("https://api.qwant.com/v3/suggest?q={}"),
+            Self::Custom => None,
+        }
+    }
+
+    /// Parse a response body into the `OpenSearch` suggestions format,
+    /// `[query, [suggestion, ...]]`. Responses of a `custom` upstream are
+    /// parsed as `format` and not normalized.
+    #[must_use]
+    pub fn parse(self, query: &str, body: &str, format: SuggestionFormat) -> Option<Value> {
+        if self == Self::Custom {
+            return parse_suggestions(body, format);
+        }
+        let response: Value = serde_json::from_str(body).ok()?;
+        let phrases: Vec<&Value> = match self {
+            // `[query, [suggestion, ...], ...]`
+            Self::Brave | Self::Google => response.get(1)?.as_array()?.iter().collect(),
+            // `[{"phrase": suggestion}, ...]`
+            Self::DuckDuckGo => {
+                response
+                    .as_array()?
+                    .iter()
+                    .filter_map(|item| item.get("phrase"))
+                    .collect()
+            },
+            // `{"status": "success", "data": {"items": [{"value": suggestion}, ...]}}`
+            Self::Qwant => {
+                response
+                    .pointer("/data/items")?
+                    .as_array()?
+                    .iter()
+                    .filter_map(|item| item.get("value"))
+                    .collect()
+            },
+            Self::Custom => unreachable!("Custom responses are returned above"),
+        };
+        let suggestions: Vec<&str> = phrases.into_iter().filter_map(Value::as_str).collect();
+        Some(json!([query, suggestions]))
+    }
+}
+
+/// How the query is encoded when substituted into `search_suggestions`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum QueryEncoding {
+    /// Percent-encoded, spaces as `%20`.
+    #[default]
+    Percent,
+    /// Like `application/x-www-form-urlencoded`, spaces as `+`.
+    Plus,
+    /// Not encoded at all, for upstreams that expect it that way.
+    Raw,
+}
+
+/// Substitute the encoded `query` into the `search_suggestions` template.
+///
+/// Returns nothing if the encoded query would break out of its parameter,
+/// which only the `raw` encoding leaves possible, so a query can't add
+/// parameters to or cut off the upstream request.
+#[must_use]
+pub fn suggestion_url(template: &str, query: &str, encoding: QueryEncoding) -> Option<String> {
+    let query = match encoding {
+        QueryEncoding::Percent => urlencoding::encode(query),
+        QueryEncoding::Plus => Cow::Owned(urlencoding::encode(query).replace("%20", "+")),
+        QueryEncoding::Raw => Cow::Borrowed(query),
+    };
+    if query.contains(['&', '#', '\r', '\n']) {
+        return None;
+    }
+    Some(template.replace("{}", &query))
+}
+
+/// Strip a JSONP callback wrapper, returning the wrapped JSON.
+#[must_use]
+pub fn strip_jsonp(body: &str) -> Option<&str> {
+    let body = body.trim().trim_start_matches("/**/").trim_start();
+    let open = body.find('(')?;
+    let callback = body[..open].trim_end();
+    if callback.is_empty()
+        || !callback
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '.'))
+    {
+        return None;
+    }
+    let inner = body[open + 1..]
+        .trim_end()
+        .trim_end_matches(';')
+        .trim_end()
+        .strip_suffix(')')?;
+    Some(inner)
+}
+
+/// Parse a response body of the suggestion upstream.
+#[must_use]
+pub fn parse_suggestions(body: &str, format: SuggestionFormat) -> Option<Value> {
+    let json = || serde_json::from_str(body).ok();
+    let jsonp = || serde_json::from_str(strip_jsonp(body)?).ok();
+    match format {
+        SuggestionFormat::Auto => json().or_else(jsonp),
+        SuggestionFormat::Json => json(),
+        SuggestionFormat::Jsonp => jsonp(),
+    }
+}
+
+/// Description of the suggestion for searching without a bang.
+const DEFAULT_SEARCH_LABEL: &str = "Search without a bang (default search)";
+
+/// Suggestions answered without the upstream: matching bangs for queries
+/// ending in a partial bang, nothing otherwise, in the `OpenSearch`
+/// suggestions format.
+///
+/// With `suggest_default_search`, a lone `!` at the end is also suggested to
+/// search the rest of the query without a bang, labeled in the descriptions.
+/// A query that is only `!` gets the `suggest_top_bangs`, described by their
+/// short names, and no default search.
+#[must_use]
+pub fn local_suggestions(app_config: &AppConfig, query: &str) -> Value {
+    local_suggestions_in(&BANG_CACHE.read(), app_config, query)
+}
+
+/// [`local_suggestions`] with the bangs of `cache`.
+fn local_suggestions_in(cache: &BangCache, app_config: &AppConfig, query: &str) -> Value {
+    let term = default_search_term(app_config, query);
+    let mut completions = Vec::new();
+    let mut descriptions = Vec::new();
+    if let Some(term) = term {
+        completions.push(term.to_string());
+        descriptions.push(DEFAULT_SEARCH_LABEL.to_string());
+    }
+    if let Some(top_bangs) = top_bangs(cache, app_config, query) {
+        for (bang, short_name) in top_bangs {
+            completions.push(bang);
+            descriptions.push(short_name);
+        }
+    } else {
+        let bangs = resolver::complete(
+            cache,
+            &app_config.resolve_options(),
+            query,
+            LOCAL_SUGGESTION_LIMIT,
+        );
+        if term.is_none() {
+            return json!([query, bangs]);
+        }
+        completions.extend(bangs);
+        descriptions.resize(completions.len(), String::new());
+    }
+    json!([query, completions, descriptions])
+}
+
+/// Whether the query is only the `!` of a bang, without a trigger or term.
+fn is_bare_prefix(query: &str) -> bool {
+    query.trim() == "!"
+}
+
+/// The `suggest_top_bangs` known in `cache` with their short names, if the
+/// query is only `!` and there are any configured.
+fn top_bangs(
+    cache: &BangCache,
+    app_config: &AppConfig,
+    query: &str,
+) -> Option<Vec<(String, String)>> {
+    if !is_bare_prefix(query) || app_config.suggest_top_bangs.is_empty() {
+        return None;
+    }
+    let top_bangs = app_config
+        .suggest_top_bangs
+        .iter()
+        .filter_map(|trigger| {
+            let trigger = trigger.trim().trim_start_matches('!').to_ascii_lowercase();
+            let short_name = cache.get(&trigger)?.bang.short_name.clone();
+            Some((format!("!{trigger}"), short_name.unwrap_or_default()))
+        })
+        .take(LOCAL_SUGGESTION_LIMIT)
+        .collect();
+    Some(top_bangs)
+}
+
+/// The query before a lone `!` it ends in, if the default search is
+/// suggested for it, never for a query that is only `!`.
+fn default_search_term<'q>(app_config: &AppConfig, query: &'q str) -> Option<&'q str> {
+    if !app_config.suggest_default_search {
+        return None;
+    }
+    let query = query.trim_start();
+    let token = partial_bang(&app_config.resolve_options(), query)?;
+    token
+        .trigger
+        .is_empty()
+        .then(|| query[..token.start].trim_end())
+        .filter(|term| !term.is_empty())
+}
+
+/// Headers describing the connection to us rather than the request, never
+/// forwarded even if listed (RFC 9110, section 7.6.1).
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The headers of `request_headers` listed in `suggestion_forward_headers`,
+/// without hop-by-hop headers and the ones named in `Connection`.
+fn forwarded_headers(app_config: &AppConfig, request_headers: &HeaderMap) -> HeaderMap {
+    let connection_headers: Vec<String> = request_headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    let mut headers = HeaderMap::new();
+    for name in &app_config.suggestion_forward_headers {
+        // names are case-insensitive, invalid ones can't be in the request
+        let Ok(name) = HeaderName::try_from(name.as_str()) else {
+            continue;
+        };
+        if HOP_BY_HOP_HEADERS.contains(&name.as_str())
+            || connection_headers.iter().any(|hop| hop == name.as_str())
+        {
+            continue;
+        }
+        for value in request_headers.get_all(&name) {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+    headers
+}
+
+/// Read the body of `response`, giving up as soon as it exceeds `limit`
+/// bytes instead of buffering all of it.
+async fn read_body(mut response: reqwest::Response, limit: usize) -> anyhow::Result<String> {
+    if response
+        .content_length()
+        .is_some_and(|length| length > limit as u64)
+    {
+        bail!("The response is larger than the limit of {limit} bytes");
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            bail!("The response is larger than the limit of {limit} bytes");
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Suggestions for `query` from the upstream, or [`local_suggestions`] if it
+/// can't be used, its circuit breaker is open or in `offline` mode.
+///
+/// The `suggestion_forward_headers` are copied from `request_headers` to the
+/// upstream request.
+pub async fn suggestions(app_state: &AppState, query: &str, request_headers: &HeaderMap) -> Value {
+    let app_config = app_state.get_config();
+    // the upstream knows nothing about bangs, and little about a letter or two
+    if app_config.offline
+        || is_bare_prefix(query) && !app_config.suggest_top_bangs.is_empty()
+        || query.chars().count() < app_config.min_suggest_len
+    {
+        return local_suggestions(&app_config, query);
+    }
+    let Some(suggest_api_url) = suggestion_url(
+        &app_config.search_suggestions,
+        query,
+        app_config.suggestion_query_encoding,
+    ) else {
+        warn!("Refusing to pass a query with parameter delimiters to the suggestion API unencoded");
+        return local_suggestions(&app_config, query);
+    };
+
+    let checked = app_state
+        .suggestion_policy
+        .read()
+        .check_url(&suggest_api_url);
+    match checked {
+        Ok(_) if !app_state.suggestion_breaker.allow(Instant::now()) => {
+            debug!("The suggestion upstream keeps failing, answering locally");
+        },
+        Ok(url) => {
+            let client = app_state.suggestion_client.read().clone();
+            // give up before `/suggest` runs out of time, so a hanging
+            // upstream counts as failing
+            let limit = app_config.timeouts.suggestion_upstream();
+            let fetched = tokio::time::timeout(
+                limit,
+                fetch_suggestions(&client, url, request_headers, &app_config, query),
+            )
+            .await;
+            match fetched {
+                Ok(Ok(json)) => {
+                    app_state.suggestion_breaker.record_success();
+                    return json;
+                },
+                Ok(Err(e)) => {
+                    app_state.suggestion_breaker.record_failure(Instant::now());
+                    error!("{e:#}");
+                },
+                Err(_) => {
+                    app_state.suggestion_breaker.record_failure(Instant::now());
+                    error!("Search suggestion API didn't answer within {limit:?}");
+                },
+            }
+        },
+        Err(e) => {
+            error!("Refusing to fetch suggestions from '{suggest_api_url}': {e}");
+        },
+    }
+    local_suggestions(&app_config, query)
+}
+
+/// Fetch and parse the suggestions for `query` at `url`.
+async fn fetch_suggestions(
+    client: &reqwest::Client,
+    url: reqwest::Url,
+    request_headers: &HeaderMap,
+    app_config: &AppConfig,
+    query: &str,
+) -> anyhow::Result<Value> {
+    let response = client
+        .get(url)
+        .headers(forwarded_headers(app_config, request_headers))
+        .send()
+        .await
+        .context("Failed to fetch suggestions from search suggestion API")?;
+    let body = read_body(response, app_config.suggestion_max_response_bytes)
+        .await
+        .context("Failed to read suggestions from search suggestion API")?;
+    app_config
+        .suggestion_provider
+        .parse(query, &body, app_config.suggestion_format)
+        .with_context(|| {
+            format!(
+                "Search suggestion API returned an unexpected response, expected {:?}",
+                app_config.suggestion_provider
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::Router;
+    use axum::extract::{RawQuery, State};
+    use axum::routing::get;
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::bang::{Bang, test_bang};
+    use crate::config::{BreakerConfig, TimeoutsConfig};
+    use crate::resolver::build_cache;
+
+    #[tokio::test]
+    async fn test_suggestion_query_encoding() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new()
+            .route(
+                "/suggest",
+                get(
+                    |State(received): State<Arc<Mutex<Vec<String>>>>, RawQuery(query): RawQuery| {
+                        async move {
+                            received.lock().push(query.unwrap_or_default());
+                            r#"["", []]"#
+                        }
+                    },
+                ),
+            )
+            .with_state(Arc::clone(&received));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        for encoding in [QueryEncoding::Percent, QueryEncoding::Plus] {
+            let app_state = AppState::new(AppConfig {
+                search_suggestions: format!("http://{addr}/suggest?q={{}}&client=test"),
+                suggestion_provider: SuggestionProvider::Custom,
+                suggestion_allow_hosts: vec!["127.0.0.1".to_string()],
+                suggestion_query_encoding: encoding,
+                ..AppConfig::default()
+            });
+            suggestions(&app_state, "rust async & await?", &HeaderMap::new()).await;
+            suggestions(&app_state, "rust &foo=bar#top", &HeaderMap::new()).await;
+        }
+        assert_eq!(
+            *received.lock(),
+            [
+                "q=rust%20async%20%26%20await%3F&client=test",
+                "q=rust%20%26foo%3Dbar%23top&client=test",
+                "q=rust+async+%26+await%3F&client=test",
+                "q=rust+%26foo%3Dbar%23top&client=test",
+            ]
+        );
+
+        let template = "https://x.test/?q={}&client=test";
+        assert_eq!(
+            suggestion_url(template, "a b", QueryEncoding::Raw).as_deref(),
+            Some("https://x.test/?q=a b&client=test")
+        );
+        for query in ["rust &foo=bar", "rust#fragment", "a\r\nHost: evil"] {
+            assert_eq!(
+                suggestion_url(template, query, QueryEncoding::Raw),
+                None,
+                "{query:?} must not break out of the parameter"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_headers() {
+        type Received = Arc<Mutex<Vec<HeaderMap>>>;
+        let received: Received = Arc::default();
+        let app = Router::new()
+            .route(
+                "/suggest",
+                get(|State(received): State<Received>, headers: HeaderMap| {
+                    async move {
+                        received.lock().push(headers);
+                        r#"["", []]"#
+                    }
+                }),
+            )
+            .with_state(Arc::clone(&received));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert("accept-language", "de-CH, de;q=0.9".parse().unwrap());
+        request_headers.insert("cookie", "session=secret".parse().unwrap());
+        request_headers.insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        request_headers.insert("connection", "keep-alive, X-Hop".parse().unwrap());
+        request_headers.insert("keep-alive", "timeout=5".parse().unwrap());
+        request_headers.insert("te", "trailers".parse().unwrap());
+        request_headers.insert("x-hop", "1".parse().unwrap());
+        let listed = ["Accept-Language", "Connection", "Keep-Alive", "TE", "X-Hop"];
+        for forward_headers in [vec![], listed.map(str::to_string).to_vec()] {
+            let app_state = AppState::new(AppConfig {
+                search_suggestions: format!("http://{addr}/suggest?q={{}}"),
+                suggestion_provider: SuggestionProvider::Custom,
+                suggestion_allow_hosts: vec!["127.0.0.1".to_string()],
+                suggestion_forward_headers: forward_headers,
+                ..AppConfig::default()
+            });
+            suggestions(&app_state, "rust", &request_headers).await;
+        }
+
+        let received = received.lock();
+        for headers in received.iter() {
+            assert!(!headers.contains_key("cookie"));
+            assert!(!headers.contains_key("x-forwarded-for"));
+            assert!(!headers.contains_key("keep-alive"), "{headers:?}");
+            assert!(!headers.contains_key("te"), "{headers:?}");
+            assert!(
+                !headers.contains_key("x-hop"),
+                "headers named in Connection are hop-by-hop as well: {headers:?}"
+            );
+        }
+        assert!(
+            !received[0].contains_key("accept-language"),
+            "nothing is forwarded by default"
+        );
+        assert_eq!(received[1]["accept-language"], "de-CH, de;q=0.9");
+    }
+
+    #[tokio::test]
+    async fn test_oversized_response() {
+        let app = Router::new()
+            .route("/small", get(|| async { r#"["rust", ["rust lang"]]"# }))
+            .route(
+                "/large",
+                get(|| async { format!(r#"["rust", ["{}"]]"#, "a".repeat(4096)) }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // without a Content-Length and never ending
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endless_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt as _;
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n[\"rus\r\n")
+                .await
+                .unwrap();
+            while stream.write_all(b"400\r\n").await.is_ok()
+                && stream.write_all(&[b' '; 0x400]).await.is_ok()
+                && stream.write_all(b"\r\n").await.is_ok()
+            {}
+        });
+
+        let suggest = |search_suggestions: String| {
+            async move {
+                let app_state = AppState::new(AppConfig {
+                    search_suggestions,
+                    suggestion_provider: SuggestionProvider::Custom,
+                    suggestion_allow_hosts: vec!["127.0.0.1".to_string()],
+                    suggestion_max_response_bytes: 1024,
+                    ..AppConfig::default()
+                });
+                suggestions(&app_state, "rust", &HeaderMap::new()).await
+            }
+        };
+        let local = json!(["rust", []]);
+        assert_eq!(
+            suggest(format!("http://{addr}/small?q={{}}")).await,
+            json!(["rust", ["rust lang"]])
+        );
+        assert_eq!(suggest(format!("http://{addr}/large?q={{}}")).await, local);
+        let endless = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            suggest(format!("http://{endless_addr}/?q={{}}")),
+        );
+        assert_eq!(endless.await.unwrap(), local);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        #[derive(Clone, Default)]
+        struct Upstream {
+            requests: Arc<Mutex<usize>>,
+            healthy: Arc<Mutex<bool>>,
+        }
+        let upstream = Upstream::default();
+        let app = Router::new()
+            .route(
+                "/suggest",
+                get(|State(upstream): State<Upstream>| {
+                    async move {
+                        *upstream.requests.lock() += 1;
+                        if *upstream.healthy.lock() {
+                            r#"["rust", ["rust lang"]]"#
+                        } else {
+                            "<html>Service Unavailable</html>"
+                        }
+                    }
+                }),
+            )
+            .with_state(upstream.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let app_state = AppState::new(AppConfig {
+            search_suggestions: format!("http://{addr}/suggest?q={{}}"),
+            suggestion_provider: SuggestionProvider::Custom,
+            suggestion_allow_hosts: vec!["127.0.0.1".to_string()],
+            suggestion_breaker: BreakerConfig {
+                failures: 3,
+                cooldown_ms: 200,
+            },
+            ..AppConfig::default()
+        });
+        let local = json!(["rust", []]);
+        for _ in 0..5 {
+            assert_eq!(
+                suggestions(&app_state, "rust", &HeaderMap::new()).await,
+                local
+            );
+        }
+        assert_eq!(
+            *upstream.requests.lock(),
+            3,
+            "the breaker opens after 3 failures"
+        );
+        assert!(app_state.suggestion_breaker.is_open());
+
+        *upstream.healthy.lock() = true;
+        assert_eq!(
+            suggestions(&app_state, "rust", &HeaderMap::new()).await,
+            local,
+            "the upstream isn't asked during the cooldown"
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        assert_eq!(
+            suggestions(&app_state, "rust", &HeaderMap::new()).await,
+            json!(["rust", ["rust lang"]]),
+            "a probe after the cooldown"
+        );
+        assert!(!app_state.suggestion_breaker.is_open());
+        suggestions(&app_state, "rust", &HeaderMap::new()).await;
+        assert_eq!(*upstream.requests.lock(), 5, "closed again");
+    }
+
+    #[tokio::test]
+    async fn test_hanging_upstream() {
+        let requests = Arc::new(Mutex::new(0));
+        let app = Router::new()
+            .route(
+                "/suggest",
+                get(|State(requests): State<Arc<Mutex<usize>>>| {
+                    async move {
+                        *requests.lock() += 1;
+                        std::future::pending::<&str>().await
+                    }
+                }),
+            )
+            .with_state(Arc::clone(&requests));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let app_state = AppState::new(AppConfig {
+            search_suggestions: format!("http://{addr}/suggest?q={{}}"),
+            suggestion_provider: SuggestionProvider::Custom,
+            suggestion_allow_hosts: vec!["127.0.0.1".to_string()],
+            suggestion_breaker: BreakerConfig {
+                failures: 2,
+                cooldown_ms: 60_000,
+            },
+            timeouts: TimeoutsConfig {
+                suggest_ms: 100,
+                ..TimeoutsConfig::default()
+            },
+            ..AppConfig::default()
+        });
+        for _ in 0..3 {
+            assert_eq!(
+                suggestions(&app_state, "rust", &HeaderMap::new()).await,
+                json!(["rust", []]),
+                "local suggestions once the upstream times out"
+            );
+        }
+        assert_eq!(*requests.lock(), 2, "the breaker opens after 2 timeouts");
+        assert!(app_state.suggestion_breaker.is_open());
+    }
+
+    #[tokio::test]
+    async fn test_min_suggest_len() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new()
+            .route(
+                "/suggest",
+                get(
+                    |State(requests): State<Arc<Mutex<Vec<String>>>>, RawQuery(query): RawQuery| {
+                        async move {
+                            requests.lock().push(query.unwrap_or_default());
+                            r#"["rust", ["rust lang"]]"#
+                        }
+                    },
+                ),
+            )
+            .with_state(Arc::clone(&requests));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let app_state = AppState::new(AppConfig {
+            search_suggestions: format!("http://{addr}/suggest?q={{}}"),
+            suggestion_provider: SuggestionProvider::Custom,
+            suggestion_allow_hosts: vec!["127.0.0.1".to_string()],
+            min_suggest_len: 3,
+            ..AppConfig::default()
+        });
+        for query in ["r", "ru", "üb"] {
+            assert_eq!(
+                suggestions(&app_state, query, &HeaderMap::new()).await,
+                json!([query, []]),
+                "{query} is answered locally"
+            );
+        }
+        assert!(requests.lock().is_empty(), "{:?}", requests.lock());
+        assert_eq!(
+            suggestions(&app_state, "rus", &HeaderMap::new()).await,
+            json!(["rust", ["rust lang"]])
+        );
+        assert_eq!(*requests.lock(), ["q=rus"]);
+    }
+
+    #[test]
+    fn test_provider_presets() {
+        // responses for "rust l" in the shape each provider answers in
+        let fixtures = [
+            (
+                SuggestionProvider::Brave,
+                r#"["rust l",["rust lang","rust language","rust lighting"]]"#,
+            ),
+            (
+                SuggestionProvider::DuckDuckGo,
+                r#"[{"phrase":"rust lang"},{"phrase":"rust language"},{"phrase":"rust lighting"}]"#,
+            ),
+            (
+                SuggestionProvider::Google,
+                r#"["rust l",["rust lang","rust language","rust lighting"],[],{"google:suggestsubtypes":[[512],[512],[512]]}]"#,
+            ),
+            (
+                SuggestionProvider::Qwant,
+                r#"{"status":"success","data":{"items":[{"value":"rust lang","suggestType":3},{"value":"rust language","suggestType":3},{"value":"rust lighting","suggestType":3}],"special":[]}}"#,
+            ),
+        ];
+        let expected = json!(["rust l", ["rust lang", "rust language", "rust lighting"]]);
+        for (provider, body) in fixtures {
+            assert!(provider.template().unwrap().contains("{}"), "{provider:?}");
+            assert_eq!(
+                provider.parse("rust l", body, SuggestionFormat::Auto),
+                Some(expected.clone()),
+                "{provider:?}"
+            );
+            assert_eq!(
+                provider.parse(
+                    "rust l",
+                    "<html>rate limited</html>",
+                    SuggestionFormat::Auto
+                ),
+                None,
+                "{provider:?}"
+            );
+        }
+
+        // custom responses are passed on as they are
+        let body = r#"["rust l",["rust lang"],["Rust"],["https://www.rust-lang.org/"]]"#;
+        assert_eq!(SuggestionProvider::Custom.template(), None);
+        assert_eq!(
+            SuggestionProvider::Custom.parse("rust l", body, SuggestionFormat::Json),
+            serde_json::from_str(body).ok()
+        );
+    }
+
+    #[test]
+    fn test_default_search_suggestion() {
+        // `!` alone gets the top bangs otherwise, see test_top_bangs
+        let app_config = AppConfig {
+            suggest_top_bangs: Vec::new(),
+            ..AppConfig::default()
+        };
+        for (query, term) in [("rust !", "rust"), ("  rust lang   !", "rust lang")] {
+            let suggestions = local_suggestions(&app_config, query);
+            assert_eq!(suggestions[0], query);
+            let completions = suggestions[1].as_array().unwrap();
+            let descriptions = suggestions[2].as_array().unwrap();
+            assert_eq!(completions[0], term, "{query}");
+            assert_eq!(descriptions[0], DEFAULT_SEARCH_LABEL, "{query}");
+            // the bangs keep their place, without a description
+            assert_eq!(completions.len(), descriptions.len());
+            assert!(
+                descriptions[1..]
+                    .iter()
+                    .all(|description| description == "")
+            );
+        }
+        // only for a lone `!`, not a partial bang or a quoted one
+        let quoted = AppConfig {
+            ignore_quoted_bangs: true,
+            ..app_config.clone()
+        };
+        for (app_config, query) in [
+            (&app_config, "rust"),
+            (&app_config, "rust !g"),
+            (&app_config, "! rust"),
+            (&quoted, "\"rust !"),
+            // nothing to search without a bang
+            (&app_config, "!"),
+            (&app_config, "  ! "),
+        ] {
+            assert_eq!(default_search_term(app_config, query), None, "{query}");
+        }
+
+        let disabled = AppConfig {
+            suggest_default_search: false,
+            ..app_config
+        };
+        assert_eq!(default_search_term(&disabled, "rust !"), None);
+        assert_eq!(
+            local_suggestions(&disabled, "rust !")
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_top_bangs() {
+        let bang = |trigger: &str, short_name: Option<&str>| {
+            Bang {
+                short_name: short_name.map(str::to_string),
+                ..test_bang(
+                    trigger,
+                    &format!("https://{trigger}.example/?q={{{{{{s}}}}}}"),
+                )
+            }
+        };
+        let cache = build_cache(
+            vec![
+                bang("w", Some("Wikipedia")),
+                bang("gh", Some("GitHub")),
+                bang("a", None),
+                bang("aw", Some("ArchWiki")),
+            ],
+            &[],
+        );
+        let app_config = AppConfig {
+            suggest_top_bangs: ["gh", "!W", "unknown", "a"].map(str::to_string).to_vec(),
+            ..AppConfig::default()
+        };
+        assert_eq!(
+            local_suggestions_in(&cache, &app_config, "!"),
+            json!(["!", ["!gh", "!w", "!a"], ["GitHub", "Wikipedia", ""]])
+        );
+        assert_eq!(
+            local_suggestions_in(&cache, &app_config, " ! ")[1],
+            json!(["!gh", "!w", "!a"]),
+            "the default search of a bare `!` would be empty"
+        );
+        let without_default_search = AppConfig {
+            suggest_default_search: false,
+            ..app_config.clone()
+        };
+        assert_eq!(
+            local_suggestions_in(&cache, &without_default_search, " ! "),
+            json!([" ! ", ["!gh", "!w", "!a"], ["GitHub", "Wikipedia", ""]])
+        );
+        // anything more than `!` is completed as before
+        assert_eq!(
+            local_suggestions_in(&cache, &app_config, "!a"),
+            json!(["!a", ["!a", "!aw"]])
+        );
+        assert_eq!(
+            local_suggestions_in(&cache, &app_config, "rust !")[1],
+            json!(["rust", "rust !a", "rust !w", "rust !aw", "rust !gh"])
+        );
+
+        // an empty list completes all triggers
+        let all = AppConfig {
+            suggest_top_bangs: Vec::new(),
+            suggest_default_search: false,
+            ..app_config
+        };
+        assert_eq!(
+            local_suggestions_in(&cache, &all, "!"),
+            json!(["!", ["!a", "!w", "!aw", "!gh"]])
+        );
+    }
+
+    #[test]
+    fn test_jsonp_suggestions() {
+        let expected = json!(["rust", ["rust lang", "rustup"]]);
+        let jsonp = r#"/**/ suggest_cb(["rust",["rust lang","rustup"]]);"#;
+        assert_eq!(
+            strip_jsonp(jsonp),
+            Some(r#"["rust",["rust lang","rustup"]]"#)
+        );
+        assert_eq!(
+            parse_suggestions(jsonp, SuggestionFormat::Jsonp),
+            Some(expected.clone())
+        );
+        assert_eq!(
+            parse_suggestions(jsonp, SuggestionFormat::Auto),
+            Some(expected.clone())
+        );
+        assert_eq!(parse_suggestions(jsonp, SuggestionFormat::Json), None);
+
+        let plain = r#"["rust",["rust lang","rustup"]]"#;
+        assert_eq!(
+            parse_suggestions(plain, SuggestionFormat::Auto),
+            Some(expected)
+        );
+        assert_eq!(parse_suggestions(plain, SuggestionFormat::Jsonp), None);
+
+        for garbage in ["<html>rate limited</html>", "alert(1", "a b([])", ""] {
+            assert_eq!(
+                parse_suggestions(garbage, SuggestionFormat::Auto),
+                None,
+                "{garbage:?}"
+            );
+        }
+    }
+}
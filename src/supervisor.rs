@@ -0,0 +1,240 @@
+//! Supervision of long running background tasks like the bang updater.
+//!
+//! A spawned task whose handle is dropped can panic or return without anyone
+//! noticing, leaving e.g. a silently frozen bang list. Every task started
+//! through the [`Supervisor`] is awaited, its end is logged with its name and
+//! it is restarted with an exponential backoff. While it is down the health
+//! is reported as degraded.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::task::AbortHandle;
+use tracing::error;
+
+/// Delay before the first restart, doubled with every consecutive one.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_mins(5);
+
+/// State of a supervised task, as reported by `/healthz`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TaskStatus {
+    pub name: &'static str,
+    pub running: bool,
+    /// How often the task was restarted.
+    pub restarts: u32,
+    /// Why the task last ended, if it did.
+    pub last_exit: Option<String>,
+}
+
+#[derive(Debug)]
+struct Task {
+    status: TaskStatus,
+    abort: AbortHandle,
+}
+
+#[derive(Clone, Debug)]
+pub struct Supervisor {
+    tasks: Arc<Mutex<BTreeMap<&'static str, Task>>>,
+    initial_backoff: Duration,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::with_backoff(INITIAL_BACKOFF)
+    }
+}
+
+impl Supervisor {
+    /// Create a supervisor waiting `initial_backoff` before the first restart
+    /// of a task.
+    #[must_use]
+    pub fn with_backoff(initial_backoff: Duration) -> Self {
+        Self {
+            tasks: Arc::default(),
+            initial_backoff,
+        }
+    }
+
+    /// Run the future returned by `start` as the task `name`, starting it
+    /// again whenever it ends.
+    pub fn spawn<F, Fut>(&self, name: &'static str, start: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let mut backoff = supervisor.initial_backoff;
+            loop {
+                let handle = tokio::spawn(start());
+                let started = Instant::now();
+                supervisor.started(name, handle.abort_handle());
+                let exit = match handle.await {
+                    Ok(()) => "returned".to_string(),
+                    Err(e) if e.is_panic() => {
+                        format!("panicked: {}", panic_message(&*e.into_panic()))
+                    },
+                    Err(_) => "was aborted".to_string(),
+                };
+                if started.elapsed() > MAX_BACKOFF {
+                    backoff = supervisor.initial_backoff;
+                }
+                error!("Background task '{name}' {exit}, restarting it in {backoff:?}");
+                supervisor.stopped(name, exit);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        });
+    }
+
+    fn started(&self, name: &'static str, abort: AbortHandle) {
+        let mut tasks = self.tasks.lock();
+        if let Some(task) = tasks.get_mut(name) {
+            task.status.running = true;
+            task.status.restarts += 1;
+            task.abort = abort;
+        } else {
+            tasks.insert(
+                name,
+                Task {
+                    status: TaskStatus {
+                        name,
+                        running: true,
+                        restarts: 0,
+                        last_exit: None,
+                    },
+                    abort,
+                },
+            );
+        }
+    }
+
+    fn stopped(&self, name: &'static str, exit: String) {
+        if let Some(task) = self.tasks.lock().get_mut(name) {
+            task.status.running = false;
+            task.status.last_exit = Some(exit);
+        }
+    }
+
+    /// Abort the current run of the task `name`, it is restarted like after
+    /// any other end.
+    pub fn abort(&self, name: &str) {
+        if let Some(task) = self.tasks.lock().get_mut(name) {
+            task.abort.abort();
+            task.status.running = false;
+        }
+    }
+
+    /// The status of all supervised tasks, ordered by name.
+    #[must_use]
+    pub fn statuses(&self) -> Vec<TaskStatus> {
+        self.tasks
+            .lock()
+            .values()
+            .map(|task| task.status.clone())
+            .collect()
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Log panics through `tracing`, so they end up in the structured logs
+/// instead of only on stderr.
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let location = info.location().map(ToString::to_string).unwrap_or_default();
+        let thread = std::thread::current();
+        error!(
+            target: "panic",
+            location,
+            thread = thread.name().unwrap_or("unnamed"),
+            "{}",
+            panic_message(info.payload())
+        );
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use reqwest::StatusCode;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::config::{AppConfig, AppState};
+    use crate::server::{router, serve};
+
+    async fn wait_for(supervisor: &Supervisor, condition: fn(&TaskStatus) -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !supervisor.statuses().first().is_some_and(condition) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("timed out, tasks: {:?}", supervisor.statuses()));
+    }
+
+    #[tokio::test]
+    async fn test_restart_after_abort_and_panic() {
+        let app_state = AppState {
+            tasks: Supervisor::with_backoff(Duration::from_millis(50)),
+            ..AppState::new(AppConfig::default())
+        };
+        app_state.record_update();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, router(app_state.clone()), false));
+        let healthz = async || {
+            let response = reqwest::get(format!("http://{addr}/healthz"))
+                .await
+                .unwrap();
+            let status = response.status();
+            (status, response.json::<serde_json::Value>().await.unwrap())
+        };
+        let runs = Arc::new(AtomicU32::new(0));
+        app_state.tasks.spawn("flaky", {
+            let runs = Arc::clone(&runs);
+            move || {
+                let run = runs.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    assert_ne!(run, 1, "deliberate panic");
+                    std::future::pending::<()>().await;
+                }
+            }
+        });
+        wait_for(&app_state.tasks, |task| task.running).await;
+        let (status, health) = healthz().await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(health["status"], "healthy");
+
+        app_state.tasks.abort("flaky");
+        let (status, health) = healthz().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(health["status"], "degraded");
+        assert_eq!(health["tasks"][0]["name"], "flaky");
+        assert_eq!(health["tasks"][0]["running"], false);
+
+        // the first restart panics, the second one keeps running
+        wait_for(&app_state.tasks, |task| task.running && task.restarts == 2).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        let (status, health) = healthz().await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(health["status"], "healthy");
+        assert_eq!(health["tasks"][0]["restarts"], 2);
+        let last_exit = health["tasks"][0]["last_exit"].as_str().unwrap();
+        assert!(last_exit.contains("deliberate panic"), "{last_exit}");
+    }
+}
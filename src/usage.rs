@@ -0,0 +1,189 @@
+//! Timestamped usage of the bangs, to answer which were used most in a
+//! recent time window.
+//!
+//! Redirects are counted per trigger in one bucket per minute, buckets older
+//! than [`RETENTION`] are dropped. Only the trigger is recorded, never the
+//! search terms.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// Resolution of the usage records.
+const BUCKET_SECONDS: u64 = 60;
+/// How long usage is remembered, and so the longest window that can be
+/// queried.
+pub const RETENTION: Duration = Duration::from_hours(7 * 24);
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TriggerCount {
+    pub trigger: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct UsageLog {
+    /// Counts per trigger, oldest bucket first, keyed by the start of their
+    /// minute in seconds since the Unix epoch.
+    buckets: VecDeque<(u64, HashMap<String, u64>)>,
+}
+
+impl UsageLog {
+    /// Record a use of `trigger` now.
+    pub fn record(&mut self, trigger: &str) {
+        self.record_at(trigger, unix_now());
+    }
+
+    /// Record a use of `trigger` at `timestamp` seconds since the Unix epoch.
+    ///
+    /// Timestamps older than the newest recorded one are counted in the
+    /// newest bucket.
+    pub fn record_at(&mut self, trigger: &str, timestamp: u64) {
+        let bucket_start = timestamp - timestamp % BUCKET_SECONDS;
+        match self.buckets.back_mut() {
+            Some((start, counts)) if *start >= bucket_start => {
+                *counts.entry(trigger.to_string()).or_default() += 1;
+            },
+            _ => {
+                self.buckets
+                    .push_back((bucket_start, HashMap::from([(trigger.to_string(), 1)])));
+            },
+        }
+        let oldest = bucket_start.saturating_sub(RETENTION.as_secs());
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(start, _)| *start < oldest)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// The `limit` most used triggers within `window` before now.
+    #[must_use]
+    pub fn top(&self, window: Duration, limit: usize) -> Vec<TriggerCount> {
+        self.top_at(unix_now(), window, limit)
+    }
+
+    /// The `limit` most used triggers within `window` before `now`, the most
+    /// used first and alphabetically among equally used ones.
+    #[must_use]
+    pub fn top_at(&self, now: u64, window: Duration, limit: usize) -> Vec<TriggerCount> {
+        let since = now.saturating_sub(window.as_secs());
+        let mut totals: HashMap<&str, u64> = HashMap::new();
+        for (_, counts) in self
+            .buckets
+            .iter()
+            .rev()
+            // a bucket counts if any of its minute is within the window
+            .take_while(|(start, _)| start + BUCKET_SECONDS > since)
+        {
+            for (trigger, count) in counts {
+                *totals.entry(trigger).or_default() += count;
+            }
+        }
+        let mut top: Vec<TriggerCount> = totals
+            .into_iter()
+            .map(|(trigger, count)| {
+                TriggerCount {
+                    trigger: trigger.to_string(),
+                    count,
+                }
+            })
+            .collect();
+        top.sort_unstable_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.trigger.cmp(&b.trigger))
+        });
+        top.truncate(limit);
+        top
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Parse a window like `90s`, `30m`, `24h` or `7d`.
+#[must_use]
+pub fn parse_window(window: &str) -> Option<Duration> {
+    let window = window.trim();
+    let unit_start = window.find(|c: char| !c.is_ascii_digit())?;
+    let amount: u64 = window[..unit_start].parse().ok()?;
+    let unit = match &window[unit_start..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(amount.checked_mul(unit)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_in_window() {
+        let now = 1_791_964_800;
+        let mut usage = UsageLog::default();
+        // two days ago
+        for _ in 0..5 {
+            usage.record_at("yt", now - 2 * 24 * 60 * 60);
+        }
+        // within the last day
+        for (trigger, minutes_ago) in [
+            ("g", 600),
+            ("w", 300),
+            ("g", 120),
+            ("gh", 60),
+            ("w", 10),
+            ("g", 0),
+        ] {
+            usage.record_at(trigger, now - minutes_ago * 60);
+        }
+
+        let count = |trigger: &str, count| {
+            TriggerCount {
+                trigger: trigger.to_string(),
+                count,
+            }
+        };
+        let day = parse_window("24h").unwrap();
+        assert_eq!(
+            usage.top_at(now, day, 10),
+            [count("g", 3), count("w", 2), count("gh", 1)]
+        );
+        assert_eq!(usage.top_at(now, day, 1), [count("g", 3)]);
+        assert_eq!(
+            usage.top_at(now, parse_window("7d").unwrap(), 2),
+            [count("yt", 5), count("g", 3)]
+        );
+        assert_eq!(
+            usage.top_at(now, parse_window("15m").unwrap(), 10),
+            [count("g", 1), count("w", 1)]
+        );
+
+        // records past the retention are forgotten
+        let later = now + RETENTION.as_secs() + 60;
+        usage.record_at("g", later);
+        assert_eq!(usage.top_at(later, Duration::MAX, 10), [count("g", 1)]);
+    }
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_window("30m"), Some(Duration::from_mins(30)));
+        assert_eq!(parse_window("24h"), Some(Duration::from_hours(24)));
+        assert_eq!(parse_window("7d"), Some(Duration::from_hours(7 * 24)));
+        for window in ["", "24", "h", "1w", "-1h", "1.5h"] {
+            assert_eq!(parse_window(window), None, "{window}");
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! The supported library API, the items re-exported from the crate root and
+//! the paths kept working after moves. Removing or changing one of them breaks
+//! this test, update it together with the changelog of the release.
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::BTreeMap;
+
+    use redirector::bang::Bang;
+    use redirector::config::{AppConfig, AppState};
+    use redirector::{
+        BANG_CACHE, BangCache, BangOrigin, CachedBang, PostForm, Resolution, SourceMetadata,
+    };
+
+    /// Signatures of the async and fallible functions, type checked but never
+    /// run.
+    async fn signatures(config: &AppConfig, state: AppState) {
+        let _: Result<SourceMetadata, _> = redirector::update_bangs(config).await;
+        let _: Result<SourceMetadata, _> = redirector::refresh_bangs(config).await;
+        let _: Result<SourceMetadata, _> = redirector::update_bangs_offline(config);
+        let _: Result<Option<SourceMetadata>, _> = redirector::load_binary_cache(config);
+        let _: Result<usize, _> = redirector::pin_bangs(config).await;
+        let _: Result<usize, _> = redirector::source::pin_bangs(config).await;
+        let () = redirector::periodic_update(state).await;
+    }
+
+    #[test]
+    fn test_root_functions() {
+        let _ = signatures;
+        let _: fn(&AppConfig, &str) -> String = redirector::resolve;
+        let _: fn(&AppConfig, &str) -> Resolution = redirector::resolve_with_info;
+        let _: fn(&BangCache, &AppConfig, &str) -> Resolution = redirector::resolve_in;
+        let _: fn(&AppConfig, &str, usize) -> Vec<String> = redirector::complete_bangs;
+        let _: fn(&str) -> Option<&str> = redirector::get_bang;
+        let _: fn(&str) -> Cow<'_, str> = redirector::sanitize_query;
+        let _: usize = BANG_CACHE.read().len();
+
+        let cache = BangCache::new();
+        let resolution = redirector::resolve_in(&cache, &AppConfig::default(), "rust");
+        assert!(resolution.fallback, "nothing to match in an empty cache");
+    }
+
+    #[test]
+    fn test_root_types() {
+        // struct literals, so adding a field fails to compile
+        let cached = CachedBang {
+            bang: Bang::default(),
+            origin: BangOrigin::Remote,
+        };
+        match cached.origin {
+            BangOrigin::Remote | BangOrigin::Config => {},
+        }
+        let resolution = Resolution {
+            url: String::from("https://example.com/post"),
+            trigger: Some(cached.bang.trigger),
+            origin: Some(BangOrigin::Config),
+            note: None,
+            post: Some(PostForm {
+                field: String::from("q"),
+                term: String::from("rust"),
+                hidden: BTreeMap::new(),
+            }),
+            fallback: false,
+        };
+        assert!(resolution.post.is_some(), "{resolution:?}");
+        let metadata = SourceMetadata {
+            source: String::from("https://example.com/bangs.json"),
+            final_url: None,
+            fetched_at: 0,
+            last_modified: None,
+            etag: None,
+            content_length: None,
+            entries: 0,
+            content_hash: String::new(),
+        };
+        assert_eq!(metadata.entries, 0, "{metadata:?}");
+    }
+
+    #[test]
+    #[expect(deprecated, reason = "the moved paths have to keep working")]
+    fn test_deprecated_paths() {
+        let _: Option<redirector::cache::SourceMetadata> = None::<SourceMetadata>;
+        let _ = redirector::cache::pin_bangs;
+        let _ = redirector::cache::fetch_bang_file;
+    }
+}
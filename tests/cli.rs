@@ -0,0 +1,506 @@
+//! The command line is an interface for scripts, so its output and exit codes
+//! are checked against the actual binary.
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read as _, Write as _};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::path::Path;
+    use std::process::{Child, Command, Output, Stdio};
+    use std::time::{Duration, Instant};
+
+    const BANG_LIST: &str = r#"[
+        {"t":"gh","s":"GitHub","u":"https://github.com/search?q={{{s}}}&type=code"},
+        {"t":"w","u":"https://en.wikipedia.org/?search="}
+    ]"#;
+
+    /// Run redirector offline, with `dir` as both the configuration and the
+    /// temporary directory holding the bang cache.
+    fn redirector(dir: &Path, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_redirector"))
+            .args(args)
+            .env("TMPDIR", dir)
+            .env("XDG_CONFIG_HOME", dir)
+            .env("HOME", dir)
+            .output()
+            .expect("Failed to run redirector")
+    }
+
+    #[test]
+    fn test_pick_format_and_trigger() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("bang-cache.json"), BANG_LIST).unwrap();
+
+        let output = redirector(dir.path(), &["bangs", "list", "--pick-format"]);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "gh\tGitHub\thttps://github.com/search?q={}&type=code\nw\t\thttps://en.wikipedia.org/?search={}\n",
+            "the built-in aliases aren't listed"
+        );
+
+        let output = redirector(dir.path(), &["resolve", "--trigger", "gh", "!w rust"]);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "https://github.com/search?q=%21w%20rust&type=code\n"
+        );
+
+        let output = redirector(dir.path(), &["resolve", "--trigger", "nope", "rust"]);
+        assert_eq!(output.status.code(), Some(2), "unknown bangs exit with 2");
+        assert!(output.stdout.is_empty(), "nothing must be resolved");
+    }
+
+    #[test]
+    fn test_resolve_many() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("bang-cache.json"), BANG_LIST).unwrap();
+
+        let output = redirector(dir.path(), &["resolve", "!gh rust", "serde !w", "!gh"]);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "https://github.com/search?q=rust&type=code\nhttps://en.wikipedia.org/?search=serde\n\
+             https://github.com/search?q=&type=code\n",
+            "one URL per query, in order"
+        );
+
+        let output = redirector(
+            dir.path(),
+            &["resolve", "--json", "--trigger", "w", "rust", "!gh serde"],
+        );
+        assert!(output.status.success(), "{output:?}");
+        let resolved: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(
+            resolved,
+            serde_json::json!([
+                {
+                    "query": "rust",
+                    "target": "https://en.wikipedia.org/?search=rust",
+                    "matched_trigger": "w",
+                },
+                {
+                    "query": "!gh serde",
+                    "target": "https://en.wikipedia.org/?search=%21gh%20serde",
+                    "matched_trigger": "w",
+                },
+            ])
+        );
+
+        let output = redirector(dir.path(), &["resolve", "--json", "plain words"]);
+        let resolved: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(resolved[0]["matched_trigger"], serde_json::Value::Null);
+        assert_eq!(resolved[0]["query"], "plain words");
+    }
+
+    #[test]
+    fn test_config_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("redirector")).unwrap();
+        let config = dir.path().join("redirector").join("config.toml");
+        std::fs::write(&config, "port = 3000\n").unwrap();
+        let output = redirector(dir.path(), &["config", "validate"]);
+        assert!(output.status.success(), "{output:?}");
+
+        std::fs::write(&config, "port = 3000\n[[bangs]]\ntrigger = gh\n").unwrap();
+        let output = redirector(dir.path(), &["config", "validate"]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stdout.is_empty(), "{output:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("line 3, column 11: "), "{stderr}");
+        assert!(stderr.contains("3 | trigger = gh\n"), "{stderr}");
+    }
+
+    #[test]
+    fn test_bad_config() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("bang-cache.json"), BANG_LIST).unwrap();
+        std::fs::create_dir_all(dir.path().join("redirector")).unwrap();
+        let config = dir.path().join("redirector").join("config.toml");
+        std::fs::write(
+            &config,
+            "default_search = \"https://example.com/?q=\"\nbangs_url = \
+             \"duckduckgo.com/bang.js\"\n",
+        )
+        .unwrap();
+
+        let output = redirector(dir.path(), &["resolve", "rust"]);
+        assert_eq!(output.status.code(), Some(1), "{output:?}");
+        assert!(output.stdout.is_empty(), "nothing must be resolved");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains(
+                r#"default_search = "https://example.com/?q=" has no {} placeholder for the query"#
+            ),
+            "{stderr}"
+        );
+        assert!(
+            stderr.contains(r#"bangs_url = "duckduckgo.com/bang.js" needs an http:// or https://"#),
+            "{stderr}"
+        );
+
+        let output = redirector(dir.path(), &["config", "validate"]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stdout.is_empty(), "{output:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("default_search = "), "{stderr}");
+        assert!(stderr.contains("bangs_url = "), "{stderr}");
+
+        // the escape hatch starts anyway, with warnings
+        let output = redirector(
+            dir.path(),
+            &["--ignore-bad-config", "resolve", "--trigger", "gh", "rust"],
+        );
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "https://github.com/search?q=rust&type=code\n"
+        );
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains("Ignoring invalid configuration"),
+            "{stderr}"
+        );
+    }
+
+    #[test]
+    fn test_config_flag() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("bang-cache.json"), BANG_LIST).unwrap();
+        let config = dir.path().join("elsewhere.toml");
+        std::fs::write(
+            &config,
+            "[[bangs]]\ntrigger = \"rs\"\nurl_template = \"https://docs.rs/{{{s}}}\"\n",
+        )
+        .unwrap();
+        let config_arg = config.to_str().unwrap();
+        let resolve_rs = [
+            "--config",
+            config_arg,
+            "resolve",
+            "--trigger",
+            "rs",
+            "serde",
+        ];
+
+        // instances with their own configuration don't share the cache
+        let output = redirector(dir.path(), &resolve_rs);
+        assert_eq!(output.status.code(), Some(1), "{output:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("No bangs cached at "), "{stderr}");
+        assert!(!stderr.contains("bang-cache.json"), "{stderr}");
+
+        let pinned = dir.path().join("pinned.json");
+        std::fs::write(&pinned, BANG_LIST).unwrap();
+        std::fs::write(
+            &config,
+            format!(
+                "pinned_bangs_path = {:?}\n\n[[bangs]]\ntrigger = \"rs\"\nurl_template = \
+                 \"https://docs.rs/{{{{{{s}}}}}}\"\n",
+                pinned.to_str().unwrap()
+            ),
+        )
+        .unwrap();
+        let output = redirector(dir.path(), &resolve_rs);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "https://docs.rs/serde\n"
+        );
+        let output = redirector(dir.path(), &["resolve", "--trigger", "rs", "serde"]);
+        assert_eq!(
+            output.status.code(),
+            Some(2),
+            "the default path has no such bang"
+        );
+
+        let output = redirector(dir.path(), &["config", "validate", "--config", config_arg]);
+        assert!(output.status.success(), "{output:?}");
+        assert!(
+            String::from_utf8(output.stdout)
+                .unwrap()
+                .contains("elsewhere.toml")
+        );
+
+        std::fs::write(&config, "port = \"nope\"\n").unwrap();
+        let output = redirector(dir.path(), &["--config", config_arg, "config", "validate"]);
+        assert_eq!(output.status.code(), Some(1), "{output:?}");
+
+        // an explicitly given file that can't be used is an error, not the
+        // defaults
+        let output = redirector(dir.path(), &resolve_rs);
+        assert_eq!(output.status.code(), Some(1), "{output:?}");
+        let missing = dir.path().join("missing.toml");
+        let output = redirector(
+            dir.path(),
+            &["--config", missing.to_str().unwrap(), "resolve", "rust"],
+        );
+        assert_eq!(output.status.code(), Some(1), "{output:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("Configuration file not found"), "{stderr}");
+    }
+
+    #[test]
+    fn test_init() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("redirector").join("config.toml");
+
+        let output = redirector(dir.path(), &["init"]);
+        assert_eq!(output.status.code(), Some(1), "questions need a terminal");
+        assert!(!config.exists());
+
+        let output = redirector(dir.path(), &["init", "--defaults"]);
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.contains("http://127.0.0.1:3000/opensearch.xml"),
+            "{stdout}"
+        );
+        let written = std::fs::read_to_string(&config).unwrap();
+        assert!(written.contains("listen = \"127.0.0.1:3000\""), "{written}");
+        let output = redirector(dir.path(), &["config", "validate"]);
+        assert!(output.status.success(), "{output:?}");
+
+        std::fs::write(&config, "port = 4000\n").unwrap();
+        let output = redirector(dir.path(), &["init", "--defaults"]);
+        assert_eq!(output.status.code(), Some(1), "{output:?}");
+        assert_eq!(std::fs::read_to_string(&config).unwrap(), "port = 4000\n");
+        let output = redirector(dir.path(), &["init", "--defaults", "--force"]);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(std::fs::read_to_string(&config).unwrap(), written);
+    }
+
+    #[test]
+    fn test_import_bookmarks() {
+        let dir = tempfile::tempdir().unwrap();
+        let bookmarks = dir.path().join("bookmarks.html");
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("res/bookmarks.html"),
+            &bookmarks,
+        )
+        .unwrap();
+        let import = ["bangs", "import", "--format", "bookmarks-html"];
+        let args = [&import[..], &[bookmarks.to_str().unwrap()]].concat();
+        let output = redirector(dir.path(), &args);
+        assert_eq!(
+            output.status.code(),
+            Some(1),
+            "there is no config to import into"
+        );
+
+        std::fs::create_dir_all(dir.path().join("redirector")).unwrap();
+        let config = dir.path().join("redirector").join("config.toml");
+        std::fs::write(&config, "port = 3000\n").unwrap();
+        let output = redirector(dir.path(), &args);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "Imported 5 bangs, skipped 4 entries\n"
+        );
+        let contents = std::fs::read_to_string(&config).unwrap();
+        assert!(
+            contents.contains(
+                "trigger = \"docs\"\nurl_template = \"https://docs.rs/releases/search?query={{{s}}}&i-want=results\"\nshort_name = \"docs.rs & friends\""
+            ),
+            "{contents}"
+        );
+        let output = redirector(dir.path(), &["config", "validate"]);
+        assert!(output.status.success(), "{output:?}");
+    }
+
+    #[test]
+    fn test_open_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("bang-cache.json"), BANG_LIST).unwrap();
+
+        let output = redirector(dir.path(), &["open", "--dry-run", "!gh redirector"]);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "https://github.com/search?q=redirector&type=code\n"
+        );
+    }
+
+    #[test]
+    fn test_bench() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("bang-cache.json"), BANG_LIST).unwrap();
+        let queries = dir.path().join("queries.txt");
+        std::fs::write(&queries, "!gh redirector\nrust lang\n\nw! rust\n").unwrap();
+
+        let output = redirector(
+            dir.path(),
+            &[
+                "bench",
+                "--queries",
+                queries.to_str().unwrap(),
+                "--iterations",
+                "5",
+            ],
+        );
+        assert!(output.status.success(), "{output:?}");
+        let table = String::from_utf8(output.stdout).unwrap();
+        assert!(table.contains("queries      3\n"), "{table}");
+        assert!(table.contains("resolutions  15\n"), "{table}");
+        // without the built-in aliases !github, !wiki and !wikipedia
+        assert!(table.contains("cache size   2 bangs\n"), "{table}");
+        for row in ["throughput", "p50", "p90", "p99", "max"] {
+            assert!(table.contains(row), "{row} is missing in {table}");
+        }
+
+        let empty = dir.path().join("empty.txt");
+        std::fs::write(&empty, "\n").unwrap();
+        let output = redirector(dir.path(), &["bench", "--queries", empty.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(1), "{output:?}");
+    }
+
+    #[test]
+    fn test_offline_without_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = redirector(dir.path(), &["resolve", "--trigger", "gh", "rust"]);
+        assert_eq!(output.status.code(), Some(1), "{output:?}");
+    }
+
+    /// Send a request without a body to `addr`, returning the raw response.
+    fn http(addr: SocketAddr, method: &str, target: &str) -> std::io::Result<String> {
+        let mut stream = TcpStream::connect(addr)?;
+        write!(
+            stream,
+            "{method} {target} HTTP/1.1\r\nHost: localhost\r\nConnection: \
+             close\r\nContent-Length: 0\r\n\r\n"
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    /// Kills the server when the test ends, even if it fails.
+    struct Server(Child);
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    #[test]
+    fn test_no_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("bang-cache.json"), BANG_LIST).unwrap();
+        std::fs::create_dir_all(dir.path().join("redirector")).unwrap();
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config_path = dir.path().join("redirector").join("config.toml");
+        let config = format!(
+            "port = {port}\noffline = true\n[[bangs]]\ntrigger = \"mine\"\nurl_template = \"https://example.com/?q={{{{{{s}}}}}}\"\n"
+        );
+        std::fs::write(&config_path, &config).unwrap();
+
+        let _server = Server(
+            Command::new(env!("CARGO_BIN_EXE_redirector"))
+                .args(["--no-persist", "serve"])
+                .env("TMPDIR", dir.path())
+                .env("XDG_CONFIG_HOME", dir.path())
+                .env("HOME", dir.path())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("Failed to run redirector"),
+        );
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let started = Instant::now();
+        while http(addr, "GET", "/healthz").is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "the server didn't start"
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        let response = http(
+            addr,
+            "POST",
+            "/add_bang?trigger=added&url_template=https%3A%2F%2Fexample.org%2F%3Fq%3D%7B%7B%7Bs%\
+             7D%7D%7D",
+        )
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        // the bang is usable right away
+        let response = http(addr, "GET", "/?q=!added+rust").unwrap();
+        assert!(
+            response.contains("location: https://example.org/?q=rust\r\n"),
+            "{response}"
+        );
+
+        assert_eq!(std::fs::read_to_string(&config_path).unwrap(), config);
+        let mut files: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort_unstable();
+        assert_eq!(files, ["bang-cache.json", "redirector"]);
+    }
+
+    #[test]
+    fn test_listen_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = redirector(
+            dir.path(),
+            &["serve", "--listen", "127.0.0.1:8080", "--port", "9090"],
+        );
+        assert_eq!(output.status.code(), Some(2), "{output:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("conflicts with the port 9090"), "{stderr}");
+
+        let output = redirector(dir.path(), &["serve", "--listen", "localhost"]);
+        assert_eq!(output.status.code(), Some(2), "{output:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("'localhost'"), "{stderr}");
+    }
+
+    #[test]
+    fn test_completions_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let completions = dir.path().join("completions");
+        std::fs::create_dir_all(&completions).unwrap();
+
+        for (shell, file_name, start) in [
+            ("zsh", "_redirector", "#compdef redirector"),
+            ("bash", "redirector.bash", "_redirector()"),
+            ("fish", "redirector.fish", "# Print an optspec"),
+        ] {
+            let output = redirector(
+                dir.path(),
+                &[
+                    "completions",
+                    shell,
+                    "--output",
+                    completions.to_str().unwrap(),
+                ],
+            );
+            assert!(output.status.success(), "{output:?}");
+            let path = completions.join(file_name);
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                format!("{}\n", path.display()),
+                "only the path is printed"
+            );
+            let script = std::fs::read_to_string(&path).unwrap();
+            assert!(script.starts_with(start), "{shell}: {script}");
+        }
+
+        let output = redirector(dir.path(), &["completions", "zsh"]);
+        assert!(output.status.success(), "{output:?}");
+        assert!(output.stdout.starts_with(b"#compdef redirector"));
+
+        let output = redirector(
+            dir.path(),
+            &["completions", "zsh", "--output", "/nonexistent/completions"],
+        );
+        assert_eq!(output.status.code(), Some(1), "{output:?}");
+    }
+}